@@ -0,0 +1,54 @@
+//! Time a `--batch` run over a directory of documents, as a file manager opening a folder would.
+//!
+//! `cargo bench`, then `cargo bench --features mmap` to compare reading through a buffer with
+//! mapping documents into memory.
+#[path = "../tests/common/mod.rs"]
+mod common;
+use common::*;
+use std::time::{Duration, Instant};
+
+/// Documents in the directory.
+const DOCUMENTS: usize = 300;
+/// Times to thumbnail all of them, of which the fastest is reported.
+const ROUNDS: usize = 5;
+
+fn main() {
+    let dir = temp_dir("bench-batch");
+    // Layer data ahead of the thumbnail, as fuzzpaint writes it, for the chunk walk to skip.
+    let layers = vec![0x55; 512 * 1024];
+    let document = fzp(&[
+        (b"dims", &[0, 8, 0, 0, 0, 8, 0, 0]),
+        (b"LIST", &list(b"lyrs", &[(b"layr", &layers)])),
+        (b"thmb", &qoi_pattern(256, 256)),
+    ]);
+    let out_dir = dir.join("thumbs");
+    std::fs::create_dir(&out_dir).unwrap();
+    let list: String = (0..DOCUMENTS)
+        .map(|i| {
+            let path = write_file(&dir, &format!("{i}.fzp"), &document);
+            format!("{}\n", path.display())
+        })
+        .collect();
+    let list = write_file(&dir, "list.txt", list.as_bytes());
+
+    let fastest = (0..ROUNDS)
+        .map(|_| {
+            let start = Instant::now();
+            let output = thumbnailer(&dir)
+                .args(["--force", "--jobs", "1", "--size", "128", "--files-from"])
+                .arg(&list)
+                .arg("--out-dir")
+                .arg(&out_dir)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{output:?}");
+            start.elapsed()
+        })
+        .min()
+        .unwrap_or(Duration::ZERO);
+    println!(
+        "batch of {DOCUMENTS} documents: {fastest:.2?}, {:.2?} each",
+        fastest / DOCUMENTS as u32
+    );
+    let _ = std::fs::remove_dir_all(&dir);
+}
@@ -0,0 +1,39 @@
+//! Time decoding a 1024x1024 thumbnail, the largest allowed by default, as `--timings` reports it.
+//!
+//! Written at its own size, so it's decoded whole rather than scaled in strips. Run before and
+//! after a change to the decoder to compare.
+#[path = "../tests/common/mod.rs"]
+mod common;
+use common::*;
+
+/// Times to thumbnail it, of which the fastest decode is reported.
+const ROUNDS: usize = 20;
+
+fn main() {
+    let dir = temp_dir("bench-decode");
+    let input = write_file(&dir, "in.fzp", &simple_fzp(1024, 1024));
+    let out = dir.join("out.png");
+
+    let fastest = (0..ROUNDS)
+        .map(|_| {
+            let output = thumbnailer(&dir)
+                .args(["--force", "--timings"])
+                .arg(&input)
+                .arg("native")
+                .arg(&out)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{output:?}");
+            // `timings: open=0.123ms scan=0.456ms decode=7.890ms ...`
+            let stderr = String::from_utf8_lossy(&output.stderr);
+            let decode = stderr
+                .split_whitespace()
+                .find_map(|stage| stage.strip_prefix("decode="))
+                .and_then(|ms| ms.strip_suffix("ms"))
+                .and_then(|ms| ms.parse::<f64>().ok());
+            decode.unwrap_or_else(|| panic!("no decode timing in {stderr}"))
+        })
+        .fold(f64::INFINITY, f64::min);
+    println!("decoding a 1024x1024 thumbnail: {fastest:.3}ms");
+    let _ = std::fs::remove_dir_all(&dir);
+}
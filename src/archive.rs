@@ -0,0 +1,249 @@
+//! Documents within zip archives, as file managers browsing an archive ask for them: named by
+//! `archive.zip#inner/doc.fzp`, or by a GVfs `archive://` URI.
+//!
+//! Only with the `zip` feature, otherwise such names are taken to be paths like any other. Even
+//! with it, a path that exists is always the file it names.
+use crate::error::Error;
+use crate::Opened;
+use std::path::{Path, PathBuf};
+
+/// Largest member read, which is buffered whole to be seekable. Far beyond where fuzzpaint puts
+/// thumbnails.
+#[cfg(feature = "zip")]
+const MAX_MEMBER_BYTES: u64 = 32 * 1024 * 1024;
+
+/// A document within an archive.
+#[derive(Debug, PartialEq, Eq)]
+struct Member {
+    archive: PathBuf,
+    /// Path of the document within the archive.
+    name: String,
+    /// The `archive://` URI it was named by, if it was.
+    uri: Option<String>,
+}
+
+/// The archive member `in_path` names, or `None` if it names a file, or nothing like a member.
+fn member(in_path: &Path) -> Option<Member> {
+    if in_path.exists() {
+        return None;
+    }
+    let name = in_path.to_str()?;
+    if let Some(uri) = name.strip_prefix("archive://") {
+        // The archive's own URI is escaped twice over as the host, then the member's path.
+        let (host, path) = uri.split_once('/')?;
+        let archive = percent_decode(&percent_decode(host)?)?;
+        let archive = percent_decode(archive.strip_prefix("file://")?)?;
+        return Some(Member {
+            archive: archive.into(),
+            name: percent_decode(path)?,
+            uri: Some(name.to_owned()),
+        });
+    }
+    // The first `#` after which is an archive, as names within it may have their own.
+    name.match_indices('#').find_map(|(at, _)| {
+        let archive = Path::new(&name[..at]);
+        archive.is_file().then(|| Member {
+            archive: archive.to_owned(),
+            name: name[at + 1..].to_owned(),
+            uri: None,
+        })
+    })
+}
+
+/// Decode `%XX` escapes, or `None` if they're malformed or don't decode to UTF-8.
+fn percent_decode(escaped: &str) -> Option<String> {
+    let mut bytes = Vec::with_capacity(escaped.len());
+    let mut rest = escaped.as_bytes();
+    while let Some((&byte, after)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = std::str::from_utf8(after.get(..2)?).ok()?;
+            bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            rest = &after[2..];
+        } else {
+            bytes.push(byte);
+            rest = after;
+        }
+    }
+    String::from_utf8(bytes).ok()
+}
+
+/// URI of the archive member `in_path` names, or `None` if it doesn't name one: the
+/// `archive://` URI it was given as, or the archive's `file://` URI with the member as its
+/// fragment.
+pub fn member_uri(in_path: &Path) -> Option<Result<String, Error>> {
+    if !cfg!(feature = "zip") {
+        return None;
+    }
+    let member = member(in_path)?;
+    Some(match member.uri {
+        Some(uri) => Ok(uri),
+        None => crate::file_uri(&member.archive).map(|uri| format!("{uri}#{}", member.name)),
+    })
+}
+
+/// Open the archive member `in_path` names, or `None` if it doesn't name one.
+///
+/// The member is read into memory, and taken to have been modified when its entry says, unless
+/// `mtime_override` is given.
+#[cfg(feature = "zip")]
+pub fn open_member(
+    in_path: &Path,
+    mtime_override: Option<std::time::Duration>,
+) -> Option<Result<Opened, Error>> {
+    member(in_path).map(|member| open(&member, mtime_override))
+}
+
+/// Without the `zip` feature, nothing names an archive member.
+#[cfg(not(feature = "zip"))]
+pub fn open_member(
+    _in_path: &Path,
+    _mtime_override: Option<std::time::Duration>,
+) -> Option<Result<Opened, Error>> {
+    None
+}
+
+#[cfg(feature = "zip")]
+fn open(member: &Member, mtime_override: Option<std::time::Duration>) -> Result<Opened, Error> {
+    use crate::error::Class;
+    use std::io::Read;
+    use zip::result::ZipError;
+    let zip_err = |err: ZipError| match err {
+        ZipError::Io(io) => Error::from_input("failed to read archive", &io),
+        ZipError::FileNotFound => Error::new(
+            Class::InputIo,
+            format!("archive has no member {:?}", member.name),
+        ),
+        err => Error::new(Class::Corrupt, format!("failed to read archive: {err}")),
+    };
+    let file = std::fs::File::open(&member.archive)
+        .map_err(|io| Error::new(Class::InputIo, format!("failed to access archive: {io}")))?;
+    let mut archive = zip::ZipArchive::new(std::io::BufReader::new(file)).map_err(zip_err)?;
+    let mut entry = archive.by_name(&member.name).map_err(zip_err)?;
+    // Before extracting any of it, however much it claims.
+    if entry.size() > MAX_MEMBER_BYTES {
+        return Err(too_large());
+    }
+    let mtime = mtime_override.or_else(|| entry.last_modified().map(unix_time));
+    let mut data = Vec::new();
+    // One more than allowed, in case its entry understates it.
+    (&mut entry)
+        .take(MAX_MEMBER_BYTES + 1)
+        .read_to_end(&mut data)
+        .map_err(|io| Error::from_input("failed to extract archive member", &io))?;
+    if data.len() as u64 > MAX_MEMBER_BYTES {
+        return Err(too_large());
+    }
+    crate::log::verbose(format_args!(
+        "extracted {} bytes of {} from {}",
+        data.len(),
+        member.name,
+        member.archive.display()
+    ));
+    Ok(Opened {
+        fzp: crate::compressed::decompress(Box::new(std::io::Cursor::new(data)))?,
+        mtime,
+        version: None,
+        sidecar: None,
+    })
+}
+
+#[cfg(feature = "zip")]
+fn too_large() -> Error {
+    Error::new(
+        crate::error::Class::Corrupt,
+        format!(
+            "archive member too large to thumbnail, over {} MiB",
+            MAX_MEMBER_BYTES >> 20
+        ),
+    )
+}
+
+/// Seconds since the Unix epoch of a zip entry's timestamp, which has no time zone, so is taken
+/// to be UTC.
+#[cfg(feature = "zip")]
+fn unix_time(time: zip::DateTime) -> std::time::Duration {
+    // Days from 1970-01-01 to the start of the month, as in Howard Hinnant's `days_from_civil`.
+    let (month, day) = (u64::from(time.month()), u64::from(time.day()));
+    let year = u64::from(time.year()) - u64::from(month <= 2);
+    let era = year / 400;
+    let year_of_era = year % 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146_097 + day_of_era - 719_468;
+    let secs =
+        u64::from(time.hour()) * 3600 + u64::from(time.minute()) * 60 + u64::from(time.second());
+    std::time::Duration::from_secs(days * 86_400 + secs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        let dir = std::env::temp_dir().join(format!("archive-names-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let archive = dir.join("a#b.zip");
+        std::fs::write(&archive, b"").unwrap();
+        let archive_str = archive.to_str().unwrap();
+
+        let named = |name: String| member(Path::new(&name));
+        assert_eq!(
+            named(format!("{archive_str}#art/doc#2.fzp")),
+            Some(Member {
+                archive: archive.clone(),
+                name: "art/doc#2.fzp".to_owned(),
+                uri: None,
+            })
+        );
+        // Only an archive that exists.
+        assert_eq!(named(format!("{}/none.zip#doc.fzp", dir.display())), None);
+        // Nor a path that does.
+        assert_eq!(named(archive_str.to_owned()), None);
+
+        let escape = |text: &str| -> String {
+            text.bytes()
+                .map(|b| match b {
+                    b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'.' | b'_' => {
+                        char::from(b).to_string()
+                    }
+                    _ => format!("%{b:02X}"),
+                })
+                .collect()
+        };
+        let host = escape(&escape(&crate::xdg::path_uri(&archive)));
+        let uri = format!("archive://{host}/art/my%20doc.fzp");
+        assert_eq!(
+            named(uri.clone()),
+            Some(Member {
+                archive: archive.clone(),
+                name: "art/my doc.fzp".to_owned(),
+                uri: Some(uri),
+            })
+        );
+        assert_eq!(named("archive://file%253A%25/doc.fzp".to_owned()), None);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
+    fn decodes() {
+        assert_eq!(percent_decode("a%20b%2Fc").as_deref(), Some("a b/c"));
+        assert_eq!(percent_decode("%e2%9C%93").as_deref(), Some("✓"));
+        assert_eq!(percent_decode("%2"), None);
+        assert_eq!(percent_decode("%zz"), None);
+        assert_eq!(percent_decode("%ff"), None);
+    }
+    #[test]
+    #[cfg(feature = "zip")]
+    fn entry_times() {
+        let time = |year, month, day, hour, minute, second| {
+            let time =
+                zip::DateTime::from_date_and_time(year, month, day, hour, minute, second).unwrap();
+            unix_time(time).as_secs()
+        };
+        assert_eq!(time(1980, 1, 1, 0, 0, 0), 315_532_800);
+        assert_eq!(time(2000, 2, 29, 12, 30, 16), 951_827_416);
+        // Only to the even second.
+        assert_eq!(time(2000, 2, 29, 12, 30, 15), 951_827_414);
+        assert_eq!(time(2023, 11, 14, 22, 13, 20), 1_700_000_000);
+    }
+}
@@ -0,0 +1,2833 @@
+//! Command line parsing.
+//!
+//! Accepts both named flags and the legacy positional form used by existing `.thumbnailer` entries:
+//! `fuzzpaint-thumbnailer <in_path> <size in px> <out_path> [in_uri]`
+use crate::config::{self, Defaults, Sources};
+use crate::log::Verbosity;
+use crate::template::Template;
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+use std::path::PathBuf;
+
+/// Largest `--size` we're willing to produce.
+// We only have so much input data to work with!
+// I don't believe any shell would request anything much larger than 512,
+// but just in case to avoid expensive calc and lots of mem for an accidental request.
+pub const MAX_SIZE: u32 = 2048;
+
+/// Largest `--max-dimension` we'll accept.
+// A 8192x8192 RGBA8 thumbnail is already 256MiB decoded.
+pub const MAX_INPUT_DIMENSION_LIMIT: u32 = 8192;
+
+/// How `--sizes` names its outputs, unless told otherwise.
+pub const DEFAULT_NAME_TEMPLATE: &str = "{stem}-{size}.png";
+
+/// How `--files-from` names its outputs, unless told otherwise. As the XDG cache does.
+pub const DEFAULT_FILES_FROM_TEMPLATE: &str = "{md5uri}.png";
+
+pub fn usage() -> String {
+    format!(
+        "\
+Usage: fuzzpaint-thumbnailer [OPTIONS] <in_path> <size> <out_path> [in_uri]
+       fuzzpaint-thumbnailer --input <in_path> --size <size> --output <out_path> [--uri <in_uri>]
+       fuzzpaint-thumbnailer --batch --size <size> [--jobs <N>] [<in_path>:<out_path>]...
+       fuzzpaint-thumbnailer --sizes <size>,... --out-dir <dir> <in_path> [in_uri]
+       fuzzpaint-thumbnailer --files-from <list> --out-dir <dir> --size <size> [--jobs <N>]
+       fuzzpaint-thumbnailer --probe <in_path>
+       fuzzpaint-thumbnailer --info <in_path>
+       fuzzpaint-thumbnailer --list-chunks <in_path>
+       fuzzpaint-thumbnailer --validate [--max-dimension <N>] <in_path>
+       fuzzpaint-thumbnailer --extract <in_path> <out_path>
+
+Options:
+  -i, --input <PATH>    Document to read the thumbnail from, or `-` for stdin, which is read
+                        whole and so must be no more than {stdin_mib} MiB
+      --in-fd <N>       Read the document from the already-open file descriptor N instead of
+                        <in_path>. Must be a regular file, and requires <in_uri>. Not stdin,
+                        which is `-`, nor --out-fd's. Unix only
+  -o, --output <PATH>   Where to write the PNG thumbnail, or `-` for stdout
+      --out-fd <N>      Write the PNG thumbnail to the already-open file descriptor N instead
+                        of <out_path>, as is. Not stdout, which is `-`. Unix only
+  -s, --size <SIZE>     Fit the thumbnail into a square of SIZE px, or a WIDTHxHEIGHT box.
+                        Each dimension must be in 1..={MAX_SIZE}. `native` skips resizing entirely
+  -u, --uri <URI>       URI of the document, written as Thumb::URI. Defaults to the
+                        file:// URI of <in_path>, and is required if that is `-`
+      --mtime <SECONDS> Modification time of the document as a Unix timestamp, written as
+                        Thumb::MTime instead of that of <in_path>
+      --batch           Thumbnail each <in_path>:<out_path> pair, continuing past failures
+      --files-from <PATH>
+                        Thumbnail every path listed in a file (or `-` for stdin), implying --batch.
+                        Paths are NUL-delimited if the start of the list contains a NUL,
+                        otherwise newline-delimited
+      --out-dir <DIR>   With --files-from or --sizes, where to write thumbnails
+      --sizes <SIZE>,...
+                        Decode once and write a thumbnail for each comma-separated <size> into
+                        --out-dir, continuing past failures
+      --out-template <TEMPLATE>
+                        With --files-from or --sizes, how to name each thumbnail in --out-dir.
+                        `{{stem}}` and `{{ext}}` are replaced by <in_path>'s name without its
+                        extension and the extension, `{{size}}`, `{{width}}`, and `{{height}}` by
+                        the size, and `{{md5uri}}` by the MD5 of the URI. Write `{{{{` and `}}}}`
+                        for literal braces. Defaults to `{DEFAULT_FILES_FROM_TEMPLATE}` with
+                        --files-from, and `{DEFAULT_NAME_TEMPLATE}` with --sizes.
+                        Also accepted as --name-template
+  -j, --jobs <N>        With --batch, thumbnail up to N files at once. Defaults to the CPU count
+      --report jsonl    With --batch or --sizes, print one JSON object per thumbnail to stdout
+                        instead of human-readable messages. With --dry-run, print the plan as
+                        a JSON object
+      --dry-run         Read the document and work out the thumbnail, then print what would be
+                        written as `key=value` lines instead of writing anything
+      --probe           Only check whether <in_path> has a thumbnail, writing nothing
+      --info            Print the thumbnail's dimensions and format as JSON, writing nothing
+      --list-chunks     Print the offset, fourcc, and size of each chunk in <in_path>, as
+                        thumbnailing reads them, indenting those within lists
+      --validate        Check <in_path>'s structure and thumbnail, printing one line per problem
+                        and exiting nonzero if there are any. Writes nothing
+      --extract         Copy the embedded QOI thumbnail to <out_path> byte-for-byte
+      --data-uri        Print the thumbnail to stdout as a `data:image/png;base64,` URI, instead
+                        of writing <out_path>
+      --png-text <KEY=VALUE>
+                        Add a tEXt chunk to the thumbnail. May be repeated
+      --mode <fit|cover>
+                        Fit the image inside <size> (the default), or fill it exactly by
+                        trimming the image's overflowing edges
+      --filter <nearest|bilinear|catmullrom|lanczos3|box>
+                        Resampling filter used when resizing. Defaults to choosing by how far
+                        the image is scaled: nearest if hardly at all, catmullrom to half size
+                        or larger, and lanczos3 below that
+      --allow-upscale   Scale images smaller than <size> up to it, rather than leaving them at
+                        their own size
+      --no-upscale      Never scale the image up to <size>, only down. The default
+      --gamma-correct   Resize in linear light rather than on the sRGB-encoded values, so
+                        fine light and dark detail averages to the right brightness. Only for
+                        thumbnails without an ICC profile, and takes twice the memory
+      --no-premultiply  Resize colors as they are, without first weighing them by their
+                        alpha, which lets the colors of transparent pixels bleed into edges
+      --compression <fast|balanced|best>
+                        How hard to compress the PNG. Defaults to fast
+      --square          Pad the scaled image with transparency to exactly <size>, centered
+      --background <RRGGBB[AA]|doc>
+                        Composite the image over this color, with an optional leading `#`, or
+                        `doc` for the document's own background color, if it has one.
+                        Opaque colors (no AA, or FF) write an RGB PNG with no alpha channel
+      --threads <N>     Resize thumbnails of more than 512x512 pixels on N threads, to the same
+                        result as on one. Defaults to one per CPU core, shared between --jobs
+      --max-dimension <N>
+                        Refuse thumbnails stored with more pixels than an N by N square, in
+                        any shape, for N in 1..={MAX_INPUT_DIMENSION_LIMIT}. Defaults to {}.
+                        Thumbnails over {max_side} px on either side are always refused
+      --crop <X,Y,WxH>  Only thumbnail this rectangle of the stored image, clamped to its edges
+      --rotate <DEGREES>
+                        Turn the image clockwise by 90, 180, or 270 degrees, after cropping
+      --flip <h|v>      Mirror the image horizontally or vertically, after rotating
+                        Either overrides the document's own orientation, if it has one
+      --no-optimize-color
+                        Always write RGB or RGBA, even if every pixel is gray
+      --force-rgba      Always write RGBA, even if every pixel is gray or opaque, as the
+                        thumbnail spec strictly asks
+      --allow-blank     Write thumbnails that are fully transparent, rather than failing as
+                        though the document had none
+      --force           Regenerate the thumbnail even if <out_path> already has a matching
+                        Thumb::URI and Thumb::MTime
+      --timings         Report how long each stage of thumbnailing took, on stderr (or in the
+                        --report)
+      --print-hash      Print `sha256:<hex> <out_path>` for each thumbnail written, on stdout
+                        (or stderr, if the thumbnail itself is going to stdout)
+      --log-file <PATH> Also append every diagnostic to PATH, timestamped, even those only shown
+                        with --verbose
+      --strict          Fail on problems that would otherwise only be warned about or coped
+                        with, such as an invalid config file or environment variable, or a
+                        chunk declaring more bytes than the document has
+      --lenient         Skip chunk headers that can't be right, with a fourcc that isn't
+                        printable or a size that can't fit, to the next plausible one within
+                        {resync_kib} KiB. May find thumbnails behind a corrupted chunk. Also
+                        accepts thumbnails stored in up to {lenient_ratio} times the bytes their
+                        size could ever need, rather than {encoded_ratio}
+      --max-chunks <N>  Look no further than the first N chunks of a document for its
+                        thumbnail, counting those nested in lists. Defaults to {max_chunks}
+      --chunk <N>       Take a document's Nth thumbnail, in the order its thumbnail index lists
+                        them or else the order they're stored in, rather than the one best
+                        suited to the size
+      --max-thumb-bytes <N>
+                        Treat thumbnails stored in more than N bytes as corrupt. Defaults to
+                        {max_thumb_bytes}
+      --accept-form <FORM>
+                        Also accept RIFF documents of the form type FORM, four characters
+                        such as `fzp3`, treating them like fuzzpaint documents
+      --colorspace <srgb|linear|auto>
+                        Take thumbnails' pixels to be sRGB or linear light, whatever they're
+                        tagged as. Defaults to auto, trusting the tag
+      --recover         If a document or its thumbnail can't be parsed, decode whatever looks
+                        like QOI data in its first {recover_mib} MiB instead. May salvage
+                        thumbnails from damaged documents, but may also produce garbage
+      --allow-flatten   If a document has no thumbnail, scale down its full-size flattened
+                        canvas instead, if it has no more pixels than a {flatten_limit} px (or
+                        --max-dimension) square. Slower, and far more memory hungry
+      --sidecar         If a document has no thumbnail, use the QOI image in the file beside
+                        it named after it with `.thumb` appended, e.g. `art.fzp.thumb`, if
+                        there is one. Never for stdin or --in-fd
+  -v, --verbose         Narrate each stage of the work on stderr
+  -q, --quiet           Print nothing on stderr but the final error
+  -h, --help            Print this message
+  -V, --version         Print the version
+
+Positional arguments fill whichever of input, size, output, uri were not given as flags, in that order.
+Built with the `zip` feature, <in_path> may also name a document within a zip archive, as
+`archive.zip#inner/doc.fzp` or an `archive://` URI.
+
+Defaults for --filter, --no-upscale, --compression, --max-dimension, --threads, and --log-file
+may be set in $XDG_CONFIG_HOME/fuzzpaint-thumbnailer/config.toml, as `key = value` lines named
+after the option without its leading `--`, e.g. `filter = \"lanczos3\"` or `no-upscale = true`.
+The environment variables FUZZPAINT_THUMB_FILTER, FUZZPAINT_THUMB_NO_UPSCALE,
+FUZZPAINT_THUMB_COMPRESSION, FUZZPAINT_THUMB_MAX_DIM, FUZZPAINT_THUMB_THREADS, and
+FUZZPAINT_THUMB_LOG_FILE override the config file, and the command line overrides both.
+
+Exit status:
+  0  success
+  1  --validate found problems, or some --batch or --sizes thumbnails failed
+  2  the command line, or with --strict the config file or environment, is invalid
+  3  the document is valid but has no thumbnail
+  4  the document or its thumbnail is corrupt
+  5  the input couldn't be read
+  6  the output couldn't be written
+  7  the input changed while it was being read, even after reading it again",
+        crate::MAX_INPUT_IMAGE_DIMENSION,
+        max_chunks = crate::MAX_CHUNKS,
+        max_thumb_bytes = crate::MAX_THUMB_BYTES,
+        recover_mib = crate::RECOVER_SCAN_LEN >> 20,
+        stdin_mib = crate::MAX_STDIN_BYTES >> 20,
+        max_side = crate::MAX_INPUT_IMAGE_SIDE,
+        flatten_limit = crate::MAX_FLATTEN_DIMENSION,
+        resync_kib = crate::chunks::RESYNC_WINDOW >> 10,
+        encoded_ratio = crate::MAX_ENCODED_RATIO,
+        lenient_ratio = crate::LENIENT_ENCODED_RATIO,
+    )
+}
+
+/// Settings that apply whatever the command.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Global {
+    pub verbosity: Verbosity,
+    /// Fail rather than warn about problems we could carry on past.
+    pub strict: bool,
+    /// Skip malformed chunk headers rather than trusting them.
+    pub lenient: bool,
+    /// Chunks of a document to examine for a thumbnail before giving up, counting those nested
+    /// in lists.
+    pub max_chunks: u32,
+    /// Largest thumbnail chunk to accept, in bytes.
+    pub max_thumb_bytes: u64,
+    /// Take the thumbnail in this position, counting from 1, rather than choosing one.
+    pub chunk: Option<std::num::NonZeroU32>,
+    /// Search damaged documents for anything that decodes as a thumbnail.
+    pub recover: bool,
+    /// Thumbnail documents without a thumbnail from their flattened canvas.
+    pub allow_flatten: bool,
+    /// Thumbnail documents without a thumbnail from a sidecar file beside them.
+    pub sidecar: bool,
+    /// Accept documents of this form type too, as if they were fuzzpaint documents.
+    pub accept_form: Option<[u8; 4]>,
+    /// Take thumbnails to be in this colorspace, rather than the one they're tagged with.
+    pub colorspace: Colorspace,
+    /// Where to also append every diagnostic, whatever the verbosity.
+    pub log_file: Option<PathBuf>,
+}
+
+/// What the user asked of us.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Command {
+    Help,
+    Version,
+    Thumbnail(Args),
+    Batch(Batch),
+    Sizes(Sizes),
+    /// Check for the presence of a thumbnail.
+    Probe {
+        input: PathBuf,
+    },
+    /// Describe the thumbnail.
+    Info {
+        input: PathBuf,
+    },
+    /// Describe the container structure.
+    ListChunks {
+        input: PathBuf,
+    },
+    /// Check the document for problems.
+    Validate {
+        input: PathBuf,
+        /// Largest width or height of thumbnail that's acceptable.
+        max_dimension: u32,
+    },
+    /// Copy out the raw thumbnail data.
+    Extract {
+        input: PathBuf,
+        output: PathBuf,
+    },
+}
+
+/// Options shared by every thumbnail we generate in this invocation.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Options {
+    /// Fit into a box of this size, or `None` to keep the thumbnail's stored size.
+    pub size: Option<Size>,
+    /// How to scale into `size`.
+    pub mode: ScaleMode,
+    /// How to resample when scaling, or `None` to choose by how far, see
+    /// [`crate::fit::choose_filter`].
+    pub filter: Option<Filter>,
+    /// Only ever scale down, leaving smaller images at their stored size, unless
+    /// `--allow-upscale`.
+    pub no_upscale: bool,
+    /// How hard to compress the PNG.
+    pub compression: Compression,
+    /// Refuse thumbnails stored larger than this in either dimension.
+    pub max_dimension: u32,
+    /// Pad the scaled image out to exactly `size`.
+    pub square: bool,
+    /// Flatten transparency onto this.
+    pub background: Option<Background>,
+    /// Report how long each stage took.
+    pub timings: bool,
+    /// Print the SHA-256 of each written thumbnail.
+    pub print_hash: bool,
+    /// Regenerate thumbnails even if the output is already up to date.
+    pub force: bool,
+    /// Write the PNG as a single `data:` URI line, rather than raw.
+    pub data_uri: bool,
+    /// Additional `(keyword, text)` tEXt chunks, already validated.
+    pub png_text: Vec<(String, String)>,
+    /// Only thumbnail this region of the stored image.
+    pub crop: Option<Crop>,
+    /// Turn the (cropped) image clockwise before scaling.
+    pub rotate: Option<Rotation>,
+    /// Mirror the (rotated) image before scaling.
+    pub flip: Option<Flip>,
+    /// Write thumbnails even if every pixel is fully transparent.
+    pub allow_blank: bool,
+    /// Keep all three color channels, rather than one if every pixel is gray.
+    pub no_optimize_color: bool,
+    /// Keep all four channels, as `--force-rgba` asks.
+    pub force_rgba: bool,
+    /// Scale in linear light, as `--gamma-correct` asks.
+    pub gamma_correct: bool,
+    /// Scale colours without weighing them by alpha, as `--no-premultiply` asks.
+    pub no_premultiply: bool,
+    /// Threads to resize large thumbnails on, or `None` for one per core.
+    pub threads: Option<std::num::NonZeroUsize>,
+}
+
+/// Arguments for generating a single thumbnail.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Args {
+    /// Empty if `in_fd` is given.
+    pub input: PathBuf,
+    /// Inherited descriptor to read from instead of `input`.
+    pub in_fd: Option<i32>,
+    /// Empty if `out_fd` is given.
+    pub output: PathBuf,
+    /// Inherited descriptor to write to instead of `output`.
+    pub out_fd: Option<i32>,
+    /// `None` to derive it from `input`.
+    pub uri: Option<String>,
+    /// Unix timestamp, or `None` to read it from `input`'s metadata.
+    pub mtime: Option<u64>,
+    /// Only describe what would be written.
+    pub dry_run: bool,
+    /// Describe it in this format instead of `key=value` lines.
+    pub report: Option<ReportFormat>,
+    pub options: Options,
+}
+
+/// Arguments for generating several sizes of thumbnail from one document.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Sizes {
+    pub input: PathBuf,
+    /// `None` to derive it from `input`.
+    pub uri: Option<String>,
+    /// Unix timestamp, or `None` to read it from `input`'s metadata.
+    pub mtime: Option<u64>,
+    /// Distinct, in the order given.
+    pub sizes: Vec<Size>,
+    pub out_dir: PathBuf,
+    /// Output file name within `out_dir`.
+    pub out_template: Template,
+    /// Report results in this format instead of human-readable messages.
+    pub report: Option<ReportFormat>,
+    /// `size` is always `None`, each of `sizes` is used instead.
+    pub options: Options,
+}
+
+/// Where batch work comes from.
+#[derive(Debug, PartialEq, Eq)]
+pub enum BatchItems {
+    /// `(input, output)` paths.
+    Pairs(Vec<(PathBuf, PathBuf)>),
+    /// Input paths are listed in a file, outputs are generated.
+    FilesFrom {
+        /// Path of the list, or `-` for stdin.
+        list: PathBuf,
+        out_dir: PathBuf,
+        /// Output file name within `out_dir`.
+        out_template: Template,
+    },
+}
+
+/// Machine-readable output formats.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ReportFormat {
+    /// One JSON object per line.
+    Jsonl,
+}
+
+/// Arguments for generating many thumbnails.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Batch {
+    pub items: BatchItems,
+    /// Worker thread count, or `None` to pick based on the CPU count.
+    pub jobs: Option<std::num::NonZeroUsize>,
+    /// Report results in this format instead of human-readable messages.
+    pub report: Option<ReportFormat>,
+    pub options: Options,
+}
+
+/// A rectangle of the stored thumbnail, which may extend past its edges.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Crop {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// How to scale the image into the requested size.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum ScaleMode {
+    /// Fit entirely inside the box, leaving part of it empty if the aspect ratios differ.
+    #[default]
+    Fit,
+    /// Fill the box exactly, cutting off the overflow evenly from both sides.
+    Cover,
+}
+
+/// Resampling filters for resizing.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Filter {
+    Nearest,
+    Bilinear,
+    CatmullRom,
+    Lanczos3,
+    Box,
+}
+impl Filter {
+    /// Every filter, with its name on the command line.
+    pub const NAMED: [(&'static str, Self); 5] = [
+        ("nearest", Self::Nearest),
+        ("bilinear", Self::Bilinear),
+        ("catmullrom", Self::CatmullRom),
+        ("lanczos3", Self::Lanczos3),
+        ("box", Self::Box),
+    ];
+}
+
+/// PNG compression levels, trading time for size.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Compression {
+    /// The shell re-encodes our output anyway, so don't spend long on it.
+    #[default]
+    Fast,
+    Balanced,
+    Best,
+}
+impl Compression {
+    /// Every level, with its name on the command line.
+    pub const NAMED: [(&'static str, Self); 3] = [
+        ("fast", Self::Fast),
+        ("balanced", Self::Balanced),
+        ("best", Self::Best),
+    ];
+}
+
+/// How to take the colorspace of a thumbnail's pixels.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub enum Colorspace {
+    /// As the thumbnail's header says.
+    #[default]
+    Auto,
+    Srgb,
+    Linear,
+}
+impl Colorspace {
+    /// Every colorspace, with its name on the command line.
+    pub const NAMED: [(&'static str, Self); 3] = [
+        ("srgb", Self::Srgb),
+        ("linear", Self::Linear),
+        ("auto", Self::Auto),
+    ];
+}
+
+/// What to flatten transparency onto.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Background {
+    /// This RGBA color.
+    Color([u8; 4]),
+    /// The document's background color, or nothing if it hasn't one.
+    Document,
+}
+
+/// A clockwise rotation.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Rotation {
+    /// 90 degrees.
+    Quarter,
+    /// 180 degrees.
+    Half,
+    /// 270 degrees.
+    ThreeQuarter,
+}
+
+/// An axis to mirror across.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Flip {
+    /// Swap left and right.
+    Horizontal,
+    /// Swap top and bottom.
+    Vertical,
+}
+
+/// A box to fit the thumbnail within.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Size {
+    /// Non-zero and at most [`MAX_SIZE`].
+    pub width: u32,
+    /// Non-zero and at most [`MAX_SIZE`].
+    pub height: u32,
+}
+impl Size {
+    pub fn square(size: u32) -> Self {
+        Self {
+            width: size,
+            height: size,
+        }
+    }
+}
+impl std::fmt::Display for Size {
+    /// As it would be given on the command line.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.width == self.height {
+            write!(f, "{}", self.width)
+        } else {
+            write!(f, "{}x{}", self.width, self.height)
+        }
+    }
+}
+
+/// Parse `X,Y,WxH`.
+fn parse_crop(crop: &str) -> Result<Crop, Cow<'static, str>> {
+    let parsed = crop.split_once(',').and_then(|(x, rest)| {
+        let (y, size) = rest.split_once(',')?;
+        let (width, height) = size.split_once('x')?;
+        Some(Crop {
+            x: x.parse().ok()?,
+            y: y.parse().ok()?,
+            width: width.parse().ok()?,
+            height: height.parse().ok()?,
+        })
+    });
+    parsed.ok_or_else(|| {
+        format!("<crop> parameter `{crop}` is not of the form X,Y,WIDTHxHEIGHT").into()
+    })
+}
+
+/// Parse `RRGGBB` or `RRGGBBAA`, optionally prefixed by `#`.
+fn parse_color(color: &str) -> Result<[u8; 4], Cow<'static, str>> {
+    let hex = color.strip_prefix('#').unwrap_or(color);
+    let channel = |i: usize| {
+        hex.get(i * 2..i * 2 + 2)
+            .filter(|pair| pair.bytes().all(|b| b.is_ascii_hexdigit()))
+            .and_then(|pair| u8::from_str_radix(pair, 16).ok())
+    };
+    let parsed = match hex.len() {
+        6 => (0..3)
+            .map(channel)
+            .chain([Some(255)])
+            .collect::<Option<Vec<_>>>(),
+        8 => (0..4).map(channel).collect(),
+        _ => None,
+    };
+    parsed
+        .and_then(|channels| channels.try_into().ok())
+        .ok_or_else(|| {
+            format!("<background> parameter `{color}` is not of the form RRGGBB or RRGGBBAA").into()
+        })
+}
+
+/// Parse and validate a `KEYWORD=TEXT` pair for a PNG tEXt chunk.
+///
+/// Both must be representable in Latin-1. Keywords must be 1-79 printable characters with no
+/// leading, trailing, or consecutive spaces, and can't be one we write ourselves.
+fn parse_png_text(pair: &str) -> Result<(String, String), Cow<'static, str>> {
+    let Some((keyword, text)) = pair.split_once('=') else {
+        return Err(format!("--png-text `{pair}` is not of the form KEY=VALUE").into());
+    };
+    let bad_keyword = |why: &str| Err(format!("--png-text keyword `{keyword}` {why}").into());
+    let printable_latin1 = |c: char| matches!(c, ' '..='~' | '\u{a1}'..='\u{ff}');
+    match keyword.chars().count() {
+        0 => return Err("--png-text keyword must not be empty".into()),
+        1..=79 => (),
+        _ => return bad_keyword("is longer than 79 characters"),
+    }
+    if !keyword.chars().all(printable_latin1) {
+        return bad_keyword("must contain only printable Latin-1 characters");
+    }
+    if keyword.starts_with(' ') || keyword.ends_with(' ') || keyword.contains("  ") {
+        return bad_keyword("must not have leading, trailing, or consecutive spaces");
+    }
+    if crate::PNG_TEXT_KEYWORDS.contains(&keyword) {
+        return bad_keyword("is already written by the thumbnailer");
+    }
+    if !text.chars().all(|c| c != '\0' && u32::from(c) <= 0xff) {
+        return Err(format!(
+            "--png-text value for `{keyword}` must contain only Latin-1 characters, and no NUL"
+        )
+        .into());
+    }
+    Ok((keyword.to_owned(), text.to_owned()))
+}
+
+/// Parse either `N` for a square, `WxH` for a box, or `native` for no resizing.
+fn parse_size(size: &str) -> Result<Option<Size>, Cow<'static, str>> {
+    if size == "native" {
+        return Ok(None);
+    }
+    let parse_dimension = |dim: &str| {
+        let Ok(dim): Result<u32, _> = dim.parse() else {
+            return Err("<size> parameter must be a non-negative integer or WIDTHxHEIGHT");
+        };
+        if dim == 0 {
+            return Err("<size> parameter must not be zero");
+        }
+        if dim > MAX_SIZE {
+            return Err("<size> parameter larger than reasonable");
+        }
+        Ok(dim)
+    };
+    match size.split_once('x') {
+        Some((width, height)) => Ok(Some(Size {
+            width: parse_dimension(width)?,
+            height: parse_dimension(height)?,
+        })),
+        None => parse_dimension(size).map(|size| Some(Size::square(size))),
+    }
+    .map_err(Cow::Borrowed)
+}
+
+/// Look up `name` among `named`, such as [`Filter::NAMED`], listing them all if it isn't one.
+pub fn parse_named<T: Copy>(
+    kind: &str,
+    named: &[(&'static str, T)],
+    name: &str,
+) -> Result<T, Cow<'static, str>> {
+    match named.iter().find(|(n, _)| *n == name) {
+        Some(&(_, value)) => Ok(value),
+        None => {
+            let names: Vec<_> = named.iter().map(|(name, _)| *name).collect();
+            Err(format!(
+                "unknown {kind} `{name}`, expected one of: {}",
+                names.join(", ")
+            )
+            .into())
+        }
+    }
+}
+
+/// Parse `--max-dimension`, which must be in `1..=MAX_INPUT_DIMENSION_LIMIT`.
+pub fn parse_max_dimension(value: &str) -> Result<u32, Cow<'static, str>> {
+    match value.parse() {
+        Ok(dim @ 1..=MAX_INPUT_DIMENSION_LIMIT) => Ok(dim),
+        _ => Err(format!(
+            "<max-dimension> parameter must be an integer in 1..={MAX_INPUT_DIMENSION_LIMIT}"
+        )
+        .into()),
+    }
+}
+
+/// Parse `--threads`, which must be positive.
+pub fn parse_threads(value: &str) -> Result<std::num::NonZeroUsize, Cow<'static, str>> {
+    value
+        .parse()
+        .map_err(|_| "<threads> parameter must be a positive integer".into())
+}
+
+/// Parse a comma-separated list of sizes for `--sizes`, none of which may be `native`.
+fn parse_sizes(sizes: &str) -> Result<Vec<Size>, Cow<'static, str>> {
+    let mut parsed: Vec<Size> = Vec::new();
+    for size in sizes.split(',') {
+        let Some(size) = parse_size(size)? else {
+            return Err("--sizes does not accept `native`".into());
+        };
+        if parsed.contains(&size) {
+            return Err(format!("--sizes lists {size} more than once").into());
+        }
+        parsed.push(size);
+    }
+    Ok(parsed)
+}
+
+/// Parse an `--out-template`, which must stay inside `--out-dir`.
+fn parse_out_template(template: &str) -> Result<Template, Cow<'static, str>> {
+    if template.is_empty() {
+        return Err("--out-template must not be empty".into());
+    }
+    if template.contains(['/', std::path::MAIN_SEPARATOR]) {
+        return Err("--out-template must be a file name, not a path".into());
+    }
+    Template::parse(template)
+}
+
+/// Parse the <size> slot, checking it makes sense with the chosen `--mode` and `--square`.
+fn parse_scale(
+    size: OsString,
+    mode: Option<ScaleMode>,
+    square: bool,
+) -> Result<(Option<Size>, ScaleMode), Cow<'static, str>> {
+    let size = parse_size(&slot_str(size, "size")?)?;
+    if size.is_none() && mode == Some(ScaleMode::Cover) {
+        return Err("--mode cover requires a <size> other than `native`".into());
+    }
+    if size.is_none() && square {
+        return Err("--square requires a <size> other than `native`".into());
+    }
+    Ok((size, mode.unwrap_or_default()))
+}
+
+/// Fetch the value for `flag`, either from `--flag=value` or the next argument.
+fn flag_value(
+    flag: &str,
+    inline_value: Option<OsString>,
+    args: &mut impl Iterator<Item = OsString>,
+) -> Result<OsString, Cow<'static, str>> {
+    inline_value
+        .or_else(|| args.next())
+        .ok_or_else(|| format!("option `{flag}` requires a value\n\n{}", usage()).into())
+}
+
+/// [`flag_value`], for values that aren't paths and so must be UTF-8.
+fn flag_str(
+    flag: &str,
+    inline_value: Option<OsString>,
+    args: &mut impl Iterator<Item = OsString>,
+) -> Result<String, Cow<'static, str>> {
+    flag_value(flag, inline_value, args)?
+        .into_string()
+        .map_err(|_| format!("option `{flag}` requires a UTF-8 value").into())
+}
+
+/// Split `arg` around the first (or, if `last`, the final) occurrence of the ASCII byte `sep`.
+fn split_os(arg: &OsStr, sep: u8, last: bool) -> Option<(OsString, OsString)> {
+    debug_assert!(sep.is_ascii());
+    let bytes = arg.as_encoded_bytes();
+    let at = if last {
+        bytes.iter().rposition(|&b| b == sep)
+    } else {
+        bytes.iter().position(|&b| b == sep)
+    }?;
+    // SAFETY: Both halves came from `as_encoded_bytes` and are split next to an ASCII
+    // character, which std guarantees leaves them valid.
+    let (before, after) = unsafe {
+        (
+            OsStr::from_encoded_bytes_unchecked(&bytes[..at]),
+            OsStr::from_encoded_bytes_unchecked(&bytes[at + 1..]),
+        )
+    };
+    Some((before.to_owned(), after.to_owned()))
+}
+
+/// Paths may be anything, but the other slots need to be UTF-8.
+fn slot_str(value: OsString, name: &str) -> Result<String, Cow<'static, str>> {
+    value
+        .into_string()
+        .map_err(|_| format!("<{name}> parameter must be valid UTF-8").into())
+}
+
+/// Boolean flags don't take a value.
+fn no_value(flag: &str, inline_value: Option<OsString>) -> Result<(), Cow<'static, str>> {
+    match inline_value {
+        Some(_) => Err(format!("option `{flag}` does not take a value").into()),
+        None => Ok(()),
+    }
+}
+
+/// Parse arguments, not including the program name, falling back on `sources` for options
+/// that aren't given.
+pub fn parse(
+    args: impl IntoIterator<Item = OsString>,
+    sources: &Sources,
+) -> Result<(Command, Global), Cow<'static, str>> {
+    let mut given = GivenGlobal::default();
+    let command = parse_command(args, sources, &mut given)?;
+    if given.strict && given.lenient {
+        return Err("--strict cannot be combined with --lenient".into());
+    }
+    let log_file = config::resolve(
+        Defaults {
+            log_file: given.log_file,
+            ..Defaults::default()
+        },
+        sources,
+    )
+    .log_file;
+    let global = Global {
+        verbosity: given.verbosity.unwrap_or_default(),
+        strict: given.strict,
+        lenient: given.lenient,
+        max_chunks: given
+            .max_chunks
+            .map_or(crate::MAX_CHUNKS, std::num::NonZeroU32::get),
+        max_thumb_bytes: given
+            .max_thumb_bytes
+            .map_or(crate::MAX_THUMB_BYTES, std::num::NonZeroU64::get),
+        chunk: given.chunk,
+        recover: given.recover,
+        allow_flatten: given.allow_flatten,
+        sidecar: given.sidecar,
+        accept_form: given.accept_form,
+        colorspace: given.colorspace,
+        log_file,
+    };
+    Ok((command, global))
+}
+
+/// [`Global`] settings as given on the command line, before falling back on defaults.
+#[derive(Default)]
+struct GivenGlobal {
+    verbosity: Option<Verbosity>,
+    strict: bool,
+    lenient: bool,
+    log_file: Option<PathBuf>,
+    max_chunks: Option<std::num::NonZeroU32>,
+    max_thumb_bytes: Option<std::num::NonZeroU64>,
+    chunk: Option<std::num::NonZeroU32>,
+    recover: bool,
+    allow_flatten: bool,
+    sidecar: bool,
+    accept_form: Option<[u8; 4]>,
+    colorspace: Colorspace,
+}
+
+fn parse_command(
+    args: impl IntoIterator<Item = OsString>,
+    sources: &Sources,
+    global: &mut GivenGlobal,
+) -> Result<Command, Cow<'static, str>> {
+    // Slots in legacy positional order.
+    const SLOT_NAMES: [&str; 4] = ["input", "size", "output", "uri"];
+    let mut slots: [Option<OsString>; 4] = Default::default();
+    let mut positionals = Vec::new();
+    let mut batch = false;
+    let mut jobs = None;
+    let mut files_from = None;
+    let mut sizes = None;
+    let mut out_template = None;
+    let mut out_dir = None;
+    let mut report = None;
+    let mut mtime = None;
+    let mut out_fd = None;
+    let mut in_fd = None;
+    let mut timings = false;
+    let mut print_hash = false;
+    let mut dry_run = false;
+    let mut force = false;
+    let mut data_uri = false;
+    let mut png_text: Vec<(String, String)> = Vec::new();
+    let mut scale_mode = None;
+    let mut filter = None;
+    let mut no_upscale = None;
+    let mut compression = None;
+    let mut square = false;
+    let mut max_dimension = None;
+    let mut threads = None;
+    let mut background = None;
+    let mut crop = None;
+    let mut rotate = None;
+    let mut flip = None;
+    let mut allow_blank = false;
+    let mut no_optimize_color = false;
+    let mut force_rgba = false;
+    let mut gamma_correct = false;
+    let mut no_premultiply = false;
+    // Modes other than thumbnailing, which are mutually exclusive.
+    let mut mode: Option<String> = None;
+
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        // Allow both `--flag value` and `--flag=value`
+        let (flag, inline_value) = match split_os(&arg, b'=', false) {
+            Some((flag, value)) if arg.as_encoded_bytes().starts_with(b"--") => (flag, Some(value)),
+            _ => (arg, None),
+        };
+        // All flags are UTF-8, anything else is a positional (or a typo).
+        let flag = match flag.into_string() {
+            Ok(flag) => flag,
+            Err(positional) if inline_value.is_none() => {
+                positionals.push(positional);
+                continue;
+            }
+            Err(flag) => {
+                let flag = flag.to_string_lossy();
+                return Err(format!("unknown option `{flag}`\n\n{}", usage()).into());
+            }
+        };
+        let slot = match flag.as_str() {
+            "-h" | "--help" => return Ok(Command::Help),
+            "-V" | "--version" => return Ok(Command::Version),
+            "-i" | "--input" => 0,
+            "-s" | "--size" => 1,
+            "-o" | "--output" => 2,
+            "-u" | "--uri" => 3,
+            "--probe" | "--info" | "--list-chunks" | "--validate" | "--extract" => {
+                no_value(&flag, inline_value)?;
+                if let Some(other) = mode.replace(flag.clone()) {
+                    return Err(format!("{other} cannot be combined with {flag}").into());
+                }
+                continue;
+            }
+            "--batch" => {
+                no_value(&flag, inline_value)?;
+                batch = true;
+                continue;
+            }
+            "--timings" => {
+                no_value(&flag, inline_value)?;
+                timings = true;
+                continue;
+            }
+            "--print-hash" => {
+                no_value(&flag, inline_value)?;
+                print_hash = true;
+                continue;
+            }
+            "--force" => {
+                no_value(&flag, inline_value)?;
+                force = true;
+                continue;
+            }
+            "--dry-run" => {
+                no_value(&flag, inline_value)?;
+                dry_run = true;
+                continue;
+            }
+            "--data-uri" => {
+                no_value(&flag, inline_value)?;
+                data_uri = true;
+                continue;
+            }
+            "--mode" => {
+                scale_mode = match flag_str(&flag, inline_value, &mut args)?.as_str() {
+                    "fit" => Some(ScaleMode::Fit),
+                    "cover" => Some(ScaleMode::Cover),
+                    other => {
+                        return Err(
+                            format!("unknown mode `{other}`, expected `fit` or `cover`").into()
+                        )
+                    }
+                };
+                continue;
+            }
+            "--filter" => {
+                let name = flag_str(&flag, inline_value, &mut args)?;
+                filter = Some(parse_named("filter", &Filter::NAMED, &name)?);
+                continue;
+            }
+            "--compression" => {
+                let name = flag_str(&flag, inline_value, &mut args)?;
+                compression = Some(parse_named("compression", &Compression::NAMED, &name)?);
+                continue;
+            }
+            "--no-upscale" => {
+                no_value(&flag, inline_value)?;
+                no_upscale = Some(true);
+                continue;
+            }
+            "--allow-upscale" => {
+                no_value(&flag, inline_value)?;
+                no_upscale = Some(false);
+                continue;
+            }
+            "--strict" => {
+                no_value(&flag, inline_value)?;
+                global.strict = true;
+                continue;
+            }
+            "--lenient" => {
+                no_value(&flag, inline_value)?;
+                global.lenient = true;
+                continue;
+            }
+            "--max-chunks" => {
+                let value = flag_str(&flag, inline_value, &mut args)?;
+                let Ok(value) = value.parse() else {
+                    return Err("<max-chunks> parameter must be a positive integer".into());
+                };
+                global.max_chunks = Some(value);
+                continue;
+            }
+            "--max-thumb-bytes" => {
+                let value = flag_str(&flag, inline_value, &mut args)?;
+                let Ok(value) = value.parse() else {
+                    return Err("<max-thumb-bytes> parameter must be a positive integer".into());
+                };
+                global.max_thumb_bytes = Some(value);
+                continue;
+            }
+            "--chunk" => {
+                let value = flag_str(&flag, inline_value, &mut args)?;
+                let Ok(value) = value.parse() else {
+                    return Err("<chunk> parameter must be a positive integer".into());
+                };
+                global.chunk = Some(value);
+                continue;
+            }
+            "--recover" => {
+                no_value(&flag, inline_value)?;
+                global.recover = true;
+                continue;
+            }
+            "--allow-flatten" => {
+                no_value(&flag, inline_value)?;
+                global.allow_flatten = true;
+                continue;
+            }
+            "--sidecar" => {
+                no_value(&flag, inline_value)?;
+                global.sidecar = true;
+                continue;
+            }
+            "--accept-form" => {
+                let form = flag_str(&flag, inline_value, &mut args)?;
+                let Ok(form) = <[u8; 4]>::try_from(form.as_bytes()) else {
+                    return Err(format!(
+                        "<form> parameter must be exactly 4 bytes, like `fzp `, not `{form}`"
+                    )
+                    .into());
+                };
+                global.accept_form = Some(form);
+                continue;
+            }
+            "--colorspace" => {
+                let name = flag_str(&flag, inline_value, &mut args)?;
+                global.colorspace = parse_named("colorspace", &Colorspace::NAMED, &name)?;
+                continue;
+            }
+            "--log-file" => {
+                global.log_file = Some(flag_value(&flag, inline_value, &mut args)?.into());
+                continue;
+            }
+            "--square" => {
+                no_value(&flag, inline_value)?;
+                square = true;
+                continue;
+            }
+            "--allow-blank" => {
+                no_value(&flag, inline_value)?;
+                allow_blank = true;
+                continue;
+            }
+            "--no-optimize-color" => {
+                no_value(&flag, inline_value)?;
+                no_optimize_color = true;
+                continue;
+            }
+            "--force-rgba" => {
+                no_value(&flag, inline_value)?;
+                force_rgba = true;
+                continue;
+            }
+            "--gamma-correct" => {
+                no_value(&flag, inline_value)?;
+                gamma_correct = true;
+                continue;
+            }
+            "--no-premultiply" => {
+                no_value(&flag, inline_value)?;
+                no_premultiply = true;
+                continue;
+            }
+            "--background" => {
+                background = Some(match &*flag_str(&flag, inline_value, &mut args)? {
+                    "doc" => Background::Document,
+                    color => Background::Color(parse_color(color)?),
+                });
+                continue;
+            }
+            "--threads" => {
+                threads = Some(parse_threads(&flag_str(&flag, inline_value, &mut args)?)?);
+                continue;
+            }
+            "--max-dimension" => {
+                max_dimension = Some(parse_max_dimension(&flag_str(
+                    &flag,
+                    inline_value,
+                    &mut args,
+                )?)?);
+                continue;
+            }
+            "--crop" => {
+                crop = Some(parse_crop(&flag_str(&flag, inline_value, &mut args)?)?);
+                continue;
+            }
+            "--rotate" => {
+                rotate = match flag_str(&flag, inline_value, &mut args)?.as_str() {
+                    "90" => Some(Rotation::Quarter),
+                    "180" => Some(Rotation::Half),
+                    "270" => Some(Rotation::ThreeQuarter),
+                    other => {
+                        return Err(
+                            format!("unknown rotation `{other}`, expected 90, 180, or 270").into(),
+                        )
+                    }
+                };
+                continue;
+            }
+            "--flip" => {
+                flip = match flag_str(&flag, inline_value, &mut args)?.as_str() {
+                    "h" => Some(Flip::Horizontal),
+                    "v" => Some(Flip::Vertical),
+                    other => {
+                        return Err(format!("unknown flip `{other}`, expected `h` or `v`").into())
+                    }
+                };
+                continue;
+            }
+            "--png-text" => {
+                let (keyword, text) = parse_png_text(&flag_str(&flag, inline_value, &mut args)?)?;
+                if png_text.iter().any(|(existing, _)| *existing == keyword) {
+                    return Err(format!("--png-text `{keyword}` given more than once").into());
+                }
+                png_text.push((keyword, text));
+                continue;
+            }
+            "-v" | "--verbose" | "-q" | "--quiet" => {
+                no_value(&flag, inline_value)?;
+                let level = if matches!(flag.as_str(), "-v" | "--verbose") {
+                    Verbosity::Verbose
+                } else {
+                    Verbosity::Quiet
+                };
+                if global
+                    .verbosity
+                    .replace(level)
+                    .is_some_and(|other| other != level)
+                {
+                    return Err("--verbose cannot be combined with --quiet".into());
+                }
+                continue;
+            }
+            "--files-from" => {
+                files_from = Some(flag_value(&flag, inline_value, &mut args)?);
+                continue;
+            }
+            "--sizes" => {
+                sizes = Some(parse_sizes(&flag_str(&flag, inline_value, &mut args)?)?);
+                continue;
+            }
+            "--out-template" | "--name-template" => {
+                out_template = Some(parse_out_template(&flag_str(
+                    &flag,
+                    inline_value,
+                    &mut args,
+                )?)?);
+                continue;
+            }
+            "--out-dir" => {
+                out_dir = Some(flag_value(&flag, inline_value, &mut args)?);
+                continue;
+            }
+            "--report" => {
+                report = match flag_str(&flag, inline_value, &mut args)?.as_str() {
+                    "jsonl" => Some(ReportFormat::Jsonl),
+                    other => {
+                        return Err(
+                            format!("unknown report format `{other}`, expected `jsonl`").into()
+                        )
+                    }
+                };
+                continue;
+            }
+            "--mtime" => {
+                let value = flag_str(&flag, inline_value, &mut args)?;
+                if value.starts_with('-') {
+                    return Err("<mtime> parameter must not be negative".into());
+                }
+                let Ok(value) = value.parse() else {
+                    return Err("<mtime> parameter must be a whole number of seconds".into());
+                };
+                mtime = Some(value);
+                continue;
+            }
+            "--out-fd" | "--in-fd" => {
+                let value = flag_str(&flag, inline_value, &mut args)?;
+                let Ok(value @ 0..) = value.parse() else {
+                    return Err(format!(
+                        "<{}> parameter must be a non-negative integer",
+                        &flag[2..]
+                    )
+                    .into());
+                };
+                // Owned, and closed, once taken, which the standard streams mustn't be.
+                if value <= 2 {
+                    return Err(format!(
+                        "<{}> parameter must not be a standard stream, use `-` instead",
+                        &flag[2..]
+                    )
+                    .into());
+                }
+                if flag == "--out-fd" {
+                    out_fd = Some(value);
+                } else {
+                    in_fd = Some(value);
+                }
+                continue;
+            }
+            "-j" | "--jobs" => {
+                let value = flag_str(&flag, inline_value, &mut args)?;
+                let Ok(value) = value.parse() else {
+                    return Err("<jobs> parameter must be a positive integer".into());
+                };
+                jobs = Some(value);
+                continue;
+            }
+            // Everything after `--` is positional.
+            "--" => {
+                positionals.extend(args.by_ref());
+                continue;
+            }
+            // Lone `-` is a valid positional.
+            _ if flag.starts_with('-') && flag.len() > 1 => {
+                return Err(format!("unknown option `{flag}`\n\n{}", usage()).into())
+            }
+            _ => {
+                positionals.push(flag.into());
+                continue;
+            }
+        };
+        let value = flag_value(&flag, inline_value, &mut args)?;
+        if slots[slot].replace(value).is_some() {
+            return Err(format!("<{}> given more than once", SLOT_NAMES[slot]).into());
+        }
+    }
+
+    // Anything the command line didn't say, the environment or config might have.
+    let given = Defaults {
+        filter,
+        compression,
+        no_upscale,
+        max_dimension,
+        threads,
+        log_file: None,
+    };
+    let resolved = config::resolve(given.clone(), sources);
+    let max_dimension = resolved
+        .max_dimension
+        .unwrap_or(crate::MAX_INPUT_IMAGE_DIMENSION);
+
+    if let Some(flag) = mode {
+        if batch || files_from.is_some() {
+            return Err(format!("{flag} cannot be combined with --batch").into());
+        }
+        if sizes.is_some() || out_template.is_some() {
+            return Err(format!("{flag} cannot be combined with --sizes").into());
+        }
+        if mtime.is_some()
+            || out_fd.is_some()
+            || in_fd.is_some()
+            || timings
+            || print_hash
+            || force
+            || dry_run
+            || data_uri
+            || !png_text.is_empty()
+            || scale_mode.is_some()
+            || given.filter.is_some()
+            || given.no_upscale.is_some()
+            || given.compression.is_some()
+            || square
+            || background.is_some()
+            || (given.max_dimension.is_some() && flag != "--validate")
+            || crop.is_some()
+            || rotate.is_some()
+            || flip.is_some()
+            || allow_blank
+            || no_optimize_color
+            || force_rgba
+            || gamma_correct
+            || no_premultiply
+            || given.threads.is_some()
+        {
+            return Err(format!("{flag} cannot be combined with thumbnailing options").into());
+        }
+        if flag == "--extract" {
+            let [input, None, output, None] = slots else {
+                return Err("--extract accepts only <in_path> and <out_path>".into());
+            };
+            let mut positionals = positionals.into_iter();
+            let input = input.or_else(|| positionals.next());
+            let output = output.or_else(|| positionals.next());
+            return match (input, output, positionals.next()) {
+                (Some(input), Some(output), None) => Ok(Command::Extract {
+                    input: input.into(),
+                    output: output.into(),
+                }),
+                _ => Err("--extract requires exactly <in_path> and <out_path>".into()),
+            };
+        }
+        let input: PathBuf = match (slots, positionals.as_mut_slice()) {
+            ([Some(input), None, None, None], []) => input.into(),
+            ([None, None, None, None], [input]) => std::mem::take(input).into(),
+            _ => return Err(format!("{flag} accepts only <in_path>").into()),
+        };
+        return Ok(match flag.as_str() {
+            "--probe" => Command::Probe { input },
+            "--info" => Command::Info { input },
+            "--list-chunks" => Command::ListChunks { input },
+            _ => Command::Validate {
+                input,
+                max_dimension,
+            },
+        });
+    }
+
+    let filter = resolved.filter;
+    // Enlarging only blurs it, and consumers cope with smaller thumbnails than they asked for.
+    let no_upscale = resolved.no_upscale.unwrap_or(true);
+    let compression = resolved.compression.unwrap_or_default();
+
+    if out_template.is_some() && sizes.is_none() && files_from.is_none() {
+        return Err("--out-template requires --files-from or --sizes".into());
+    }
+
+    // Each is owned, and closed, once taken, so the one descriptor can't be both.
+    if in_fd.is_some() && in_fd == out_fd {
+        return Err("--in-fd and --out-fd cannot be the same descriptor".into());
+    }
+    if (out_fd.is_some() || in_fd.is_some()) && (sizes.is_some() || batch || files_from.is_some()) {
+        return Err("--in-fd and --out-fd cannot be combined with --batch or --sizes".into());
+    }
+    if dry_run {
+        if sizes.is_some() || batch || files_from.is_some() {
+            return Err("--dry-run cannot be combined with --batch or --sizes".into());
+        }
+        if print_hash {
+            return Err("--dry-run cannot be combined with --print-hash".into());
+        }
+    }
+
+    if let Some(sizes) = sizes {
+        if batch || files_from.is_some() {
+            return Err("--sizes cannot be combined with --batch".into());
+        }
+        if jobs.is_some() {
+            return Err("--jobs requires --batch".into());
+        }
+        if data_uri {
+            return Err("--data-uri cannot be combined with --sizes".into());
+        }
+        if print_hash && report.is_some() {
+            // Both want stdout.
+            return Err("--print-hash cannot be combined with --report".into());
+        }
+        let Some(out_dir) = out_dir else {
+            return Err("--sizes requires --out-dir".into());
+        };
+        let [input, None, None, uri] = slots else {
+            return Err("--sizes does not accept --size or --output".into());
+        };
+        let mut positionals = positionals.into_iter();
+        let input = input.or_else(|| positionals.next());
+        let uri = uri.or_else(|| positionals.next());
+        let (Some(input), None) = (input, positionals.next()) else {
+            return Err("--sizes requires exactly <in_path>, and optionally <in_uri>".into());
+        };
+        let out_template = match out_template {
+            Some(template) => template,
+            None => Template::parse(DEFAULT_NAME_TEMPLATE)?,
+        };
+        if sizes.len() > 1 && !out_template.uses_size() {
+            return Err(
+                "--out-template must contain `{size}`, `{width}`, or `{height}` when given several --sizes"
+                    .into(),
+            );
+        }
+        return Ok(Command::Sizes(Sizes {
+            input: input.into(),
+            uri: uri.map(|uri| slot_str(uri, "in_uri")).transpose()?,
+            mtime,
+            sizes,
+            out_dir: out_dir.into(),
+            out_template,
+            report,
+            options: Options {
+                size: None,
+                mode: scale_mode.unwrap_or_default(),
+                filter,
+                no_upscale,
+                compression,
+                max_dimension,
+                square,
+                background,
+                timings,
+                print_hash,
+                force,
+                data_uri: false,
+                png_text,
+                crop,
+                rotate,
+                flip,
+                allow_blank,
+                no_optimize_color,
+                force_rgba,
+                gamma_correct,
+                no_premultiply,
+                threads: resolved.threads,
+            },
+        }));
+    }
+
+    if batch || files_from.is_some() {
+        if mtime.is_some() {
+            return Err("--mtime cannot be combined with --batch".into());
+        }
+        if data_uri {
+            return Err("--data-uri cannot be combined with --batch".into());
+        }
+        if print_hash && report.is_some() {
+            // Both want stdout.
+            return Err("--print-hash cannot be combined with --report".into());
+        }
+        let [None, Some(size), None, None] = slots else {
+            return Err(
+                "--batch requires --size, and does not accept --input, --output, or --uri".into(),
+            );
+        };
+        let (size, mode) = parse_scale(size, scale_mode, square)?;
+        let out_template = match out_template {
+            Some(template) => template,
+            None => Template::parse(DEFAULT_FILES_FROM_TEMPLATE)?,
+        };
+        if size.is_none() && out_template.uses_size() {
+            return Err("--out-template uses the size, so <size> must not be `native`".into());
+        }
+        if !out_template.has_placeholders() {
+            // Every input would be written to the same place.
+            return Err("--out-template must contain a placeholder with --files-from".into());
+        }
+        let items = match (files_from, out_dir) {
+            (Some(list), Some(out_dir)) if positionals.is_empty() => BatchItems::FilesFrom {
+                list: list.into(),
+                out_dir: out_dir.into(),
+                out_template,
+            },
+            (Some(_), Some(_)) => {
+                return Err("--files-from does not accept additional inputs".into())
+            }
+            (Some(_), None) => return Err("--files-from requires --out-dir".into()),
+            (None, Some(_)) => return Err("--out-dir requires --files-from".into()),
+            (None, None) => BatchItems::Pairs(
+                positionals
+                    .into_iter()
+                    .map(|pair| match split_os(&pair, b':', true) {
+                        Some((input, output)) if !input.is_empty() && !output.is_empty() => {
+                            Ok((input.into(), output.into()))
+                        }
+                        _ => Err(Cow::Owned(format!(
+                            "batch item `{}` is not of the form <in_path>:<out_path>",
+                            pair.to_string_lossy()
+                        ))),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?,
+            ),
+        };
+        return Ok(Command::Batch(Batch {
+            items,
+            jobs,
+            report,
+            options: Options {
+                size,
+                mode,
+                filter,
+                no_upscale,
+                compression,
+                max_dimension,
+                square,
+                background,
+                timings,
+                print_hash,
+                force,
+                data_uri: false,
+                png_text,
+                crop,
+                rotate,
+                flip,
+                allow_blank,
+                no_optimize_color,
+                force_rgba,
+                gamma_correct,
+                no_premultiply,
+                threads: resolved.threads,
+            },
+        }));
+    }
+
+    if jobs.is_some() || out_dir.is_some() {
+        return Err("--jobs and --out-dir require --batch".into());
+    }
+    if report.is_some() && !dry_run {
+        return Err("--report requires --batch, --sizes, or --dry-run".into());
+    }
+
+    if data_uri {
+        if slots[2].is_some() {
+            return Err("--data-uri writes to stdout, and does not accept <out_path>".into());
+        }
+        slots[2] = Some("-".into());
+    }
+    if out_fd.is_some() {
+        if data_uri {
+            return Err("--out-fd cannot be combined with --data-uri".into());
+        }
+        if slots[2].is_some() {
+            return Err("--out-fd cannot be combined with <out_path>".into());
+        }
+        // Placeholder, so positionals skip it.
+        slots[2] = Some(OsString::new());
+    }
+    if in_fd.is_some() {
+        if slots[0].is_some() {
+            return Err("--in-fd cannot be combined with <in_path>".into());
+        }
+        // Placeholder, so positionals skip it.
+        slots[0] = Some(OsString::new());
+    }
+
+    // Fill remaining slots with positionals, in order.
+    let mut positionals = positionals.into_iter();
+    for slot in slots.iter_mut().filter(|slot| slot.is_none()) {
+        *slot = positionals.next();
+    }
+    if positionals.next().is_some() {
+        return Err(format!("too many arguments\n\n{}", usage()).into());
+    }
+
+    let [Some(input), Some(size), Some(output), uri] = slots else {
+        return Err(usage().into());
+    };
+    let (size, mode) = parse_scale(size, scale_mode, square)?;
+    if in_fd.is_some() && uri.is_none() {
+        // No path to derive it from.
+        return Err("--in-fd requires <in_uri>".into());
+    }
+
+    Ok(Command::Thumbnail(Args {
+        input: input.into(),
+        in_fd,
+        output: output.into(),
+        out_fd,
+        uri: uri.map(|uri| slot_str(uri, "in_uri")).transpose()?,
+        mtime,
+        dry_run,
+        report,
+        options: Options {
+            size,
+            mode,
+            filter,
+            no_upscale,
+            compression,
+            max_dimension,
+            square,
+            background,
+            timings,
+            print_hash,
+            force,
+            data_uri,
+            png_text,
+            crop,
+            rotate,
+            flip,
+            allow_blank,
+            no_optimize_color,
+            force_rgba,
+            gamma_correct,
+            no_premultiply,
+            threads: resolved.threads,
+        },
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_strs(args: &[&str]) -> Result<Command, Cow<'static, str>> {
+        parse(args.iter().map(OsString::from), &Sources::default()).map(|(command, _)| command)
+    }
+    fn expected() -> Command {
+        Command::Thumbnail(Args {
+            input: "in.fzp".into(),
+            in_fd: None,
+            output: "out.png".into(),
+            out_fd: None,
+            uri: Some("file:///in.fzp".into()),
+            mtime: None,
+            dry_run: false,
+            report: None,
+            options: Options {
+                size: Some(Size::square(256)),
+                mode: ScaleMode::Fit,
+                filter: None,
+                no_upscale: true,
+                compression: Compression::Fast,
+                max_dimension: 1024,
+                square: false,
+                background: None,
+                timings: false,
+                print_hash: false,
+                force: false,
+                data_uri: false,
+                png_text: Vec::new(),
+                crop: None,
+                rotate: None,
+                flip: None,
+                allow_blank: false,
+                no_optimize_color: false,
+                force_rgba: false,
+                gamma_correct: false,
+                no_premultiply: false,
+                threads: None,
+            },
+        })
+    }
+
+    #[test]
+    fn legacy_positional() {
+        assert_eq!(
+            parse_strs(&["in.fzp", "256", "out.png", "file:///in.fzp"]).unwrap(),
+            expected()
+        );
+    }
+    #[test]
+    fn uri_optional() {
+        let Command::Thumbnail(args) = parse_strs(&["in.fzp", "256", "out.png"]).unwrap() else {
+            panic!("expected a thumbnail command");
+        };
+        assert_eq!(args.uri, None);
+        assert_eq!(args.output, PathBuf::from("out.png"));
+    }
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8() {
+        use std::os::unix::ffi::OsStringExt;
+        let os = |bytes: &[u8]| OsString::from_vec(bytes.to_vec());
+        let latin1 = os(b"caf\xe9.fzp");
+
+        let Command::Thumbnail(args) = parse(
+            [latin1.clone(), "256".into(), os(b"out\xff.png")],
+            &Sources::default(),
+        )
+        .unwrap()
+        .0
+        else {
+            panic!("expected a thumbnail command");
+        };
+        assert_eq!(args.input.as_os_str(), latin1);
+        assert_eq!(args.output.as_os_str(), os(b"out\xff.png"));
+
+        let Command::Thumbnail(args) = parse(
+            [os(b"--input=caf\xe9.fzp"), "256".into(), "out.png".into()],
+            &Sources::default(),
+        )
+        .unwrap()
+        .0
+        else {
+            panic!("expected a thumbnail command");
+        };
+        assert_eq!(args.input.as_os_str(), latin1);
+
+        let Command::Batch(batch) = parse(
+            [
+                "--batch".into(),
+                "--size=1".into(),
+                os(b"caf\xe9.fzp:a.png"),
+            ],
+            &Sources::default(),
+        )
+        .unwrap()
+        .0
+        else {
+            panic!("expected a batch command");
+        };
+        assert_eq!(
+            batch.items,
+            BatchItems::Pairs(vec![(latin1.clone().into(), "a.png".into())])
+        );
+
+        // Only paths may be arbitrary bytes.
+        assert!(parse(
+            [latin1.clone(), os(b"25\xff"), "out.png".into()],
+            &Sources::default()
+        )
+        .is_err());
+        assert!(parse(
+            [
+                "in.fzp".into(),
+                "256".into(),
+                "out.png".into(),
+                os(b"file:///\xff")
+            ],
+            &Sources::default()
+        )
+        .is_err());
+        assert!(parse(
+            [
+                os(b"--jobs=\xff"),
+                "--batch".into(),
+                "-s".into(),
+                "1".into()
+            ],
+            &Sources::default()
+        )
+        .is_err());
+    }
+    #[test]
+    fn verbosity() {
+        let verbosity = |args: &[&str]| {
+            parse(args.iter().map(OsString::from), &Sources::default())
+                .map(|(_, global)| global.verbosity)
+        };
+        let thumbnail = ["in.fzp", "256", "out.png"];
+        assert_eq!(verbosity(&thumbnail).unwrap(), Verbosity::Normal);
+        assert_eq!(
+            verbosity(&[&["-v"], &thumbnail[..]].concat()).unwrap(),
+            Verbosity::Verbose
+        );
+        assert_eq!(
+            verbosity(&[&thumbnail[..], &["--quiet"]].concat()).unwrap(),
+            Verbosity::Quiet
+        );
+        assert_eq!(
+            verbosity(&["-q", "--batch", "-s", "1", "a:b"]).unwrap(),
+            Verbosity::Quiet
+        );
+        assert!(verbosity(&[&["-v", "-q"], &thumbnail[..]].concat()).is_err());
+        assert!(verbosity(&[&["--verbose=1"], &thumbnail[..]].concat()).is_err());
+    }
+    #[test]
+    fn log_file() {
+        let log_file = |args: &[&str], sources: &Sources| {
+            parse(args.iter().map(OsString::from), sources)
+                .unwrap()
+                .1
+                .log_file
+        };
+        let sources = Sources {
+            env: Defaults {
+                log_file: Some("env.log".into()),
+                ..Defaults::default()
+            },
+            ..Sources::default()
+        };
+        let thumbnail = ["in.fzp", "256", "out.png"];
+        assert_eq!(log_file(&thumbnail, &Sources::default()), None);
+        assert_eq!(log_file(&thumbnail, &sources), Some("env.log".into()));
+        assert_eq!(
+            log_file(
+                &[&["--log-file=cli.log"], &thumbnail[..]].concat(),
+                &sources
+            ),
+            Some("cli.log".into())
+        );
+        // Whatever the command.
+        assert_eq!(
+            log_file(&["--probe", "--log-file", "cli.log", "in.fzp"], &sources),
+            Some("cli.log".into())
+        );
+        assert!(parse_strs(&["in.fzp", "256", "out.png", "--log-file"]).is_err());
+    }
+    #[test]
+    fn max_chunks() {
+        let max_chunks = |args: &[&str]| {
+            parse(args.iter().map(OsString::from), &Sources::default())
+                .map(|(_, global)| global.max_chunks)
+        };
+        assert_eq!(
+            max_chunks(&["--probe", "in.fzp"]).unwrap(),
+            crate::MAX_CHUNKS
+        );
+        assert_eq!(
+            max_chunks(&["--max-chunks", "3", "in.fzp", "256", "out.png"]).unwrap(),
+            3
+        );
+        assert_eq!(
+            max_chunks(&["--validate", "--max-chunks=1000", "in.fzp"]).unwrap(),
+            1000
+        );
+        for bad in ["0", "-1", "many", ""] {
+            assert!(
+                max_chunks(&["--max-chunks", bad, "in.fzp"]).is_err(),
+                "{bad}"
+            );
+        }
+    }
+    #[test]
+    fn max_thumb_bytes() {
+        let max_thumb_bytes = |args: &[&str]| {
+            parse(args.iter().map(OsString::from), &Sources::default())
+                .map(|(_, global)| global.max_thumb_bytes)
+        };
+        assert_eq!(
+            max_thumb_bytes(&["--probe", "in.fzp"]).unwrap(),
+            crate::MAX_THUMB_BYTES
+        );
+        assert_eq!(
+            max_thumb_bytes(&["--max-thumb-bytes=100", "in.fzp", "256", "out.png"]).unwrap(),
+            100
+        );
+        for bad in ["0", "-1", "16MiB", ""] {
+            assert!(
+                max_thumb_bytes(&["--max-thumb-bytes", bad, "in.fzp"]).is_err(),
+                "{bad}"
+            );
+        }
+    }
+    #[test]
+    fn chunk() {
+        let chunk = |args: &[&str]| {
+            parse(args.iter().map(OsString::from), &Sources::default())
+                .map(|(_, global)| global.chunk.map(std::num::NonZeroU32::get))
+        };
+        assert_eq!(chunk(&["in.fzp", "256", "out.png"]).unwrap(), None);
+        assert_eq!(
+            chunk(&["--chunk", "2", "in.fzp", "256", "out.png"]).unwrap(),
+            Some(2)
+        );
+        assert_eq!(
+            chunk(&["--extract", "--chunk=3", "in.fzp", "out.qoi"]).unwrap(),
+            Some(3)
+        );
+        for bad in ["0", "-1", "second", ""] {
+            assert!(chunk(&["--chunk", bad, "in.fzp"]).is_err(), "{bad}");
+        }
+    }
+    #[test]
+    fn lenient() {
+        let lenient = |args: &[&str]| {
+            parse(args.iter().map(OsString::from), &Sources::default())
+                .map(|(_, global)| global.lenient)
+        };
+        assert_eq!(lenient(&["in.fzp", "256", "out.png"]), Ok(false));
+        assert_eq!(lenient(&["--lenient", "--probe", "in.fzp"]), Ok(true));
+        assert_eq!(
+            lenient(&["--lenient", "--strict", "in.fzp", "256", "out.png"]),
+            Err("--strict cannot be combined with --lenient".into())
+        );
+        assert!(lenient(&["--lenient=yes", "in.fzp"]).is_err());
+    }
+    #[test]
+    fn recover() {
+        let recover = |args: &[&str]| {
+            parse(args.iter().map(OsString::from), &Sources::default())
+                .map(|(_, global)| global.recover)
+        };
+        assert_eq!(recover(&["in.fzp", "256", "out.png"]), Ok(false));
+        assert_eq!(
+            recover(&["--recover", "in.fzp", "256", "out.png"]),
+            Ok(true)
+        );
+        assert_eq!(recover(&["--recover", "--probe", "in.fzp"]), Ok(true));
+        assert!(recover(&["--recover=yes", "in.fzp", "256", "out.png"]).is_err());
+    }
+    #[test]
+    fn allow_flatten() {
+        let allow_flatten = |args: &[&str]| {
+            parse(args.iter().map(OsString::from), &Sources::default())
+                .map(|(_, global)| global.allow_flatten)
+        };
+        assert_eq!(allow_flatten(&["in.fzp", "256", "out.png"]), Ok(false));
+        assert_eq!(
+            allow_flatten(&["--allow-flatten", "in.fzp", "256", "out.png"]),
+            Ok(true)
+        );
+        assert!(allow_flatten(&["--allow-flatten=1", "in.fzp", "256", "out.png"]).is_err());
+    }
+    #[test]
+    fn sidecar() {
+        let sidecar = |args: &[&str]| {
+            parse(args.iter().map(OsString::from), &Sources::default())
+                .map(|(_, global)| global.sidecar)
+        };
+        assert_eq!(sidecar(&["in.fzp", "256", "out.png"]), Ok(false));
+        assert_eq!(
+            sidecar(&["in.fzp", "256", "out.png", "--sidecar"]),
+            Ok(true)
+        );
+        assert!(sidecar(&["--sidecar=art.thumb", "in.fzp", "256", "out.png"]).is_err());
+    }
+    #[test]
+    fn accept_form() {
+        let accept_form = |args: &[&str]| {
+            parse(args.iter().map(OsString::from), &Sources::default())
+                .map(|(_, global)| global.accept_form)
+        };
+        assert_eq!(accept_form(&["in.fzp", "256", "out.png"]), Ok(None));
+        assert_eq!(
+            accept_form(&["--accept-form", "fzp3", "in.fzp", "256", "out.png"]),
+            Ok(Some(*b"fzp3"))
+        );
+        assert_eq!(
+            accept_form(&["--accept-form=ab c", "--probe", "in.fzp"]),
+            Ok(Some(*b"ab c"))
+        );
+        for bad in ["--accept-form=fzp", "--accept-form=fzp33", "--accept-form="] {
+            assert!(accept_form(&[bad, "in.fzp", "256", "out.png"]).is_err());
+        }
+    }
+    #[test]
+    fn colorspace() {
+        let colorspace = |args: &[&str]| {
+            parse(args.iter().map(OsString::from), &Sources::default())
+                .map(|(_, global)| global.colorspace)
+        };
+        assert_eq!(
+            colorspace(&["in.fzp", "256", "out.png"]),
+            Ok(Colorspace::Auto)
+        );
+        for (name, expected) in Colorspace::NAMED {
+            assert_eq!(
+                colorspace(&["--colorspace", name, "in.fzp", "256", "out.png"]),
+                Ok(expected)
+            );
+        }
+        // Whatever is done with the thumbnail.
+        assert_eq!(
+            colorspace(&["--colorspace=linear", "--info", "in.fzp"]),
+            Ok(Colorspace::Linear)
+        );
+        let unknown = colorspace(&["--colorspace=rgb", "in.fzp", "256", "out.png"]).unwrap_err();
+        assert!(unknown.contains("srgb, linear, auto"), "{unknown}");
+    }
+    #[test]
+    fn timings() {
+        let Command::Thumbnail(args) =
+            parse_strs(&["--timings", "in.fzp", "256", "out.png"]).unwrap()
+        else {
+            panic!("expected a thumbnail command");
+        };
+        assert!(args.options.timings);
+        let Command::Batch(batch) =
+            parse_strs(&["--batch", "-s", "1", "--timings", "a:b"]).unwrap()
+        else {
+            panic!("expected a batch command");
+        };
+        assert!(batch.options.timings);
+        assert!(parse_strs(&["--timings", "--info", "in.fzp"]).is_err());
+    }
+    #[test]
+    fn print_hash() {
+        let Command::Thumbnail(args) =
+            parse_strs(&["--print-hash", "in.fzp", "256", "out.png"]).unwrap()
+        else {
+            panic!("expected a thumbnail command");
+        };
+        assert!(args.options.print_hash);
+        assert!(parse_strs(&["--batch", "-s", "1", "--print-hash", "a:b"]).is_ok());
+        assert!(parse_strs(&[
+            "--batch",
+            "-s",
+            "1",
+            "--print-hash",
+            "--report=jsonl",
+            "a:b"
+        ])
+        .is_err());
+        assert!(parse_strs(&["--print-hash", "--probe", "in.fzp"]).is_err());
+    }
+    #[test]
+    fn data_uri() {
+        let Command::Thumbnail(args) = parse_strs(&["--data-uri", "in.fzp", "256"]).unwrap() else {
+            panic!("expected a thumbnail command");
+        };
+        assert!(args.options.data_uri);
+        assert_eq!(args.output, PathBuf::from("-"));
+        assert!(parse_strs(&["--data-uri", "in.fzp", "256", "-o", "out.png"]).is_err());
+        assert!(parse_strs(&["--data-uri", "--batch", "-s", "1", "a:b"]).is_err());
+    }
+    #[test]
+    fn dry_run() {
+        let Command::Thumbnail(args) =
+            parse_strs(&["--dry-run", "--report=jsonl", "in.fzp", "256", "out.png"]).unwrap()
+        else {
+            panic!("expected a thumbnail command");
+        };
+        assert!(args.dry_run);
+        assert_eq!(args.report, Some(ReportFormat::Jsonl));
+        assert!(parse_strs(&["--report=jsonl", "in.fzp", "256", "out.png"]).is_err());
+        assert!(parse_strs(&["--dry-run", "--print-hash", "in.fzp", "256", "out.png"]).is_err());
+        assert!(parse_strs(&["--dry-run", "--batch", "-s", "1", "a:b"]).is_err());
+        assert!(parse_strs(&["--dry-run", "--probe", "in.fzp"]).is_err());
+    }
+    #[test]
+    fn png_text() {
+        let Command::Thumbnail(args) = parse_strs(&[
+            "--png-text",
+            "X-Pipeline::JobId=42",
+            "--png-text=Comment=caf\u{e9} = good",
+            "in.fzp",
+            "256",
+            "out.png",
+        ])
+        .unwrap() else {
+            panic!("expected a thumbnail command");
+        };
+        assert_eq!(
+            args.options.png_text,
+            [
+                ("X-Pipeline::JobId".to_owned(), "42".to_owned()),
+                ("Comment".to_owned(), "caf\u{e9} = good".to_owned())
+            ]
+        );
+        let png_text = |pair: &str| parse_strs(&["--png-text", pair, "in.fzp", "256", "out.png"]);
+        assert!(png_text("Empty=").is_ok());
+        assert!(png_text(&"k".repeat(79)).is_err());
+        assert!(png_text(&format!("{}=v", "k".repeat(79))).is_ok());
+        for bad in [
+            "no-equals",
+            "=value",
+            &format!("{}=v", "k".repeat(80)),
+            " Leading=v",
+            "Trailing =v",
+            "Double  space=v",
+            "Tab\there=v",
+            "Non\u{100}Latin1=v",
+            "Key=\u{1f3a8}",
+            "Key=nul\0",
+            "Thumb::URI=file:///elsewhere",
+        ] {
+            assert!(png_text(bad).is_err(), "{bad:?}");
+        }
+        let dup = [
+            "--png-text",
+            "A=1",
+            "--png-text",
+            "A=2",
+            "in.fzp",
+            "256",
+            "out.png",
+        ];
+        assert!(parse_strs(&dup).is_err());
+        assert!(parse_strs(&["--png-text", "A=1", "--info", "in.fzp"]).is_err());
+    }
+    #[test]
+    fn crop() {
+        let crop = |crop: &str| match parse_strs(&["--crop", crop, "in.fzp", "256", "out.png"]) {
+            Ok(Command::Thumbnail(args)) => Ok(args.options.crop.unwrap()),
+            Ok(_) => panic!("expected a thumbnail command"),
+            Err(e) => Err(e),
+        };
+        assert_eq!(
+            crop("1,2,30x40").unwrap(),
+            Crop {
+                x: 1,
+                y: 2,
+                width: 30,
+                height: 40
+            }
+        );
+        // Checked once the image size is known.
+        assert!(crop("0,0,0x0").is_ok());
+        for bad in [
+            "",
+            "1,2",
+            "1,2,3",
+            "1,2,3x",
+            "-1,2,3x4",
+            "1,2,3x4,5",
+            "a,b,cxd",
+        ] {
+            assert!(crop(bad).is_err(), "{bad:?}");
+        }
+        assert!(parse_strs(&["--probe", "in.fzp", "--crop", "0,0,1x1"]).is_err());
+    }
+    #[test]
+    fn scale_mode() {
+        let mode = |args: &[&str]| match parse_strs(args) {
+            Ok(Command::Thumbnail(args)) => Ok(args.options.mode),
+            Ok(Command::Batch(batch)) => Ok(batch.options.mode),
+            Ok(_) => panic!("expected a thumbnail command"),
+            Err(e) => Err(e),
+        };
+        assert_eq!(mode(&["in.fzp", "256", "out.png"]).unwrap(), ScaleMode::Fit);
+        assert_eq!(
+            mode(&["--mode", "fit", "in.fzp", "256", "out.png"]).unwrap(),
+            ScaleMode::Fit
+        );
+        assert_eq!(
+            mode(&["--mode=cover", "in.fzp", "256", "out.png"]).unwrap(),
+            ScaleMode::Cover
+        );
+        assert_eq!(
+            mode(&["--batch", "-s", "64", "--mode", "cover", "a.fzp:a.png"]).unwrap(),
+            ScaleMode::Cover
+        );
+        assert!(mode(&["--mode", "fill", "in.fzp", "256", "out.png"]).is_err());
+        assert!(mode(&["--mode", "cover", "in.fzp", "native", "out.png"]).is_err());
+        assert!(mode(&["--mode", "fit", "in.fzp", "native", "out.png"]).is_ok());
+        assert!(parse_strs(&["--probe", "in.fzp", "--mode", "fit"]).is_err());
+    }
+    #[test]
+    fn filter() {
+        let filter = |args: &[&str]| match parse_strs(args) {
+            Ok(Command::Thumbnail(args)) => Ok(args.options.filter),
+            Ok(Command::Batch(batch)) => Ok(batch.options.filter),
+            Ok(_) => panic!("expected a thumbnail command"),
+            Err(e) => Err(e),
+        };
+        assert_eq!(filter(&["in.fzp", "256", "out.png"]).unwrap(), None);
+        for (name, expected) in Filter::NAMED {
+            assert_eq!(
+                filter(&["--filter", name, "in.fzp", "256", "out.png"]).unwrap(),
+                Some(expected)
+            );
+        }
+        assert_eq!(
+            filter(&["--batch", "-s", "64", "--filter=box", "a.fzp:a.png"]).unwrap(),
+            Some(Filter::Box)
+        );
+        let unknown = filter(&["--filter", "Lanczos", "in.fzp", "256", "out.png"]).unwrap_err();
+        assert!(unknown.contains("nearest, bilinear, catmullrom, lanczos3, box"));
+        assert!(parse_strs(&["--probe", "in.fzp", "--filter", "box"]).is_err());
+    }
+    #[test]
+    fn upscale() {
+        let no_upscale = |args: &[&str], sources: &Sources| match parse(
+            args.iter().map(OsString::from),
+            sources,
+        ) {
+            Ok((Command::Thumbnail(args), _)) => args.options.no_upscale,
+            Ok((Command::Batch(batch), _)) => batch.options.no_upscale,
+            other => panic!("expected a thumbnail command, got {other:?}"),
+        };
+        let none = Sources::default();
+        assert!(no_upscale(&["in.fzp", "256", "out.png"], &none));
+        assert!(no_upscale(
+            &["--no-upscale", "in.fzp", "256", "out.png"],
+            &none
+        ));
+        assert!(!no_upscale(
+            &["--allow-upscale", "in.fzp", "256", "out.png"],
+            &none
+        ));
+        assert!(!no_upscale(
+            &["--batch", "--allow-upscale", "-s", "1", "a:b"],
+            &none
+        ));
+        // The last given wins.
+        assert!(no_upscale(
+            &[
+                "--allow-upscale",
+                "--no-upscale",
+                "in.fzp",
+                "256",
+                "out.png"
+            ],
+            &none
+        ));
+        // As does the command line over the config file, either way.
+        let config = |no_upscale| Sources {
+            config: Defaults {
+                no_upscale: Some(no_upscale),
+                ..Defaults::default()
+            },
+            env: Defaults::default(),
+        };
+        assert!(!no_upscale(&["in.fzp", "256", "out.png"], &config(false)));
+        assert!(no_upscale(
+            &["--no-upscale", "in.fzp", "256", "out.png"],
+            &config(false)
+        ));
+        assert!(!no_upscale(
+            &["--allow-upscale", "in.fzp", "256", "out.png"],
+            &config(true)
+        ));
+        assert!(parse_strs(&["--allow-upscale=yes", "in.fzp", "256", "out.png"]).is_err());
+    }
+    #[test]
+    fn max_dimension() {
+        let max_dimension = |args: &[&str]| match parse_strs(args) {
+            Ok(Command::Thumbnail(args)) => Ok(args.options.max_dimension),
+            Ok(Command::Batch(batch)) => Ok(batch.options.max_dimension),
+            Ok(Command::Validate { max_dimension, .. }) => Ok(max_dimension),
+            Ok(_) => panic!("expected a thumbnail command"),
+            Err(e) => Err(e),
+        };
+        assert_eq!(max_dimension(&["in.fzp", "256", "out.png"]).unwrap(), 1024);
+        assert_eq!(
+            max_dimension(&["--max-dimension", "2048", "in.fzp", "256", "out.png"]).unwrap(),
+            2048
+        );
+        assert_eq!(
+            max_dimension(&["--batch", "-s", "64", "--max-dimension=8192", "a:b"]).unwrap(),
+            8192
+        );
+        assert_eq!(
+            max_dimension(&["--validate", "--max-dimension=1", "in.fzp"]).unwrap(),
+            1
+        );
+        for bad in ["0", "8193", "-1", "big", ""] {
+            assert!(
+                max_dimension(&["--max-dimension", bad, "in.fzp", "256", "out.png"]).is_err(),
+                "{bad:?}"
+            );
+        }
+        assert!(parse_strs(&["--info", "in.fzp", "--max-dimension", "2048"]).is_err());
+    }
+    #[test]
+    fn threads() {
+        let threads = |args: &[&str]| match parse_strs(args) {
+            Ok(Command::Thumbnail(args)) => {
+                Ok(args.options.threads.map(std::num::NonZeroUsize::get))
+            }
+            Ok(Command::Batch(batch)) => Ok(batch.options.threads.map(std::num::NonZeroUsize::get)),
+            Ok(_) => panic!("expected a thumbnail command"),
+            Err(e) => Err(e),
+        };
+        assert_eq!(threads(&["in.fzp", "256", "out.png"]).unwrap(), None);
+        assert_eq!(
+            threads(&["--threads", "2", "in.fzp", "256", "out.png"]).unwrap(),
+            Some(2)
+        );
+        assert_eq!(
+            threads(&["--batch", "-s", "64", "--threads=16", "a:b"]).unwrap(),
+            Some(16)
+        );
+        for bad in ["0", "-1", "many", ""] {
+            assert!(
+                threads(&["--threads", bad, "in.fzp", "256", "out.png"]).is_err(),
+                "{bad:?}"
+            );
+        }
+        assert!(parse_strs(&["--info", "in.fzp", "--threads", "2"]).is_err());
+    }
+    #[test]
+    fn config_defaults() {
+        let sources = Sources {
+            config: Defaults {
+                filter: Some(Filter::Lanczos3),
+                compression: Some(Compression::Best),
+                no_upscale: Some(true),
+                max_dimension: Some(2048),
+                threads: None,
+                log_file: None,
+            },
+            env: Defaults::default(),
+        };
+        let options = |args: &[&str]| match parse(args.iter().map(OsString::from), &sources) {
+            Ok((Command::Thumbnail(args), _)) => args.options,
+            other => panic!("expected a thumbnail command, got {other:?}"),
+        };
+        let seeded = options(&["in.fzp", "256", "out.png"]);
+        assert_eq!(
+            (
+                seeded.filter,
+                seeded.compression,
+                seeded.no_upscale,
+                seeded.max_dimension
+            ),
+            (Some(Filter::Lanczos3), Compression::Best, true, 2048)
+        );
+        // The command line wins.
+        let overridden = options(&[
+            "--filter=box",
+            "--compression=fast",
+            "--max-dimension=64",
+            "in.fzp",
+            "256",
+            "out.png",
+        ]);
+        assert_eq!(
+            (
+                overridden.filter,
+                overridden.compression,
+                overridden.max_dimension
+            ),
+            (Some(Filter::Box), Compression::Fast, 64)
+        );
+        let Ok((Command::Validate { max_dimension, .. }, _)) =
+            parse(["--validate", "in.fzp"].map(OsString::from), &sources)
+        else {
+            panic!("expected --validate");
+        };
+        assert_eq!(max_dimension, 2048);
+        // Still only thumbnailing options on the command line.
+        assert!(parse_strs(&["--probe", "--no-upscale", "in.fzp"]).is_err());
+        assert!(parse_strs(&["--probe", "--allow-upscale", "in.fzp"]).is_err());
+        assert!(parse_strs(&["--probe", "--compression=best", "in.fzp"]).is_err());
+        assert!(parse_strs(&["--strict", "--probe", "in.fzp"]).is_ok());
+        assert!(parse_strs(&["--compression=tiny", "in.fzp", "256", "out.png"]).is_err());
+    }
+    #[test]
+    fn square() {
+        let square = |args: &[&str]| match parse_strs(args) {
+            Ok(Command::Thumbnail(args)) => Ok(args.options.square),
+            Ok(Command::Batch(batch)) => Ok(batch.options.square),
+            Ok(_) => panic!("expected a thumbnail command"),
+            Err(e) => Err(e),
+        };
+        assert!(!square(&["in.fzp", "256", "out.png"]).unwrap());
+        assert!(square(&["--square", "in.fzp", "256", "out.png"]).unwrap());
+        assert!(square(&["--batch", "--square", "-s", "64", "a.fzp:a.png"]).unwrap());
+        assert!(square(&["--square=yes", "in.fzp", "256", "out.png"]).is_err());
+        assert!(square(&["--square", "in.fzp", "native", "out.png"]).is_err());
+        assert!(parse_strs(&["--probe", "in.fzp", "--square"]).is_err());
+    }
+    #[test]
+    fn allow_blank() {
+        let allow_blank = |args: &[&str]| match parse_strs(args) {
+            Ok(Command::Thumbnail(args)) => Ok(args.options.allow_blank),
+            Ok(Command::Batch(batch)) => Ok(batch.options.allow_blank),
+            Ok(Command::Sizes(sizes)) => Ok(sizes.options.allow_blank),
+            Ok(_) => panic!("expected a thumbnail command"),
+            Err(e) => Err(e),
+        };
+        assert!(!allow_blank(&["in.fzp", "256", "out.png"]).unwrap());
+        assert!(allow_blank(&["--allow-blank", "in.fzp", "256", "out.png"]).unwrap());
+        assert!(allow_blank(&["--batch", "--allow-blank", "-s", "64", "a.fzp:a.png"]).unwrap());
+        assert!(allow_blank(&["--allow-blank=yes", "in.fzp", "256", "out.png"]).is_err());
+        assert!(parse_strs(&["--probe", "in.fzp", "--allow-blank"]).is_err());
+    }
+    #[test]
+    fn no_optimize_color() {
+        let no_optimize_color = |args: &[&str]| match parse_strs(args) {
+            Ok(Command::Thumbnail(args)) => Ok(args.options.no_optimize_color),
+            Ok(Command::Batch(batch)) => Ok(batch.options.no_optimize_color),
+            Ok(_) => panic!("expected a thumbnail command"),
+            Err(e) => Err(e),
+        };
+        assert!(!no_optimize_color(&["in.fzp", "256", "out.png"]).unwrap());
+        assert!(no_optimize_color(&["--no-optimize-color", "in.fzp", "256", "out.png"]).unwrap());
+        assert!(no_optimize_color(&["--batch", "--no-optimize-color", "-s", "1", "a:b"]).unwrap());
+        assert!(no_optimize_color(&["--no-optimize-color=1", "in.fzp", "256", "out.png"]).is_err());
+        assert!(parse_strs(&["--probe", "in.fzp", "--no-optimize-color"]).is_err());
+    }
+    #[test]
+    fn force_rgba() {
+        let force_rgba = |args: &[&str]| match parse_strs(args) {
+            Ok(Command::Thumbnail(args)) => Ok(args.options.force_rgba),
+            Ok(Command::Batch(batch)) => Ok(batch.options.force_rgba),
+            Ok(_) => panic!("expected a thumbnail command"),
+            Err(e) => Err(e),
+        };
+        assert!(!force_rgba(&["in.fzp", "256", "out.png"]).unwrap());
+        assert!(force_rgba(&["--force-rgba", "in.fzp", "256", "out.png"]).unwrap());
+        assert!(force_rgba(&["--batch", "--force-rgba", "-s", "1", "a:b"]).unwrap());
+        assert!(force_rgba(&["--force-rgba=no", "in.fzp", "256", "out.png"]).is_err());
+        assert!(parse_strs(&["--probe", "in.fzp", "--force-rgba"]).is_err());
+    }
+    #[test]
+    fn gamma_correct() {
+        let gamma_correct = |args: &[&str]| match parse_strs(args) {
+            Ok(Command::Thumbnail(args)) => Ok(args.options.gamma_correct),
+            Ok(Command::Batch(batch)) => Ok(batch.options.gamma_correct),
+            Ok(_) => panic!("expected a thumbnail command"),
+            Err(e) => Err(e),
+        };
+        assert!(!gamma_correct(&["in.fzp", "256", "out.png"]).unwrap());
+        assert!(gamma_correct(&["--gamma-correct", "in.fzp", "256", "out.png"]).unwrap());
+        assert!(gamma_correct(&["--batch", "--gamma-correct", "-s", "1", "a:b"]).unwrap());
+        assert!(gamma_correct(&["--gamma-correct=yes", "in.fzp", "256", "out.png"]).is_err());
+        assert!(parse_strs(&["--probe", "in.fzp", "--gamma-correct"]).is_err());
+    }
+    #[test]
+    fn no_premultiply() {
+        let no_premultiply = |args: &[&str]| match parse_strs(args) {
+            Ok(Command::Thumbnail(args)) => Ok(args.options.no_premultiply),
+            Ok(Command::Batch(batch)) => Ok(batch.options.no_premultiply),
+            Ok(_) => panic!("expected a thumbnail command"),
+            Err(e) => Err(e),
+        };
+        assert!(!no_premultiply(&["in.fzp", "256", "out.png"]).unwrap());
+        assert!(no_premultiply(&["--no-premultiply", "in.fzp", "256", "out.png"]).unwrap());
+        assert!(no_premultiply(&["--batch", "--no-premultiply", "-s", "1", "a:b"]).unwrap());
+        assert!(no_premultiply(&["--no-premultiply=1", "in.fzp", "256", "out.png"]).is_err());
+        assert!(parse_strs(&["--probe", "in.fzp", "--no-premultiply"]).is_err());
+    }
+    #[test]
+    fn background() {
+        let background =
+            |color: &str| match parse_strs(&["--background", color, "in.fzp", "256", "out.png"]) {
+                Ok(Command::Thumbnail(args)) => Ok(args.options.background.unwrap()),
+                Ok(_) => panic!("expected a thumbnail command"),
+                Err(e) => Err(e),
+            };
+        let color = |color| Ok(Background::Color(color));
+        assert_eq!(background("ff8000"), color([255, 128, 0, 255]));
+        assert_eq!(background("#FF8000"), color([255, 128, 0, 255]));
+        assert_eq!(background("#12345678"), color([0x12, 0x34, 0x56, 0x78]));
+        assert_eq!(background("doc"), Ok(Background::Document));
+        for bad in [
+            "",
+            "#",
+            "fff",
+            "#ff800",
+            "ff80000",
+            "ff8000ff00",
+            "gg0000",
+            "+f8000",
+            "ü0000",
+            "Doc",
+            "#doc",
+        ] {
+            assert!(background(bad).is_err(), "{bad:?}");
+        }
+        assert!(parse_strs(&["--info", "in.fzp", "--background", "000000"]).is_err());
+    }
+    #[test]
+    fn orientation() {
+        let options = |args: &[&str]| match parse_strs(args) {
+            Ok(Command::Thumbnail(args)) => Ok((args.options.rotate, args.options.flip)),
+            Ok(_) => panic!("expected a thumbnail command"),
+            Err(e) => Err(e),
+        };
+        let with = |flags: &[&'static str]| {
+            let mut args = flags.to_vec();
+            args.extend(["in.fzp", "256", "out.png"]);
+            options(&args)
+        };
+        assert_eq!(with(&[]).unwrap(), (None, None));
+        assert_eq!(
+            with(&["--rotate", "90"]).unwrap(),
+            (Some(Rotation::Quarter), None)
+        );
+        assert_eq!(
+            with(&["--rotate=270", "--flip", "v"]).unwrap(),
+            (Some(Rotation::ThreeQuarter), Some(Flip::Vertical))
+        );
+        assert_eq!(
+            with(&["--flip=h", "--rotate", "180"]).unwrap(),
+            (Some(Rotation::Half), Some(Flip::Horizontal))
+        );
+        for bad in [
+            &["--rotate", "0"][..],
+            &["--rotate", "-90"],
+            &["--rotate", "45"],
+            &["--flip", "x"],
+            &["--flip", "H"],
+            &["--flip"],
+        ] {
+            assert!(with(bad).is_err(), "{bad:?}");
+        }
+        assert!(parse_strs(&["--info", "in.fzp", "--rotate", "90"]).is_err());
+        assert!(parse_strs(&["--info", "in.fzp", "--flip", "h"]).is_err());
+    }
+    #[test]
+    fn mtime() {
+        let Command::Thumbnail(args) =
+            parse_strs(&["--mtime", "1700000000", "in.fzp", "256", "out.png"]).unwrap()
+        else {
+            panic!("expected a thumbnail command");
+        };
+        assert_eq!(args.mtime, Some(1_700_000_000));
+        assert!(parse_strs(&["--mtime=0", "in.fzp", "256", "out.png"]).is_ok());
+        let negative = parse_strs(&["--mtime", "-5", "in.fzp", "256", "out.png"]);
+        assert!(negative.unwrap_err().contains("negative"));
+        assert!(parse_strs(&["--mtime", "soon", "in.fzp", "256", "out.png"]).is_err());
+        assert!(parse_strs(&["--mtime", "1.5", "in.fzp", "256", "out.png"]).is_err());
+        assert!(parse_strs(&["--mtime", "1", "--batch", "-s", "1", "a:b"]).is_err());
+        assert!(parse_strs(&["--mtime", "1", "--probe", "in.fzp"]).is_err());
+    }
+    #[test]
+    fn out_fd() {
+        let Command::Thumbnail(args) =
+            parse_strs(&["--out-fd", "3", "in.fzp", "256", "file:///in.fzp"]).unwrap()
+        else {
+            panic!("expected a thumbnail command");
+        };
+        assert_eq!(args.out_fd, Some(3));
+        assert_eq!(args.output, PathBuf::new());
+        assert_eq!(args.uri.as_deref(), Some("file:///in.fzp"));
+        for bad in [
+            &["--out-fd", "3", "in.fzp", "256", "-o", "out.png"][..],
+            &["--out-fd", "-1", "in.fzp", "256"],
+            &["--out-fd", "three", "in.fzp", "256"],
+            &["--out-fd=3", "--data-uri", "in.fzp", "256"],
+            &["--out-fd=3", "--batch", "-s", "1", "a:b"],
+            &["--out-fd=3", "--sizes", "1", "--out-dir", "d", "in.fzp"],
+            &["--out-fd=3", "--probe", "in.fzp"],
+            &["--out-fd", "1", "in.fzp", "256"],
+            &["--out-fd=2", "in.fzp", "256", "file:///in.fzp"],
+        ] {
+            assert!(parse_strs(bad).is_err(), "{bad:?}");
+        }
+    }
+    #[test]
+    fn in_fd() {
+        let Command::Thumbnail(args) =
+            parse_strs(&["--in-fd=4", "256", "out.png", "file:///in.fzp"]).unwrap()
+        else {
+            panic!("expected a thumbnail command");
+        };
+        assert_eq!(args.in_fd, Some(4));
+        assert_eq!(args.input, PathBuf::new());
+        assert_eq!(args.output, PathBuf::from("out.png"));
+        assert!(
+            parse_strs(&["--in-fd=4", "--out-fd=5", "-s", "1", "-u", "file:///in.fzp"]).is_ok()
+        );
+        for bad in [
+            &["--in-fd", "4", "256", "out.png"][..],
+            &[
+                "--in-fd",
+                "4",
+                "-i",
+                "in.fzp",
+                "256",
+                "out.png",
+                "file:///in.fzp",
+            ],
+            &["--in-fd", "-4", "256", "out.png", "file:///in.fzp"],
+            &["--in-fd=4", "--batch", "-s", "1", "a:b"],
+            &["--in-fd=4", "--info"],
+            &["--in-fd", "0", "256", "out.png", "file:///in.fzp"],
+            &["--in-fd=4", "--out-fd=4", "256", "file:///in.fzp"],
+            &["--in-fd=4", "--out-fd=4", "-s", "1", "-u", "file:///in.fzp"],
+        ] {
+            assert!(parse_strs(bad).is_err(), "{bad:?}");
+        }
+    }
+    #[test]
+    fn named_any_order() {
+        assert_eq!(
+            parse_strs(&[
+                "--uri",
+                "file:///in.fzp",
+                "--output",
+                "out.png",
+                "--size=256",
+                "-i",
+                "in.fzp"
+            ])
+            .unwrap(),
+            expected()
+        );
+    }
+    #[test]
+    fn mixed() {
+        assert_eq!(
+            parse_strs(&["--size", "256", "in.fzp", "out.png", "file:///in.fzp"]).unwrap(),
+            expected()
+        );
+    }
+    #[test]
+    fn help_and_version() {
+        assert_eq!(parse_strs(&["--help"]).unwrap(), Command::Help);
+        assert_eq!(parse_strs(&["in.fzp", "-h"]).unwrap(), Command::Help);
+        assert_eq!(parse_strs(&["--version"]).unwrap(), Command::Version);
+    }
+    #[test]
+    fn usage_errors() {
+        // Unknown flag
+        assert!(parse_strs(&["--frobnicate", "in.fzp", "256", "out.png", "uri"]).is_err());
+        // Missing value
+        assert!(parse_strs(&["in.fzp", "256", "out.png", "--uri"]).is_err());
+        // Missing args
+        assert!(parse_strs(&["in.fzp", "256"]).is_err());
+        assert!(parse_strs(&[]).is_err());
+        // Too many
+        assert!(parse_strs(&["in.fzp", "256", "out.png", "uri", "extra"]).is_err());
+        // Duplicate
+        assert!(parse_strs(&["--size", "1", "--size", "2", "in", "out", "uri"]).is_err());
+    }
+    #[test]
+    fn size_limits() {
+        for bad in ["0", "-1", "2049", "big", ""] {
+            assert!(
+                parse_strs(&["in.fzp", bad, "out.png", "uri"]).is_err(),
+                "accepted {bad:?}"
+            );
+        }
+        for good in ["1", "2048"] {
+            assert!(parse_strs(&["in.fzp", good, "out.png", "uri"]).is_ok());
+        }
+    }
+    #[test]
+    fn size_box() {
+        assert_eq!(
+            parse_size("320x180").unwrap(),
+            Some(Size {
+                width: 320,
+                height: 180
+            })
+        );
+        assert_eq!(parse_size("2048x1").unwrap().unwrap().height, 1);
+        assert_eq!(parse_size("1x2048").unwrap().unwrap().width, 1);
+        assert_eq!(parse_size("native").unwrap(), None);
+        for bad in [
+            "0x10", "10x0", "2049x1", "1x2049", "x", "10x", "x10", "10x10x10", "-1x5",
+        ] {
+            assert!(parse_size(bad).is_err(), "accepted {bad:?}");
+        }
+    }
+    #[test]
+    fn batch() {
+        assert_eq!(
+            parse_strs(&["--batch", "a.fzp:a.png", "--size", "256", "dir/b.fzp:b.png"]).unwrap(),
+            Command::Batch(Batch {
+                items: BatchItems::Pairs(vec![
+                    ("a.fzp".into(), "a.png".into()),
+                    ("dir/b.fzp".into(), "b.png".into())
+                ]),
+                jobs: None,
+                report: None,
+                options: Options {
+                    size: Some(Size::square(256)),
+                    mode: ScaleMode::Fit,
+                    filter: None,
+                    no_upscale: true,
+                    compression: Compression::Fast,
+                    max_dimension: 1024,
+                    square: false,
+                    background: None,
+                    timings: false,
+                    print_hash: false,
+                    force: false,
+                    data_uri: false,
+                    png_text: Vec::new(),
+                    crop: None,
+                    rotate: None,
+                    flip: None,
+                    allow_blank: false,
+                    no_optimize_color: false,
+                    force_rgba: false,
+                    gamma_correct: false,
+                    no_premultiply: false,
+                    threads: None,
+                },
+            })
+        );
+        // Size is required
+        assert!(parse_strs(&["--batch", "a.fzp:a.png"]).is_err());
+        // Single-file flags are not
+        assert!(parse_strs(&["--batch", "--size=1", "--uri=x", "a.fzp:a.png"]).is_err());
+        // Malformed pairs
+        assert!(parse_strs(&["--batch", "--size=1", "a.fzp"]).is_err());
+        assert!(parse_strs(&["--batch", "--size=1", "a.fzp:"]).is_err());
+        assert!(parse_strs(&["--batch=yes", "--size=1", "a.fzp:a.png"]).is_err());
+    }
+    #[test]
+    fn jobs() {
+        let Command::Batch(batch) = parse_strs(&["--batch", "-s", "1", "-j", "4", "a:b"]).unwrap()
+        else {
+            panic!("not batch")
+        };
+        assert_eq!(batch.jobs, std::num::NonZeroUsize::new(4));
+        assert!(parse_strs(&["--batch", "-s", "1", "--jobs=0", "a:b"]).is_err());
+        // Meaningless without batch
+        assert!(parse_strs(&["-j", "2", "in.fzp", "256", "out.png", "uri"]).is_err());
+    }
+    #[test]
+    fn files_from() {
+        assert_eq!(
+            parse_strs(&["--files-from=-", "--size", "128", "--out-dir", "thumbs"]).unwrap(),
+            Command::Batch(Batch {
+                items: BatchItems::FilesFrom {
+                    list: "-".into(),
+                    out_dir: "thumbs".into(),
+                    out_template: Template::parse(DEFAULT_FILES_FROM_TEMPLATE).unwrap(),
+                },
+                jobs: None,
+                report: None,
+                options: Options {
+                    size: Some(Size::square(128)),
+                    mode: ScaleMode::Fit,
+                    filter: None,
+                    no_upscale: true,
+                    compression: Compression::Fast,
+                    max_dimension: 1024,
+                    square: false,
+                    background: None,
+                    timings: false,
+                    print_hash: false,
+                    force: false,
+                    data_uri: false,
+                    png_text: Vec::new(),
+                    crop: None,
+                    rotate: None,
+                    flip: None,
+                    allow_blank: false,
+                    no_optimize_color: false,
+                    force_rgba: false,
+                    gamma_correct: false,
+                    no_premultiply: false,
+                    threads: None,
+                },
+            })
+        );
+        assert!(parse_strs(&["--files-from=-", "--size", "128"]).is_err());
+        assert!(parse_strs(&["--files-from=-", "--size=1", "--out-dir=x", "a:b"]).is_err());
+        assert!(parse_strs(&["--batch", "--size=1", "--out-dir=x", "a:b"]).is_err());
+    }
+    #[test]
+    fn out_template() {
+        let files_from = |flags: &[&str]| -> Result<Template, Cow<'static, str>> {
+            let mut args = vec!["--files-from=-", "--out-dir=thumbs"];
+            args.extend(flags);
+            match parse_strs(&args)? {
+                Command::Batch(Batch {
+                    items: BatchItems::FilesFrom { out_template, .. },
+                    ..
+                }) => Ok(out_template),
+                other => panic!("expected --files-from, got {other:?}"),
+            }
+        };
+        assert_eq!(
+            files_from(&["-s", "64", "--out-template={stem}.{ext}-{width}.png"])
+                .unwrap()
+                .to_string(),
+            "{stem}.{ext}-{width}.png"
+        );
+        assert!(files_from(&["-s", "native", "--out-template={md5uri}.png"]).is_ok());
+        for bad in [
+            &["-s", "native", "--out-template={stem}-{height}.png"][..],
+            &["-s", "64", "--out-template=same.png"],
+            &["-s", "64", "--out-template={nope}.png"],
+            &["-s", "64", "--out-template={stem.png"],
+            &["-s", "64", "--out-template=sub/{stem}.png"],
+        ] {
+            assert!(files_from(bad).is_err(), "{bad:?}");
+        }
+        // Pairs already name their outputs.
+        assert!(parse_strs(&["--batch", "-s", "1", "--out-template={stem}.png", "a:b"]).is_err());
+    }
+    #[test]
+    fn sizes() {
+        let Command::Sizes(sizes) = parse_strs(&[
+            "--sizes",
+            "128,256,320x180",
+            "--out-dir",
+            "thumbs",
+            "in.fzp",
+            "file:///in.fzp",
+        ])
+        .unwrap() else {
+            panic!("expected a sizes command");
+        };
+        assert_eq!(
+            sizes.sizes,
+            [
+                Size::square(128),
+                Size::square(256),
+                Size {
+                    width: 320,
+                    height: 180
+                }
+            ]
+        );
+        assert_eq!(sizes.input, PathBuf::from("in.fzp"));
+        assert_eq!(sizes.uri.as_deref(), Some("file:///in.fzp"));
+        assert_eq!(sizes.out_dir, PathBuf::from("thumbs"));
+        assert_eq!(sizes.out_template.to_string(), DEFAULT_NAME_TEMPLATE);
+        assert_eq!(sizes.options.size, None);
+
+        let sizes = |flags: &[&str]| {
+            let mut args = vec!["--out-dir", "thumbs", "-i", "in.fzp"];
+            args.extend(flags);
+            parse_strs(&args)
+        };
+        assert!(sizes(&[
+            "--sizes=64",
+            "--report",
+            "jsonl",
+            "--name-template",
+            "x.png"
+        ])
+        .is_ok());
+        assert!(sizes(&["--sizes=64,128", "--name-template", "{stem}_{size}.png"]).is_ok());
+        assert!(sizes(&["--sizes=64,128", "--out-template", "{width}x{height}.png"]).is_ok());
+        for bad in [
+            &["--sizes", "64,native"][..],
+            &["--sizes", "64,,128"],
+            &["--sizes", "64,64"],
+            &["--sizes", "0"],
+            &["--sizes=64,128", "--name-template", "{stem}.png"],
+            &["--sizes=64", "--name-template", "sub/{size}.png"],
+            &["--sizes=64", "--name-template="],
+            &["--sizes=64", "--size", "64"],
+            &["--sizes=64", "-o", "out.png"],
+            &["--sizes=64", "extra", "uri", "more"],
+            &["--sizes=64", "--batch"],
+            &["--sizes=64", "--jobs=2"],
+            &["--sizes=64", "--data-uri"],
+            &["--sizes=64", "--print-hash", "--report=jsonl"],
+            &["--sizes=64", "--probe"],
+            &["--name-template", "{size}.png", "256", "out.png"],
+        ] {
+            assert!(sizes(bad).is_err(), "{bad:?}");
+        }
+        assert!(parse_strs(&["--sizes=64", "in.fzp"]).is_err());
+    }
+    #[test]
+    fn report() {
+        let Command::Batch(batch) =
+            parse_strs(&["--batch", "-s", "1", "--report", "jsonl"]).unwrap()
+        else {
+            panic!("not batch")
+        };
+        assert_eq!(batch.report, Some(ReportFormat::Jsonl));
+        assert!(parse_strs(&["--batch", "-s", "1", "--report", "xml"]).is_err());
+        assert!(parse_strs(&["--report=jsonl", "in.fzp", "256", "out.png", "uri"]).is_err());
+    }
+    #[test]
+    fn probe() {
+        let expected = Command::Probe {
+            input: "in.fzp".into(),
+        };
+        assert_eq!(parse_strs(&["--probe", "in.fzp"]).unwrap(), expected);
+        assert_eq!(parse_strs(&["-i", "in.fzp", "--probe"]).unwrap(), expected);
+        assert!(parse_strs(&["--probe"]).is_err());
+        assert!(parse_strs(&["--probe", "in.fzp", "256"]).is_err());
+        assert!(parse_strs(&["--probe", "--batch", "-s", "1", "in.fzp"]).is_err());
+        assert!(parse_strs(&["--probe", "--info", "in.fzp"]).is_err());
+        assert_eq!(
+            parse_strs(&["--info", "in.fzp"]).unwrap(),
+            Command::Info {
+                input: "in.fzp".into()
+            }
+        );
+        assert_eq!(
+            parse_strs(&["--list-chunks", "in.fzp"]).unwrap(),
+            Command::ListChunks {
+                input: "in.fzp".into()
+            }
+        );
+        assert!(parse_strs(&["--list-chunks", "--info", "in.fzp"]).is_err());
+        let extract = Command::Extract {
+            input: "in.fzp".into(),
+            output: "out.qoi".into(),
+        };
+        assert_eq!(
+            parse_strs(&["--extract", "in.fzp", "out.qoi"]).unwrap(),
+            extract
+        );
+        assert_eq!(
+            parse_strs(&["-o", "out.qoi", "--extract", "in.fzp"]).unwrap(),
+            extract
+        );
+        assert!(parse_strs(&["--extract", "in.fzp"]).is_err());
+        assert!(parse_strs(&["--extract", "in.fzp", "-s", "256", "out.qoi"]).is_err());
+        assert!(parse_strs(&["--extract", "--probe", "in.fzp", "out.qoi"]).is_err());
+        assert_eq!(
+            parse_strs(&["--validate", "in.fzp"]).unwrap(),
+            Command::Validate {
+                input: "in.fzp".into(),
+                max_dimension: 1024,
+            }
+        );
+    }
+}
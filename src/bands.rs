@@ -0,0 +1,409 @@
+//! Resizing large thumbnails on several threads, in bands resized just as `fast_image_resize`
+//! would resize them all at once, so the output is the same to the bit however many there are.
+//!
+//! Its filters are separable: each row is scaled across on its own, then each column down on its
+//! own. So scaling across is split into bands of rows, and scaling down into bands of columns.
+//! Every band is cropped out of the whole image rather than copied out of it, as the weights
+//! `fast_image_resize` works out depend on the size of the image and where in it the crop is, and
+//! it reaches past the edges of the crop for the pixels beyond them.
+use crate::{args, fit, resizer};
+use fast_image_resize as fr;
+use std::num::{NonZeroU32, NonZeroUsize};
+
+/// Thumbnails scaling to fewer pixels than this are resized on one thread, as they'd be done
+/// before the others were started.
+pub const MIN_PIXELS: u64 = 512 * 512;
+
+/// `fast_image_resize`'s pixels, which it resizes as [`fr::DynamicImageView`]s.
+pub trait Resizable: fr::pixels::PixelExt {
+    fn view(view: fr::ImageView<'_, Self>) -> fr::DynamicImageView<'_>;
+    fn view_mut(view: fr::ImageViewMut<'_, Self>) -> fr::DynamicImageViewMut<'_>;
+}
+impl<T: fr::pixels::PixelExt> Resizable for T
+where
+    for<'a> fr::DynamicImageView<'a>: From<fr::ImageView<'a, T>>,
+    for<'a> fr::DynamicImageViewMut<'a>: From<fr::ImageViewMut<'a, T>>,
+{
+    fn view(view: fr::ImageView<'_, Self>) -> fr::DynamicImageView<'_> {
+        view.into()
+    }
+    fn view_mut(view: fr::ImageViewMut<'_, Self>) -> fr::DynamicImageViewMut<'_> {
+        view.into()
+    }
+}
+
+/// How many threads to scale to `scaled` on: `limit`, or one per core if there's none, or just
+/// the one if it's small.
+pub fn count(
+    (scaled_width, scaled_height): (NonZeroU32, NonZeroU32),
+    limit: Option<NonZeroUsize>,
+) -> NonZeroUsize {
+    if u64::from(scaled_width.get()) * u64::from(scaled_height.get()) < MIN_PIXELS {
+        return NonZeroUsize::MIN;
+    }
+    limit.unwrap_or_else(|| std::thread::available_parallelism().unwrap_or(NonZeroUsize::MIN))
+}
+
+/// Threads each of `jobs` thumbnails made at once may resize on, to keep to one per core between
+/// them rather than one per core each.
+pub fn per_job(jobs: NonZeroUsize) -> NonZeroUsize {
+    let cores = std::thread::available_parallelism().unwrap_or(NonZeroUsize::MIN);
+    NonZeroUsize::new(cores.get() / jobs.get()).unwrap_or(NonZeroUsize::MIN)
+}
+
+/// Resize `region` of the `width`x`height` image of `T` pixels in `source` into `destination`,
+/// `scaled_width`x`scaled_height` of them, on up to `threads` threads.
+pub fn resize<T: Resizable>(
+    filter: args::Filter,
+    (width, height): (NonZeroU32, NonZeroU32),
+    source: &[u8],
+    region: fit::Region,
+    (scaled_width, scaled_height): (NonZeroU32, NonZeroU32),
+    destination: &mut [u8],
+    threads: NonZeroUsize,
+) {
+    let across = scaled_width != region.width;
+    let down = scaled_height != region.height;
+    // Nearest-neighbour isn't split in two passes, and is quick anyway.
+    if threads.get() == 1 || filter == args::Filter::Nearest || !(across || down) {
+        let source = view::<T>((width, height), source, region);
+        let destination = view_mut::<T>((scaled_width, scaled_height), destination);
+        resize_view(filter, &source, destination);
+        return;
+    }
+    if !down {
+        across_rows::<T>(
+            filter,
+            (width, height),
+            source,
+            region,
+            scaled_width,
+            destination,
+            threads,
+        );
+        return;
+    }
+    if !across {
+        down_columns::<T>(
+            filter,
+            (width, height),
+            source,
+            region,
+            scaled_height,
+            destination,
+            threads,
+        );
+        return;
+    }
+
+    // Scaled across into an image as tall as the source, so that scaling it down weighs its rows
+    // as scaling the source down would. Only the rows within reach of the filter are filled.
+    let scale = region.height.get().div_ceil(scaled_height.get());
+    // Lanczos3 reaches furthest, three source pixels either side of the centre, further when
+    // scaling down. One more for rounding.
+    let reach = 3 * scale + 1;
+    let top = region.top.saturating_sub(reach);
+    let bottom = (region.top + region.height.get() + reach).min(height.get());
+    let row_bytes = scaled_width.get() as usize * T::size();
+    let mut buffer = zeroed(row_bytes * height.get() as usize);
+    let scaled_across: &mut [u8] = bytemuck::cast_slice_mut(&mut buffer);
+    let scaled_across = &mut scaled_across[..row_bytes * height.get() as usize];
+    across_rows::<T>(
+        filter,
+        (width, height),
+        source,
+        fit::Region {
+            top,
+            // OK - the region's rows are among them.
+            height: NonZeroU32::new(bottom - top).unwrap(),
+            ..region
+        },
+        scaled_width,
+        &mut scaled_across[row_bytes * top as usize..row_bytes * bottom as usize],
+        threads,
+    );
+    down_columns::<T>(
+        filter,
+        (scaled_width, height),
+        scaled_across,
+        fit::Region {
+            left: 0,
+            width: scaled_width,
+            ..region
+        },
+        scaled_height,
+        destination,
+        threads,
+    );
+}
+
+/// Scale `region` of the image in `source` across to `scaled_width`, keeping its height, in bands
+/// of rows.
+fn across_rows<T: Resizable>(
+    filter: args::Filter,
+    size: (NonZeroU32, NonZeroU32),
+    source: &[u8],
+    region: fit::Region,
+    scaled_width: NonZeroU32,
+    destination: &mut [u8],
+    threads: NonZeroUsize,
+) {
+    let band_rows = region.height.get().div_ceil(threads.get() as u32);
+    let band_bytes = band_rows as usize * scaled_width.get() as usize * T::size();
+    std::thread::scope(|scope| {
+        for (top, band) in (region.top..region.top + region.height.get())
+            .step_by(band_rows as usize)
+            .zip(destination.chunks_mut(band_bytes))
+        {
+            scope.spawn(move || {
+                // OK - nonzero, as `top` is within the region.
+                let rows = NonZeroU32::new(region.height.get() - (top - region.top))
+                    .unwrap()
+                    .min(NonZeroU32::new(band_rows).unwrap());
+                let band_region = fit::Region {
+                    top,
+                    height: rows,
+                    ..region
+                };
+                let source = view::<T>(size, source, band_region);
+                let destination = view_mut::<T>((scaled_width, rows), band);
+                resize_view(filter, &source, destination);
+            });
+        }
+    });
+}
+
+/// Scale `region` of the image in `source` down to `scaled_height`, keeping its width, in bands
+/// of columns.
+fn down_columns<T: Resizable>(
+    filter: args::Filter,
+    size: (NonZeroU32, NonZeroU32),
+    source: &[u8],
+    region: fit::Region,
+    scaled_height: NonZeroU32,
+    destination: &mut [u8],
+    threads: NonZeroUsize,
+) {
+    let band_columns = region.width.get().div_ceil(threads.get() as u32);
+    let row_bytes = region.width.get() as usize * T::size();
+    std::thread::scope(|scope| {
+        let bands: Vec<_> = (0..region.width.get())
+            .step_by(band_columns as usize)
+            .map(|left| {
+                scope.spawn(move || {
+                    // OK - nonzero, as `left` is within the region.
+                    let columns =
+                        NonZeroU32::new(band_columns.min(region.width.get() - left)).unwrap();
+                    let band_region = fit::Region {
+                        left: region.left + left,
+                        width: columns,
+                        ..region
+                    };
+                    let source = view::<T>(size, source, band_region);
+                    let band_bytes = columns.get() as usize * T::size();
+                    let mut band = zeroed(band_bytes * scaled_height.get() as usize);
+                    let destination = view_mut::<T>(
+                        (columns, scaled_height),
+                        bytemuck::cast_slice_mut(&mut band),
+                    );
+                    resize_view(filter, &source, destination);
+                    (left as usize * T::size(), band_bytes, band)
+                })
+            })
+            .collect();
+        // Columns aren't contiguous, so each band is scaled on its own then copied into place.
+        for band in bands {
+            // OK - only panics if resizing did.
+            let (offset, band_bytes, band) = band.join().unwrap();
+            let band: &[u8] = bytemuck::cast_slice(&band);
+            for (row, band_row) in destination
+                .chunks_exact_mut(row_bytes)
+                .zip(band.chunks_exact(band_bytes))
+            {
+                row[offset..offset + band_bytes].copy_from_slice(band_row);
+            }
+        }
+    });
+}
+
+/// `len` zeroed bytes, aligned for any of `fast_image_resize`'s pixels, the widest of which are
+/// aligned to four. There may be a few more, to round up to a whole `u32`.
+fn zeroed(len: usize) -> Vec<u32> {
+    vec![0; len.div_ceil(4)]
+}
+
+/// The `width`x`height` image in `source`, cropped to `region`.
+fn view<T: Resizable>(
+    (width, height): (NonZeroU32, NonZeroU32),
+    source: &[u8],
+    region: fit::Region,
+) -> fr::ImageView<'_, T> {
+    // OK - sized by the caller, and aligned as `T`'s channels are.
+    let mut view = fr::ImageView::<'_, T>::from_buffer(width, height, source).unwrap();
+    view.set_crop_box(fr::CropBox {
+        left: region.left,
+        top: region.top,
+        width: region.width,
+        height: region.height,
+    })
+    // OK - clamped to the image by `fit::clamp_crop`, and bands to the region.
+    .unwrap();
+    view
+}
+
+/// The `width`x`height` image in `destination`.
+fn view_mut<T: Resizable>(
+    (width, height): (NonZeroU32, NonZeroU32),
+    destination: &mut [u8],
+) -> fr::ImageViewMut<'_, T> {
+    // OK - as above.
+    fr::ImageViewMut::<'_, T>::from_buffer(width, height, destination).unwrap()
+}
+
+/// Resize all of `source`'s crop into all of `destination`.
+fn resize_view<T: Resizable>(
+    filter: args::Filter,
+    source: &fr::ImageView<'_, T>,
+    destination: fr::ImageViewMut<'_, T>,
+) {
+    resizer(filter)
+        .resize(&T::view(source.clone()), &mut T::view_mut(destination))
+        // Unwrap ok - the same pixel type for both.
+        .unwrap();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A `width`x`height` image of `channels` bytes a pixel, noisy enough that any pixel weighed
+    /// differently shows.
+    fn noise(width: u32, height: u32, channels: usize) -> Vec<u32> {
+        let mut state = 0x2545_f491_u32;
+        (0..(width as usize * height as usize * channels).div_ceil(4))
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 17;
+                state ^= state << 5;
+                state
+            })
+            .collect()
+    }
+
+    fn dims(width: u32, height: u32) -> (NonZeroU32, NonZeroU32) {
+        (
+            NonZeroU32::new(width).unwrap(),
+            NonZeroU32::new(height).unwrap(),
+        )
+    }
+
+    fn region(left: u32, top: u32, width: u32, height: u32) -> fit::Region {
+        let (width, height) = dims(width, height);
+        fit::Region {
+            left,
+            top,
+            width,
+            height,
+        }
+    }
+
+    fn resized<T: Resizable>(
+        filter: args::Filter,
+        size: (NonZeroU32, NonZeroU32),
+        source: &[u32],
+        region: fit::Region,
+        scaled: (NonZeroU32, NonZeroU32),
+        threads: usize,
+    ) -> Vec<u32> {
+        let mut destination = zeroed(scaled.0.get() as usize * scaled.1.get() as usize * T::size());
+        resize::<T>(
+            filter,
+            size,
+            bytemuck::cast_slice(source),
+            region,
+            scaled,
+            bytemuck::cast_slice_mut(&mut destination),
+            NonZeroUsize::new(threads).unwrap(),
+        );
+        destination
+    }
+
+    fn assert_threads_alike<T: Resizable>() {
+        let (width, height) = (97, 83);
+        let source = noise(width, height, T::size());
+        let size = dims(width, height);
+        let regions = [
+            region(0, 0, width, height),
+            region(5, 7, 60, 50),
+            region(90, 0, 7, height),
+            region(0, 80, width, 3),
+        ];
+        for filter in [
+            args::Filter::Nearest,
+            args::Filter::Bilinear,
+            args::Filter::CatmullRom,
+            args::Filter::Lanczos3,
+            args::Filter::Box,
+        ] {
+            for region in regions {
+                let (rw, rh) = (region.width.get(), region.height.get());
+                for scaled in [
+                    dims(rw, rh),
+                    // Across, down, and both.
+                    dims(rw.div_ceil(3), rh),
+                    dims(rw, rh.div_ceil(3)),
+                    dims(rw.div_ceil(2), rh.div_ceil(5)),
+                    dims(rw * 2, rh * 3 / 2),
+                    dims(1, 1),
+                ] {
+                    let once = resized::<T>(filter, size, &source, region, scaled, 1);
+                    for threads in [2, 3, 8] {
+                        assert!(
+                            once == resized::<T>(filter, size, &source, region, scaled, threads),
+                            "{filter:?} {region:?} to {scaled:?} on {threads} threads"
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rgba() {
+        assert_threads_alike::<fr::pixels::U8x4>();
+    }
+
+    #[test]
+    fn rgb() {
+        assert_threads_alike::<fr::pixels::U8x3>();
+    }
+
+    #[test]
+    fn linear() {
+        assert_threads_alike::<fr::pixels::U16x4>();
+        assert_threads_alike::<fr::pixels::U16x3>();
+    }
+
+    #[test]
+    fn small_on_one_thread() {
+        let limit = NonZeroUsize::new(4);
+        assert_eq!(count(dims(511, 512), limit).get(), 1);
+        assert_eq!(count(dims(4096, 1), limit).get(), 1);
+        assert_eq!(count(dims(512, 512), limit), limit.unwrap());
+        assert_eq!(count(dims(1024, 1024), NonZeroUsize::new(1)).get(), 1);
+    }
+
+    #[test]
+    fn shared_between_jobs() {
+        let cores = std::thread::available_parallelism().unwrap();
+        assert_eq!(per_job(NonZeroUsize::MIN), cores);
+        assert_eq!(per_job(cores).get(), 1);
+        assert_eq!(
+            per_job(cores.saturating_mul(NonZeroUsize::new(4).unwrap())).get(),
+            1
+        );
+        if let Some(half) = NonZeroUsize::new(cores.get() / 2) {
+            assert_eq!(per_job(NonZeroUsize::new(2).unwrap()), half);
+        }
+    }
+}
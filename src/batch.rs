@@ -0,0 +1,220 @@
+//! Thumbnailing many documents in one process.
+use crate::args;
+use crate::error::{Class, Error};
+use crate::log;
+use crate::report::ItemReport;
+use crate::template;
+use crate::timings::Timings;
+use std::collections::hash_map::{Entry, HashMap};
+use std::io::{BufRead, Result as IOResult};
+use std::panic::AssertUnwindSafe;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+/// An input path, and either its output path or why there isn't one.
+/// The input is empty if the failure was in reading the list of inputs.
+type Item = (PathBuf, Result<PathBuf, Error>);
+
+/// Thumbnail every item, reporting failures as we go rather than bailing.
+///
+/// Thumbnails are found as `scan` says, so with [`crate::Scan::strict`] anything wrong with a
+/// document fails its item.
+pub fn run(batch: &args::Batch, scan: crate::Scan) -> Result<ExitCode, Error> {
+    let items: Box<dyn Iterator<Item = Item> + Send> = match &batch.items {
+        args::BatchItems::Pairs(pairs) => Box::new(
+            pairs
+                .iter()
+                .map(|(input, output)| (input.clone(), Ok(output.clone()))),
+        ),
+        args::BatchItems::FilesFrom {
+            list,
+            out_dir,
+            out_template,
+        } => {
+            let list: Box<dyn BufRead + Send> = if list.as_os_str() == "-" {
+                Box::new(std::io::BufReader::new(std::io::stdin()))
+            } else {
+                let file = std::fs::File::open(list).map_err(|io| {
+                    Error::new(Class::InputIo, format!("failed to open --files-from: {io}"))
+                })?;
+                Box::new(std::io::BufReader::new(file))
+            };
+            let out_dir = out_dir.clone();
+            let out_template = out_template.clone();
+            let size = batch.options.size;
+            // Only the names are kept, to catch two inputs being named the same.
+            let mut claimed: HashMap<PathBuf, PathBuf> = HashMap::new();
+            Box::new(read_list(list).map(move |path| {
+                let path = match path.map(entry_path) {
+                    Ok(Ok(path)) => path,
+                    Ok(Err((path, err))) => return (path, Err(err)),
+                    Err(io) => {
+                        let err = format!("failed to read --files-from: {io}");
+                        return (PathBuf::new(), Err(Error::new(Class::InputIo, err)));
+                    }
+                };
+                let out_path = crate::file_uri(&path).and_then(|uri| {
+                    let fields = template::Fields {
+                        stem: path.file_stem(),
+                        ext: path.extension(),
+                        size,
+                        md5uri: &crate::xdg::md5_uri(&uri),
+                    };
+                    let out_path = out_template
+                        .expand(&fields)
+                        .map(|name| out_dir.join(name))
+                        .map_err(|message| Error::new(Class::Usage, message))?;
+                    match claimed.entry(out_path.clone()) {
+                        Entry::Occupied(other) => Err(Error::new(
+                            Class::Usage,
+                            format!(
+                                "--out-template names this the same as {}: `{}`",
+                                other.get().display(),
+                                out_path.display()
+                            ),
+                        )),
+                        Entry::Vacant(entry) => {
+                            entry.insert(path.clone());
+                            Ok(out_path)
+                        }
+                    }
+                });
+                (path, out_path)
+            }))
+        }
+    };
+
+    let jobs = batch
+        .jobs
+        .or_else(|| std::thread::available_parallelism().ok())
+        .unwrap_or(std::num::NonZeroUsize::MIN);
+    // The cores are shared between the jobs, rather than every job resizing on all of them.
+    let options = args::Options {
+        threads: batch.options.threads.or(Some(crate::bands::per_job(jobs))),
+        ..batch.options.clone()
+    };
+    // Items are pulled one at a time, so the list is never held in memory all at once. Each
+    // item is claimed by exactly one worker, so no two workers ever write the same output.
+    let items = Mutex::new(items);
+    let total = AtomicUsize::new(0);
+    let failures = AtomicUsize::new(0);
+    let worker = || loop {
+        // Poison OK - the iterator isn't left in a broken state by a panicking `next`.
+        let Some((in_path, out_path)) = items.lock().unwrap_or_else(|p| p.into_inner()).next()
+        else {
+            break;
+        };
+        total.fetch_add(1, Ordering::Relaxed);
+        let start = std::time::Instant::now();
+        let mut timings = Timings::start(batch.options.timings);
+        let (out_path, result) = match out_path {
+            Ok(out_path) => {
+                // Contain panics to the item that caused them, which is why even release builds
+                // unwind.
+                // Unwind safe - a panic leaves nothing worse than partial timings.
+                let result = std::panic::catch_unwind(AssertUnwindSafe(|| {
+                    log::with_context(in_path.display(), || {
+                        crate::file_uri(&in_path).and_then(|uri| {
+                            crate::thumbnail_one(
+                                crate::Source::Path(&in_path),
+                                crate::Destination::Path(&out_path),
+                                &uri,
+                                None,
+                                &options,
+                                scan,
+                                &mut timings,
+                            )
+                        })
+                    })
+                }))
+                .unwrap_or_else(|_| Err(Error::new(Class::Corrupt, "thumbnailer panicked")));
+                (out_path, result)
+            }
+            Err(err) => (PathBuf::new(), Err(err)),
+        };
+        if result.is_err() {
+            failures.fetch_add(1, Ordering::Relaxed);
+        }
+        match batch.report {
+            Some(args::ReportFormat::Jsonl) => {
+                let report = ItemReport {
+                    input: &in_path,
+                    output: &out_path,
+                    result: result.as_ref().copied(),
+                    elapsed: start.elapsed(),
+                    timings: timings.enabled().then_some(&timings),
+                };
+                // One call per line, so lines from different workers don't interleave.
+                println!("{}", report.to_json());
+            }
+            None => {
+                if timings.enabled() && !in_path.as_os_str().is_empty() {
+                    // Asked for explicitly, so not subject to --quiet.
+                    eprintln!("{}: {timings}", in_path.display());
+                }
+                if let Err(err) = result {
+                    if in_path.as_os_str().is_empty() {
+                        log::warn(err);
+                    } else {
+                        log::warn(format_args!("{}: {err}", in_path.display()));
+                    }
+                }
+            }
+        }
+    };
+    if jobs.get() == 1 {
+        worker();
+    } else {
+        std::thread::scope(|scope| {
+            for _ in 0..jobs.get() {
+                scope.spawn(worker);
+            }
+        });
+    }
+
+    match failures.into_inner() {
+        0 => Ok(ExitCode::SUCCESS),
+        failures => {
+            if batch.report.is_none() {
+                eprintln!("{failures} of {} thumbnails failed", total.into_inner());
+            }
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+/// Iterate over the non-empty entries of a list of paths.
+///
+/// If the first buffer of the list contains a NUL byte, entries are NUL-delimited (as from
+/// `find -print0`), allowing paths containing newlines. Otherwise, they're newline-delimited.
+fn read_list(mut list: impl BufRead) -> impl Iterator<Item = IOResult<Vec<u8>>> {
+    let delimiter = match list.fill_buf() {
+        Ok(buf) if buf.contains(&0) => 0,
+        // Errors are reported by the split.
+        _ => b'\n',
+    };
+    list.split(delimiter)
+        .filter(|entry| entry.as_ref().map_or(true, |entry| !entry.is_empty()))
+}
+
+/// Interpret an entry of a list as a path.
+///
+/// On Unix, paths are arbitrary bytes. Elsewhere, they must be UTF-8 - on failure, the (lossy)
+/// path is returned alongside the error so it can still be reported.
+#[cfg(unix)]
+fn entry_path(entry: Vec<u8>) -> Result<PathBuf, (PathBuf, Error)> {
+    use std::os::unix::ffi::OsStringExt;
+    Ok(std::ffi::OsString::from_vec(entry).into())
+}
+#[cfg(not(unix))]
+fn entry_path(entry: Vec<u8>) -> Result<PathBuf, (PathBuf, Error)> {
+    String::from_utf8(entry).map(PathBuf::from).map_err(|e| {
+        let path = String::from_utf8_lossy(e.as_bytes()).into_owned();
+        (
+            path.into(),
+            Error::new(Class::InputIo, "input path is not UTF-8"),
+        )
+    })
+}
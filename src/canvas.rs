@@ -0,0 +1,120 @@
+//! Placing the scaled thumbnail onto a canvas, for `--square` and `--background`.
+//!
+//! Images are row-major slices of `width * height` pixels.
+
+/// Center a `width`x`height` image on a `canvas_width`x`canvas_height` canvas of `fill`.
+///
+/// The canvas must be at least as large as the image. Where the leftover space is odd, the
+/// extra pixel goes to the right or bottom.
+pub fn pad<P: Copy>(
+    pixels: &[P],
+    (width, height): (usize, usize),
+    (canvas_width, canvas_height): (usize, usize),
+    fill: P,
+) -> Vec<P> {
+    debug_assert_eq!(pixels.len(), width * height);
+    debug_assert!(width <= canvas_width && height <= canvas_height);
+    let left = (canvas_width - width) / 2;
+    let top = (canvas_height - height) / 2;
+    let mut canvas = vec![fill; canvas_width * canvas_height];
+    if width == 0 {
+        return canvas;
+    }
+    for (row, canvas_row) in pixels
+        .chunks_exact(width)
+        .zip(canvas.chunks_exact_mut(canvas_width).skip(top))
+    {
+        canvas_row[left..left + width].copy_from_slice(row);
+    }
+    canvas
+}
+
+/// Composite straight-alpha RGBA8 pixels over `background`, in place.
+///
+/// An opaque background leaves every pixel opaque.
+pub fn flatten(pixels: &mut [[u8; 4]], background: [u8; 4]) {
+    let [br, bg, bb, ba] = background.map(u32::from);
+    for pixel in pixels {
+        let [r, g, b, a] = pixel.map(u32::from);
+        // Coverage left over for the background to show through, scaled by 255 * 255.
+        let under = ba * (255 - a);
+        let alpha = a * 255 + under;
+        if alpha == 0 {
+            *pixel = [0; 4];
+            continue;
+        }
+        // Rounded. OK - a weighted average of u8s is itself in range.
+        let channel = |c: u32, bc: u32| ((c * a * 255 + bc * under + alpha / 2) / alpha) as u8;
+        *pixel = [
+            channel(r, br),
+            channel(g, bg),
+            channel(b, bb),
+            ((alpha + 127) / 255) as u8,
+        ];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn flattened() {
+        let flat = |pixel: [u8; 4], background| {
+            let mut pixels = [pixel];
+            flatten(&mut pixels, background);
+            pixels[0]
+        };
+        let white = [255, 255, 255, 255];
+        // Transparent takes the background, whatever its color.
+        assert_eq!(flat([10, 20, 30, 0], white), white);
+        assert_eq!(flat([0, 0, 0, 0], [1, 2, 3, 255]), [1, 2, 3, 255]);
+        // Opaque is untouched.
+        assert_eq!(flat([10, 20, 30, 255], white), [10, 20, 30, 255]);
+        // Half blends halfway, rounded.
+        assert_eq!(flat([0, 0, 0, 128], white), [127, 127, 127, 255]);
+        assert_eq!(
+            flat([255, 0, 100, 128], [0, 255, 0, 255]),
+            [128, 127, 50, 255]
+        );
+        // Translucent backgrounds stay translucent.
+        assert_eq!(flat([10, 20, 30, 0], [0, 0, 0, 0]), [0, 0, 0, 0]);
+        assert_eq!(flat([10, 20, 30, 255], [0, 0, 0, 128]), [10, 20, 30, 255]);
+        assert_eq!(flat([0, 0, 0, 0], [40, 50, 60, 128]), [40, 50, 60, 128]);
+        assert_eq!(flat([200, 0, 0, 128], [0, 0, 200, 128]), [134, 0, 66, 192]);
+    }
+
+    #[test]
+    fn centered() {
+        // 2x1 onto 4x3, even leftover across, odd down.
+        assert_eq!(
+            pad(&[1, 2], (2, 1), (4, 3), 0),
+            [
+                0, 0, 0, 0, //
+                0, 1, 2, 0, //
+                0, 0, 0, 0,
+            ]
+        );
+        // 1x2 onto 4x4, odd leftover goes right.
+        assert_eq!(
+            pad(&[1, 2], (1, 2), (4, 4), 0),
+            [
+                0, 0, 0, 0, //
+                0, 1, 0, 0, //
+                0, 2, 0, 0, //
+                0, 0, 0, 0,
+            ]
+        );
+        // 2x2 onto 3x3, odd both ways.
+        assert_eq!(
+            pad(&[1, 2, 3, 4], (2, 2), (3, 3), 9),
+            [
+                1, 2, 9, //
+                3, 4, 9, //
+                9, 9, 9,
+            ]
+        );
+        // Already the canvas size.
+        assert_eq!(pad(&[1, 2, 3, 4], (2, 2), (2, 2), 0), [1, 2, 3, 4]);
+    }
+}
@@ -0,0 +1,743 @@
+//! Walking the chunks of a RIFF container, or of a `LIST` within one.
+use crate::error::{Class, Error};
+use crate::take::MyTake;
+use crate::{escape_fourcc, read_chunk_header, BufReadSeek};
+use std::io::{BufRead, Error as IOError, Read, Seek, SeekFrom};
+
+/// Bytes after a malformed chunk header searched for a plausible one to carry on from.
+pub const RESYNC_WINDOW: u64 = 64 * 1024;
+
+/// Something wrong with a container that was coped with, rather than failing.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub enum Warning {
+    /// A chunk declares more bytes than its parent, the RIFF header or a `LIST`, leaves for it,
+    /// so only `available` of them were used.
+    ChunkOverrun {
+        fourcc: [u8; 4],
+        offset: u64,
+        declared: u64,
+        available: u64,
+    },
+    /// A parent leaves `len` bytes at its end, too few for a chunk, so they were ignored.
+    TrailingBytes { offset: u64, len: u64 },
+    /// The document ended between chunks, `missing` bytes short of what its parent declares.
+    Truncated { offset: u64, missing: u64 },
+    /// The document's header declares `declared` bytes after its size field, but `actual` follow
+    /// it, which were used instead.
+    SizeMismatch {
+        magic: [u8; 4],
+        declared: u64,
+        actual: u64,
+    },
+    /// A chunk's size is still the placeholder written before its data, so it was taken to run
+    /// to the end of the document, or its parent if that's sooner, `available` bytes in all.
+    PlaceholderSize {
+        fourcc: [u8; 4],
+        offset: u64,
+        available: u64,
+    },
+    /// A `thix` chunk is malformed, so thumbnails were searched for instead.
+    BadIndex { offset: u64, problem: String },
+    /// A `dims` chunk is malformed, so the thumbnail's own size was given as the canvas size.
+    BadCanvasSize { offset: u64, problem: String },
+    /// The chunk header at `offset` is malformed, so `skipped` bytes were skipped to the next
+    /// plausible one.
+    Resynced { offset: u64, skipped: u64 },
+}
+impl std::fmt::Display for Warning {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ChunkOverrun {
+                fourcc,
+                offset,
+                declared,
+                available,
+            } => write!(
+                f,
+                "chunk \"{}\" at offset {offset} declares {declared} bytes, but its parent leaves only {available}",
+                escape_fourcc(fourcc)
+            ),
+            Self::TrailingBytes { offset, len } => write!(
+                f,
+                "{len} bytes at offset {offset} are too few to be a chunk"
+            ),
+            Self::Truncated { offset, missing } => write!(
+                f,
+                "document ends at offset {offset}, {missing} bytes earlier than declared"
+            ),
+            Self::SizeMismatch {
+                magic,
+                declared,
+                actual,
+            } => write!(
+                f,
+                "{} header declares {declared} bytes, but {actual} follow it",
+                escape_fourcc(magic)
+            ),
+            Self::PlaceholderSize {
+                fourcc,
+                offset,
+                available,
+            } => write!(
+                f,
+                "chunk \"{}\" at offset {offset} has a placeholder size, as left by an interrupted save, so its remaining {available} bytes were used",
+                escape_fourcc(fourcc)
+            ),
+            Self::BadIndex { offset, problem } => write!(
+                f,
+                "thumbnail index at offset {offset} {problem}"
+            ),
+            Self::BadCanvasSize { offset, problem } => write!(
+                f,
+                "canvas size at offset {offset} {problem}"
+            ),
+            Self::Resynced { offset, skipped } => write!(
+                f,
+                "chunk header at offset {offset} is malformed, so {skipped} bytes were skipped to the next plausible one"
+            ),
+        }
+    }
+}
+
+/// Byte order of the sizes in a container's chunk headers: little-endian in `RIFF` containers,
+/// big-endian in `RIFX`. Chunk data is the same either way.
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy)]
+pub enum ByteOrder {
+    #[default]
+    Little,
+    Big,
+}
+impl ByteOrder {
+    /// Read a size field in this order.
+    pub fn u32(self, bytes: [u8; 4]) -> u32 {
+        match self {
+            Self::Little => u32::from_le_bytes(bytes),
+            Self::Big => u32::from_be_bytes(bytes),
+        }
+    }
+}
+
+/// Sizes of chunks too large for the 32 bits of their headers, which declare [`u32::MAX`]
+/// instead, as listed by the `ds64` chunk of an RF64 document.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct LargeSizes(pub Vec<([u8; 4], u64)>);
+impl LargeSizes {
+    /// The real size of a chunk whose header declares `declared`.
+    pub fn size(&self, fourcc: &[u8; 4], declared: u32) -> u64 {
+        if declared != u32::MAX {
+            return declared.into();
+        }
+        self.0
+            .iter()
+            .find(|(large, _)| large == fourcc)
+            .map_or(declared.into(), |&(_, size)| size)
+    }
+}
+
+/// Reads the chunks of a container one at a time, in order.
+///
+/// Not an [`Iterator`], as each chunk borrows the reader. Chunk sizes are trimmed to the
+/// container, and the pad byte after odd-sized chunks is skipped.
+pub struct ChunkReader<R> {
+    /// The reader, as a take of the current chunk's data, reused from one chunk to the next.
+    reader: MyTake<R>,
+    /// Bytes of the container after the chunks read so far.
+    remaining: u64,
+    /// Offset of the next chunk within the document.
+    offset: u64,
+    /// Length of the whole document as stored, if known, to tell chunks cut short by it.
+    file_len: Option<u64>,
+    large_sizes: LargeSizes,
+    byte_order: ByteOrder,
+    /// Skip malformed chunk headers, rather than taking them at their word.
+    resync: bool,
+    /// Why the last chunk couldn't be skipped, if it couldn't.
+    skip_error: Option<IOError>,
+}
+
+/// A chunk of a container, whose data is read through [`Chunk::data`].
+///
+/// When dropped, the reader is moved past whatever is left of it, to the next chunk.
+pub struct Chunk<'a, R: Seek> {
+    pub fourcc: [u8; 4],
+    /// Offset of the chunk's header within the document.
+    pub offset: u64,
+    /// Size the chunk's header declares, which may be more than its container leaves for it.
+    pub declared: u64,
+    /// The chunk's data, trimmed to its container.
+    pub data: &'a mut MyTake<R>,
+    /// Whether a pad byte follows the data.
+    padded: bool,
+    skip_error: &'a mut Option<IOError>,
+    /// Of its container, for the chunks of a `LIST`.
+    file_len: Option<u64>,
+    byte_order: ByteOrder,
+    resync: bool,
+}
+impl<R: BufRead + Seek> Chunk<'_, R> {
+    /// Read the list type of a `LIST` chunk, then the chunks after it, which are read as its
+    /// container's are.
+    pub fn list(&mut self) -> std::io::Result<([u8; 4], ChunkReader<&mut dyn BufReadSeek>)> {
+        let mut list_type = [0; 4];
+        self.data.read_exact(&mut list_type)?;
+        let len = self.data.remaining();
+        let mut list = ChunkReader::new(
+            &mut *self.data as &mut dyn BufReadSeek,
+            len,
+            self.offset + 12,
+        )
+        .with_byte_order(self.byte_order)
+        .with_resync(self.resync);
+        if let Some(file_len) = self.file_len {
+            list = list.with_file_len(file_len);
+        }
+        Ok((list_type, list))
+    }
+}
+
+impl<R: BufRead + Seek> ChunkReader<R> {
+    /// Read the chunks in the `len` bytes at `reader`'s position, which is `offset` within the
+    /// document.
+    pub fn new(reader: R, len: u64, offset: u64) -> Self {
+        Self {
+            reader: MyTake::new(reader, 0),
+            remaining: len,
+            offset,
+            file_len: None,
+            large_sizes: LargeSizes::default(),
+            byte_order: ByteOrder::Little,
+            resync: false,
+            skip_error: None,
+        }
+    }
+    /// Fail on chunks that claim more than is left of a document `file_len` bytes long, rather
+    /// than finding out part way through reading them.
+    pub fn with_file_len(self, file_len: u64) -> Self {
+        Self {
+            file_len: Some(file_len),
+            ..self
+        }
+    }
+    /// Take the size of chunks declaring [`u32::MAX`] from `large_sizes`.
+    pub fn with_large_sizes(self, large_sizes: LargeSizes) -> Self {
+        Self {
+            large_sizes,
+            ..self
+        }
+    }
+    /// Read chunk sizes in `byte_order`, rather than little-endian.
+    pub fn with_byte_order(self, byte_order: ByteOrder) -> Self {
+        Self { byte_order, ..self }
+    }
+    /// If `resync`, skip chunk headers with a fourcc that isn't printable or a size that can't
+    /// fit, looking up to [`RESYNC_WINDOW`] bytes on for a plausible one to carry on from.
+    pub fn with_resync(self, resync: bool) -> Self {
+        Self { resync, ..self }
+    }
+    /// The reader, moved to the end of the last chunk read.
+    pub fn into_inner(self) -> std::io::Result<R> {
+        self.reader.into_inner_at_end()
+    }
+    /// The next chunk, or `None` at the end of the container.
+    ///
+    /// Anything malformed that can be carried on past is pushed to `warnings` instead of failing.
+    pub fn next(&mut self, warnings: &mut Vec<Warning>) -> Result<Option<Chunk<'_, R>>, Error> {
+        let io_err = |io: IOError| Error::from_io(&io);
+        if let Some(io) = self.skip_error.take() {
+            return Err(io_err(io));
+        }
+        let offset = self.offset;
+        if self.remaining == 0 {
+            return Ok(None);
+        }
+        // Bytes actually left of the document from `offset`.
+        let file_len = self.file_len;
+        let left = move |offset: u64| file_len.map(|file_len| file_len.saturating_sub(offset));
+        // As with chunks, the document ending where the container would go on is it being cut
+        // short.
+        if let Some(left @ 1..=7) = left(offset).filter(|&left| left <= self.remaining) {
+            return Err(truncated(format_args!(
+                "chunk header at offset {offset} needs 8 bytes but only {left} remain"
+            )));
+        }
+        if self.remaining < 8 {
+            warnings.push(Warning::TrailingBytes {
+                offset,
+                len: std::mem::take(&mut self.remaining),
+            });
+            return Ok(None);
+        }
+        // Ending between chunks is only short, ending within a header is corrupt.
+        if self.reader.get_mut().fill_buf().map_err(io_err)?.is_empty() {
+            warnings.push(Warning::Truncated {
+                offset,
+                missing: std::mem::take(&mut self.remaining),
+            });
+            return Ok(None);
+        }
+        let (fourcc, declared) =
+            read_chunk_header(self.reader.get_mut(), self.byte_order).map_err(io_err)?;
+        let mut declared = self.large_sizes.size(&fourcc, declared);
+        let as_declared = declared;
+        self.remaining -= 8;
+        let room = left(offset + 8).unwrap_or(u64::MAX).min(self.remaining);
+        // Placeholder sizes can't fit either, but are coped with below.
+        let fits = declared <= room || declared == u32::MAX.into();
+        if self.resync && !(plausible_fourcc(&fourcc) && fits) {
+            if let Some(skipped) = self.skip_to_plausible().map_err(io_err)? {
+                warnings.push(Warning::Resynced { offset, skipped });
+                return self.next(warnings);
+            }
+        }
+        // Written before the real size is known, and never patched if saving was interrupted.
+        if declared == u32::MAX.into() {
+            let to_end = left(offset + 8).unwrap_or(declared).min(self.remaining);
+            warnings.push(Warning::PlaceholderSize {
+                fourcc,
+                offset,
+                available: to_end,
+            });
+            declared = to_end;
+        }
+        // Running past the end of the document where the container would have allowed it is the
+        // document being cut short, rather than the chunk being wrong.
+        if let Some(left) =
+            left(offset + 8).filter(|&left| left < declared && left <= self.remaining)
+        {
+            return Err(truncated(format_args!(
+                "chunk \"{}\" at offset {offset} claims {declared} bytes but only {left} remain",
+                escape_fourcc(&fourcc)
+            )));
+        }
+        let available = declared.min(self.remaining);
+        if available < declared {
+            warnings.push(Warning::ChunkOverrun {
+                fourcc,
+                offset,
+                declared,
+                available,
+            });
+        }
+        // Chunks are word-aligned, odd sizes are followed by a pad byte.
+        let padded = declared % 2 == 1 && self.remaining > declared;
+        self.remaining -= available + u64::from(padded);
+        // Saturating, as `ds64` sizes may be anything. Those running past the end are the last.
+        self.offset = self
+            .offset
+            .saturating_add(8 + declared % 2)
+            .saturating_add(declared);
+        self.reader.set_len(available);
+        Ok(Some(Chunk {
+            fourcc,
+            offset,
+            declared: as_declared,
+            data: &mut self.reader,
+            padded,
+            skip_error: &mut self.skip_error,
+            file_len: self.file_len,
+            byte_order: self.byte_order,
+            resync: self.resync,
+        }))
+    }
+    /// Having just read a malformed chunk header, move to the next plausible one within
+    /// [`RESYNC_WINDOW`] bytes of its start, returning how many bytes were skipped, or `None` if
+    /// there isn't one, leaving the reader where it was.
+    fn skip_to_plausible(&mut self) -> std::io::Result<Option<u64>> {
+        // Bytes of the container from the start of the malformed header.
+        let total = self.remaining + 8;
+        let reader = self.reader.get_mut();
+        reader.seek(SeekFrom::Current(-7))?;
+        let mut window = Vec::new();
+        (&mut *reader)
+            .take(RESYNC_WINDOW.min(total - 1))
+            .read_to_end(&mut window)?;
+        let found = window.windows(8).enumerate().position(|(at, header)| {
+            let skipped = at as u64 + 1;
+            let fourcc = header[..4].try_into().unwrap();
+            let declared = self.byte_order.u32(header[4..].try_into().unwrap());
+            let room = total - skipped - 8;
+            let room = self.file_len.map_or(room, |file_len| {
+                room.min(file_len.saturating_sub(self.offset + skipped + 8))
+            });
+            plausible_fourcc(fourcc) && self.large_sizes.size(fourcc, declared) <= room
+        });
+        let Some(at) = found else {
+            self.reader
+                .get_mut()
+                .seek(SeekFrom::Current(7 - window.len() as i64))?;
+            return Ok(None);
+        };
+        self.reader
+            .get_mut()
+            .seek(SeekFrom::Current(at as i64 - window.len() as i64))?;
+        let skipped = at as u64 + 1;
+        self.offset += skipped;
+        self.remaining = total - skipped;
+        Ok(Some(skipped))
+    }
+}
+
+/// Whether a fourcc could be real: letters and digits, then perhaps trailing spaces.
+fn plausible_fourcc(fourcc: &[u8; 4]) -> bool {
+    let name_len = fourcc
+        .iter()
+        .rposition(|&b| b != b' ')
+        .map_or(0, |last| last + 1);
+    name_len > 0 && fourcc[..name_len].iter().all(u8::is_ascii_alphanumeric)
+}
+
+/// The document was cut short, as by a copy that didn't finish.
+fn truncated(details: std::fmt::Arguments) -> Error {
+    Error::new(Class::Corrupt, format!("document is truncated ({details})"))
+}
+
+impl<R: Seek> Drop for Chunk<'_, R> {
+    fn drop(&mut self) {
+        let skipped = self.data.skip_remaining().and_then(|_| {
+            if self.padded {
+                self.data.get_mut().seek(SeekFrom::Current(1))?;
+            }
+            Ok(())
+        });
+        // Reported by the next read instead.
+        if let Err(io) = skipped {
+            *self.skip_error = Some(io);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{Cursor, Read};
+
+    /// The bytes of each chunk, which are given with their declared size and data that may
+    /// disagree. Odd sizes are padded.
+    fn container(chunks: &[(&[u8; 4], u32, &[u8])]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for (fourcc, declared, data) in chunks {
+            bytes.extend_from_slice(*fourcc);
+            bytes.extend_from_slice(&declared.to_le_bytes());
+            bytes.extend_from_slice(data);
+            if data.len() % 2 == 1 {
+                bytes.push(0);
+            }
+        }
+        bytes
+    }
+    /// A chunk as `(fourcc, offset, data)`.
+    type Walked = ([u8; 4], u64, Vec<u8>);
+    /// Every chunk in `bytes`, reading only `read` bytes of each.
+    fn walk(bytes: &[u8], len: u64, read: usize) -> (Vec<Walked>, Vec<Warning>) {
+        let mut cursor = Cursor::new(bytes);
+        let mut reader = ChunkReader::new(&mut cursor, len, 12);
+        let mut chunks = Vec::new();
+        let mut warnings = Vec::new();
+        while let Some(chunk) = reader.next(&mut warnings).unwrap() {
+            let mut data = Vec::new();
+            (&mut *chunk.data)
+                .take(read as u64)
+                .read_to_end(&mut data)
+                .unwrap();
+            chunks.push((chunk.fourcc, chunk.offset, data));
+        }
+        (chunks, warnings)
+    }
+
+    #[test]
+    fn walks_in_order() {
+        let bytes = container(&[(b"meta", 3, b"abc"), (b"hist", 0, b""), (b"thmb", 2, b"de")]);
+        let expected = [
+            (*b"meta", 12, b"abc".to_vec()),
+            (*b"hist", 24, Vec::new()),
+            (*b"thmb", 32, b"de".to_vec()),
+        ];
+        // However much of each chunk is read, the next is found.
+        for read in [0, 1, 100] {
+            let (chunks, warnings) = walk(&bytes, bytes.len() as u64, read);
+            let expected: Vec<_> = expected
+                .iter()
+                .map(|(fourcc, offset, data)| {
+                    (*fourcc, *offset, data[..read.min(data.len())].to_vec())
+                })
+                .collect();
+            assert_eq!(chunks, expected, "reading {read}");
+            assert_eq!(warnings, []);
+        }
+        // Only as far as the container goes.
+        let (chunks, warnings) = walk(&bytes, 12, 100);
+        assert_eq!(chunks, [(*b"meta", 12, b"abc".to_vec())]);
+        assert_eq!(warnings, []);
+    }
+    #[test]
+    fn copes_with_bad_sizes() {
+        // Trimmed to the container.
+        let bytes = container(&[(b"meta", 2, b"ab"), (b"thmb", 30, b"xyz")]);
+        let (chunks, warnings) = walk(&bytes, bytes.len() as u64, 100);
+        assert_eq!(chunks[1], (*b"thmb", 22, b"xyz\0".to_vec()));
+        assert_eq!(
+            warnings,
+            [Warning::ChunkOverrun {
+                fourcc: *b"thmb",
+                offset: 22,
+                declared: 30,
+                available: 4,
+            }]
+        );
+        assert_eq!(
+            warnings[0].to_string(),
+            "chunk \"thmb\" at offset 22 declares 30 bytes, but its parent leaves only 4"
+        );
+
+        // Leftovers too few for a chunk.
+        let bytes = container(&[(b"meta", 2, b"ab"), (b"thmb", 3, b"xyz")]);
+        let (chunks, warnings) = walk(&bytes, 13, 100);
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(warnings, [Warning::TrailingBytes { offset: 22, len: 3 }]);
+
+        // Ending between chunks, or within one.
+        let (chunks, warnings) = walk(&bytes, 100, 100);
+        assert_eq!(chunks.len(), 2);
+        assert_eq!(
+            warnings,
+            [Warning::Truncated {
+                offset: 34,
+                missing: 78
+            }]
+        );
+        let mut cursor = Cursor::new(&bytes[..14]);
+        let mut reader = ChunkReader::new(&mut cursor, 100, 12);
+        let mut warnings = Vec::new();
+        drop(reader.next(&mut warnings).unwrap());
+        assert!(reader.next(&mut warnings).is_err());
+    }
+    #[test]
+    fn cut_short() {
+        let bytes = container(&[(b"meta", 2, b"ab"), (b"thmb", 3, b"xyz")]);
+        let next = |file_len| {
+            let mut cursor = Cursor::new(&bytes[..(file_len - 12) as usize]);
+            let mut reader = ChunkReader::new(&mut cursor, 100, 12).with_file_len(file_len);
+            let mut warnings = Vec::new();
+            let mut messages = Vec::new();
+            loop {
+                match reader.next(&mut warnings) {
+                    Ok(Some(_)) => (),
+                    Ok(None) => return (messages, warnings),
+                    Err(err) => {
+                        assert_eq!(err.class, Class::Corrupt);
+                        messages.push(err.message);
+                        return (messages, warnings);
+                    }
+                }
+            }
+        };
+        // Ending between chunks is still only short.
+        let (messages, warnings) = next(22);
+        assert!(messages.is_empty());
+        assert!(matches!(
+            warnings[..],
+            [Warning::Truncated { offset: 22, .. }]
+        ));
+        // The pad byte doesn't matter.
+        assert!(next(33).0.is_empty());
+        assert_eq!(
+            next(32).0,
+            ["document is truncated (chunk \"thmb\" at offset 22 claims 3 bytes but only 2 remain)"]
+        );
+        assert_eq!(
+            next(25).0,
+            ["document is truncated (chunk header at offset 22 needs 8 bytes but only 3 remain)"]
+        );
+    }
+    #[test]
+    fn into_inner() {
+        let bytes = container(&[(b"meta", 3, b"abc"), (b"hist", 0, b"")]);
+        let mut reader = ChunkReader::new(Cursor::new(&bytes), bytes.len() as u64, 12);
+        let mut warnings = Vec::new();
+        let meta = reader.next(&mut warnings).unwrap().unwrap();
+        meta.data.read_exact(&mut [0; 1]).unwrap();
+        drop(meta);
+        // Past the rest of the chunk, and its pad byte.
+        let mut inner = reader.into_inner().unwrap();
+        assert_eq!(inner.position(), 12);
+        let mut next = [0; 4];
+        inner.read_exact(&mut next).unwrap();
+        assert_eq!(&next, b"hist");
+    }
+    #[test]
+    fn large_sizes() {
+        let bytes = container(&[(b"data", u32::MAX, b"abcd"), (b"thmb", u32::MAX, b"ef")]);
+        let large_sizes = LargeSizes(vec![(*b"data", 4)]);
+        assert_eq!(large_sizes.size(b"data", 2), 2);
+        assert_eq!(large_sizes.size(b"thmb", u32::MAX), u64::from(u32::MAX));
+        let mut cursor = Cursor::new(&bytes);
+        let mut reader =
+            ChunkReader::new(&mut cursor, bytes.len() as u64, 12).with_large_sizes(large_sizes);
+        let mut warnings = Vec::new();
+        let data = reader.next(&mut warnings).unwrap().unwrap();
+        assert_eq!(data.data.remaining(), 4);
+        drop(data);
+        // Only those listed are large, the rest are placeholders.
+        let thmb = reader.next(&mut warnings).unwrap().unwrap();
+        assert_eq!((thmb.offset, thmb.data.remaining()), (24, 2));
+        drop(thmb);
+        assert!(matches!(
+            warnings[..],
+            [Warning::PlaceholderSize { available: 2, .. }]
+        ));
+    }
+    #[test]
+    fn placeholder_sizes() {
+        let bytes = container(&[(b"meta", 2, b"ab"), (b"thmb", u32::MAX, b"xyz")]);
+        // As far as the document goes, even if the container claims more.
+        let mut cursor = Cursor::new(&bytes);
+        let mut reader = ChunkReader::new(&mut cursor, 1000, 12).with_file_len(12 + 21);
+        let mut warnings = Vec::new();
+        drop(reader.next(&mut warnings).unwrap());
+        let thmb = reader.next(&mut warnings).unwrap().unwrap();
+        let mut data = Vec::new();
+        thmb.data.read_to_end(&mut data).unwrap();
+        assert_eq!(data, b"xyz");
+        drop(thmb);
+        assert!(reader.next(&mut warnings).unwrap().is_none());
+        assert_eq!(
+            warnings,
+            [
+                Warning::PlaceholderSize {
+                    fourcc: *b"thmb",
+                    offset: 22,
+                    available: 3
+                },
+                // Past the pad byte the document would have had.
+                Warning::Truncated {
+                    offset: 34,
+                    missing: 1000 - 22
+                },
+            ]
+        );
+        assert_eq!(
+            warnings[0].to_string(),
+            "chunk \"thmb\" at offset 22 has a placeholder size, as left by an interrupted save, \
+             so its remaining 3 bytes were used"
+        );
+        // As far as the container goes, if that's sooner.
+        let (chunks, warnings) = walk(&bytes, 20, 100);
+        assert_eq!(chunks[1].2, b"xy");
+        assert!(matches!(
+            warnings[..],
+            [Warning::PlaceholderSize { available: 2, .. }]
+        ));
+    }
+    #[test]
+    fn resyncs() {
+        let walk_resyncing = |bytes: &[u8]| {
+            let mut cursor = Cursor::new(bytes);
+            let mut reader =
+                ChunkReader::new(&mut cursor, bytes.len() as u64, 12).with_resync(true);
+            let mut chunks = Vec::new();
+            let mut warnings = Vec::new();
+            while let Some(chunk) = reader.next(&mut warnings).unwrap() {
+                let mut data = Vec::new();
+                chunk.data.read_to_end(&mut data).unwrap();
+                chunks.push((chunk.fourcc, chunk.offset, data));
+            }
+            (chunks, warnings)
+        };
+        // A size that can't fit.
+        let bytes = container(&[
+            (b"meta", 0x7fff_0000, &[0xff; 6]),
+            (b"thmb", 2, b"de"),
+            (b"icc ", 1, b"f"),
+        ]);
+        let (chunks, warnings) = walk(&bytes, bytes.len() as u64, 100);
+        assert_eq!(chunks.len(), 1);
+        assert!(matches!(warnings[..], [Warning::ChunkOverrun { .. }]));
+        let (chunks, warnings) = walk_resyncing(&bytes);
+        assert_eq!(
+            chunks,
+            [
+                (*b"thmb", 26, b"de".to_vec()),
+                (*b"icc ", 36, b"f".to_vec())
+            ]
+        );
+        assert_eq!(
+            warnings,
+            [Warning::Resynced {
+                offset: 12,
+                skipped: 14
+            }]
+        );
+        assert_eq!(
+            warnings[0].to_string(),
+            "chunk header at offset 12 is malformed, so 14 bytes were skipped to the next plausible one"
+        );
+
+        // A fourcc that isn't.
+        let bytes = container(&[(b"\0\x01ab", 2, b"\xfe\xfe"), (b"thmb", 2, b"de")]);
+        let (chunks, warnings) = walk_resyncing(&bytes);
+        assert_eq!(chunks, [(*b"thmb", 22, b"de".to_vec())]);
+        assert_eq!(
+            warnings,
+            [Warning::Resynced {
+                offset: 12,
+                skipped: 10
+            }]
+        );
+
+        // Nothing plausible, so it's taken as it is.
+        let bytes = container(&[(b"meta", 100, &[0xff; 6])]);
+        let (chunks, warnings) = walk_resyncing(&bytes);
+        assert_eq!(chunks, [(*b"meta", 12, vec![0xff; 6])]);
+        assert!(matches!(warnings[..], [Warning::ChunkOverrun { .. }]));
+    }
+    #[test]
+    fn big_endian() {
+        let mut bytes = container(&[(b"meta", 3, b"abc"), (b"thmb", 2, b"de")]);
+        bytes[4..8].reverse();
+        bytes[16..20].reverse();
+        let mut cursor = Cursor::new(&bytes);
+        let mut reader =
+            ChunkReader::new(&mut cursor, bytes.len() as u64, 12).with_byte_order(ByteOrder::Big);
+        let mut warnings = Vec::new();
+        let meta = reader.next(&mut warnings).unwrap().unwrap();
+        assert_eq!((meta.fourcc, meta.data.remaining()), (*b"meta", 3));
+        drop(meta);
+        let thmb = reader.next(&mut warnings).unwrap().unwrap();
+        assert_eq!((thmb.fourcc, thmb.offset), (*b"thmb", 24));
+        drop(thmb);
+        assert!(reader.next(&mut warnings).unwrap().is_none());
+        assert_eq!(warnings, []);
+        assert_eq!(ByteOrder::Big.u32([0, 0, 1, 2]), 258);
+        assert_eq!(ByteOrder::Little.u32([0, 0, 1, 2]), 0x0201_0000);
+    }
+    #[test]
+    fn nested() {
+        let list = [
+            b"meta".as_slice(),
+            &container(&[(b"name", 1, b"a"), (b"thmb", 2, b"bc")]),
+        ]
+        .concat();
+        let bytes = container(&[(b"LIST", list.len() as u32, &list), (b"hist", 1, b"d")]);
+        let mut cursor = Cursor::new(&bytes);
+        let mut reader = ChunkReader::new(&mut cursor, bytes.len() as u64, 12);
+        let mut warnings = Vec::new();
+
+        let list = reader.next(&mut warnings).unwrap().unwrap();
+        let mut list_type = [0; 4];
+        list.data.read_exact(&mut list_type).unwrap();
+        assert_eq!(&list_type, b"meta");
+        let len = list.data.remaining();
+        let mut inner = ChunkReader::new(&mut *list.data, len, list.offset + 12);
+        let name = inner.next(&mut warnings).unwrap().unwrap();
+        assert_eq!((name.fourcc, name.offset), (*b"name", 24));
+        drop(name);
+        // Skipping out of a list part way through still finds the next chunk after it.
+        drop(inner);
+        drop(list);
+        let hist = reader.next(&mut warnings).unwrap().unwrap();
+        assert_eq!((hist.fourcc, hist.offset), (*b"hist", 44));
+        drop(hist);
+        assert!(reader.next(&mut warnings).unwrap().is_none());
+        assert_eq!(warnings, []);
+    }
+}
@@ -0,0 +1,67 @@
+//! Documents wrapped in a compression format, such as archived `.fzp.zst`, which are
+//! decompressed into memory to be searched like any other.
+//!
+//! Only with the `zstd` feature, otherwise they're reported as not being documents at all.
+use crate::error::Error;
+use crate::BufReadSeek;
+
+/// Leading bytes of a zstd frame.
+pub const ZSTD_MAGIC: [u8; 4] = [0x28, 0xb5, 0x2f, 0xfd];
+
+/// Give up on compressed documents larger than this once decompressed. Documents must be
+/// seekable, so are decompressed whole, and this is far beyond where fuzzpaint puts thumbnails.
+#[cfg(feature = "zstd")]
+const MAX_DECOMPRESSED_BYTES: u64 = 32 * 1024 * 1024;
+
+/// If `input` is zstd-compressed, decompress it into memory, otherwise return it untouched.
+#[cfg(feature = "zstd")]
+pub fn decompress(mut input: Box<dyn BufReadSeek>) -> Result<Box<dyn BufReadSeek>, Error> {
+    use crate::error::Class;
+    use std::io::Read;
+    // The decoder reports anything wrong with the data as `Other`, rather than `InvalidData`.
+    let decompress_err = |io: std::io::Error| match io.kind() {
+        std::io::ErrorKind::Other => Error::new(
+            Class::Corrupt,
+            format!("failed to decompress input file: {io}"),
+        ),
+        _ => Error::from_input("failed to decompress input file", &io),
+    };
+    let start = input
+        .fill_buf()
+        .map_err(|io| Error::from_input("failed to read input file", &io))?;
+    if !start.starts_with(&ZSTD_MAGIC) {
+        return Ok(input);
+    }
+    let decoder = ruzstd::decoding::StreamingDecoder::new(input).map_err(|err| {
+        Error::new(
+            Class::Corrupt,
+            format!("failed to decompress input file: {err}"),
+        )
+    })?;
+    let mut data = Vec::new();
+    // One more than allowed, to tell if there was more.
+    decoder
+        .take(MAX_DECOMPRESSED_BYTES + 1)
+        .read_to_end(&mut data)
+        .map_err(decompress_err)?;
+    if data.len() as u64 > MAX_DECOMPRESSED_BYTES {
+        return Err(Error::new(
+            Class::Corrupt,
+            format!(
+                "compressed document too large to thumbnail, over {} MiB decompressed",
+                MAX_DECOMPRESSED_BYTES >> 20
+            ),
+        ));
+    }
+    crate::log::verbose(format_args!(
+        "decompressed {} bytes of zstd-compressed document",
+        data.len()
+    ));
+    Ok(Box::new(std::io::Cursor::new(data)))
+}
+
+/// Without the `zstd` feature, nothing is decompressed.
+#[cfg(not(feature = "zstd"))]
+pub fn decompress(input: Box<dyn BufReadSeek>) -> Result<Box<dyn BufReadSeek>, Error> {
+    Ok(input)
+}
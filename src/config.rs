@@ -0,0 +1,390 @@
+//! Option defaults from outside the command line.
+//!
+//! Desktop-wide defaults come from `$XDG_CONFIG_HOME/fuzzpaint-thumbnailer/config.toml`. Only the flat `key = value` subset of TOML is understood, which is all there is to set:
+//! ```toml
+//! filter = "lanczos3"
+//! compression = "best"
+//! no-upscale = false
+//! max-dimension = 2048
+//! threads = 2
+//! log-file = "/tmp/fuzzpaint-thumbnailer.log"
+//! ```
+//! Keys are named after their command line options.
+//!
+//! For callers that can't pass flags, `FUZZPAINT_THUMB_*` environment variables override the
+//! config file. The command line overrides both, see [`resolve`].
+use crate::args::{self, Compression, Filter};
+use crate::xdg;
+use std::borrow::Cow;
+use std::path::PathBuf;
+
+/// Name of our subdirectory within `$XDG_CONFIG_HOME`.
+const APP_NAME: &str = "fuzzpaint-thumbnailer";
+
+/// Environment variables, and the config key each overrides.
+const ENV_VARS: [(&str, &str); 6] = [
+    ("FUZZPAINT_THUMB_FILTER", "filter"),
+    ("FUZZPAINT_THUMB_MAX_DIM", "max-dimension"),
+    ("FUZZPAINT_THUMB_COMPRESSION", "compression"),
+    ("FUZZPAINT_THUMB_NO_UPSCALE", "no-upscale"),
+    ("FUZZPAINT_THUMB_LOG_FILE", "log-file"),
+    ("FUZZPAINT_THUMB_THREADS", "threads"),
+];
+
+/// Defaults for options not given on the command line, or `None` to use the built-in default.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Defaults {
+    pub filter: Option<Filter>,
+    pub compression: Option<Compression>,
+    pub no_upscale: Option<bool>,
+    pub max_dimension: Option<u32>,
+    pub threads: Option<std::num::NonZeroUsize>,
+    pub log_file: Option<PathBuf>,
+}
+
+/// Every source of defaults, from lowest precedence to highest.
+#[derive(Debug, PartialEq, Eq, Clone, Default)]
+pub struct Sources {
+    pub config: Defaults,
+    pub env: Defaults,
+}
+
+/// Layer the options given on the command line over those from the environment, over those
+/// from the config file. Whatever is still `None` takes the built-in default.
+pub fn resolve(cli: Defaults, sources: &Sources) -> Defaults {
+    let Sources { config, env } = sources;
+    Defaults {
+        filter: cli.filter.or(env.filter).or(config.filter),
+        compression: cli.compression.or(env.compression).or(config.compression),
+        no_upscale: cli.no_upscale.or(env.no_upscale).or(config.no_upscale),
+        max_dimension: cli
+            .max_dimension
+            .or(env.max_dimension)
+            .or(config.max_dimension),
+        threads: cli.threads.or(env.threads).or(config.threads),
+        log_file: cli
+            .log_file
+            .or_else(|| env.log_file.clone())
+            .or_else(|| config.log_file.clone()),
+    }
+}
+
+/// `$XDG_CONFIG_HOME/fuzzpaint-thumbnailer/config.toml`.
+pub fn path() -> Option<PathBuf> {
+    Some(xdg::config_dir()?.join(APP_NAME).join("config.toml"))
+}
+
+/// Read the config file, if there is one.
+///
+/// Returns the defaults it sets, and a description of each problem with it. Keys with problems
+/// are left at their built-in defaults.
+pub fn load() -> (Defaults, Vec<String>) {
+    let Some(path) = path() else {
+        return Default::default();
+    };
+    match std::fs::read_to_string(&path) {
+        Ok(text) => {
+            let (defaults, problems) = parse(&text);
+            let problems = problems
+                .into_iter()
+                .map(|problem| format!("config file {}: {problem}", path.display()))
+                .collect();
+            (defaults, problems)
+        }
+        // Entirely optional.
+        Err(io) if io.kind() == std::io::ErrorKind::NotFound => Default::default(),
+        Err(io) => (
+            Defaults::default(),
+            vec![format!(
+                "config file {}: failed to read: {io}",
+                path.display()
+            )],
+        ),
+    }
+}
+
+/// Read the `FUZZPAINT_THUMB_*` environment variables.
+///
+/// Returns the defaults they set, and a description of each problem with them. Variables with
+/// problems are left unset.
+pub fn from_env() -> (Defaults, Vec<String>) {
+    env_with(|var| std::env::var_os(var))
+}
+
+/// [`from_env`], looking variables up with `var`.
+fn env_with(var: impl Fn(&str) -> Option<std::ffi::OsString>) -> (Defaults, Vec<String>) {
+    let mut defaults = Defaults::default();
+    let mut problems = Vec::new();
+    for (name, key) in ENV_VARS {
+        // Set but empty is as good as unset.
+        let Some(value) = var(name).filter(|value| !value.is_empty()) else {
+            continue;
+        };
+        let Some(value) = value.to_str() else {
+            problems.push(format!("{name}: value is not valid UTF-8"));
+            continue;
+        };
+        let value = match (key, value) {
+            ("no-upscale", "1") => "true",
+            ("no-upscale", "0") => "false",
+            _ => value,
+        };
+        if let Err(message) = set(&mut defaults, key, value) {
+            problems.push(format!("{name}: {message}"));
+        }
+    }
+    (defaults, problems)
+}
+
+/// Parse the text of a config file. See [`load`].
+pub fn parse(text: &str) -> (Defaults, Vec<String>) {
+    let mut defaults = Defaults::default();
+    let mut problems = Vec::new();
+    let mut seen: Vec<&str> = Vec::new();
+    for (number, line) in text.lines().enumerate() {
+        let number = number + 1;
+        let line = strip_comment(line).trim();
+        if line.is_empty() {
+            continue;
+        }
+        if line.starts_with('[') {
+            problems.push(format!("line {number}: tables are not supported"));
+            continue;
+        }
+        let Some((key, value)) = line.split_once('=') else {
+            problems.push(format!("line {number}: expected `key = value`"));
+            continue;
+        };
+        let (key, value) = (key.trim(), value.trim());
+        if seen.contains(&key) {
+            problems.push(format!("line {number}: `{key}` given more than once"));
+            continue;
+        }
+        seen.push(key);
+        // The names are strings, the rest are bare.
+        let value = match key {
+            "filter" | "compression" | "log-file" => string(value),
+            _ => Ok(value),
+        };
+        if let Err(message) = value.and_then(|value| set(&mut defaults, key, value)) {
+            problems.push(format!("line {number}: `{key}`: {message}"));
+        }
+    }
+    (defaults, problems)
+}
+
+/// Set `key` from its unquoted `value`.
+fn set(defaults: &mut Defaults, key: &str, value: &str) -> Result<(), Cow<'static, str>> {
+    match key {
+        "filter" => {
+            defaults.filter = Some(args::parse_named("filter", &Filter::NAMED, value)?);
+        }
+        "compression" => {
+            defaults.compression = Some(args::parse_named(
+                "compression",
+                &Compression::NAMED,
+                value,
+            )?);
+        }
+        "no-upscale" => {
+            defaults.no_upscale = Some(match value {
+                "true" => true,
+                "false" => false,
+                _ => return Err("expected `true` or `false`".into()),
+            });
+        }
+        "max-dimension" => defaults.max_dimension = Some(args::parse_max_dimension(value)?),
+        "threads" => defaults.threads = Some(args::parse_threads(value)?),
+        "log-file" => defaults.log_file = Some(PathBuf::from(value)),
+        _ => return Err("unknown key".into()),
+    }
+    Ok(())
+}
+
+/// `line` up to any `#` that isn't within a string.
+fn strip_comment(line: &str) -> &str {
+    let mut in_string = false;
+    for (i, c) in line.char_indices() {
+        match c {
+            '"' => in_string = !in_string,
+            '#' if !in_string => return &line[..i],
+            _ => (),
+        }
+    }
+    line
+}
+
+/// The contents of a double-quoted string value. None of our values need escapes.
+fn string(value: &str) -> Result<&str, Cow<'static, str>> {
+    match value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+    {
+        Some(value) if value.contains(['"', '\\']) => Err("escapes are not supported".into()),
+        Some(value) => Ok(value),
+        None => Err("expected a double-quoted string".into()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn every_key() {
+        let (defaults, problems) = parse(
+            "# Desktop policy\n\
+             filter = \"lanczos3\"  # sharper\n\
+             \n\
+             compression = \"best\"\n\
+             no-upscale = true\n\
+             max-dimension = 2048\n\
+             threads = 3\n\
+             log-file = \"/tmp/thumbs.log\"\n",
+        );
+        assert_eq!(problems, Vec::<String>::new());
+        assert_eq!(
+            defaults,
+            Defaults {
+                filter: Some(Filter::Lanczos3),
+                compression: Some(Compression::Best),
+                no_upscale: Some(true),
+                max_dimension: Some(2048),
+                threads: std::num::NonZeroUsize::new(3),
+                log_file: Some("/tmp/thumbs.log".into()),
+            }
+        );
+        assert_eq!(parse(""), (Defaults::default(), Vec::new()));
+    }
+    #[test]
+    fn layering() {
+        let sources = Sources {
+            config: Defaults {
+                filter: Some(Filter::Box),
+                compression: Some(Compression::Best),
+                no_upscale: Some(true),
+                max_dimension: None,
+                threads: std::num::NonZeroUsize::new(8),
+                log_file: Some("config.log".into()),
+            },
+            env: Defaults {
+                filter: Some(Filter::Nearest),
+                compression: None,
+                no_upscale: Some(false),
+                max_dimension: Some(512),
+                threads: std::num::NonZeroUsize::new(2),
+                log_file: None,
+            },
+        };
+        let cli = Defaults {
+            no_upscale: Some(true),
+            ..Defaults::default()
+        };
+        assert_eq!(
+            resolve(cli, &sources),
+            Defaults {
+                // Environment over config.
+                filter: Some(Filter::Nearest),
+                // Config alone.
+                compression: Some(Compression::Best),
+                // Command line over both.
+                no_upscale: Some(true),
+                // Environment alone.
+                max_dimension: Some(512),
+                threads: std::num::NonZeroUsize::new(2),
+                log_file: Some("config.log".into()),
+            }
+        );
+        // Nothing anywhere is left for the built-in default.
+        assert_eq!(
+            resolve(Defaults::default(), &Sources::default()),
+            Defaults::default()
+        );
+    }
+    #[test]
+    fn env() {
+        let (defaults, problems) = env_with(|var| match var {
+            "FUZZPAINT_THUMB_FILTER" => Some("catmullrom".into()),
+            "FUZZPAINT_THUMB_MAX_DIM" => Some("4096".into()),
+            "FUZZPAINT_THUMB_COMPRESSION" => Some("balanced".into()),
+            "FUZZPAINT_THUMB_NO_UPSCALE" => Some("1".into()),
+            "FUZZPAINT_THUMB_LOG_FILE" => Some("/tmp/thumbs.log".into()),
+            "FUZZPAINT_THUMB_THREADS" => Some("4".into()),
+            _ => None,
+        });
+        assert_eq!(problems, Vec::<String>::new());
+        assert_eq!(
+            defaults,
+            Defaults {
+                filter: Some(Filter::CatmullRom),
+                compression: Some(Compression::Balanced),
+                no_upscale: Some(true),
+                max_dimension: Some(4096),
+                threads: std::num::NonZeroUsize::new(4),
+                log_file: Some("/tmp/thumbs.log".into()),
+            }
+        );
+
+        let (defaults, problems) = env_with(|var| match var {
+            // Quoted like the config file, which isn't how the environment works.
+            "FUZZPAINT_THUMB_FILTER" => Some("\"box\"".into()),
+            "FUZZPAINT_THUMB_MAX_DIM" => Some("".into()),
+            "FUZZPAINT_THUMB_COMPRESSION" => Some("best".into()),
+            "FUZZPAINT_THUMB_NO_UPSCALE" => Some("maybe".into()),
+            "FUZZPAINT_THUMB_THREADS" => Some("0".into()),
+            _ => None,
+        });
+        assert_eq!(
+            defaults,
+            Defaults {
+                compression: Some(Compression::Best),
+                ..Defaults::default()
+            }
+        );
+        assert_eq!(
+            problems,
+            [
+                "FUZZPAINT_THUMB_FILTER: unknown filter `\"box\"`, expected one of: nearest, bilinear, catmullrom, lanczos3, box",
+                "FUZZPAINT_THUMB_NO_UPSCALE: expected `true` or `false`",
+                "FUZZPAINT_THUMB_THREADS: <threads> parameter must be a positive integer",
+            ]
+        );
+    }
+    #[test]
+    fn problems_skip_only_their_key() {
+        let (defaults, problems) = parse(
+            "compression = best\n\
+             filter = \"box\"\n\
+             no-upscale = yes\n\
+             max-dimension = 0\n\
+             colour = \"red\"\n\
+             [section]\n\
+             garbage\n\
+             filter = \"nearest\"\n",
+        );
+        assert_eq!(
+            defaults,
+            Defaults {
+                filter: Some(Filter::Box),
+                ..Defaults::default()
+            }
+        );
+        assert_eq!(
+            problems,
+            [
+                "line 1: `compression`: expected a double-quoted string",
+                "line 3: `no-upscale`: expected `true` or `false`",
+                "line 4: `max-dimension`: <max-dimension> parameter must be an integer in 1..=8192",
+                "line 5: `colour`: unknown key",
+                "line 6: tables are not supported",
+                "line 7: expected `key = value`",
+                "line 8: `filter` given more than once",
+            ]
+        );
+        let (_, problems) = parse("compression = \"smallest\"");
+        assert_eq!(
+            problems,
+            ["line 1: `compression`: unknown compression `smallest`, expected one of: fast, balanced, best"]
+        );
+    }
+}
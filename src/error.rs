@@ -0,0 +1,76 @@
+//! Failures, classified by what a caller might want to do about them.
+use std::borrow::Cow;
+use std::io::{Error as IOError, ErrorKind as IOErrorKind};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Class {
+    /// The command line doesn't make sense.
+    Usage,
+    /// The document is readable, it just doesn't have a thumbnail.
+    NoThumbnail,
+    /// The document or its thumbnail is malformed.
+    Corrupt,
+    /// Failed to read the input.
+    InputIo,
+    /// Failed to write the output.
+    OutputIo,
+    /// The input changed while it was being read, so may well not next time.
+    Transient,
+}
+
+impl Class {
+    /// Exit status for failing this way, as documented in `--help`.
+    pub fn exit_code(self) -> std::process::ExitCode {
+        std::process::ExitCode::from(match self {
+            Self::Usage => 2,
+            Self::NoThumbnail => 3,
+            Self::Corrupt => 4,
+            Self::InputIo => 5,
+            Self::OutputIo => 6,
+            Self::Transient => 7,
+        })
+    }
+    /// Whether failing this way is down to the document, so that this version of it is sure to
+    /// fail again, and is worth a fail marker. Not the output, the command line, or the disk.
+    pub fn marks_failed(self) -> bool {
+        matches!(self, Self::NoThumbnail | Self::Corrupt)
+    }
+}
+
+#[derive(Debug)]
+pub struct Error {
+    pub class: Class,
+    pub message: Cow<'static, str>,
+}
+impl Error {
+    pub fn new(class: Class, message: impl Into<Cow<'static, str>>) -> Self {
+        Self {
+            class,
+            message: message.into(),
+        }
+    }
+    /// Classify an error from reading the document.
+    ///
+    /// Malformed data is reported as [`IOErrorKind::InvalidData`] or
+    /// [`IOErrorKind::UnexpectedEof`], anything else is a failure to read.
+    pub fn from_io(io: &IOError) -> Self {
+        let class = match io.kind() {
+            IOErrorKind::InvalidData | IOErrorKind::UnexpectedEof => Class::Corrupt,
+            _ => Class::InputIo,
+        };
+        Self::new(class, io.to_string())
+    }
+    /// [`Error::from_io`], prefixing the message with `context`.
+    pub fn from_input(context: &str, io: &IOError) -> Self {
+        Self::from_io(io).context(context)
+    }
+    /// Prefix the message with `context`, keeping the class.
+    pub fn context(self, context: &str) -> Self {
+        Self::new(self.class, format!("{context}: {}", self.message))
+    }
+}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.message)
+    }
+}
@@ -0,0 +1,511 @@
+//! Computing the dimensions of the output thumbnail, and how to scale to them.
+use crate::args::{Crop, Filter, Size};
+use std::num::NonZeroU32;
+
+/// `len`, or a single pixel if it's zero.
+fn at_least_one(len: u32) -> NonZeroU32 {
+    NonZeroU32::new(len).unwrap_or(NonZeroU32::MIN)
+}
+
+/// `len * to / from`, rounded to nearest with halves up.
+///
+/// All scaled dimensions are worked out with this, in exact integer math rather than floats, so
+/// that the same document always gives the same size of thumbnail, whatever it's run on.
+fn scale_len(len: u64, to: u64, from: u64) -> u64 {
+    (len * to + from / 2) / from
+}
+
+/// Scale `width`x`height` to fit inside `bounds`, preserving aspect ratio.
+///
+/// The most constrained axis fills `bounds` exactly, and the other is scaled by as much with
+/// [`scale_len`]. Never overflowing `bounds`, nor shrinking to nothing: an axis that would round
+/// to zero is kept at a pixel, as are zero `bounds`.
+pub fn fit(width: NonZeroU32, height: NonZeroU32, bounds: Size) -> (NonZeroU32, NonZeroU32) {
+    let (width, height) = (u64::from(width.get()), u64::from(height.get()));
+    let (bw, bh) = (
+        u64::from(at_least_one(bounds.width).get()),
+        u64::from(at_least_one(bounds.height).get()),
+    );
+    // Never less than a pixel however extreme the aspect ratio.
+    let (scaled_width, scaled_height) = if bw * height <= bh * width {
+        // Width decides the scale.
+        (bw, scale_len(height, bw, width).clamp(1, bh))
+    } else {
+        (scale_len(width, bh, height).clamp(1, bw), bh)
+    };
+    // OK - clamped to within the (u32) bounds above.
+    (
+        at_least_one(scaled_width as u32),
+        at_least_one(scaled_height as u32),
+    )
+}
+
+/// A non-empty rectangle within an image.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub struct Region {
+    pub left: u32,
+    pub top: u32,
+    pub width: NonZeroU32,
+    pub height: NonZeroU32,
+}
+impl Region {
+    /// All of a `width`x`height` image.
+    pub fn whole(width: NonZeroU32, height: NonZeroU32) -> Self {
+        Self {
+            left: 0,
+            top: 0,
+            width,
+            height,
+        }
+    }
+}
+
+/// Scale `region` to fill `bounds` exactly, preserving aspect ratio by trimming whichever axis
+/// overflows evenly from both sides.
+///
+/// Returns the trimmed part of `region` to scale, and the size to scale it to. Zero `bounds` are
+/// taken as a pixel, as in [`fit`].
+pub fn cover(region: Region, bounds: Size) -> (Region, (NonZeroU32, NonZeroU32)) {
+    let bounds_width = at_least_one(bounds.width);
+    let bounds_height = at_least_one(bounds.height);
+    let (width, height) = (
+        u64::from(region.width.get()),
+        u64::from(region.height.get()),
+    );
+    let (bw, bh) = (
+        u64::from(bounds_width.get()),
+        u64::from(bounds_height.get()),
+    );
+    // The axis that decides the scale is kept whole exactly, and the other never less than a
+    // pixel however extreme the aspect ratio.
+    let (kept_width, kept_height) = if bw * height >= bh * width {
+        // Width decides the scale, the top and bottom overflow.
+        (width, scale_len(width, bh, bw).clamp(1, height))
+    } else {
+        (scale_len(height, bw, bh).clamp(1, width), height)
+    };
+    // OK - clamped to within the (u32) region above.
+    let kept = Region {
+        left: region.left + ((width - kept_width) / 2) as u32,
+        top: region.top + ((height - kept_height) / 2) as u32,
+        width: at_least_one(kept_width as u32),
+        height: at_least_one(kept_height as u32),
+    };
+    (kept, (bounds_width, bounds_height))
+}
+
+/// Clamp `crop` to the bounds of a `width`x`height` image.
+///
+/// Returns the clamped region, or `None` if nothing is left of it, and whether any clamping
+/// was needed.
+pub fn clamp_crop(crop: Crop, width: NonZeroU32, height: NonZeroU32) -> (Option<Region>, bool) {
+    let clamp_axis = |start: u32, len: u32, limit: NonZeroU32| {
+        let start = start.min(limit.get());
+        let end = start.saturating_add(len).min(limit.get());
+        (start, end - start)
+    };
+    let (left, clamped_width) = clamp_axis(crop.x, crop.width, width);
+    let (top, clamped_height) = clamp_axis(crop.y, crop.height, height);
+    let clamped =
+        (left, top, clamped_width, clamped_height) != (crop.x, crop.y, crop.width, crop.height);
+    let region = NonZeroU32::new(clamped_width)
+        .zip(NonZeroU32::new(clamped_height))
+        .map(|(width, height)| Region {
+            left,
+            top,
+            width,
+            height,
+        });
+    (region, clamped)
+}
+
+/// How far from 1 the scale may be on either axis and still be taken as no scaling at all, as one
+/// part in this many, where nearest-neighbour only skips or repeats one pixel in a hundred.
+const IDENTITY_TOLERANCE_PARTS: u64 = 100;
+
+/// The filter best suited to scaling `width`x`height` to `scaled_width`x`scaled_height`.
+///
+/// Nearest-neighbour when hardly scaled, as any other filter would only blur it. Catmull-Rom
+/// for enlarging, and shrinking to as little as half size, where it's sharper than bilinear.
+/// Lanczos3 for shrinking any further, where anything narrower aliases.
+pub fn choose_filter(
+    (width, height): (NonZeroU32, NonZeroU32),
+    (scaled_width, scaled_height): (NonZeroU32, NonZeroU32),
+) -> Filter {
+    // Compared as integers, as the dimensions are, so the choice is as reproducible.
+    let axes = [(width, scaled_width), (height, scaled_height)]
+        .map(|(from, to)| (u64::from(from.get()), u64::from(to.get())));
+    if axes
+        .iter()
+        .all(|&(from, to)| from.abs_diff(to) * IDENTITY_TOLERANCE_PARTS <= to)
+    {
+        Filter::Nearest
+    } else if axes.iter().all(|&(from, to)| from <= 2 * to) {
+        Filter::CatmullRom
+    } else {
+        Filter::Lanczos3
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fit_ints(width: u32, height: u32, bounds: (u32, u32)) -> (u32, u32) {
+        let (width, height) = fit(
+            NonZeroU32::new(width).unwrap(),
+            NonZeroU32::new(height).unwrap(),
+            Size {
+                width: bounds.0,
+                height: bounds.1,
+            },
+        );
+        (width.get(), height.get())
+    }
+
+    #[test]
+    fn chooses_filter_by_ratio() {
+        let choose = |from: (u32, u32), to: (u32, u32)| {
+            let size = |(width, height)| {
+                (
+                    NonZeroU32::new(width).unwrap(),
+                    NonZeroU32::new(height).unwrap(),
+                )
+            };
+            choose_filter(size(from), size(to))
+        };
+        // Unscaled, or close enough.
+        assert_eq!(choose((256, 256), (256, 256)), Filter::Nearest);
+        assert_eq!(choose((1000, 500), (991, 500)), Filter::Nearest);
+        assert_eq!(choose((1000, 500), (1010, 496)), Filter::Nearest);
+        assert_eq!(choose((1000, 500), (980, 500)), Filter::CatmullRom);
+        // Exactly at the tolerance, one pixel in a hundred of the output, either side of it.
+        assert_eq!(choose((101, 100), (100, 100)), Filter::Nearest);
+        assert_eq!(choose((99, 100), (100, 100)), Filter::Nearest);
+        assert_eq!(choose((100, 100), (99, 100)), Filter::CatmullRom);
+        // Mild shrinking, up to half size.
+        assert_eq!(choose((300, 200), (256, 171)), Filter::CatmullRom);
+        assert_eq!(choose((512, 512), (256, 256)), Filter::CatmullRom);
+        assert_eq!(choose((513, 512), (256, 256)), Filter::Lanczos3);
+        // Far more, as from 1024 to 128.
+        assert_eq!(choose((1024, 1024), (128, 128)), Filter::Lanczos3);
+        assert_eq!(choose((4096, 64), (2048, 1)), Filter::Lanczos3);
+        // Enlarging.
+        assert_eq!(choose((16, 9), (320, 180)), Filter::CatmullRom);
+        // Whichever axis shrinks most decides, even if the other grows.
+        assert_eq!(choose((64, 1024), (128, 128)), Filter::Lanczos3);
+    }
+    #[test]
+    fn square() {
+        assert_eq!(fit_ints(1024, 1024, (256, 256)), (256, 256));
+        assert_eq!(fit_ints(1024, 512, (256, 256)), (256, 128));
+        assert_eq!(fit_ints(512, 1024, (256, 256)), (128, 256));
+    }
+    #[test]
+    fn boxed() {
+        // Width-constrained
+        assert_eq!(fit_ints(1024, 1024, (320, 180)), (180, 180));
+        assert_eq!(fit_ints(1920, 1080, (320, 180)), (320, 180));
+        // Height-constrained
+        assert_eq!(fit_ints(1080, 1920, (320, 180)), (101, 180));
+        // Upscale
+        assert_eq!(fit_ints(16, 9, (320, 180)), (320, 180));
+    }
+    #[test]
+    fn never_exceeds_bounds() {
+        // Every shape up to 1024 on a side, in a few boxes, one side of each filled exactly and
+        // the other within a pixel of the aspect ratio.
+        for bounds in [
+            (1, 1),
+            (7, 7),
+            (128, 128),
+            (256, 256),
+            (300, 200),
+            (97, 1000),
+        ] {
+            for width in 1..=1024 {
+                for height in 1..=1024 {
+                    let (scaled_width, scaled_height) = fit_ints(width, height, bounds);
+                    let context = format!("{width}x{height} in {bounds:?}");
+                    assert!(scaled_width <= bounds.0, "{context}");
+                    assert!(scaled_height <= bounds.1, "{context}");
+                    assert!(
+                        scaled_width == bounds.0 || scaled_height == bounds.1,
+                        "{context}"
+                    );
+                    // Against the exact size of the axis derived from the one that decides the
+                    // scale. Within a pixel even where that's clamped up to one.
+                    let width_decides = bounds.0 * height <= bounds.1 * width;
+                    let (width, height) = (f64::from(width), f64::from(height));
+                    let error = if width_decides {
+                        f64::from(scaled_height) - height * f64::from(scaled_width) / width
+                    } else {
+                        f64::from(scaled_width) - width * f64::from(scaled_height) / height
+                    };
+                    assert!(error.abs() < 1.0, "{context}: off by {error}");
+                }
+            }
+        }
+        // As a float scale used to overflow.
+        assert_eq!(fit_ints(750, 1000, (256, 256)), (192, 256));
+        assert_eq!(fit_ints(1000, 750, (256, 256)), (256, 192));
+    }
+    #[test]
+    fn extreme_boxes() {
+        assert_eq!(fit_ints(1024, 1024, (2048, 1)), (1, 1));
+        assert_eq!(fit_ints(1024, 1024, (1, 2048)), (1, 1));
+        assert_eq!(fit_ints(1024, 512, (2048, 1)), (2, 1));
+        assert_eq!(fit_ints(512, 1024, (1, 2048)), (1, 2));
+        assert_eq!(fit_ints(1, 1024, (2048, 1)), (1, 1));
+        assert_eq!(fit_ints(1024, 1, (2048, 1)), (1024, 1));
+        assert_eq!(fit_ints(1024, 1, (2048, 2048)), (2048, 2));
+    }
+    #[test]
+    fn golden_sizes() {
+        // Awkward shapes, whose sizes mustn't change from build to build or machine to machine.
+        for (source, bounds, expected) in [
+            ((750, 1000), (256, 256), (192, 256)),
+            ((1000, 750), (256, 256), (256, 192)),
+            ((333, 1000), (256, 256), (85, 256)),
+            ((1920, 1080), (256, 256), (256, 144)),
+            ((1080, 1920), (128, 128), (72, 128)),
+            ((4097, 4095), (128, 128), (128, 128)),
+            ((999, 1000), (100, 100), (100, 100)),
+            ((1001, 1000), (100, 100), (100, 100)),
+            ((3, 2), (5, 5), (5, 3)),
+            ((1, 3), (2, 2), (1, 2)),
+            // Exactly half a pixel rounds up.
+            ((2, 4), (3, 3), (2, 3)),
+            ((16384, 9), (1024, 1024), (1024, 1)),
+            ((5, 1000), (256, 256), (1, 256)),
+            ((6, 1000), (256, 256), (2, 256)),
+            ((641, 479), (320, 240), (320, 239)),
+            ((700, 300), (320, 180), (320, 137)),
+            ((7, 3), (1024, 1024), (1024, 439)),
+            ((65535, 65521), (1024, 1024), (1024, 1024)),
+            ((65521, 65535), (1024, 1024), (1024, 1024)),
+        ] {
+            assert_eq!(
+                fit_ints(source.0, source.1, bounds),
+                expected,
+                "{source:?} {bounds:?}"
+            );
+        }
+    }
+    #[test]
+    fn monotonic() {
+        // Growing either side of the source never shrinks that side of the thumbnail, nor grows
+        // the other, and a larger box never gives a smaller thumbnail.
+        for bounds in [(64, 64), (256, 256), (320, 180)] {
+            for height in [1, 7, 100, 333, 1000] {
+                let sizes: Vec<_> = (1..=2000)
+                    .map(|width| fit_ints(width, height, bounds))
+                    .collect();
+                for pair in sizes.windows(2) {
+                    assert!(
+                        pair[0].0 <= pair[1].0,
+                        "{pair:?} tall {height} in {bounds:?}"
+                    );
+                    assert!(
+                        pair[0].1 >= pair[1].1,
+                        "{pair:?} tall {height} in {bounds:?}"
+                    );
+                    assert!(pair[1].0 <= bounds.0 && pair[1].1 <= bounds.1);
+                }
+                let sizes: Vec<_> = (1..=2000)
+                    .map(|width| fit_ints(height, width, bounds))
+                    .collect();
+                for pair in sizes.windows(2) {
+                    assert!(
+                        pair[0].1 <= pair[1].1,
+                        "{pair:?} wide {height} in {bounds:?}"
+                    );
+                    assert!(
+                        pair[0].0 >= pair[1].0,
+                        "{pair:?} wide {height} in {bounds:?}"
+                    );
+                }
+            }
+        }
+        for source in [(1, 1), (3, 2), (750, 1000), (1920, 1080), (1, 4000)] {
+            let sizes: Vec<_> = (1..=1024)
+                .map(|size| fit_ints(source.0, source.1, (size, size)))
+                .collect();
+            for pair in sizes.windows(2) {
+                assert!(
+                    pair[0].0 <= pair[1].0 && pair[0].1 <= pair[1].1,
+                    "{pair:?} {source:?}"
+                );
+            }
+        }
+    }
+    #[test]
+    fn thin_sources() {
+        let nonzero = |len| NonZeroU32::new(len).unwrap();
+        for size in [1, 2, 16, 128, 256, 1024] {
+            for len in [1, 2, 3, 100, 1023, 1024, 4096, 65535] {
+                // Scaled along to fill the box, and across by as much, which rounds to nothing
+                // when shrinking far enough, so is kept at a pixel.
+                let across = ((size + len / 2) / len).max(1);
+                assert_eq!(
+                    fit_ints(1, len, (size, size)),
+                    (across, size),
+                    "1x{len} in {size}"
+                );
+                assert_eq!(
+                    fit_ints(len, 1, (size, size)),
+                    (size, across),
+                    "{len}x1 in {size}"
+                );
+                // Covering keeps a square of it, however thin.
+                let bounds = Size::square(size);
+                let (kept, scaled) = cover(Region::whole(nonzero(1), nonzero(len)), bounds);
+                assert_eq!((kept.width.get(), kept.height.get()), (1, 1));
+                assert_eq!(
+                    (kept.top, scaled),
+                    ((len - 1) / 2, (nonzero(size), nonzero(size)))
+                );
+                let (kept, _) = cover(Region::whole(nonzero(len), nonzero(1)), bounds);
+                assert_eq!(
+                    (kept.left, kept.width.get(), kept.height.get()),
+                    ((len - 1) / 2, 1, 1)
+                );
+            }
+        }
+        // And zero bounds, which can't be parsed, still give a pixel.
+        assert_eq!(fit_ints(1, 1024, (0, 0)), (1, 1));
+    }
+    #[test]
+    fn crop_clamping() {
+        let clamp = |(x, y, width, height), (image_width, image_height)| {
+            let (region, clamped) = clamp_crop(
+                Crop {
+                    x,
+                    y,
+                    width,
+                    height,
+                },
+                NonZeroU32::new(image_width).unwrap(),
+                NonZeroU32::new(image_height).unwrap(),
+            );
+            let region = region.map(|r| (r.left, r.top, r.width.get(), r.height.get()));
+            (region, clamped)
+        };
+        // Inside
+        assert_eq!(clamp((8, 4, 16, 8), (64, 32)), (Some((8, 4, 16, 8)), false));
+        assert_eq!(
+            clamp((0, 0, 64, 32), (64, 32)),
+            (Some((0, 0, 64, 32)), false)
+        );
+        // Overhanging
+        assert_eq!(
+            clamp((48, 24, 32, 32), (64, 32)),
+            (Some((48, 24, 16, 8)), true)
+        );
+        assert_eq!(
+            clamp((0, 0, u32::MAX, u32::MAX), (64, 32)),
+            (Some((0, 0, 64, 32)), true)
+        );
+        // Entirely outside, or empty
+        assert_eq!(clamp((64, 0, 8, 8), (64, 32)), (None, true));
+        assert_eq!(clamp((100, 100, 8, 8), (64, 32)), (None, true));
+        assert_eq!(clamp((0, 0, 0, 8), (64, 32)), (None, false));
+    }
+    #[test]
+    fn cropped_fit() {
+        // The crop's aspect decides the fit, not the image's.
+        let fit_crop = |crop, image: (u32, u32), bounds| {
+            let (region, _) = clamp_crop(
+                crop,
+                NonZeroU32::new(image.0).unwrap(),
+                NonZeroU32::new(image.1).unwrap(),
+            );
+            let region = region.unwrap();
+            fit_ints(region.width.get(), region.height.get(), bounds)
+        };
+        let crop = |x, y, width, height| Crop {
+            x,
+            y,
+            width,
+            height,
+        };
+        assert_eq!(
+            fit_crop(crop(0, 0, 256, 128), (1024, 1024), (64, 64)),
+            (64, 32)
+        );
+        assert_eq!(
+            fit_crop(crop(0, 0, 128, 256), (1024, 1024), (64, 64)),
+            (32, 64)
+        );
+        assert_eq!(
+            fit_crop(crop(512, 0, 1024, 1024), (1024, 512), (64, 64)),
+            (64, 64)
+        );
+        assert_eq!(
+            fit_crop(crop(10, 10, 20, 10), (64, 64), (200, 50)),
+            (100, 50)
+        );
+    }
+    #[test]
+    fn cover_trims_overflow() {
+        let cover_ints = |(left, top, width, height), bounds: (u32, u32)| {
+            let region = Region {
+                left,
+                top,
+                width: NonZeroU32::new(width).unwrap(),
+                height: NonZeroU32::new(height).unwrap(),
+            };
+            let (kept, (out_width, out_height)) = cover(
+                region,
+                Size {
+                    width: bounds.0,
+                    height: bounds.1,
+                },
+            );
+            assert_eq!((out_width.get(), out_height.get()), bounds);
+            (kept.left, kept.top, kept.width.get(), kept.height.get())
+        };
+        // Same aspect, nothing trimmed.
+        assert_eq!(cover_ints((0, 0, 512, 256), (64, 32)), (0, 0, 512, 256));
+        assert_eq!(cover_ints((0, 0, 10, 10), (64, 64)), (0, 0, 10, 10));
+        // Wide and tall into a square.
+        assert_eq!(cover_ints((0, 0, 1024, 512), (64, 64)), (256, 0, 512, 512));
+        assert_eq!(cover_ints((0, 0, 512, 1024), (64, 64)), (0, 256, 512, 512));
+        // Square into wide and tall.
+        assert_eq!(cover_ints((0, 0, 100, 100), (200, 50)), (0, 37, 100, 25));
+        assert_eq!(cover_ints((0, 0, 100, 100), (50, 200)), (37, 0, 25, 100));
+        // An odd leftover pixel is trimmed from the far side.
+        assert_eq!(cover_ints((0, 0, 5, 2), (2, 2)), (1, 0, 2, 2));
+        // Relative to the region.
+        assert_eq!(
+            cover_ints((10, 20, 1024, 512), (64, 64)),
+            (266, 20, 512, 512)
+        );
+    }
+    #[test]
+    fn cover_extremes() {
+        let cover_ints = |(width, height), bounds: (u32, u32)| {
+            let region = Region::whole(
+                NonZeroU32::new(width).unwrap(),
+                NonZeroU32::new(height).unwrap(),
+            );
+            let (kept, _) = cover(
+                region,
+                Size {
+                    width: bounds.0,
+                    height: bounds.1,
+                },
+            );
+            (kept.left, kept.top, kept.width.get(), kept.height.get())
+        };
+        assert_eq!(cover_ints((1000, 10), (64, 64)), (495, 0, 10, 10));
+        assert_eq!(cover_ints((10, 1000), (64, 64)), (0, 495, 10, 10));
+        assert_eq!(cover_ints((1024, 1), (1024, 1024)), (511, 0, 1, 1));
+        // Less than a pixel would be kept, so keep a pixel.
+        assert_eq!(cover_ints((10, 1000), (1024, 1)), (0, 499, 10, 1));
+        assert_eq!(cover_ints((1000, 10), (1, 1024)), (499, 0, 1, 10));
+        assert_eq!(cover_ints((1, 1), (crate::args::MAX_SIZE, 1)), (0, 0, 1, 1));
+        // Nor an empty box.
+        assert_eq!(cover_ints((100, 10), (0, 1)), (45, 0, 10, 10));
+    }
+}
@@ -0,0 +1,56 @@
+//! Thumbnails stored in formats besides QOI and PNG, as some third-party exporters write JPEG or
+//! WebP previews, decoded by the `image` crate.
+//!
+//! Only with the `extra-formats` feature, otherwise they're reported as QOI images with the wrong
+//! magic.
+#[cfg(feature = "extra-formats")]
+use crate::error::{Class, Error};
+
+/// Decode a whole thumbnail in whichever format its bytes say, to RGBA, held to the same limits
+/// as a QOI thumbnail. Taken to be sRGB, unless `colorspace` says otherwise.
+#[cfg(feature = "extra-formats")]
+pub fn decode(
+    data: &[u8],
+    max_dimension: u32,
+    max_encoded_ratio: u64,
+    colorspace: Option<qoi::ColorSpace>,
+) -> Result<crate::DecodedImage, Error> {
+    let image_err = |context, err| match err {
+        image::ImageError::IoError(io) => Error::from_input(context, &io),
+        other => Error::new(Class::Corrupt, format!("{context}: {other}")),
+    };
+    let format = image::guess_format(data).map_err(|_| {
+        Error::new(
+            Class::Corrupt,
+            "failed to parse thumbnail header: not QOI, PNG, or any other known format",
+        )
+    })?;
+    let mut reader = image::ImageReader::with_format(std::io::Cursor::new(data), format);
+    let mut limits = image::Limits::default();
+    limits.max_image_width = Some(crate::MAX_INPUT_IMAGE_SIDE);
+    limits.max_image_height = Some(crate::MAX_INPUT_IMAGE_SIDE);
+    // The largest image allowed, as RGBA.
+    limits.max_alloc = Some(u64::from(max_dimension).pow(2) * 4);
+    reader.limits(limits);
+    // Formats without the feature for them are only recognized, so fail here.
+    let decoder = reader
+        .into_decoder()
+        .map_err(|err| image_err("failed to parse thumbnail header", err))?;
+    let (width, height) = image::ImageDecoder::dimensions(&decoder);
+    let header = qoi::Header {
+        width,
+        height,
+        channels: qoi::Channels::Rgba,
+        // Nothing these formats store says otherwise.
+        colorspace: colorspace.unwrap_or(qoi::ColorSpace::Srgb),
+    };
+    let size = crate::check_size(&header, max_dimension)?;
+    crate::check_len(&header, data.len() as u64, max_encoded_ratio)?;
+    let rgba = image::DynamicImage::from_decoder(decoder)
+        .map_err(|err| image_err("failed to parse thumbnail data", err))?
+        .into_rgba8();
+    crate::decode_qoi_with(header, size, header.n_bytes(), |buf| {
+        buf.copy_from_slice(&rgba);
+        Ok(())
+    })
+}
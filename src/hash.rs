@@ -0,0 +1,79 @@
+//! Hashing output as it's written, for `--print-hash`.
+use sha2::{Digest, Sha256};
+use std::io::{Result as IOResult, Write};
+
+/// Passes writes through to `W`, hashing exactly the bytes it accepted.
+pub struct Sha256Writer<W> {
+    inner: W,
+    hasher: Sha256,
+}
+impl<W> Sha256Writer<W> {
+    pub fn new(inner: W) -> Self {
+        Self {
+            inner,
+            hasher: Sha256::new(),
+        }
+    }
+    /// Lowercase hex digest of everything written so far.
+    pub fn finish(self) -> String {
+        self.hasher
+            .finalize()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+}
+impl<W: Write> Write for Sha256Writer<W> {
+    fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+        let written = self.inner.write(buf)?;
+        // Short writes are retried by the caller, don't hash what wasn't taken.
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+    fn flush(&mut self) -> IOResult<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Accepts at most 3 bytes per write.
+    struct Trickle(Vec<u8>);
+    impl Write for Trickle {
+        fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+            let len = buf.len().min(3);
+            self.0.extend_from_slice(&buf[..len]);
+            Ok(len)
+        }
+        fn flush(&mut self) -> IOResult<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn known_vectors() {
+        // FIPS 180-2 examples.
+        let mut writer = Sha256Writer::new(Vec::new());
+        writer.write_all(b"abc").unwrap();
+        assert_eq!(
+            writer.finish(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+
+        let mut writer = Sha256Writer::new(Trickle(Vec::new()));
+        let message = b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq";
+        writer.write_all(message).unwrap();
+        assert_eq!(writer.inner.0, message);
+        assert_eq!(
+            writer.finish(),
+            "248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+
+        assert_eq!(
+            Sha256Writer::new(std::io::sink()).finish(),
+            "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+    }
+}
@@ -0,0 +1,165 @@
+//! ICC colour profiles, carried from a document's `icc ` chunk into the thumbnail's `iCCP`.
+use std::io::{Read, Write};
+
+/// Largest profile carried over, in bytes. Larger ones are left out entirely, as a profile cut
+/// short is no use.
+const MAX_PROFILE_LEN: u64 = 1024 * 1024;
+
+/// Name given to profiles that don't describe themselves in a way that fits a PNG keyword.
+const UNNAMED: &str = "ICC profile";
+
+/// Read a `len`-byte profile, or `None` if it's too large or can't be read.
+pub fn read_profile(icc: &mut impl Read, len: u64) -> Option<Vec<u8>> {
+    if len == 0 || len > MAX_PROFILE_LEN {
+        return None;
+    }
+    let mut profile = vec![0; len as usize];
+    icc.read_exact(&mut profile).ok()?;
+    Some(profile)
+}
+
+/// Data of an `iCCP` chunk holding `profile`: its name, then the zlib-compressed profile.
+pub fn iccp_chunk(profile: &[u8]) -> std::io::Result<Vec<u8>> {
+    let mut chunk = profile_name(profile).into_bytes();
+    // NUL separator, then compression method 0, zlib.
+    chunk.extend_from_slice(&[0, 0]);
+    let mut zlib = flate2::write::ZlibEncoder::new(chunk, flate2::Compression::default());
+    zlib.write_all(profile)?;
+    zlib.finish()
+}
+
+/// The profile's description, made into a PNG keyword: 1-79 printable Latin-1 characters,
+/// without leading, trailing, or consecutive spaces. [`UNNAMED`] if there's nothing usable.
+fn profile_name(profile: &[u8]) -> String {
+    let Some(description) = description(profile) else {
+        return UNNAMED.to_owned();
+    };
+    let mut name = String::new();
+    for c in description.chars() {
+        let c = match c {
+            ' '..='~' | '\u{a1}'..='\u{ff}' => c,
+            // Whitespace and controls alike.
+            _ if c.is_whitespace() || c.is_control() => ' ',
+            _ => '?',
+        };
+        if c == ' ' && (name.is_empty() || name.ends_with(' ')) {
+            continue;
+        }
+        if name.chars().count() == 79 {
+            break;
+        }
+        name.push(c);
+    }
+    let name = name.trim_end();
+    if name.is_empty() {
+        UNNAMED.to_owned()
+    } else {
+        name.to_owned()
+    }
+}
+
+/// Text of the profile's `desc` tag, as ASCII in ICC v2 profiles, or the first UTF-16 record
+/// in v4.
+fn description(profile: &[u8]) -> Option<String> {
+    let u32_at = |offset: usize| -> Option<u32> {
+        let bytes = profile.get(offset..offset.checked_add(4)?)?;
+        Some(u32::from_be_bytes(bytes.try_into().unwrap()))
+    };
+    let tag_count = u32_at(128)?;
+    let (offset, len) = (0..tag_count).find_map(|i| {
+        let entry = 132 + 12 * i as usize;
+        (profile.get(entry..entry + 4)? == b"desc")
+            .then(|| Some((u32_at(entry + 4)? as usize, u32_at(entry + 8)? as usize)))?
+    })?;
+    let tag = profile.get(offset..offset.checked_add(len)?)?;
+    let tag_u32 = |offset: usize| -> Option<usize> {
+        let bytes = tag.get(offset..offset + 4)?;
+        Some(u32::from_be_bytes(bytes.try_into().unwrap()) as usize)
+    };
+    match tag.get(0..4)? {
+        b"desc" => {
+            let ascii = tag.get(12..12usize.checked_add(tag_u32(8)?)?)?;
+            let ascii = ascii.split(|&b| b == 0).next().unwrap_or_default();
+            Some(ascii.iter().map(|&b| char::from(b)).collect())
+        }
+        b"mluc" if tag_u32(8)? > 0 => {
+            let (len, offset) = (tag_u32(20)?, tag_u32(24)?);
+            let utf16: Vec<u16> = tag
+                .get(offset..offset.checked_add(len)?)?
+                .chunks_exact(2)
+                .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                .collect();
+            Some(String::from_utf16_lossy(&utf16))
+        }
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A profile holding only a header and the given `desc` tag.
+    fn profile_with_desc(tag: &[u8]) -> Vec<u8> {
+        let mut profile = vec![0; 128];
+        profile[36..40].copy_from_slice(b"acsp");
+        profile.extend_from_slice(&1u32.to_be_bytes());
+        profile.extend_from_slice(b"desc");
+        profile.extend_from_slice(&144u32.to_be_bytes());
+        profile.extend_from_slice(&(tag.len() as u32).to_be_bytes());
+        profile.extend_from_slice(tag);
+        let len = profile.len() as u32;
+        profile[0..4].copy_from_slice(&len.to_be_bytes());
+        profile
+    }
+
+    /// A v2 `desc` tag of `text`.
+    fn desc(text: &[u8]) -> Vec<u8> {
+        let mut tag = b"desc\0\0\0\0".to_vec();
+        tag.extend_from_slice(&(text.len() as u32 + 1).to_be_bytes());
+        tag.extend_from_slice(text);
+        tag.push(0);
+        tag
+    }
+
+    #[test]
+    fn names() {
+        let name = |tag: &[u8]| profile_name(&profile_with_desc(tag));
+        assert_eq!(name(&desc(b"sRGB IEC61966-2.1")), "sRGB IEC61966-2.1");
+        assert_eq!(name(&desc(b"  Display\t\tP3  ")), "Display P3");
+        assert_eq!(name(&desc(&[b'a'; 100])), "a".repeat(79));
+        assert_eq!(name(&desc(b"")), UNNAMED);
+        assert_eq!(name(b"XYZ \0\0\0\0"), UNNAMED);
+
+        let mut mluc = b"mluc\0\0\0\0".to_vec();
+        mluc.extend_from_slice(&1u32.to_be_bytes());
+        mluc.extend_from_slice(&12u32.to_be_bytes());
+        mluc.extend_from_slice(b"enUS");
+        let text: Vec<u8> = "Adobe RGB 😀"
+            .encode_utf16()
+            .flat_map(u16::to_be_bytes)
+            .collect();
+        mluc.extend_from_slice(&(text.len() as u32).to_be_bytes());
+        mluc.extend_from_slice(&28u32.to_be_bytes());
+        mluc.extend_from_slice(&text);
+        assert_eq!(name(&mluc), "Adobe RGB ?");
+
+        // Offsets past the end.
+        assert_eq!(
+            profile_name(&profile_with_desc(&desc(b"x"))[..150]),
+            UNNAMED
+        );
+        assert_eq!(profile_name(b"not a profile"), UNNAMED);
+    }
+
+    #[test]
+    fn reads_within_limit() {
+        let profile = profile_with_desc(&desc(b"x"));
+        let len = profile.len() as u64;
+        assert_eq!(read_profile(&mut &profile[..], len), Some(profile.clone()));
+        assert_eq!(read_profile(&mut &profile[..], len + 1), None);
+        assert_eq!(read_profile(&mut &profile[..], 0), None);
+        let huge = vec![0; MAX_PROFILE_LEN as usize + 1];
+        assert_eq!(read_profile(&mut &huge[..], huge.len() as u64), None);
+    }
+}
@@ -0,0 +1,242 @@
+//! Diagnostics on stderr, filtered by verbosity.
+//!
+//! Stdout is reserved for output proper (images, reports, listings), so nothing here ever
+//! writes to it. With a [log file](set_log_file), every message is also appended there, whatever
+//! the verbosity, for when nobody is watching stderr.
+use std::cell::RefCell;
+use std::fmt::Display;
+use std::io::Write;
+use std::path::Path;
+use std::sync::atomic::{AtomicU8, Ordering};
+use std::sync::OnceLock;
+
+/// How much to say on stderr.
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy, Default)]
+pub enum Verbosity {
+    /// Only the final error.
+    Quiet,
+    /// Also problems that don't stop us, like a single failed item in a batch.
+    #[default]
+    Normal,
+    /// Also narrate each stage of the work.
+    Verbose,
+}
+
+static VERBOSITY: AtomicU8 = AtomicU8::new(Verbosity::Normal as u8);
+static LOG_FILE: OnceLock<std::fs::File> = OnceLock::new();
+/// What the whole process is working on, for log file lines without a [context](with_context).
+static INPUT: OnceLock<String> = OnceLock::new();
+
+pub fn set_verbosity(verbosity: Verbosity) {
+    VERBOSITY.store(verbosity as u8, Ordering::Relaxed);
+}
+
+/// Also append every message, even narration, to the file at `path`, creating it if need be.
+///
+/// Opened for appending, so lines from other processes logging to the same file never overwrite
+/// or split ours. Only the first call has any effect.
+pub fn set_log_file(path: &Path) -> std::io::Result<()> {
+    let file = std::fs::OpenOptions::new()
+        .append(true)
+        .create(true)
+        .open(path)?;
+    let _ = LOG_FILE.set(file);
+    Ok(())
+}
+
+/// Name `input` in log file lines. Only the first call has any effect.
+pub fn set_input(input: impl Display) {
+    let _ = INPUT.set(input.to_string());
+}
+
+/// Whether messages at `level` will be shown, to skip gathering details nobody will see.
+pub fn enabled(level: Verbosity) -> bool {
+    shown(level) || LOG_FILE.get().is_some()
+}
+
+/// Whether messages at `level` go to stderr.
+fn shown(level: Verbosity) -> bool {
+    VERBOSITY.load(Ordering::Relaxed) >= level as u8
+}
+
+thread_local! {
+    /// What the current thread is working on, to tell apart interleaved batch messages.
+    static CONTEXT: RefCell<Option<String>> = const { RefCell::new(None) };
+}
+
+/// Prefix messages logged by this thread during `f` with `context`.
+pub fn with_context<T>(context: impl Display, f: impl FnOnce() -> T) -> T {
+    let previous = CONTEXT.with(|c| c.replace(Some(context.to_string())));
+    let result = f();
+    CONTEXT.with(|c| *c.borrow_mut() = previous);
+    result
+}
+
+fn log(level: Verbosity, message: impl Display) {
+    if !enabled(level) {
+        return;
+    }
+    CONTEXT.with(|context| {
+        let context = context.borrow();
+        if shown(level) {
+            match &*context {
+                Some(context) => eprintln!("{context}: {message}"),
+                None => eprintln!("{message}"),
+            }
+        }
+        append(context.as_deref(), &message);
+    });
+}
+
+/// Append a line for `message` to the log file, if there is one.
+///
+/// Best-effort - a log that can't be written mustn't fail the work it's logging.
+fn append(context: Option<&str>, message: &dyn Display) {
+    let Some(mut file) = LOG_FILE.get() else {
+        return;
+    };
+    let now = timestamp(std::time::SystemTime::now());
+    let pid = std::process::id();
+    let line = match context.or(INPUT.get().map(String::as_str)) {
+        Some(input) => format!("{now} [{pid}] {input}: {message}\n"),
+        None => format!("{now} [{pid}] {message}\n"),
+    };
+    // In one write, so it lands whole.
+    let _ = file.write_all(line.as_bytes());
+}
+
+/// `time` in UTC, as RFC 3339 with milliseconds.
+fn timestamp(time: std::time::SystemTime) -> String {
+    // A clock before 1970 is too wrong to be worth describing.
+    let since_epoch = time
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default();
+    let secs = since_epoch.as_secs();
+    let (days, secs_of_day) = (secs / 86400, secs % 86400);
+    // Days to a proleptic Gregorian date, after Howard Hinnant's `civil_from_days`.
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + u64::from(month <= 2);
+    format!(
+        "{year:04}-{month:02}-{day:02}T{:02}:{:02}:{:02}.{:03}Z",
+        secs_of_day / 3600,
+        secs_of_day / 60 % 60,
+        secs_of_day % 60,
+        since_epoch.subsec_millis()
+    )
+}
+
+/// The error we're exiting with, always shown.
+pub fn error(message: impl Display) {
+    eprintln!("Error: {message}");
+    append(None, &format_args!("error: {message}"));
+}
+
+/// A problem that doesn't stop us.
+pub fn warn(message: impl Display) {
+    log(Verbosity::Normal, message);
+}
+
+/// Narration, only shown with `--verbose`.
+pub fn verbose(message: impl Display) {
+    log(Verbosity::Verbose, message);
+}
+
+/// Stages of thumbnailing a document.
+pub enum Stage {
+    /// The thumbnail chunk's data starts at `offset`.
+    FoundThumbnail {
+        offset: u64,
+        len: u64,
+    },
+    Decoded {
+        width: u32,
+        height: u32,
+    },
+    Cropped {
+        left: u32,
+        top: u32,
+        width: u32,
+        height: u32,
+    },
+    /// Rotated or flipped.
+    Reoriented {
+        width: u32,
+        height: u32,
+    },
+    Resized {
+        width: u32,
+        height: u32,
+    },
+    /// Centered on a larger canvas.
+    Padded {
+        width: u32,
+        height: u32,
+    },
+    /// Composited over the background color.
+    Flattened {
+        opaque: bool,
+    },
+    /// Every pixel was gray, so only one color channel was written.
+    Grayscale,
+    /// The thumbnail was kept at its stored size.
+    Native,
+    Wrote {
+        bytes: u64,
+    },
+    /// The output is already up to date, so nothing was done.
+    Fresh,
+}
+impl Display for Stage {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::FoundThumbnail { offset, len } => {
+                write!(f, "found thumbnail chunk at offset {offset} ({len} bytes)")
+            }
+            Self::Decoded { width, height } => write!(f, "decoded {width}x{height}"),
+            Self::Cropped {
+                left,
+                top,
+                width,
+                height,
+            } => write!(f, "cropped to {left},{top},{width}x{height}"),
+            Self::Reoriented { width, height } => write!(f, "reoriented to {width}x{height}"),
+            Self::Resized { width, height } => write!(f, "resized to {width}x{height}"),
+            Self::Padded { width, height } => write!(f, "padded to {width}x{height}"),
+            Self::Flattened { opaque: true } => write!(f, "flattened onto an opaque background"),
+            Self::Flattened { opaque: false } => {
+                write!(f, "flattened onto a translucent background")
+            }
+            Self::Grayscale => write!(f, "packed as grayscale"),
+            Self::Native => write!(f, "kept native size"),
+            Self::Wrote { bytes } => write!(f, "wrote {bytes} bytes"),
+            Self::Fresh => write!(f, "output is already fresh, skipped"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamps() {
+        let at = |secs, millis| {
+            timestamp(
+                std::time::UNIX_EPOCH
+                    + std::time::Duration::from_secs(secs)
+                    + std::time::Duration::from_millis(millis),
+            )
+        };
+        assert_eq!(at(0, 0), "1970-01-01T00:00:00.000Z");
+        assert_eq!(at(951_782_400, 5), "2000-02-29T00:00:00.005Z");
+        assert_eq!(at(1_792_069_199, 999), "2026-10-15T12:59:59.999Z");
+        assert_eq!(at(4_107_542_400, 0), "2100-03-01T00:00:00.000Z");
+    }
+}
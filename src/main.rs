@@ -1,353 +1,3598 @@
 //! Thumbnailer for `.fzp` files.
 //!
-//! Reads the input file path (arg1), searching the first and second blocks for the "thmb" type. It will *not* generate
+//! Reads the input file path (arg1, or `-` for stdin), searching its chunks (and those nested in `LIST`s) for the "thmb" type. It will *not* generate
 //! thumbnails for files that do not have this field, as it is a high-overhead task to generate these images and this
 //! thumbnailer is designed to be run dozens of times in a short timespan.
 //!
-//! Reads a desired size from arg2, fitting the read image into a square of that size. Filtering mode is undefined.
+//! If there's more than one, the smallest at least as large as the desired size is used, or else the largest.
+//! A `thix` chunk first in the document, indexing thumbnails elsewhere in it, is chosen from the same way without searching.
 //!
-//! Reads a file path from arg3, writing a PNG of the resized image to that location.
+//! Documents of any form type in the compatibility table are read alike, their thumbnails tagged with that form's MIME type.
+//!
+//! Reads a desired size from arg2, fitting the read image into a square of that size (or a `WIDTHxHEIGHT` box,
+//! or leaving it untouched for `native`).
+//! Filtering is bilinear unless chosen with `--filter`.
+//!
+//! Reads a file path from arg3 (or `-` for stdout), writing a PNG of the resized image to that location.
+//!
+//! Each of these may instead be given as a named flag, see `--help`.
+//!
+//! On failure after the input was successfully opened, writes a failure marker to
+//! `$XDG_CACHE_HOME/thumbnails/fail/fuzzpaint-thumbnailer` so that shells don't keep retrying.
 //!
 //! Todo[XDG]: Accept file URI instead of path
-//! Todo[XDG]: write failure logs to $XDG_CACHE_HOME/thumbnails/fail/fuzzpaint-thumbnailer
 //!
 //! Todo[WINDOWS]: implement IThumbnailProvider
-//! Todo[WINDOWS]: allow RGB8 images
-use az::{CheckedAs, SaturatingAs};
+use chunks::{ByteOrder, Chunk, ChunkReader, LargeSizes, Warning};
+use error::{Class, Error};
+use metadata::DocumentInfo;
 use std::borrow::Cow;
 use std::io::{BufRead, BufReader, Error as IOError, Read, Result as IOResult, Seek};
+use std::path::Path;
+use take::MyTake;
+
+mod archive;
+mod args;
+mod bands;
+mod batch;
+mod canvas;
+mod chunks;
+mod compressed;
+mod config;
+mod error;
+mod fit;
+mod formats;
+mod hash;
+mod icc;
+mod log;
+mod mapped;
+mod metadata;
+mod orient;
+mod report;
+mod sizes;
+mod strips;
+mod take;
+mod template;
+mod timings;
+mod transfer;
+mod validate;
+mod xdg;
 
-/// Bail if the thumb image is larger than this.
+/// Bail if the thumb image has more pixels than a square this size, unless `--max-dimension`
+/// says otherwise.
+// Kept small so the common case of many quick invocations stays cheap. Counted in pixels rather
+// than per side, as a long thin strip costs no more to decode than a square of the same area.
 const MAX_INPUT_IMAGE_DIMENSION: u32 = 1024;
-const MIME_TYPE: &'static str = "application/x.fuzzpaint-doc";
+/// Bail if the thumb image is wider or taller than this, however few pixels it has.
+// A strip this long is cheap to decode, but the resizer's tables scale with each side.
+const MAX_INPUT_IMAGE_SIDE: u32 = 16384;
+const MIME_TYPE: &str = "application/x.fuzzpaint-doc";
+/// The member of an unpacked document bundle holding the document itself.
+const BUNDLE_DOCUMENT: &str = "document.riff";
+/// RIFF form types read as fuzzpaint documents, and the MIME type of each. Later revisions of the
+/// format keep the same layout of chunks, so only need adding here.
+const FORMS: [([u8; 4], &str); 3] = [
+    (*b"fzp ", MIME_TYPE),
+    (*b"fzp2", MIME_TYPE),
+    (*b"fzpB", "application/x.fuzzpaint-brush"),
+];
+/// Keywords of the tEXt chunks we write ourselves.
+const PNG_TEXT_KEYWORDS: [&str; 9] = [
+    "Software",
+    "Thumb::URI",
+    "Thumb::MTime",
+    "Thumb::Mimetype",
+    "Thumb::Image::Width",
+    "Thumb::Image::Height",
+    "X-Fuzzpaint::DPI",
+    "X-Fuzzpaint::Layers",
+    "X-Fuzzpaint::Objects",
+];
+/// Deepest nesting of `LIST` chunks searched for the thumbnail, counting the top level as 0.
+const MAX_LIST_DEPTH: u32 = 2;
+/// Give up looking for the thumbnail after this many chunks, unless `--max-chunks` says otherwise.
+// Generous, fuzzpaint writes it near the start, but a pathological file mustn't keep us forever.
+const MAX_CHUNKS: u32 = 64;
+/// Largest flattened canvas `--allow-flatten` decodes, as the side of a square of as many pixels,
+/// unless `--max-dimension` allows larger.
+// Decoded, 4096x4096 is 64MiB, and takes a good fraction of a second to decode and scale down.
+// A canvas any larger is better off unthumbnailed than holding up the file manager.
+const MAX_FLATTEN_DIMENSION: u32 = 4096;
+/// Length of a QOI header, before the pixels.
+const QOI_HEADER_LEN: usize = 14;
+/// Largest `thmb` chunk accepted, in bytes, unless `--max-thumb-bytes` says otherwise.
+// Far beyond any thumbnail fuzzpaint writes. One claiming more is corrupt, or up to something.
+const MAX_THUMB_BYTES: u64 = 16 * 1024 * 1024;
+/// Refuse thumbnails stored in more than this many times the bytes their dimensions could ever
+/// take to encode, or [`LENIENT_ENCODED_RATIO`] times with `--lenient`.
+// Flat color packs a huge image into a few bytes, but nothing needs many bytes for a small one.
+// Far more is a bogus header in front of something else, which would all be read through anyway.
+const MAX_ENCODED_RATIO: u64 = 2;
+/// [`MAX_ENCODED_RATIO`] with `--lenient`.
+const LENIENT_ENCODED_RATIO: u64 = 16;
+/// How much of a damaged document `--recover` searches for thumbnails.
+const RECOVER_SCAN_LEN: u64 = 8 * 1024 * 1024;
+/// Largest document read from stdin, which is buffered whole to be seekable.
+const MAX_STDIN_BYTES: u64 = 4 * 1024 * 1024;
 
-/// std::io::Take, except it's Seek. Not sure why std's isn't D:
+/// The header of an fzp document.
+struct FzpHeader {
+    /// `RIFF`, `RIFX` for big-endian sizes, or `RF64` for documents too large for 32-bit sizes.
+    magic: [u8; 4],
+    /// Of every size field, from the magic.
+    byte_order: ByteOrder,
+    /// Bytes after the size field, from the form type to the end of the last chunk.
+    len: u64,
+    /// Declared size of the `ds64` chunk following an `RF64` header, which is read as part of it.
+    ds64_len: Option<u32>,
+    /// Sizes of chunks too large for their headers, from the `ds64` chunk.
+    large_sizes: LargeSizes,
+    /// MIME type of the form type, from [`FORMS`].
+    mime: &'static str,
+}
+impl FzpHeader {
+    /// Offset of the first chunk after the header.
+    fn chunks_offset(&self) -> u64 {
+        12 + self
+            .ds64_len
+            .map_or(0, |len| 8 + u64::from(len) + u64::from(len % 2))
+    }
+}
+
+/// Read and check the header of an fzp document, leaving the reader at its first chunk.
 ///
-/// If the base reader is Seek, it shifts the basis of it
-/// such that the position at the time of MyTake's construction is the start,
-/// and that position + len is the end. Seeks past-the-end are clamped to the end.
-struct MyTake<R> {
-    reader: R,
-    cursor: u64,
+/// A `RIFX` document is read as any other, but with big-endian sizes. An `RF64` header's size is
+/// ignored, in favour of the 64-bit size in the `ds64` chunk that must follow it. The form type
+/// must be one of [`FORMS`], or `accept_form`, which is taken to be a fuzzpaint document. Only
+/// these bytes decide whether it's a document, never the file's name.
+fn read_fzp_header<R: Read>(r: &mut R, accept_form: Option<[u8; 4]>) -> IOResult<FzpHeader> {
+    let invalid = |message| IOError::new(std::io::ErrorKind::InvalidData, message);
+    let mut fzp_header = [0; 12];
+    // What isn't a document may well be shorter than a header, and is still worth describing.
+    let mut read = 0;
+    while read < fzp_header.len() {
+        match r.read(&mut fzp_header[read..]) {
+            Ok(0) => break,
+            Ok(n) => read += n,
+            Err(err) if err.kind() == std::io::ErrorKind::Interrupted => (),
+            Err(err) => return Err(err),
+        }
+    }
+    let magic: [u8; 4] = fzp_header[0..4].try_into().unwrap();
+    let byte_order = match &magic {
+        b"RIFF" | b"RF64" => ByteOrder::Little,
+        b"RIFX" => ByteOrder::Big,
+        _ => return Err(unrecognized(&fzp_header[..read])),
+    };
+    if read < fzp_header.len() {
+        return Err(std::io::ErrorKind::UnexpectedEof.into());
+    }
+    let form = &fzp_header[8..12];
+    let mime = FORMS
+        .iter()
+        .find(|(known, _)| known == form)
+        .map(|&(_, mime)| mime)
+        .or_else(|| {
+            accept_form
+                .filter(|accept| accept == form)
+                .map(|_| MIME_TYPE)
+        });
+    let Some(mime) = mime else {
+        return Err(IOError::new(
+            std::io::ErrorKind::InvalidData,
+            format!(
+                "unrecognized file type: {} form \"{}\"",
+                escape_fourcc(&magic),
+                escape_fourcc(form)
+            ),
+        ));
+    };
+    let len = byte_order.u32(fzp_header[4..8].try_into().unwrap());
+    if &magic != b"RF64" {
+        return Ok(FzpHeader {
+            magic,
+            byte_order,
+            len: len.into(),
+            ds64_len: None,
+            large_sizes: LargeSizes::default(),
+            mime,
+        });
+    }
+
+    let (fourcc, ds64_len) = read_chunk_header(r, byte_order)?;
+    if &fourcc != b"ds64" {
+        return Err(invalid("RF64 document doesn't start with a ds64 chunk"));
+    }
+    let mut ds64 = r.take(ds64_len.into());
+    let mut u64_field = || -> IOResult<u64> {
+        let mut field = [0; 8];
+        ds64.read_exact(&mut field)?;
+        Ok(u64::from_le_bytes(field))
+    };
+    let riff_len = u64_field()?;
+    let data_len = u64_field()?;
+    // Sample count, meaningless to anything but WAVE.
+    u64_field()?;
+    let mut table_len = [0; 4];
+    ds64.read_exact(&mut table_len)?;
+    let mut large_sizes = vec![(*b"data", data_len)];
+    for _ in 0..u32::from_le_bytes(table_len) {
+        let mut entry = [0; 12];
+        ds64.read_exact(&mut entry)?;
+        large_sizes.push((
+            entry[0..4].try_into().unwrap(),
+            u64::from_le_bytes(entry[4..12].try_into().unwrap()),
+        ));
+    }
+    // Skip whatever's left, and the pad byte.
+    std::io::copy(&mut ds64, &mut std::io::sink())?;
+    if ds64_len % 2 == 1 {
+        r.read_exact(&mut [0])?;
+    }
+    Ok(FzpHeader {
+        magic,
+        byte_order,
+        len: riff_len,
+        ds64_len: Some(ds64_len),
+        large_sizes: LargeSizes(large_sizes),
+        mime,
+    })
+}
+
+/// The eight bytes every PNG starts with.
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Leading bytes of formats often mistaken for documents, by name or by whoever invoked us.
+const MISTAKEN_FORMATS: [(&[u8], &str); 5] = [
+    (PNG_SIGNATURE, "PNG"),
+    (b"\xff\xd8\xff", "JPEG"),
+    (b"PK\x03\x04", "ZIP"),
+    (b"qoif", "a bare QOI image"),
+    // Only ever reached without the `zstd` feature.
+    (&compressed::ZSTD_MAGIC, "zstd-compressed data"),
+];
+
+/// Describe what a file starting with `start` is, given it isn't a document.
+fn unrecognized(start: &[u8]) -> IOError {
+    let message = match MISTAKEN_FORMATS
+        .iter()
+        .find(|(magic, _)| start.starts_with(magic))
+    {
+        Some((_, format)) => format!("input looks like {format}, not a fuzzpaint document"),
+        None if start.is_empty() => "input is empty, not a fuzzpaint document".to_owned(),
+        None => format!(
+            "unrecognized file type, starting with \"{}\"",
+            escape_fourcc(start)
+        ),
+    };
+    IOError::new(std::io::ErrorKind::InvalidData, message)
+}
+
+/// Reads a chunk's fourcc and size, in `byte_order`.
+fn read_chunk_header<R: Read + ?Sized>(
+    r: &mut R,
+    byte_order: ByteOrder,
+) -> IOResult<([u8; 4], u32)> {
+    let mut block_header = [0; 8];
+    r.read_exact(&mut block_header)?;
+
+    let block_size = byte_order.u32(block_header[4..8].try_into().unwrap());
+
+    Ok((block_header[0..4].try_into().unwrap(), block_size))
+}
+
+/// A thumbnail found by [`read_fzp_thmb`], and what else was learned about its document.
+struct Found<R> {
+    /// The thumbnail's QOI data.
+    thmb: MyTake<R>,
+    /// MIME type of the document's form type.
+    mime: &'static str,
+    /// Width and height of the document's canvas, from its `dims` chunk, if it was seen.
+    canvas: Option<(u32, u32)>,
+    /// From the document's `LIST INFO` and `vrsn` chunks, if they were seen.
+    info: DocumentInfo,
+    /// Whether `thmb` is really the document's `flat` canvas, for lack of a thumbnail.
+    flattened: bool,
+}
+
+/// Given a reader of fzp data, create a reader of the thumbnail data.
+/// Does not allocate except for errors and warnings.
+///
+/// Chunks are walked in order until the document ends or [`Scan::max_chunks`] have been
+/// examined. Of the `thmb` chunks, the [`Scan::chunk`]th is chosen if given, or else the smallest
+/// with at least [`Scan::size`] pixels on its longest side, or else the largest. Without any, and
+/// if [`Scan::flatten`], the first top-level `flat` chunk's full-size image of the canvas is taken
+/// instead. A thumbnail of more than [`Scan::max_thumb_bytes`] is corrupt. Anything malformed that
+/// can be carried on past is pushed to `warnings` instead of failing.
+// A lot of this logic can be recycled from fuzzpaint-vk, with a shared library crate.
+fn read_fzp_thmb<R: Read + BufRead + Seek>(
+    mut r: R,
+    scan: Scan,
+    warnings: &mut Vec<Warning>,
+) -> Result<Found<R>, Error> {
+    let header = read_fzp_header(&mut r, scan.accept_form).map_err(|io| Error::from_io(&io))?;
+    let DocumentChunks {
+        mut chunks,
+        start,
+        file_len,
+    } = document_chunks(r, &header, scan.lenient, warnings)?;
+    let mut search = Search {
+        file_len,
+        max_chunks: scan.max_chunks,
+        examined: 0,
+        selection: Selection::new(scan.size, scan.chunk),
+        flatten: scan.flatten,
+        flat: None,
+        canvas: None,
+        info: DocumentInfo::default(),
+        warnings,
+    };
+    search_chunks(&mut chunks, 0, &mut search)?;
+    let (best, flattened) = match (search.selection.best, search.flat) {
+        (Some(best), _) => (best, false),
+        (None, _) if scan.chunk.is_some() && search.selection.seen > 0 => {
+            let (chunk, seen) = (scan.chunk.unwrap(), search.selection.seen);
+            return Err(Error::new(
+                Class::NoThumbnail,
+                format!(
+                    "--chunk {chunk} is out of range, document has only {seen} thumbnail{}",
+                    if seen == 1 { "" } else { "s" }
+                ),
+            ));
+        }
+        (None, Some(flat)) if scan.flatten => {
+            log::verbose("document does not contain a thumbnail, using its flattened canvas");
+            (flat, true)
+        }
+        // So sad :(
+        (None, flat) => {
+            return Err(Error::new(
+                Class::NoThumbnail,
+                if flat.is_some() {
+                    "document does not contain a thumbnail, only a flattened canvas (see --allow-flatten)"
+                } else {
+                    "document does not contain a thumbnail"
+                },
+            ))
+        }
+    };
+    // Before anything is made of it, however much it claims.
+    if !flattened && best.len > scan.max_thumb_bytes {
+        return Err(Error::new(
+            Class::Corrupt,
+            format!(
+                "thumbnail chunk of {} bytes exceeds limit of {}",
+                best.len, scan.max_thumb_bytes
+            ),
+        ));
+    }
+    // Found it! Go back to it, and take only the reported data length.
+    let mut r = chunks.into_inner().map_err(|io| Error::from_io(&io))?;
+    r.seek(std::io::SeekFrom::Start(start + best.offset))
+        .map_err(|io| Error::from_io(&io))?;
+    Ok(Found {
+        thmb: found_thmb(r, best.len),
+        mime: header.mime,
+        canvas: search.canvas,
+        info: search.info,
+        flattened,
+    })
+}
+
+/// The chunks of a document, from [`document_chunks`].
+struct DocumentChunks<R> {
+    chunks: ChunkReader<R>,
+    /// Offset of the document within the reader.
+    start: u64,
+    /// Real length of the document, whatever its header says.
+    file_len: u64,
+}
+
+/// The chunks after `header`, which `r` has just been read past, as every mode reads them.
+///
+/// The header is often wrong about the document's length when written by anything but
+/// fuzzpaint, so the document is taken to be the whole file, and the header only trusted to say
+/// that it was cut short. Any disagreement is pushed to `warnings`.
+fn document_chunks<R: BufRead + Seek>(
+    mut r: R,
+    header: &FzpHeader,
+    resync: bool,
+    warnings: &mut Vec<Warning>,
+) -> Result<DocumentChunks<R>, Error> {
+    let chunks_offset = header.chunks_offset();
+    // Where the document starts, and what's really there.
+    let (start, file_len) = (|| {
+        let chunks = r.stream_position()?;
+        let end = r.seek(std::io::SeekFrom::End(0))?;
+        r.seek(std::io::SeekFrom::Start(chunks))?;
+        Ok((
+            chunks.saturating_sub(chunks_offset),
+            end.saturating_sub(chunks) + chunks_offset,
+        ))
+    })()
+    .map_err(|io| Error::from_io(&io))?;
+    // An RF64 header may declare anything, even more than the 64 bits of an offset can count,
+    // which is as wrong as any other size.
+    let declared_end = header.len.checked_add(8);
+    if declared_end != Some(file_len) {
+        warnings.push(Warning::SizeMismatch {
+            magic: header.magic,
+            declared: header.len,
+            actual: file_len.saturating_sub(8),
+        });
+    }
+    let mut chunks = ChunkReader::new(r, file_len - chunks_offset, chunks_offset)
+        .with_large_sizes(header.large_sizes.clone())
+        .with_byte_order(header.byte_order)
+        .with_resync(resync);
+    // Only if the header says there should be more is the document known to be cut short,
+    // rather than a chunk being wrong about its size.
+    if declared_end.is_none_or(|end| end > file_len) {
+        chunks = chunks.with_file_len(file_len);
+    }
+    Ok(DocumentChunks {
+        chunks,
+        start,
+        file_len,
+    })
+}
+
+/// A chunk at any depth, as given by [`walk_chunks`].
+type WalkedChunk<'a, 'r> = Chunk<'a, &'r mut dyn BufReadSeek>;
+
+/// Give every chunk of `chunks` to `visit` in order, with its depth, descending into `LIST`s
+/// just as far as [`search_chunks`] does. Anything malformed that can be carried on past is
+/// pushed to `warnings` instead of failing.
+fn walk_chunks(
+    chunks: &mut ChunkReader<&mut dyn BufReadSeek>,
+    depth: u32,
+    warnings: &mut Vec<Warning>,
+    visit: &mut dyn FnMut(&WalkedChunk<'_, '_>, u32) -> Result<(), Error>,
+) -> Result<(), Error> {
+    while let Some(mut chunk) = chunks.next(warnings)? {
+        visit(&chunk, depth)?;
+        if &chunk.fourcc == b"LIST" && depth < MAX_LIST_DEPTH && chunk.data.remaining() >= 4 {
+            let (_, mut list) = chunk.list().map_err(|io| Error::from_io(&io))?;
+            walk_chunks(&mut list, depth + 1, warnings, visit)?;
+        }
+    }
+    Ok(())
+}
+
+/// A `thmb` chunk found by [`search_chunks`].
+#[derive(Debug, Clone, Copy)]
+struct Candidate {
+    /// Offset of its data within the document.
+    offset: u64,
     len: u64,
+    /// Pixels on the longest side, from its QOI header, or `None` if it doesn't have one.
+    longest: Option<u32>,
+}
+impl Candidate {
+    /// Whether this is better than `other` for a thumbnail of `size` pixels, or the largest if
+    /// `None`. The first of equals is kept.
+    fn better_than(&self, other: &Self, size: Option<u32>) -> bool {
+        let (Some(new), Some(old)) = (self.longest, other.longest) else {
+            // Anything that might decode beats what won't.
+            return self.longest.is_some() && other.longest.is_none();
+        };
+        match size {
+            Some(size) if new >= size && old >= size => new < old,
+            Some(size) if new >= size || old >= size => new >= size,
+            _ => new > old,
+        }
+    }
+}
+
+/// Choosing among the thumbnails of a document, as they're found.
+#[derive(Debug, Clone, Copy)]
+struct Selection {
+    /// Prefer the one best suited to this many pixels on its longest side, as
+    /// [`Candidate::better_than`] would choose it.
+    size: Option<u32>,
+    /// Take the one found in this position instead, counting from 1.
+    nth: Option<std::num::NonZeroU32>,
+    /// How many have been considered.
+    seen: u32,
+    best: Option<Candidate>,
 }
-impl<R> MyTake<R> {
-    pub fn new(reader: R, len: u64) -> Self {
+impl Selection {
+    fn new(size: Option<u32>, nth: Option<std::num::NonZeroU32>) -> Self {
         Self {
-            reader,
+            size,
+            nth,
+            seen: 0,
+            best: None,
+        }
+    }
+    /// Consider `found`, returning whether there's any point considering more.
+    fn consider(&mut self, found: Candidate) -> bool {
+        self.seen += 1;
+        match self.nth {
+            Some(nth) => {
+                if nth.get() == self.seen {
+                    self.best = Some(found);
+                }
+                self.best.is_none()
+            }
+            None => {
+                if self
+                    .best
+                    .is_none_or(|best| found.better_than(&best, self.size))
+                {
+                    self.best = Some(found);
+                }
+                true
+            }
+        }
+    }
+}
+
+/// Read a `thix` chunk, an index of thumbnails elsewhere in the document, considering each it
+/// lists in order for `selection`, and returning it as it's left.
+///
+/// The index is a little-endian `u32` count, then that many records of `u32` width and height,
+/// and `u64` offset within the document and length of the QOI data. Fails with a description of
+/// the problem if it's malformed, including any record reaching past `file_len`.
+fn read_index(
+    index: &mut impl Read,
+    len: u64,
+    file_len: u64,
+    mut selection: Selection,
+) -> Result<Selection, String> {
+    let read_err = |io: IOError| format!("can't be read: {io}");
+    let mut count = [0; 4];
+    index.read_exact(&mut count).map_err(read_err)?;
+    let count = u32::from_le_bytes(count);
+    let room = len.saturating_sub(4) / 24;
+    if u64::from(count) > room {
+        return Err(format!(
+            "declares {count} records, but has room for only {room}"
+        ));
+    }
+    // All are checked, even once chosen.
+    let mut choosing = true;
+    for _ in 0..count {
+        let mut record = [0; 24];
+        index.read_exact(&mut record).map_err(read_err)?;
+        let u32_field = |at: usize| u32::from_le_bytes(record[at..at + 4].try_into().unwrap());
+        let u64_field = |at: usize| u64::from_le_bytes(record[at..at + 8].try_into().unwrap());
+        let (width, height, offset, len) =
+            (u32_field(0), u32_field(4), u64_field(8), u64_field(16));
+        if width == 0 || height == 0 {
+            return Err(format!("lists a {width}x{height} thumbnail"));
+        }
+        let within = offset.checked_add(len).is_some_and(|end| end <= file_len);
+        if !within {
+            return Err(format!(
+                "lists a thumbnail of {len} bytes at offset {offset}, past the end of the document"
+            ));
+        }
+        let found = Candidate {
+            offset,
             len,
-            cursor: 0,
-        }
-    }
-    pub fn remaining(&self) -> u64 {
-        self.len
-            .checked_sub(self.cursor)
-            .expect("cursor past the end")
-    }
-    pub fn into_inner(self) -> R {
-        self.reader
-    }
-}
-impl<R: Read> Read for MyTake<R> {
-    fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
-        let trimmed_len: usize = (buf.len() as u64).min(self.remaining()).saturating_as();
-        let buf = &mut buf[..trimmed_len];
-        // Short circuit if we can't read any more data
-        if buf.is_empty() {
-            return Ok(0);
-        }
-        let num_read = self.reader.read(buf)?;
-        // Defensive checks for bad inner reader impl
-        // (or my own bugs :P)
-        let new_cursor = self
-            .cursor
-            .checked_add(num_read as u64)
-            .ok_or_else(|| IOError::other("inner reader overflowed MyTake cursor"))?;
-        debug_assert!(new_cursor <= self.len);
-        self.cursor = new_cursor;
-
-        Ok(num_read)
-    }
-}
-impl<R: BufRead> BufRead for MyTake<R> {
-    fn consume(&mut self, amt: usize) {
-        // Only allow consuming as much as we're allowed to view.
-        let trimmed_amt = (amt as u64).min(self.remaining());
-        self.cursor = self
-            .cursor
-            .checked_add(trimmed_amt)
-            .expect("consume overflowed cursor");
-        debug_assert!(self.cursor <= self.len);
-
-        let trimmed_amt: usize = trimmed_amt.saturating_as();
-        self.reader.consume(trimmed_amt)
-    }
-    fn fill_buf(&mut self) -> IOResult<&[u8]> {
-        // Early call. Borrow weirdness.
-        let remaining = self.remaining();
-
-        let buf = self.reader.fill_buf()?;
-
-        // Limit buffer's size, prevent user from seeing past-the-end
-        let trimmed_len: usize = (buf.len() as u64).min(remaining).saturating_as();
-        let buf = &buf[..trimmed_len];
-
-        Ok(buf)
-    }
-}
-impl<R: Seek> Seek for MyTake<R> {
-    fn seek(&mut self, pos: std::io::SeekFrom) -> IOResult<u64> {
-        use std::io::SeekFrom;
-        let err_past_the_start = "seek offset past-the-start";
-        let err_overflow_cursor = "seek offset overflows cursor";
-        let new_cursor: u64 = match pos {
-            SeekFrom::Current(delta) => {
-                // Clamp upper bound to self length
-                let delta = if delta > 0 {
-                    // Saturate OK - we're taking the min with a i64 anyway
-                    delta.min(self.remaining().saturating_as())
+            longest: Some(width.max(height)),
+        };
+        if choosing {
+            choosing = selection.consider(found);
+        }
+    }
+    Ok(selection)
+}
+
+/// Progress of [`search_chunks`].
+struct Search<'a> {
+    /// Real length of the document, whatever its header says.
+    file_len: u64,
+    max_chunks: u32,
+    /// Chunks examined so far, at every depth.
+    examined: u32,
+    /// Of the thumbnails found so far.
+    selection: Selection,
+    /// Whether `flat` will do, for lack of `best`.
+    flatten: bool,
+    /// The first top-level `flat` chunk.
+    flat: Option<Candidate>,
+    /// From the first top-level `dims` chunk.
+    canvas: Option<(u32, u32)>,
+    /// From top-level `LIST INFO` and `vrsn` chunks.
+    info: DocumentInfo,
+    warnings: &'a mut Vec<Warning>,
+}
+impl Search<'_> {
+    /// Stop searching because of `err`, which only fails if nothing's been found to fall back on.
+    fn give_up(&self, err: Error) -> Result<bool, Error> {
+        match (self.selection.best, self.flat) {
+            (Some(_), _) => Ok(false),
+            (None, Some(_)) if self.flatten => Ok(false),
+            _ => Err(err),
+        }
+    }
+}
+
+/// Read the width and height of a document's canvas from its `len`-byte `dims` chunk, as two
+/// little-endian `u32`s, or describe what's wrong with it.
+fn read_canvas_size(dims: &mut impl Read, len: u64) -> Result<(u32, u32), String> {
+    if len != 8 {
+        return Err(format!("is {len} bytes, not 8"));
+    }
+    let mut size = [0; 8];
+    dims.read_exact(&mut size)
+        .map_err(|io| format!("can't be read: {io}"))?;
+    let width = u32::from_le_bytes(size[0..4].try_into().unwrap());
+    let height = u32::from_le_bytes(size[4..8].try_into().unwrap());
+    if width == 0 || height == 0 {
+        return Err(format!("gives a {width}x{height} canvas"));
+    }
+    Ok((width, height))
+}
+
+/// Search `chunks` for `thmb` chunks, descending into `LIST`s while `depth` is shallower than
+/// [`MAX_LIST_DEPTH`], keeping the best in `search`, along with the canvas size from `dims`.
+///
+/// Returns whether to carry on searching, which stops once `max_chunks` have been examined or
+/// the rest of the document can't be read.
+fn search_chunks<R: BufRead + Seek>(
+    chunks: &mut ChunkReader<R>,
+    depth: u32,
+    search: &mut Search,
+) -> Result<bool, Error> {
+    loop {
+        let mut chunk = match chunks.next(search.warnings) {
+            Ok(Some(chunk)) => chunk,
+            Ok(None) => return Ok(true),
+            Err(err) => return search.give_up(err),
+        };
+        if search.examined == search.max_chunks {
+            let max_chunks = search.max_chunks;
+            return search.give_up(Error::new(
+                Class::NoThumbnail,
+                format!("document does not contain a thumbnail in its first {max_chunks} chunks"),
+            ));
+        }
+        search.examined += 1;
+        match &chunk.fourcc {
+            b"thmb" => {
+                let len = chunk.data.remaining();
+                // Only the 14-byte QOI header, to tell how large it is.
+                let mut header = [0; 14];
+                let longest = chunk
+                    .data
+                    .read_exact(&mut header)
+                    .ok()
+                    .and_then(|()| qoi::decode_header(header).ok())
+                    .map(|header| header.width.max(header.height));
+                let found = Candidate {
+                    offset: chunk.offset + 8,
+                    len,
+                    longest,
+                };
+                if !search.selection.consider(found) {
+                    return Ok(false);
+                }
+            }
+            b"flat" if depth == 0 && search.flat.is_none() => {
+                search.flat = Some(Candidate {
+                    offset: chunk.offset + 8,
+                    len: chunk.data.remaining(),
+                    longest: None,
+                });
+            }
+            b"vrsn" if depth == 0 => search.info.read_version(&mut *chunk.data),
+            b"reso" if depth == 0 => {
+                let len = chunk.data.remaining();
+                search.info.read_resolution(&mut *chunk.data, len);
+            }
+            b"icc " if depth == 0 => {
+                let len = chunk.data.remaining();
+                search.info.read_icc_profile(&mut *chunk.data, len);
+            }
+            b"bgcl" if depth == 0 => {
+                let len = chunk.data.remaining();
+                search.info.read_background(&mut *chunk.data, len);
+            }
+            b"ornt" if depth == 0 => {
+                let len = chunk.data.remaining();
+                search.info.read_orientation(&mut *chunk.data, len);
+            }
+            b"strc" if depth == 0 => {
+                let len = chunk.data.remaining();
+                search.info.read_structure(&mut *chunk.data, len);
+            }
+            b"dims" if depth == 0 && search.canvas.is_none() => {
+                let (offset, len) = (chunk.offset, chunk.data.remaining());
+                match read_canvas_size(&mut *chunk.data, len) {
+                    Ok(canvas) => search.canvas = Some(canvas),
+                    Err(problem) => search
+                        .warnings
+                        .push(Warning::BadCanvasSize { offset, problem }),
+                }
+            }
+            // Only up front is it any use for skipping the search.
+            b"thix" if depth == 0 && search.examined == 1 => {
+                let (offset, len) = (chunk.offset, chunk.data.remaining());
+                match read_index(&mut *chunk.data, len, search.file_len, search.selection) {
+                    Ok(selection) if selection.seen > 0 => {
+                        search.selection = selection;
+                        return Ok(false);
+                    }
+                    // Nothing listed, so look for it.
+                    Ok(_) => (),
+                    Err(problem) => search.warnings.push(Warning::BadIndex { offset, problem }),
+                }
+            }
+            b"LIST" if depth < MAX_LIST_DEPTH && chunk.data.remaining() >= 4 => {
+                // The list type says nothing about whether there's a thumbnail inside.
+                let (list_type, mut list) = match chunk.list() {
+                    Ok(list) => list,
+                    Err(io) => return search.give_up(Error::from_io(&io)),
+                };
+                // Holds only text, never thumbnails.
+                if &list_type == b"INFO" {
+                    if depth == 0 {
+                        search.info.read_list(list, search.warnings);
+                    }
+                    continue;
+                }
+                if !search_chunks(&mut list, depth + 1, search)? {
+                    return Ok(false);
+                }
+            }
+            _ => (),
+        }
+    }
+}
+
+/// How to look for the thumbnail of a document.
+#[derive(Debug, Clone, Copy)]
+struct Scan {
+    /// Fail on anything wrong with the document, even if it could be coped with.
+    strict: bool,
+    /// Skip malformed chunk headers to the next plausible one.
+    lenient: bool,
+    /// Give up after examining this many chunks, counting those nested in lists.
+    max_chunks: u32,
+    /// Refuse `thmb` chunks of more bytes than this.
+    max_thumb_bytes: u64,
+    /// Refuse thumbnails stored in more than this many times the bytes their dimensions could
+    /// take, see [`check_len`].
+    max_encoded_ratio: u64,
+    /// Prefer the thumbnail best suited to this many pixels on its longest side, or the largest
+    /// if `None`.
+    size: Option<u32>,
+    /// Take the thumbnail found in this position instead, counting from 1.
+    chunk: Option<std::num::NonZeroU32>,
+    /// If the thumbnail can't be found or decoded, [`recover`] one.
+    recover: bool,
+    /// If there's no thumbnail, make do with the document's flattened canvas.
+    flatten: bool,
+    /// If there's no thumbnail, make do with a sidecar thumbnail beside the document.
+    sidecar: bool,
+    /// Also read documents of this form type, besides those in [`FORMS`].
+    accept_form: Option<[u8; 4]>,
+    /// Take thumbnails to be in this colorspace, whatever they're tagged as, or as tagged if
+    /// `None`.
+    colorspace: Option<qoi::ColorSpace>,
+}
+
+/// [`read_fzp_thmb`], then fail on the first thing it coped with if [`Scan::strict`], or just
+/// narrate each otherwise.
+fn find_thmb<R: Read + BufRead + Seek>(r: R, scan: Scan) -> Result<Found<R>, Error> {
+    let mut warnings = Vec::new();
+    // Warnings from a failed scan don't matter, it failed anyway.
+    let thmb = read_fzp_thmb(r, scan, &mut warnings)?;
+    if let (true, Some(warning)) = (scan.strict, warnings.first()) {
+        return Err(Error::new(Class::Corrupt, warning.to_string()));
+    }
+    for warning in &warnings {
+        log::verbose(format_args!("coped with {warning}"));
+    }
+    let resyncs = warnings
+        .iter()
+        .filter(|warning| matches!(warning, Warning::Resynced { .. }))
+        .count();
+    if resyncs > 0 {
+        log::warn(format_args!(
+            "skipped {resyncs} malformed chunk header{}",
+            if resyncs == 1 { "" } else { "s" }
+        ));
+    }
+    Ok(thmb)
+}
+
+/// Take the `len` bytes of thumbnail data at the reader's position.
+fn found_thmb<R: Seek>(mut r: R, len: u64) -> MyTake<R> {
+    if log::enabled(log::Verbosity::Verbose) {
+        // Only for narration, not worth failing over.
+        if let Ok(offset) = r.stream_position() {
+            log::verbose(log::Stage::FoundThumbnail { offset, len });
+        }
+    }
+    MyTake::new(r, len)
+}
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy)]
+#[repr(C, align(4))]
+struct U8x4(pub [u8; 4]);
+/// Unlike [`U8x4`], left unaligned: `fast_image_resize` has no SIMD path that wants 3-byte pixels
+/// aligned, so padding them to 4 would only bring back the byte an opaque thumbnail saves.
+#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy)]
+#[repr(C)]
+struct U8x3(pub [u8; 3]);
+
+/// A layout decoded thumbnails are cropped, scaled, and written in.
+trait Pixel: bytemuck::Pod {
+    /// The PNG colour type of an image of these pixels.
+    const COLOR: png::ColorType;
+    /// `fast_image_resize`'s equivalent.
+    type Resize: bands::Resizable;
+    fn view_mut(
+        view: fast_image_resize::ImageViewMut<'_, Self::Resize>,
+    ) -> fast_image_resize::DynamicImageViewMut<'_>;
+    /// `fast_image_resize`'s equivalent at 16 bits a channel, to scale in linear light with.
+    type Linear: bands::Resizable;
+    fn linear_view_mut(
+        view: fast_image_resize::ImageViewMut<'_, Self::Linear>,
+    ) -> fast_image_resize::DynamicImageViewMut<'_>;
+    /// An RGBA8 colour, dropping the alpha if these pixels have none.
+    fn from_rgba(rgba: [u8; 4]) -> Self;
+    /// Composite the pixels over `background`, returning whether they're now all opaque.
+    fn flatten(pixels: &mut std::borrow::Cow<[Self]>, background: [u8; 4]) -> bool;
+    /// Whether every one of the pixels has an alpha of exactly zero.
+    fn all_transparent(pixels: &[Self]) -> bool;
+    /// Whether every one of the pixels is fully opaque.
+    fn all_opaque(pixels: &[Self]) -> bool;
+}
+impl Pixel for U8x4 {
+    const COLOR: png::ColorType = png::ColorType::Rgba;
+    type Resize = fast_image_resize::pixels::U8x4;
+    fn view_mut(
+        view: fast_image_resize::ImageViewMut<'_, Self::Resize>,
+    ) -> fast_image_resize::DynamicImageViewMut<'_> {
+        view.into()
+    }
+    type Linear = fast_image_resize::pixels::U16x4;
+    fn linear_view_mut(
+        view: fast_image_resize::ImageViewMut<'_, Self::Linear>,
+    ) -> fast_image_resize::DynamicImageViewMut<'_> {
+        view.into()
+    }
+    fn from_rgba(rgba: [u8; 4]) -> Self {
+        U8x4(rgba)
+    }
+    fn flatten(pixels: &mut std::borrow::Cow<[Self]>, background: [u8; 4]) -> bool {
+        canvas::flatten(bytemuck::cast_slice_mut(pixels.to_mut()), background);
+        background[3] == u8::MAX
+    }
+    fn all_transparent(pixels: &[Self]) -> bool {
+        // Whole pixels at once, with no early exit, so it vectorizes.
+        let alpha = u32::from_ne_bytes([0, 0, 0, u8::MAX]);
+        bytemuck::cast_slice::<_, u32>(pixels)
+            .iter()
+            .fold(0, |any, px| any | px)
+            & alpha
+            == 0
+    }
+    fn all_opaque(pixels: &[Self]) -> bool {
+        let alpha = u32::from_ne_bytes([0, 0, 0, u8::MAX]);
+        bytemuck::cast_slice::<_, u32>(pixels)
+            .iter()
+            .fold(alpha, |all, px| all & px)
+            & alpha
+            == alpha
+    }
+}
+impl Pixel for U8x3 {
+    const COLOR: png::ColorType = png::ColorType::Rgb;
+    type Resize = fast_image_resize::pixels::U8x3;
+    fn view_mut(
+        view: fast_image_resize::ImageViewMut<'_, Self::Resize>,
+    ) -> fast_image_resize::DynamicImageViewMut<'_> {
+        view.into()
+    }
+    type Linear = fast_image_resize::pixels::U16x3;
+    fn linear_view_mut(
+        view: fast_image_resize::ImageViewMut<'_, Self::Linear>,
+    ) -> fast_image_resize::DynamicImageViewMut<'_> {
+        view.into()
+    }
+    fn from_rgba([r, g, b, _]: [u8; 4]) -> Self {
+        U8x3([r, g, b])
+    }
+    /// Already opaque, so untouched by any background.
+    fn flatten(_: &mut std::borrow::Cow<[Self]>, _: [u8; 4]) -> bool {
+        true
+    }
+    /// Opaque, so never.
+    fn all_transparent(_: &[Self]) -> bool {
+        false
+    }
+    fn all_opaque(_: &[Self]) -> bool {
+        true
+    }
+}
+fn main() -> std::process::ExitCode {
+    match run() {
+        Ok(code) => code,
+        Err(err) => {
+            log::error(&err);
+            err.class.exit_code()
+        }
+    }
+}
+
+fn run() -> Result<std::process::ExitCode, Error> {
+    let (config, mut problems) = config::load();
+    let (env, env_problems) = config::from_env();
+    problems.extend(env_problems);
+    let sources = config::Sources { config, env };
+    let (command, global) = args::parse(std::env::args_os().skip(1), &sources)
+        .map_err(|message| Error::new(Class::Usage, message))?;
+    log::set_verbosity(global.verbosity);
+    if let Some(log_file) = &global.log_file {
+        if let Err(io) = log::set_log_file(log_file) {
+            // Only an aid to debugging, never worth failing the real work over.
+            log::warn(format_args!(
+                "failed to open --log-file {}: {io}",
+                log_file.display()
+            ));
+        }
+    }
+    // Batches name each item as they go.
+    match &command {
+        args::Command::Sizes(args::Sizes { input, .. })
+        | args::Command::Probe { input }
+        | args::Command::Validate { input, .. }
+        | args::Command::Extract { input, .. }
+        | args::Command::ListChunks { input }
+        | args::Command::Info { input } => log::set_input(input.display()),
+        _ => (),
+    }
+    if let (true, Some(problem)) = (global.strict, problems.first()) {
+        return Err(Error::new(Class::Usage, format!("invalid {problem}")));
+    }
+    for problem in &problems {
+        log::warn(format_args!("ignoring invalid {problem}"));
+    }
+    let scan = Scan {
+        strict: global.strict,
+        lenient: global.lenient,
+        sidecar: global.sidecar,
+        max_chunks: global.max_chunks,
+        max_thumb_bytes: global.max_thumb_bytes,
+        max_encoded_ratio: if global.lenient {
+            LENIENT_ENCODED_RATIO
+        } else {
+            MAX_ENCODED_RATIO
+        },
+        size: None,
+        chunk: global.chunk,
+        recover: global.recover,
+        flatten: global.allow_flatten,
+        accept_form: global.accept_form,
+        colorspace: match global.colorspace {
+            args::Colorspace::Auto => None,
+            args::Colorspace::Srgb => Some(qoi::ColorSpace::Srgb),
+            args::Colorspace::Linear => Some(qoi::ColorSpace::Linear),
+        },
+    };
+    match command {
+        args::Command::Thumbnail(args) => {
+            let uri = input_uri(&args.input, args.uri)?;
+            let mtime = args.mtime.map(std::time::Duration::from_secs);
+            let input = match args.in_fd {
+                Some(fd) => Source::Fd {
+                    fd,
+                    file: open_in_fd(fd)?,
+                },
+                None => Source::Path(&args.input),
+            };
+            log::set_input(&input);
+            let out_file = args.out_fd.map(open_out_fd).transpose()?;
+            let out = match (args.out_fd, &out_file) {
+                (Some(fd), Some(file)) => Destination::Fd { fd, file },
+                _ => Destination::Path(&args.output),
+            };
+            let mut timings = timings::Timings::start(args.options.timings);
+            if args.dry_run {
+                let result = dry_run(input, out, &uri, mtime, &args.options, scan, &mut timings);
+                if timings.enabled() {
+                    // Asked for explicitly, so not subject to --quiet.
+                    eprintln!("{timings}");
+                }
+                let plan = result?;
+                match args.report {
+                    Some(args::ReportFormat::Jsonl) => println!("{}", plan.to_json()),
+                    None => print!("{plan}"),
+                }
+                return Ok(std::process::ExitCode::SUCCESS);
+            }
+            let result = thumbnail_one(input, out, &uri, mtime, &args.options, scan, &mut timings);
+            if timings.enabled() {
+                // Asked for explicitly, so not subject to --quiet.
+                eprintln!("{timings}");
+            }
+            result?;
+        }
+        args::Command::Batch(batch) => return batch::run(&batch, scan),
+        args::Command::Sizes(sizes) => return sizes::run(&sizes, scan),
+        args::Command::Probe { input } => {
+            if let Err(err) = probe(&input, scan) {
+                if err.class != Class::NoThumbnail {
+                    log::error(&err);
+                }
+                return Ok(err.class.exit_code());
+            }
+        }
+        args::Command::Validate {
+            input,
+            max_dimension,
+        } => {
+            let issues = validate::validate(&input, max_dimension, scan)?;
+            if !issues.is_empty() {
+                for issue in &issues {
+                    println!("{}: {issue}", input.display());
+                }
+                return Ok(std::process::ExitCode::FAILURE);
+            }
+        }
+        args::Command::Extract { input, output } => {
+            extract(&input, &output, scan)?;
+        }
+        args::Command::ListChunks { input } => list_chunks(&input, scan)?,
+        args::Command::Info { input } => match info(&input, scan) {
+            Ok(info) => println!("{}", info.to_json()),
+            Err(err) => {
+                println!("{}", report::error_json(&err));
+                return Ok(err.class.exit_code());
+            }
+        },
+        args::Command::Help => println!("{}", args::usage()),
+        args::Command::Version => {
+            println!("fuzzpaint-thumbnailer {}", env!("CARGO_PKG_VERSION"));
+        }
+    }
+    Ok(std::process::ExitCode::SUCCESS)
+}
+
+/// `uri`, or the `file://` URI of `input` if none was given.
+fn input_uri(input: &Path, uri: Option<String>) -> Result<String, Error> {
+    match uri {
+        Some(uri) => Ok(uri),
+        None if input.as_os_str() == "-" => Err(Error::new(
+            Class::Usage,
+            "<in_uri> is required when reading from stdin",
+        )),
+        None => archive::member_uri(input).unwrap_or_else(|| file_uri(input)),
+    }
+}
+
+/// `file://` URI of a local path.
+fn file_uri(path: &Path) -> Result<String, Error> {
+    let path = std::fs::canonicalize(path)
+        .map_err(|io| Error::new(Class::InputIo, format!("failed to access in_path: {io}")))?;
+    Ok(xdg::path_uri(&path))
+}
+
+/// A readable, seekable document source.
+trait BufReadSeek: BufRead + Seek {}
+impl<T: BufRead + Seek> BufReadSeek for T {}
+
+/// A document opened by [`open_input`].
+struct Opened {
+    fzp: Box<dyn BufReadSeek>,
+    /// Modification time (required for thumbnailing according to XDG), which is unknown for
+    /// stdin.
+    mtime: Option<std::time::Duration>,
+    /// How the file was when opened, or `None` for stdin, which can't change under us.
+    version: Option<Version>,
+    /// Where a sidecar thumbnail of the document would be, beside its path, or `None` for stdin
+    /// and file descriptors, which have no path to be beside.
+    sidecar: Option<std::path::PathBuf>,
+}
+
+/// How an input file was when opened, to tell whether it's been written to since.
+///
+/// Documents saved by replacing the file leave the one we have open as it was, so only writes
+/// in place are ever noticed, which are the ones that matter.
+struct Version {
+    /// The same open file, sharing its position.
+    file: std::fs::File,
+    /// Where in it the document starts.
+    start: u64,
+    modified: std::time::SystemTime,
+    len: u64,
+    /// Given in place of the file's own modification time.
+    mtime_override: Option<std::time::Duration>,
+}
+impl Version {
+    fn of(file: &std::fs::File, mtime_override: Option<std::time::Duration>) -> IOResult<Self> {
+        let mut file = file.try_clone()?;
+        let meta = file.metadata()?;
+        Ok(Self {
+            start: file.stream_position()?,
+            modified: meta.modified()?,
+            len: meta.len(),
+            file,
+            mtime_override,
+        })
+    }
+    /// Whether the file has been modified or changed size since. If that can't be told, it's
+    /// taken not to have been.
+    fn changed(&self) -> bool {
+        self.file.metadata().is_ok_and(|meta| {
+            meta.len() != self.len || meta.modified().is_ok_and(|time| time != self.modified)
+        })
+    }
+    /// Open the document again from the start, as it is now.
+    fn reopen(&self) -> Result<Opened, Error> {
+        let reopen_err =
+            |io: IOError| Error::new(Class::InputIo, format!("failed to reopen in_path: {io}"));
+        let mut file = self.file.try_clone().map_err(reopen_err)?;
+        file.seek(std::io::SeekFrom::Start(self.start))
+            .map_err(reopen_err)?;
+        input_file(file, self.mtime_override)
+    }
+}
+
+/// Open `in_path`, or stdin for `-`. A directory is taken to be an unpacked bundle, as autosave
+/// may leave, and its [`BUNDLE_DOCUMENT`] opened instead. Compressed documents are decompressed,
+/// and documents within archives extracted.
+///
+/// If `mtime_override` is given, it is taken as the modification time instead and the file
+/// isn't statted for it.
+fn open_input(
+    in_path: &Path,
+    mtime_override: Option<std::time::Duration>,
+) -> Result<Opened, Error> {
+    if in_path.as_os_str() == "-" {
+        let stdin = read_stdin()?;
+        return Ok(Opened {
+            fzp: compressed::decompress(Box::new(stdin))?,
+            mtime: mtime_override,
+            version: None,
+            sidecar: None,
+        });
+    }
+
+    if let Some(opened) = archive::open_member(in_path, mtime_override) {
+        return opened;
+    }
+    let access_err =
+        |io: IOError| Error::new(Class::InputIo, format!("failed to access in_path: {io}"));
+    let file = std::fs::File::open(in_path).map_err(access_err)?;
+    let with_sidecar = |opened: Opened| Opened {
+        sidecar: Some(sidecar_path(in_path)),
+        ..opened
+    };
+    if !file.metadata().map_err(access_err)?.is_dir() {
+        return input_file(file, mtime_override).map(with_sidecar);
+    }
+    let member = match std::fs::File::open(in_path.join(BUNDLE_DOCUMENT)) {
+        Ok(member) => member,
+        Err(io) if io.kind() == std::io::ErrorKind::NotFound => {
+            return Err(Error::new(
+                Class::NoThumbnail,
+                format!(
+                    "in_path is a directory without a {BUNDLE_DOCUMENT}, not a document bundle"
+                ),
+            ))
+        }
+        Err(io) => return Err(access_err(io)),
+    };
+    log::verbose(format_args!("reading bundle member {BUNDLE_DOCUMENT}"));
+    // Its own mtime, which changes with the document, unlike the directory's.
+    input_file(member, mtime_override).map(with_sidecar)
+}
+
+/// Where a sidecar thumbnail of the document at `in_path` would be: `in_path` with `.thumb`
+/// appended, as left by optimizers that strip thumbnails from documents.
+fn sidecar_path(in_path: &Path) -> std::path::PathBuf {
+    let mut sidecar = in_path.as_os_str().to_owned();
+    sidecar.push(".thumb");
+    sidecar.into()
+}
+
+/// Read an already-open document, as [`open_input`] would a path.
+fn input_file(
+    file: std::fs::File,
+    mtime_override: Option<std::time::Duration>,
+) -> Result<Opened, Error> {
+    let access_err =
+        |io: IOError| Error::new(Class::InputIo, format!("failed to access in_path: {io}"));
+    let modified_unix_time = match mtime_override {
+        Some(mtime) => mtime,
+        None => file
+            .metadata()
+            .and_then(|meta| meta.modified())
+            .map_err(access_err)?
+            .duration_since(std::time::SystemTime::UNIX_EPOCH)
+            // As left by FAT imports and mistyped `touch`es. Not worth failing over, nor worth
+            // a negative `Thumb::MTime` that shells may not parse.
+            .unwrap_or_else(|_| {
+                log::warn("in_path was modified before 1970, taking it to be at 1970");
+                std::time::Duration::ZERO
+            }),
+    };
+    let version = Version::of(&file, mtime_override).map_err(access_err)?;
+    let input = mapped::map(file).unwrap_or_else(|file| Box::new(BufReader::new(file)));
+    Ok(Opened {
+        fzp: compressed::decompress(input)?,
+        mtime: Some(modified_unix_time),
+        version: Some(version),
+        sidecar: None,
+    })
+}
+
+/// Where to read a document from.
+enum Source<'a> {
+    /// A file to open, or stdin for `-`.
+    Path(&'a Path),
+    /// An already-open file.
+    Fd { fd: i32, file: std::fs::File },
+}
+impl std::fmt::Display for Source<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Path(path) => write!(f, "{}", path.display()),
+            Self::Fd { fd, .. } => write!(f, "/dev/fd/{fd}"),
+        }
+    }
+}
+
+/// Where to write output.
+#[derive(Clone, Copy)]
+enum Destination<'a> {
+    /// A file to create, or stdout for `-`.
+    Path(&'a Path),
+    /// An already-open file, placed by whoever opened it.
+    Fd { fd: i32, file: &'a std::fs::File },
+    /// Nowhere, for `--dry-run`.
+    Discard,
+}
+impl Destination<'_> {
+    fn is_stdout(&self) -> bool {
+        matches!(self, Self::Path(path) if path.as_os_str() == "-")
+    }
+}
+impl std::fmt::Display for Destination<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Path(path) => write!(f, "{}", path.display()),
+            Self::Fd { fd, .. } => write!(f, "/dev/fd/{fd}"),
+            Self::Discard => write!(f, "/dev/null"),
+        }
+    }
+}
+
+/// Take ownership of the inherited descriptor `fd`, which must be open for writing (if `write`)
+/// or reading. On failure, describes why not.
+#[cfg(unix)]
+fn inherit_fd(fd: i32, write: bool) -> Result<std::fs::File, String> {
+    use std::os::fd::{FromRawFd, OwnedFd};
+    // SAFETY: Only reads the descriptor's flags, and fails harmlessly if it isn't open.
+    let flags = unsafe { libc::fcntl(fd, libc::F_GETFL) };
+    if flags == -1 {
+        return Err(format!("is not open: {}", IOError::last_os_error()));
+    }
+    let (unusable, access) = if write {
+        (libc::O_RDONLY, "writing")
+    } else {
+        (libc::O_WRONLY, "reading")
+    };
+    if flags & libc::O_ACCMODE == unusable {
+        return Err(format!("is not open for {access}"));
+    }
+    // SAFETY: The descriptor is open, and was handed to us to own.
+    Ok(std::fs::File::from(unsafe { OwnedFd::from_raw_fd(fd) }))
+}
+
+/// Take ownership of the inherited descriptor `fd`, which must be open for writing and not a
+/// directory.
+#[cfg(unix)]
+fn open_out_fd(fd: i32) -> Result<std::fs::File, Error> {
+    let fd_err = |why: String| Error::new(Class::OutputIo, format!("--out-fd {fd} {why}"));
+    let file = inherit_fd(fd, true).map_err(fd_err)?;
+    let meta = file
+        .metadata()
+        .map_err(|io| fd_err(format!("could not be statted: {io}")))?;
+    if meta.is_dir() {
+        return Err(fd_err("is a directory".into()));
+    }
+    Ok(file)
+}
+#[cfg(not(unix))]
+fn open_out_fd(_fd: i32) -> Result<std::fs::File, Error> {
+    Err(Error::new(
+        Class::OutputIo,
+        "--out-fd is only supported on Unix",
+    ))
+}
+
+/// Take ownership of the inherited descriptor `fd`, which must be a regular file open for
+/// reading, rewound to the start.
+#[cfg(unix)]
+fn open_in_fd(fd: i32) -> Result<std::fs::File, Error> {
+    let fd_err = |why: String| Error::new(Class::InputIo, format!("--in-fd {fd} {why}"));
+    let mut file = inherit_fd(fd, false).map_err(fd_err)?;
+    let meta = file
+        .metadata()
+        .map_err(|io| fd_err(format!("could not be statted: {io}")))?;
+    if !meta.is_file() {
+        return Err(fd_err("is not a regular file".into()));
+    }
+    file.rewind()
+        .map_err(|io| fd_err(format!("is not seekable: {io}")))?;
+    Ok(file)
+}
+#[cfg(not(unix))]
+fn open_in_fd(_fd: i32) -> Result<std::fs::File, Error> {
+    Err(Error::new(
+        Class::InputIo,
+        "--in-fd is only supported on Unix",
+    ))
+}
+
+/// Read `input` and write the thumbnail to `out`, writing a fail marker if anything goes wrong
+/// after the input is opened.
+///
+/// If `out` is a path that's already a thumbnail of this version of the input, it's left alone
+/// unless forced.
+///
+/// `mtime_override` replaces the input's modification time, see [`open_input`].
+/// The thumbnail is found as `scan` says.
+/// Stages are timed into `timings`, if it's enabled, even if they fail.
+///
+/// Returns the dimensions of the written thumbnail.
+fn thumbnail_one(
+    input: Source,
+    out: Destination,
+    in_uri: &str,
+    mtime_override: Option<std::time::Duration>,
+    options: &args::Options,
+    scan: Scan,
+    timings: &mut timings::Timings,
+) -> Result<(u32, u32), Error> {
+    // Stands in for a bug, so tests can check a panic is contained to the document that hit it.
+    #[cfg(debug_assertions)]
+    if let (Source::Path(in_path), Some(name)) =
+        (&input, std::env::var_os("FUZZPAINT_THUMB_TEST_PANIC"))
+    {
+        assert!(
+            in_path.file_name() != Some(&name),
+            "panicking on {} as asked",
+            in_path.display()
+        );
+    }
+    let opened = match input {
+        Source::Path(in_path) => open_input(in_path, mtime_override),
+        Source::Fd { file, .. } => input_file(file, mtime_override),
+    };
+    timings.open = timings.lap();
+    let opened = opened?;
+    let modified_unix_time = opened.mtime;
+
+    if let Some(size) = fresh_output(out, in_uri, modified_unix_time, options) {
+        log::verbose(log::Stage::Fresh);
+        return Ok(size);
+    }
+
+    let result = thumbnail(opened, options, out, in_uri, scan, timings);
+    // No file to stat for stdin, so no mtime and no fail marker unless one was given. Nor if
+    // it's since changed, as this isn't the version that failed, or if it wasn't the document
+    // that failed.
+    if let (Err(err), Some(mtime)) = (&result, modified_unix_time) {
+        if err.class.marks_failed() {
+            // Tell the shell not to bother retrying this version of the file.
+            // Best-effort - if the cache isn't writable, there's nothing more we can do.
+            let _ = xdg::write_fail_marker(in_uri, mtime.as_secs(), &err.to_string());
+        }
+    }
+    result
+}
+
+/// If `out` is a path that's already a thumbnail of this version of the input, and we aren't
+/// forced to regenerate it, its dimensions.
+fn fresh_output(
+    out: Destination,
+    in_uri: &str,
+    modified_unix_time: Option<std::time::Duration>,
+    options: &args::Options,
+) -> Option<(u32, u32)> {
+    match (options.force, modified_unix_time, out) {
+        (false, Some(mtime), Destination::Path(out_path)) if out_path.as_os_str() != "-" => {
+            xdg::fresh_thumbnail(out_path, in_uri, mtime.as_secs())
+        }
+        _ => None,
+    }
+}
+
+/// Everything [`thumbnail_one`] would do, short of writing anything to `out` (or a fail marker).
+///
+/// The thumbnail is still read and rendered in full, so any error it would hit is hit.
+fn dry_run(
+    input: Source,
+    out: Destination,
+    in_uri: &str,
+    mtime_override: Option<std::time::Duration>,
+    options: &args::Options,
+    scan: Scan,
+    timings: &mut timings::Timings,
+) -> Result<report::Plan, Error> {
+    let input_name = input.to_string();
+    let opened = match input {
+        Source::Path(in_path) => open_input(in_path, mtime_override),
+        Source::Fd { file, .. } => input_file(file, mtime_override),
+    };
+    timings.open = timings.lap();
+    let opened = opened?;
+    let modified_unix_time = opened.mtime;
+    let fresh = fresh_output(out, in_uri, modified_unix_time, options).is_some();
+    let (width, height) = thumbnail(opened, options, Destination::Discard, in_uri, scan, timings)?;
+    Ok(report::Plan {
+        input: input_name,
+        output: out.to_string(),
+        uri: in_uri.to_owned(),
+        size: options.size,
+        width,
+        height,
+        mtime: modified_unix_time,
+        fresh,
+    })
+}
+
+/// Describe the thumbnail of `in_path` from its header, without decoding it.
+fn info(in_path: &Path, scan: Scan) -> Result<report::ThumbnailInfo, Error> {
+    let Opened { fzp, mtime, .. } = open_input(in_path, None)?;
+    let Found { mut thmb, .. } =
+        find_thmb(fzp, scan).map_err(|err| err.context("failed to parse input file"))?;
+    let thumbnail_bytes = thmb.remaining();
+    let header = if is_png(&mut thmb) {
+        png_header(thmb)?.1
+    } else {
+        *qoi::Decoder::from_stream(thmb)
+            .map_err(|img| qoi_error("failed to parse thumbnail header", img))?
+            .header()
+    };
+    Ok(report::ThumbnailInfo {
+        width: header.width,
+        height: header.height,
+        channels: header.channels.as_u8(),
+        colorspace: header.colorspace,
+        thumbnail_bytes,
+        mtime,
+    })
+}
+
+/// Printable form of a fourcc, or any other bytes, escaping anything that isn't printable ASCII.
+fn escape_fourcc(fourcc: &[u8]) -> String {
+    fourcc
+        .iter()
+        .map(|&b| match b {
+            b'"' | b'\\' => format!("\\{}", b as char),
+            b' '..=b'~' => (b as char).to_string(),
+            _ => format!("\\x{b:02x}"),
+        })
+        .collect()
+}
+
+/// Print the offset, fourcc, and declared size of every chunk in `in_path`, one per line, as
+/// thumbnailing reads them: descending into `LIST`s, whose chunks are indented, and skipping
+/// malformed headers with [`Scan::lenient`]. Chunks that claim to extend past the end of the file
+/// are flagged, and anything coped with is warned about once the listing is done.
+fn list_chunks(in_path: &Path, scan: Scan) -> Result<(), Error> {
+    use std::io::Write;
+    let parse_err = |err: Error| err.context("failed to parse input file");
+    let mut fzp = open_input(in_path, None)?.fzp;
+    let header = read_fzp_header(&mut fzp, scan.accept_form)
+        .map_err(|io| Error::from_input("failed to parse input file", &io))?;
+    let mut warnings = Vec::new();
+    let DocumentChunks {
+        mut chunks,
+        file_len,
+        ..
+    } = document_chunks(
+        &mut *fzp as &mut dyn BufReadSeek,
+        &header,
+        scan.lenient,
+        &mut warnings,
+    )
+    .map_err(parse_err)?;
+
+    let mut stdout = std::io::stdout().lock();
+    let mut print = |depth: u32, offset: u64, fourcc: &[u8; 4], size: u64| {
+        // RF64 sizes may be anything at all, so compare without adding to them.
+        let past_eof = if size > file_len.saturating_sub(offset + 8) {
+            " past-eof"
+        } else {
+            ""
+        };
+        writeln!(
+            stdout,
+            "{:indent$}offset={offset} fourcc=\"{}\" size={size}{past_eof}",
+            "",
+            escape_fourcc(fourcc),
+            indent = 2 * depth as usize,
+        )
+        .map_err(|io| Error::new(Class::OutputIo, format!("failed to write listing: {io}")))
+    };
+    print(0, 0, &header.magic, header.len)?;
+    if let Some(ds64_len) = header.ds64_len {
+        print(0, 12, b"ds64", ds64_len.into())?;
+    }
+    let walked = walk_chunks(&mut chunks, 0, &mut warnings, &mut |chunk, depth| {
+        print(depth, chunk.offset, &chunk.fourcc, chunk.declared)
+    });
+    // After the listing, as they're only about what was coped with in reading it.
+    for warning in &warnings {
+        log::warn(format_args!("coped with {warning}"));
+    }
+    walked.map_err(parse_err)
+}
+
+/// Copy the thumbnail of `in_path` to `out_path` (or stdout, for `-`) exactly as stored.
+fn extract(in_path: &Path, out_path: &Path, scan: Scan) -> Result<(), Error> {
+    let fzp = open_input(in_path, None)?.fzp;
+    let Found {
+        thmb: mut qoi_reader,
+        ..
+    } = find_thmb(fzp, scan).map_err(|err| err.context("failed to parse input file"))?;
+    let len = qoi_reader.remaining();
+    if len == 0 {
+        return Err(Error::new(Class::Corrupt, "thumbnail chunk is empty"));
+    }
+    // Read it all before touching the output, so a truncated chunk leaves nothing behind.
+    let mut qoi = Vec::new();
+    qoi_reader
+        .read_to_end(&mut qoi)
+        .map_err(|io| Error::from_input("failed to read thumbnail", &io))?;
+    if (qoi.len() as u64) < len {
+        return Err(Error::new(
+            Class::Corrupt,
+            format!("thumbnail chunk truncated after {} bytes", qoi.len()),
+        ));
+    }
+    write_output(Destination::Path(out_path), "thumbnail", |sink| {
+        sink.write_all(&qoi)
+            .map_err(|io| Error::new(Class::OutputIo, format!("failed to write thumbnail: {io}")))
+    })
+}
+
+/// Check whether `in_path` has a thumbnail, without decoding it.
+fn probe(in_path: &Path, scan: Scan) -> Result<(), Error> {
+    let fzp = open_input(in_path, None)?.fzp;
+    find_thmb(fzp, scan).map_err(|err| err.context("failed to parse input file"))?;
+    Ok(())
+}
+
+/// Buffer stdin, since parsing requires `Seek`.
+///
+/// Documents of more than [`MAX_STDIN_BYTES`] are refused rather than cut short, which would
+/// leave the chunks that ran past the cut looking truncated.
+fn read_stdin() -> Result<std::io::Cursor<Vec<u8>>, Error> {
+    let mut data = Vec::new();
+    // One more than allowed, to tell if there was more.
+    std::io::stdin()
+        .lock()
+        .take(MAX_STDIN_BYTES + 1)
+        .read_to_end(&mut data)
+        .map_err(|io| Error::from_input("failed to read from stdin", &io))?;
+    if data.len() as u64 > MAX_STDIN_BYTES {
+        return Err(Error::new(
+            Class::Corrupt,
+            format!(
+                "input too large to read from stdin, over {} MiB",
+                MAX_STDIN_BYTES >> 20
+            ),
+        ));
+    }
+    Ok(std::io::Cursor::new(data))
+}
+
+/// Classify an error from the QOI decoder.
+fn qoi_error(context: &str, err: qoi::Error) -> Error {
+    match err {
+        qoi::Error::IoError(io) => Error::from_input(context, &io),
+        // Decoding from memory, just as decoding from a reader would have it.
+        qoi::Error::UnexpectedBufferEnd => Error::from_input(
+            context,
+            &IOError::new(
+                std::io::ErrorKind::UnexpectedEof,
+                "failed to fill whole buffer",
+            ),
+        ),
+        other => Error::new(Class::Corrupt, format!("{context}: {other}")),
+    }
+}
+
+/// Decode, resize, and write the thumbnail of an opened fzp file.
+///
+/// `Thumb::MTime` is omitted if the modification time is unknown.
+/// Returns the dimensions of the written thumbnail.
+fn thumbnail(
+    opened: Opened,
+    options: &args::Options,
+    out: Destination,
+    in_uri: &str,
+    scan: Scan,
+    timings: &mut timings::Timings,
+) -> Result<(u32, u32), Error> {
+    let scan = Scan {
+        size: options.size.map(|size| size.width.max(size.height)),
+        ..scan
+    };
+    let (image, modified_unix_time) =
+        read_settled(opened, options.max_dimension, scan, Some(options), timings)?;
+    check_blank(&image, options)?;
+    render(&image, options, out, in_uri, modified_unix_time, timings)
+}
+
+/// Fail as though there were no thumbnail if every pixel of it is fully transparent, as there's
+/// nothing to see, unless `options` allow it.
+fn check_blank(image: &DecodedImage, options: &args::Options) -> Result<(), Error> {
+    if image.blank && !options.allow_blank {
+        return Err(Error::new(
+            Class::NoThumbnail,
+            "thumbnail is blank, every pixel fully transparent",
+        ));
+    }
+    Ok(())
+}
+
+/// [`read_thumbnail`], reading it all again if the file was written to meanwhile, as by being
+/// saved over, so the thumbnail is never of one version and stamped with the time of another.
+/// If it changes again, it's given up on as [`Class::Transient`].
+///
+/// Also returns the modification time of the version read.
+fn read_settled(
+    opened: Opened,
+    max_dimension: u32,
+    scan: Scan,
+    render_with: Option<&args::Options>,
+    timings: &mut timings::Timings,
+) -> Result<(DecodedImage, Option<std::time::Duration>), Error> {
+    let Opened {
+        fzp,
+        mtime,
+        version,
+        sidecar,
+    } = opened;
+    let sidecar = sidecar.as_deref();
+    let decoded = read_thumbnail(fzp, sidecar, max_dimension, scan, render_with, timings);
+    let Some(version) = version.filter(Version::changed) else {
+        return decoded.map(|image| (image, mtime));
+    };
+    log::warn("input file changed while it was read, reading it again");
+    let Opened {
+        fzp,
+        mtime,
+        version,
+        ..
+    } = version.reopen()?;
+    let decoded = read_thumbnail(fzp, sidecar, max_dimension, scan, render_with, timings);
+    if version.is_some_and(|version| version.changed()) {
+        return Err(Error::new(
+            Class::Transient,
+            "file changed during thumbnailing",
+        ));
+    }
+    decoded.map(|image| (image, mtime))
+}
+
+/// Find and decode the thumbnail of an opened fzp file, or if it has none and
+/// [`Scan::sidecar`], its `sidecar`.
+///
+/// If it'll only be rendered once, with `render_with`, a large thumbnail is scaled as it's
+/// decoded, see [`strips`].
+fn read_thumbnail<R: BufRead + Seek>(
+    mut fzp: R,
+    sidecar: Option<&Path>,
+    max_dimension: u32,
+    scan: Scan,
+    render_with: Option<&args::Options>,
+    timings: &mut timings::Timings,
+) -> Result<DecodedImage, Error> {
+    // ========== Read FZP ============
+    // Fetch a reader of the raw image data.
+    let qoi_reader = find_thmb(&mut fzp, scan);
+    timings.scan = timings.lap();
+    // ========== Read QOI ============
+    let decoded = qoi_reader
+        .map_err(|err| err.context("failed to parse input file"))
+        .and_then(|found| {
+            // Scaled down like any other, but far larger than any thumbnail.
+            let max_dimension = if found.flattened {
+                max_dimension.max(MAX_FLATTEN_DIMENSION)
+            } else {
+                max_dimension
+            };
+            let plan =
+                |size| render_with.and_then(|options| scale_plan(size, &found.info, options));
+            decode_qoi_planned(
+                found.thmb,
+                max_dimension,
+                scan.max_encoded_ratio,
+                scan.colorspace,
+                plan,
+            )
+            .map(|decoded| DecodedImage {
+                mime: found.mime,
+                canvas: found.canvas,
+                info: found.info,
+                ..decoded
+            })
+        });
+    let decoded = match (decoded, sidecar) {
+        (Err(err), Some(sidecar)) if scan.sidecar && err.class == Class::NoThumbnail => {
+            match read_sidecar(sidecar, max_dimension, scan) {
+                Ok(Some(decoded)) => {
+                    log::verbose(format_args!(
+                        "document has no thumbnail, using sidecar {}",
+                        sidecar.display()
+                    ));
+                    Ok(decoded)
+                }
+                Ok(None) => Err(err),
+                Err(sidecar_err) => Err(sidecar_err),
+            }
+        }
+        (decoded, _) => decoded,
+    };
+    let decoded = match decoded {
+        // Nothing to salvage from what couldn't be read.
+        Err(err) if scan.recover && err.class != Class::InputIo => {
+            match recover(&mut fzp, max_dimension, scan.colorspace) {
+                Ok(Some((offset, decoded))) => {
+                    log::warn(format_args!(
+                        "recovered a thumbnail from offset {offset}, after: {err}"
+                    ));
+                    Ok(decoded)
+                }
+                // Only ever a fallback, so the original failure is what matters.
+                Ok(None) | Err(_) => Err(err),
+            }
+        }
+        decoded => decoded,
+    };
+    timings.decode = timings.lap();
+    let decoded = decoded?;
+    log::verbose(log::Stage::Decoded {
+        width: decoded.width.get(),
+        height: decoded.height.get(),
+    });
+    Ok(decoded)
+}
+
+/// Decode the QOI sidecar thumbnail at `path`, held to the same limits as a thumbnail within
+/// a document, or `None` if there isn't one.
+fn read_sidecar(
+    path: &Path,
+    max_dimension: u32,
+    scan: Scan,
+) -> Result<Option<DecodedImage>, Error> {
+    let Scan {
+        max_thumb_bytes,
+        max_encoded_ratio,
+        colorspace,
+        ..
+    } = scan;
+    let context = format!("failed to read sidecar {}", path.display());
+    let file = match std::fs::File::open(path) {
+        Ok(file) => file,
+        Err(io) if io.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(io) => return Err(Error::from_input(&context, &io)),
+    };
+    let len = file
+        .metadata()
+        .map_err(|io| Error::from_input(&context, &io))?
+        .len();
+    if len > max_thumb_bytes {
+        return Err(Error::new(
+            Class::Corrupt,
+            format!("{context}: thumbnail of {len} bytes exceeds limit of {max_thumb_bytes}"),
+        ));
+    }
+    decode_qoi(
+        MyTake::new(BufReader::new(file), len),
+        max_dimension,
+        max_encoded_ratio,
+        colorspace,
+    )
+    .map(Some)
+    .map_err(|err| err.context(&context))
+}
+
+/// Salvage a thumbnail from a document that couldn't be parsed, decoding from each `qoif` magic
+/// in its first [`RECOVER_SCAN_LEN`] bytes until one succeeds, and returning its offset too.
+///
+/// Anything that happens to decode will, however wrong, so this is never done unless asked for.
+fn recover<R: BufRead + Seek>(
+    fzp: &mut R,
+    max_dimension: u32,
+    colorspace: Option<qoi::ColorSpace>,
+) -> IOResult<Option<(u64, DecodedImage)>> {
+    fzp.rewind()?;
+    let mut head = Vec::new();
+    fzp.by_ref().take(RECOVER_SCAN_LEN).read_to_end(&mut head)?;
+    let hits = head
+        .windows(4)
+        .enumerate()
+        .filter(|(_, magic)| magic == b"qoif")
+        .map(|(offset, _)| offset as u64);
+    for offset in hits {
+        // Decoded from the document rather than `head`, in case it runs past the end of it.
+        fzp.seek(std::io::SeekFrom::Start(offset))?;
+        // However far it goes, so however many bytes that takes.
+        let qoi_reader = MyTake::new(&mut *fzp, u64::MAX);
+        if let Ok(decoded) = decode_qoi(qoi_reader, max_dimension, u64::MAX, colorspace) {
+            return Ok(Some((offset, decoded)));
+        }
+    }
+    Ok(None)
+}
+
+/// Crop, orient, resize, and write a decoded thumbnail.
+///
+/// `image` is only read from, so may be rendered again with other options.
+/// `Thumb::MTime` is omitted if `modified_unix_time` is `None`.
+/// Returns the dimensions of the written thumbnail.
+fn render(
+    image: &DecodedImage,
+    options: &args::Options,
+    out: Destination,
+    in_uri: &str,
+    modified_unix_time: Option<std::time::Duration>,
+    timings: &mut timings::Timings,
+) -> Result<(u32, u32), Error> {
+    let background = background(image, options);
+    match &image.pixels {
+        Pixels::Rgba(rgba) => render_pixels(
+            image,
+            rgba,
+            background,
+            options,
+            out,
+            in_uri,
+            modified_unix_time,
+            timings,
+        ),
+        // Padding is transparent unless there's an opaque background to fill it, and opaque
+        // pixels have nowhere to put the transparency.
+        Pixels::Rgb(rgb)
+            if options.square
+                && options.size.is_some()
+                && background.is_none_or(|[.., alpha]| alpha != u8::MAX) =>
+        {
+            let rgba: Vec<U8x4> = rgb
+                .iter()
+                .map(|&U8x3([r, g, b])| U8x4([r, g, b, u8::MAX]))
+                .collect();
+            render_pixels(
+                image,
+                &rgba,
+                background,
+                options,
+                out,
+                in_uri,
+                modified_unix_time,
+                timings,
+            )
+        }
+        Pixels::Rgb(rgb) => render_pixels(
+            image,
+            rgb,
+            background,
+            options,
+            out,
+            in_uri,
+            modified_unix_time,
+            timings,
+        ),
+    }
+}
+
+/// The colour to flatten the thumbnail onto, if any.
+fn background(image: &DecodedImage, options: &args::Options) -> Option<[u8; 4]> {
+    match options.background {
+        Some(args::Background::Color(color)) => Some(color),
+        Some(args::Background::Document) => image.info.background(),
+        None => None,
+    }
+}
+
+/// [`render`], once the thumbnail's `pixels` are in a layout to be scaled and written in.
+#[allow(clippy::too_many_arguments)]
+fn render_pixels<P: Pixel>(
+    image: &DecodedImage,
+    pixels: &[P],
+    background: Option<[u8; 4]>,
+    options: &args::Options,
+    out: Destination,
+    in_uri: &str,
+    modified_unix_time: Option<std::time::Duration>,
+    timings: &mut timings::Timings,
+) -> Result<(u32, u32), Error> {
+    let DecodedImage {
+        width,
+        height,
+        mime,
+        canvas,
+        ref info,
+        ..
+    } = *image;
+
+    // ============= Crop ===============
+    let full = fit::Region::whole(width, height);
+    let region = match options.crop {
+        None => full,
+        Some(crop) => {
+            let (region, clamped) = fit::clamp_crop(crop, width, height);
+            if clamped {
+                let (left, top, width, height) = region.map_or((0, 0, 0, 0), |r| {
+                    (r.left, r.top, r.width.get(), r.height.get())
+                });
+                log::warn(format_args!(
+                    "crop extends past the {}x{} thumbnail, clamped to {left},{top},{width}x{height}",
+                    full.width, full.height
+                ));
+            }
+            let region = region.ok_or(Error::new(Class::Corrupt, "thumbnail has zero size"))?;
+            log::verbose(log::Stage::Cropped {
+                left: region.left,
+                top: region.top,
+                width: region.width.get(),
+                height: region.height.get(),
+            });
+            region
+        }
+    };
+
+    // ============= Orient ===============
+    // As shown in the editor, unless asked for otherwise.
+    let (rotate, flip) = orientation(info, options);
+    // Reorienting copies the image anyway, so the crop is taken out along with it.
+    let (width, height, pixels, region) = if rotate.is_none() && flip.is_none() {
+        (width, height, Cow::Borrowed(pixels), region)
+    } else {
+        let mut pixels = crop_pixels(pixels, width, region);
+        let (mut width, mut height) = (region.width, region.height);
+        if let Some(rotation) = rotate {
+            pixels = orient::rotate(
+                &pixels,
+                width.get() as usize,
+                height.get() as usize,
+                rotation,
+            );
+            if rotation != args::Rotation::Half {
+                (width, height) = (height, width);
+            }
+        }
+        if let Some(flip) = flip {
+            orient::flip(&mut pixels, width.get() as usize, flip);
+        }
+        log::verbose(log::Stage::Reoriented {
+            width: width.get(),
+            height: height.get(),
+        });
+        let whole = fit::Region::whole(width, height);
+        (width, height, Cow::Owned(pixels), whole)
+    };
+
+    // ============= Scale ===============
+    let (scaled_width, scaled_height, scaled) = match options.size {
+        // Native size, encode the image as-is.
+        None if region == fit::Region::whole(width, height) => {
+            log::verbose(log::Stage::Native);
+            (width, height, pixels)
+        }
+        // Native size, only the cropped rows need copying.
+        None => {
+            log::verbose(log::Stage::Native);
+            let cropped = crop_pixels(&pixels, width, region);
+            (region.width, region.height, Cow::Owned(cropped))
+        }
+        Some(size) => {
+            let (region, (scaled_width, scaled_height)) = scale_region(region, size, options);
+            let scaled = if image.prescaled {
+                // Already scaled just so, as it was decoded.
+                pixels
+            } else {
+                let filter = filter(region, (scaled_width, scaled_height), options);
+                let premultiply = premultiply(filter, options);
+                let threads = bands::count((scaled_width, scaled_height), options.threads);
+                // Nearest-neighbour only picks pixels, never blends them.
+                let scaled = if gamma_correct(info, options) && filter != args::Filter::Nearest {
+                    resize_linear(
+                        width,
+                        &pixels,
+                        region,
+                        (scaled_width, scaled_height),
+                        filter,
+                        premultiply,
+                        threads,
+                    )
                 } else {
-                    delta
+                    resize(
+                        (width, height),
+                        &pixels,
+                        region,
+                        (scaled_width, scaled_height),
+                        filter,
+                        premultiply,
+                        threads,
+                    )
                 };
-                self.cursor
-                    .checked_add_signed(delta)
-                    // Also catches past-the-start
-                    .ok_or_else(|| IOError::other(err_overflow_cursor))?
-            }
-            SeekFrom::Start(pos) => pos.min(self.remaining()),
-            SeekFrom::End(pos) => {
-                // Clamp upper bound, flip to positive for subtraction
-                let pos = pos.max(0).unsigned_abs();
-                self.len
-                    .checked_sub(pos)
-                    .ok_or_else(|| IOError::other(err_past_the_start))?
-            }
-        };
+                // Dealloc unscaled image asap
+                drop(pixels);
+                Cow::Owned(scaled)
+            };
+            log::verbose(log::Stage::Resized {
+                width: scaled_width.get(),
+                height: scaled_height.get(),
+            });
+            (scaled_width, scaled_height, scaled)
+        }
+    };
 
-        // Each branch checks this individually. Still, make very sure.
-        debug_assert!(new_cursor <= self.len);
+    // ============= Pad ===============
+    let (scaled_width, scaled_height, mut scaled) = match options.size {
+        Some(size) if options.square => {
+            // Never crop, should the fit round past the box.
+            let canvas_width = scaled_width.max(size.width.try_into().unwrap_or(scaled_width));
+            let canvas_height = scaled_height.max(size.height.try_into().unwrap_or(scaled_height));
+            // Transparent, or the background flattening would fill it with anyway. Opaque pixels
+            // are only padded when the background is opaque too.
+            let padded = canvas::pad(
+                &scaled,
+                (scaled_width.get() as usize, scaled_height.get() as usize),
+                (canvas_width.get() as usize, canvas_height.get() as usize),
+                P::from_rgba(background.unwrap_or([0; 4])),
+            );
+            log::verbose(log::Stage::Padded {
+                width: canvas_width.get(),
+                height: canvas_height.get(),
+            });
+            (canvas_width, canvas_height, Cow::Owned(padded))
+        }
+        _ => (scaled_width, scaled_height, scaled),
+    };
 
-        // We must seek the underlying reader with a Relative seek, as we
-        // don't know what it's End and Start are relative to ours
-        let delta: i64 = new_cursor
-            .checked_as::<i64>()
-            .zip(self.cursor.checked_as::<i64>())
-            .and_then(|(new, old)| new.checked_sub(old))
-            .ok_or_else(|| IOError::other("delta seek overflows"))?;
+    // ============= Flatten ===============
+    // After padding, so the padding takes the background too.
+    let opaque = match background {
+        Some(background) => {
+            let opaque = P::flatten(&mut scaled, background);
+            log::verbose(log::Stage::Flattened { opaque });
+            opaque
+        }
+        None => false,
+    };
+    // Nothing left for the alpha channel to say, so drop it, unless it's asked for. Whether
+    // flattened onto an opaque background, or opaque all along.
+    let repacked: Vec<u8>;
+    let (color, pixels) = match P::COLOR {
+        png::ColorType::Rgb if options.force_rgba => {
+            repacked = add_alpha(bytemuck::cast_slice(&scaled[..]));
+            (png::ColorType::Rgba, repacked.as_slice())
+        }
+        png::ColorType::Rgba if !options.force_rgba && (opaque || P::all_opaque(&scaled)) => {
+            repacked = drop_alpha(bytemuck::cast_slice(&scaled[..]));
+            (png::ColorType::Rgb, repacked.as_slice())
+        }
+        color => (color, bytemuck::cast_slice(&scaled[..])),
+    };
+    // Gray all over, so one channel says as much as three. Not if the profile or the document's
+    // background are in color, as a grayscale PNG has no way to say so.
+    let gray_ok = !options.no_optimize_color
+        && !options.force_rgba
+        && info.icc_profile().is_none()
+        && info
+            .background()
+            .is_none_or(|[r, g, b, _]| r == g && g == b);
+    let gray: Vec<u8>;
+    let (color, pixels) = match gray_ok.then(|| to_gray(color, pixels)).flatten() {
+        Some((gray_color, packed)) => {
+            log::verbose(log::Stage::Grayscale);
+            gray = packed;
+            (gray_color, gray.as_slice())
+        }
+        None => (color, pixels),
+    };
 
-        self.reader.seek(SeekFrom::Current(delta))?;
-        self.cursor = new_cursor;
+    timings.resize = timings.lap();
 
-        Ok(self.cursor)
+    // ============= Write PNG ===============
+    let png = ScaledImage {
+        width: scaled_width,
+        height: scaled_height,
+        color,
+        pixels,
+        mime,
+        // Without a document to say otherwise, the thumbnail is all there is.
+        canvas: canvas.unwrap_or((width.get(), height.get())),
+        info,
+    };
+    let mut digest = None;
+    let mut encode = |sink: &mut dyn std::io::Write| {
+        if !options.print_hash {
+            return write_png(sink, &png, in_uri, modified_unix_time, options);
+        }
+        let mut hashing = hash::Sha256Writer::new(sink);
+        write_png(&mut hashing, &png, in_uri, modified_unix_time, options)?;
+        digest = Some(hashing.finish());
+        Ok(())
+    };
+    let written = if options.data_uri {
+        let mut png = Vec::new();
+        encode(&mut png).and_then(|()| {
+            write_output(out, "data URI", |sink| {
+                use base64::Engine;
+                let base64 = base64::engine::general_purpose::STANDARD.encode(&png);
+                writeln!(sink, "data:image/png;base64,{base64}").map_err(|io| {
+                    Error::new(Class::OutputIo, format!("failed to write data URI: {io}"))
+                })
+            })
+        })
+    } else {
+        write_output(out, "png", encode)
+    };
+    timings.encode = timings.lap();
+    written?;
+    if let Some(digest) = digest {
+        let line = format!("sha256:{digest} {out}");
+        // Stdout is taken up by the image itself.
+        if out.is_stdout() {
+            eprintln!("{line}");
+        } else {
+            println!("{line}");
+        }
     }
-    fn stream_position(&mut self) -> IOResult<u64> {
-        Ok(self.cursor)
+    Ok((scaled_width.get(), scaled_height.get()))
+}
+
+/// How the thumbnail is oriented: as asked, or else as shown in the editor.
+fn orientation(
+    info: &DocumentInfo,
+    options: &args::Options,
+) -> (Option<args::Rotation>, Option<args::Flip>) {
+    if options.rotate.is_some() || options.flip.is_some() {
+        (options.rotate, options.flip)
+    } else {
+        info.orientation()
     }
 }
 
-/// Given a reader of fzp data, create a reader of the thumbnail data.
-/// Does not allocate except for errors.
-// A lot of this logic can be recycled from fuzzpaint-vk, with a shared library crate.
-fn read_fzp_thmb<R: Read + BufRead + Seek>(mut r: R) -> IOResult<MyTake<R>> {
-    let mut fzp_header = [0; 12];
-    r.read_exact(&mut fzp_header)?;
-    if &fzp_header[0..4] != b"RIFF" || &fzp_header[8..12] != b"fzp " {
-        return Err(IOError::other("unrecognized file type"));
+/// The part of `region` that's scaled to fit `size`, and the size it's scaled to. Never less than
+/// a pixel either way, however thin the region.
+fn scale_region(
+    region: fit::Region,
+    size: args::Size,
+    options: &args::Options,
+) -> (fit::Region, (std::num::NonZeroU32, std::num::NonZeroU32)) {
+    let (region, (mut scaled_width, mut scaled_height)) = match options.mode {
+        args::ScaleMode::Fit => (region, fit::fit(region.width, region.height, size)),
+        // Only the part of the region that survives trimming is scaled.
+        args::ScaleMode::Cover => fit::cover(region, size),
+    };
+    if options.no_upscale && (scaled_width > region.width || scaled_height > region.height) {
+        (scaled_width, scaled_height) = (region.width, region.height);
+    }
+    (region, (scaled_width, scaled_height))
+}
+
+/// How [`render`] will scale a thumbnail, worked out before it's decoded.
+#[derive(Clone, Copy)]
+struct ScalePlan {
+    region: fit::Region,
+    scaled: (std::num::NonZeroU32, std::num::NonZeroU32),
+    filter: args::Filter,
+    premultiply: bool,
+    threads: std::num::NonZeroUsize,
+}
+
+/// How [`render`] will scale a `width`x`height` thumbnail with `options`, or `None` if it won't,
+/// or will reorient it first, which needs all of it at once.
+fn scale_plan(
+    (width, height): (std::num::NonZeroU32, std::num::NonZeroU32),
+    info: &DocumentInfo,
+    options: &args::Options,
+) -> Option<ScalePlan> {
+    let size = options.size?;
+    // Strips are scaled as they're decoded, in sRGB.
+    if orientation(info, options) != (None, None) || gamma_correct(info, options) {
+        return None;
     }
-    let mut remaining_file_size = u32::from_le_bytes(fzp_header[4..8].try_into().unwrap());
+    let region = match options.crop {
+        None => fit::Region::whole(width, height),
+        Some(crop) => fit::clamp_crop(crop, width, height).0?,
+    };
+    let (region, scaled) = scale_region(region, size, options);
+    let filter = filter(region, scaled, options);
+    Some(ScalePlan {
+        region,
+        scaled,
+        filter,
+        premultiply: premultiply(filter, options),
+        threads: bands::count(scaled, options.threads),
+    })
+}
+
+/// Whether to scale in linear light, as asked, which only makes sense of pixels known to be sRGB.
+/// The PNG is tagged sRGB unless there's a profile, and thumbnails stored linear were converted.
+fn gamma_correct(info: &DocumentInfo, options: &args::Options) -> bool {
+    options.gamma_correct && info.icc_profile().is_none()
+}
 
-    // Reads a header and size
-    let read_block = |r: &mut R| -> IOResult<([u8; 4], u32)> {
-        let mut block_header = [0; 8];
-        r.read_exact(&mut block_header)?;
+/// The filter to scale `region` to `scaled` with: the one `options` ask for, if any, otherwise
+/// [`fit::choose_filter`]'s.
+fn filter(
+    region: fit::Region,
+    scaled: (std::num::NonZeroU32, std::num::NonZeroU32),
+    options: &args::Options,
+) -> args::Filter {
+    options
+        .filter
+        .unwrap_or_else(|| fit::choose_filter((region.width, region.height), scaled))
+}
 
-        let block_size = u32::from_le_bytes(block_header[4..8].try_into().unwrap());
+/// Whether to weigh colours by their alpha when scaling with `filter`, so that the colours of
+/// transparent pixels, which could be anything, don't bleed into the visible ones. Not for
+/// nearest-neighbour, which never blends, and would only lose the precision of faint pixels.
+fn premultiply(filter: args::Filter, options: &args::Options) -> bool {
+    !options.no_premultiply && filter != args::Filter::Nearest
+}
 
-        Ok((block_header[0..4].try_into().unwrap(), block_size))
+/// Write to `out`, describing failures as failing to write `what`.
+///
+/// Files are written under a temporary name beside their path and only renamed into place once
+/// complete, so a partial output is never visible under the real name. The name is unique to the
+/// call, as other threads may be writing to the same path. Already-open files are written in
+/// place.
+fn write_output(
+    out: Destination,
+    what: &str,
+    write: impl FnOnce(&mut dyn std::io::Write) -> Result<(), Error>,
+) -> Result<(), Error> {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    /// Temporary files created so far by this process.
+    static TEMP_FILES: AtomicU64 = AtomicU64::new(0);
+    let io_err = |io: IOError| Error::new(Class::OutputIo, format!("failed to write {what}: {io}"));
+    let out_path = match out {
+        Destination::Path(path) => path,
+        Destination::Fd { file, .. } => {
+            let mut file = Counted::new(file);
+            write(&mut file)?;
+            std::io::Write::flush(&mut file).map_err(io_err)?;
+            log::verbose(log::Stage::Wrote { bytes: file.count });
+            return Ok(());
+        }
+        Destination::Discard => return write(&mut std::io::sink()),
     };
+    if out_path.as_os_str() == "-" {
+        let mut stdout = Counted::new(std::io::stdout().lock());
+        write(&mut stdout)?;
+        std::io::Write::flush(&mut stdout).map_err(io_err)?;
+        log::verbose(log::Stage::Wrote {
+            bytes: stdout.count,
+        });
+        return Ok(());
+    }
+
+    let mut temp_path = out_path.as_os_str().to_owned();
+    temp_path.push(format!(
+        ".{}.{}.tmp",
+        std::process::id(),
+        TEMP_FILES.fetch_add(1, Ordering::Relaxed)
+    ));
+    // Never shared with another writer, even one left over from a crashed process.
+    let mut file = std::fs::File::options()
+        .write(true)
+        .create_new(true)
+        .open(&temp_path)
+        .map(Counted::new)
+        .map_err(|io| {
+            Error::new(
+                Class::OutputIo,
+                format!("failed to open out_path for writing: {io}"),
+            )
+        })?;
+    let result = write(&mut file)
+        .and_then(|()| file.inner.sync_all().map_err(io_err))
+        .and_then(|()| std::fs::rename(&temp_path, out_path).map_err(io_err));
+    match result {
+        Ok(()) => log::verbose(log::Stage::Wrote { bytes: file.count }),
+        // Best-effort, there's already an error to report.
+        Err(_) => {
+            let _ = std::fs::remove_file(&temp_path);
+        }
+    }
+    result
+}
 
-    // Read first block. If not `LIST INFO` chunk, thumb will be here.
-    let (block_header, block_size) = read_block(&mut r)?;
-    if block_header == *b"thmb" {
-        // Found thmb! Take only the reported data length.
-        return Ok(MyTake::new(r, block_size.min(remaining_file_size) as u64));
+/// Counts bytes written through it.
+struct Counted<W> {
+    inner: W,
+    count: u64,
+}
+impl<W> Counted<W> {
+    fn new(inner: W) -> Self {
+        Self { inner, count: 0 }
+    }
+}
+impl<W: std::io::Write> std::io::Write for Counted<W> {
+    fn write(&mut self, buf: &[u8]) -> IOResult<usize> {
+        let written = self.inner.write(buf)?;
+        self.count += written as u64;
+        Ok(written)
+    }
+    fn flush(&mut self) -> IOResult<()> {
+        self.inner.flush()
     }
+}
+
+/// Pixels of a decoded thumbnail, in the layout it was stored in.
+enum Pixels {
+    Rgba(Vec<U8x4>),
+    /// Opaque thumbnails, kept without the alpha channel they'd only fill with `u8::MAX`.
+    Rgb(Vec<U8x3>),
+}
 
-    // Wasn't the first one. fastforward, check second one.
-    r.seek(std::io::SeekFrom::Current(block_size as i64))?;
-    // We read a header and many bytes, update remaining file size.
-    remaining_file_size = remaining_file_size
-        .saturating_sub(block_size)
-        .saturating_sub(8);
+/// A decoded thumbnail, in RGBA8 or RGB8, always sRGB-encoded, see [`transfer`].
+struct DecodedImage {
+    /// Of the thumbnail as stored.
+    width: std::num::NonZeroU32,
+    height: std::num::NonZeroU32,
+    pixels: Pixels,
+    /// Whether `pixels` were already cropped and scaled as they were decoded, just as [`render`]
+    /// would, rather than being `width`x`height`.
+    prescaled: bool,
+    /// MIME type of the document it came from.
+    mime: &'static str,
+    /// Width and height of that document's canvas, if known.
+    canvas: Option<(u32, u32)>,
+    /// Title and such of that document.
+    info: DocumentInfo,
+    /// Whether every pixel of the thumbnail as stored is fully transparent, even if `pixels`
+    /// are prescaled.
+    blank: bool,
+}
 
-    // Read second block. last chance, if not here then nowhere!
-    let (block_header, block_size) = read_block(&mut r)?;
-    if block_header == *b"thmb" {
-        // Found thmb! Take only the reported data length.
-        Ok(MyTake::new(r, block_size.min(remaining_file_size) as u64))
-    } else {
-        // So sad :(
-        Err(IOError::other("document does not contain a thumbnail"))
+/// Fully decode a QOI thumbnail, or a PNG one, see [`decode_png`], rejecting empty images or
+/// those larger than `max_dimension` allows, see [`check_size`]. Taken to be from a fuzzpaint
+/// document, until the caller says otherwise, and in `colorspace`, if given, whatever it's tagged
+/// as.
+fn decode_qoi<R: BufRead>(
+    qoi_reader: MyTake<R>,
+    max_dimension: u32,
+    max_encoded_ratio: u64,
+    colorspace: Option<qoi::ColorSpace>,
+) -> Result<DecodedImage, Error> {
+    decode_qoi_planned(
+        qoi_reader,
+        max_dimension,
+        max_encoded_ratio,
+        colorspace,
+        |_| None,
+    )
+}
+
+/// [`decode_qoi`], unless it's large and `plan` has how it'll be scaled, in which case it's
+/// scaled as it's decoded instead, see [`strips`].
+fn decode_qoi_planned<R: BufRead>(
+    mut qoi_reader: MyTake<R>,
+    max_dimension: u32,
+    max_encoded_ratio: u64,
+    colorspace: Option<qoi::ColorSpace>,
+    plan: impl FnOnce((std::num::NonZeroU32, std::num::NonZeroU32)) -> Option<ScalePlan>,
+) -> Result<DecodedImage, Error> {
+    let header_err = |img| qoi_error("failed to parse thumbnail header", img);
+    let data_err = |img| qoi_error("failed to parse thumbnail data", img);
+    // Plugins may store it as PNG instead.
+    if is_png(&mut qoi_reader) {
+        return decode_png(qoi_reader, max_dimension, max_encoded_ratio, colorspace);
+    }
+    // Or as anything else, when there's a decoder for it.
+    #[cfg(feature = "extra-formats")]
+    if qoi_reader.fill_buf().ok().and_then(|data| data.first()) != Some(&b'q') {
+        let mut data = Vec::new();
+        qoi_reader
+            .read_to_end(&mut data)
+            .map_err(|io| Error::from_input("failed to read thumbnail", &io))?;
+        return formats::decode(&data, max_dimension, max_encoded_ratio, colorspace);
+    }
+    // Decoded where it lies if it's all in memory already, as when mapped, rather than copied
+    // out a few bytes at a time.
+    let len = qoi_reader.remaining();
+    if let Ok(data) = qoi_reader.fill_buf() {
+        if data.len() as u64 == len {
+            let mut image_decoder = qoi::Decoder::new(data).map_err(header_err)?;
+            let header = interpret(*image_decoder.header(), colorspace);
+            let size = check_size(&header, max_dimension)?;
+            check_len(&header, len, max_encoded_ratio)?;
+            let len_bytes = image_decoder.required_buf_len();
+            if let Some(plan) = (len_bytes >= strips::STRIP_MIN_BYTES)
+                .then(|| plan(size))
+                .flatten()
+            {
+                return decode_strips(header, size, plan, &data[QOI_HEADER_LEN..]);
+            }
+            return decode_qoi_with(header, size, len_bytes, |buf| {
+                image_decoder.decode_to_buf(buf).map(drop).map_err(data_err)
+            });
+        }
+    }
+    // In the channels it was stored with. Opaque thumbnails only gain an alpha channel if
+    // `render` pads them with transparency.
+    let mut image_decoder = qoi::Decoder::from_stream(qoi_reader).map_err(header_err)?;
+    let header = interpret(*image_decoder.header(), colorspace);
+    let size = check_size(&header, max_dimension)?;
+    check_len(&header, len, max_encoded_ratio)?;
+    let len_bytes = image_decoder.required_buf_len();
+    if let Some(plan) = (len_bytes >= strips::STRIP_MIN_BYTES)
+        .then(|| plan(size))
+        .flatten()
+    {
+        return decode_strips(header, size, plan, image_decoder.into_reader());
+    }
+    decode_qoi_with(header, size, len_bytes, |buf| {
+        image_decoder.decode_to_buf(buf).map(drop).map_err(data_err)
+    })
+}
+
+/// Whether the thumbnail is stored as PNG rather than QOI. Told apart by the first byte alone, as
+/// that may be all the reader has buffered.
+fn is_png<R: BufRead>(thmb: &mut MyTake<R>) -> bool {
+    thmb.fill_buf().ok().and_then(|data| data.first()) == Some(&PNG_SIGNATURE[0])
+}
+
+/// As [`qoi_error`], for PNG thumbnails.
+fn png_error(context: &str, err: png::DecodingError) -> Error {
+    match err {
+        png::DecodingError::IoError(io) => Error::from_input(context, &io),
+        other => Error::new(Class::Corrupt, format!("{context}: {other}")),
     }
 }
-#[derive(bytemuck::Pod, bytemuck::Zeroable, Clone, Copy)]
-#[repr(C, align(4))]
-struct U8x4(pub [u8; 4]);
-fn main() -> Result<(), Cow<'static, str>> {
-    let args: Vec<_> = std::env::args().skip(1).take(4).collect();
-    let Ok([in_path, size, out_path, in_uri]): Result<[String; 4], _> = args.try_into() else {
-        return Err(
-            "Usage: fuzzpaint-thumbnailer <in_path> <size in px> <out_path> <in_uri>".into(),
-        );
-    };
 
-    let Ok(size): Result<u32, _> = size.parse() else {
-        return Err("<size> parameter must be a non-negative integer".into());
+/// Read the header of a PNG thumbnail, up to its first frame, and describe it as the QOI image
+/// [`decode_png`] makes of it: 8-bit RGB or RGBA, in the colorspace its `gAMA` chunk says.
+fn png_header<R: BufRead>(
+    png_reader: MyTake<R>,
+) -> Result<(png::Reader<MyTake<R>>, qoi::Header), Error> {
+    let mut decoder = png::Decoder::new(png_reader);
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let reader = decoder
+        .read_info()
+        .map_err(|err| png_error("failed to parse thumbnail header", err))?;
+    let (color, _) = reader.output_color_type();
+    let info = reader.info();
+    let header = qoi::Header {
+        width: info.width,
+        height: info.height,
+        channels: match color {
+            png::ColorType::Rgba | png::ColorType::GrayscaleAlpha => qoi::Channels::Rgba,
+            _ => qoi::Channels::Rgb,
+        },
+        // A gamma of exactly 1, that no sRGB chunk overrides, is linear light.
+        colorspace: match (info.srgb, info.source_gamma) {
+            (None, Some(gamma)) if gamma.into_scaled() == 100_000 => qoi::ColorSpace::Linear,
+            _ => qoi::ColorSpace::Srgb,
+        },
     };
-    if size == 0 {
-        return Err("<size> parameter must not be zero".into());
+    Ok((reader, header))
+}
+
+/// [`decode_qoi`] for a thumbnail stored as PNG, held to the same limits, and decoded whole as
+/// [`png_header`] describes it.
+fn decode_png<R: BufRead>(
+    png_reader: MyTake<R>,
+    max_dimension: u32,
+    max_encoded_ratio: u64,
+    colorspace: Option<qoi::ColorSpace>,
+) -> Result<DecodedImage, Error> {
+    let len = png_reader.remaining();
+    let (mut reader, header) = png_header(png_reader)?;
+    let header = interpret(header, colorspace);
+    let size = check_size(&header, max_dimension)?;
+    check_len(&header, len, max_encoded_ratio)?;
+    // Whatever IDAT holds, the frame is only ever as large as IHDR says, and fails if IDAT
+    // has too little for it.
+    let data_err = |err| png_error("failed to parse thumbnail data", err);
+    let (color, _) = reader.output_color_type();
+    decode_qoi_with(header, size, header.n_bytes(), |buf| match color {
+        png::ColorType::Rgb | png::ColorType::Rgba => {
+            reader.next_frame(buf).map(drop).map_err(data_err)
+        }
+        // Gray, with alpha or without, spread across each channel.
+        _ => {
+            let mut gray = vec![0; reader.output_buffer_size()];
+            reader.next_frame(&mut gray).map_err(data_err)?;
+            let (in_channels, out_channels) = match header.channels {
+                qoi::Channels::Rgba => (2, 4),
+                qoi::Channels::Rgb => (1, 3),
+            };
+            for (px, gray) in buf
+                .chunks_exact_mut(out_channels)
+                .zip(gray.chunks_exact(in_channels))
+            {
+                px[..3].fill(gray[0]);
+                if let [_, alpha] = gray {
+                    px[3] = *alpha;
+                }
+            }
+            Ok(())
+        }
+    })
+}
+
+/// `header`, but in `colorspace` if one is given, whatever it was tagged as. Only changes how the
+/// pixels are taken, see [`decode_qoi_with`].
+fn interpret(header: qoi::Header, colorspace: Option<qoi::ColorSpace>) -> qoi::Header {
+    qoi::Header {
+        colorspace: colorspace.unwrap_or(header.colorspace),
+        ..header
     }
-    // We only have so much input data to work with!
-    // I don't believe any shell would request anything much larger than 512,
-    // but just in case to avoid expensive calc and lots of mem for an accidental request.
-    if size > 2048 {
-        return Err("<size> parameter larger than reasonable".into());
+}
+
+/// The width and height in `header`, unless either is zero or more than
+/// [`MAX_INPUT_IMAGE_SIDE`], or there are more pixels than a `max_dimension` square.
+fn check_size(
+    header: &qoi::Header,
+    max_dimension: u32,
+) -> Result<(std::num::NonZeroU32, std::num::NonZeroU32), Error> {
+    let qoi::Header { width, height, .. } = *header;
+    if width > MAX_INPUT_IMAGE_SIDE || height > MAX_INPUT_IMAGE_SIDE {
+        return Err(Error::new(
+            Class::Corrupt,
+            format!(
+                "thumbnail size {width}x{height} exceeds limit of {MAX_INPUT_IMAGE_SIDE} px \
+                on a side"
+            ),
+        ));
     }
+    // Can't overflow, each is at most 32 bits.
+    if u64::from(width) * u64::from(height) > u64::from(max_dimension).pow(2) {
+        return Err(Error::new(
+            Class::Corrupt,
+            format!(
+                "thumbnail size {width}x{height} exceeds limit of \
+                {max_dimension}x{max_dimension} px in area"
+            ),
+        ));
+    }
+    std::num::NonZeroU32::new(width)
+        .zip(std::num::NonZeroU32::new(height))
+        .ok_or(Error::new(Class::Corrupt, "thumbnail has zero size"))
+}
 
-    // ========== Read FZP ============
-    let (modified_unix_time, qoi_reader) = {
-        // Open file and stat modification time (both required for thumbnailing according to XDG)
-        let (file, mod_time) = std::fs::File::open(in_path)
-            .and_then(|file| {
-                file.metadata()
-                    .and_then(|meta| meta.modified())
-                    .map(|time| (file, time))
-            })
-            .map_err(|io| Cow::Owned(format!("failed to access in_path: {io}")))?;
+/// Fail if the thumbnail is stored in `len` bytes, more than `max_encoded_ratio` times the most
+/// its dimensions could ever take to encode.
+fn check_len(header: &qoi::Header, len: u64, max_encoded_ratio: u64) -> Result<(), Error> {
+    let qoi::Header { width, height, .. } = *header;
+    // Every pixel as a QOI_OP_RGBA, of 5 bytes, then the end padding.
+    let most = u64::from(width) * u64::from(height) * 5 + QOI_HEADER_LEN as u64 + 8;
+    if len > most.saturating_mul(max_encoded_ratio) {
+        return Err(Error::new(
+            Class::Corrupt,
+            format!(
+                "thumbnail of {len} bytes is far more than a {width}x{height} image needs, \
+                at most {most}"
+            ),
+        ));
+    }
+    Ok(())
+}
 
-        let unix_time = mod_time
-            .duration_since(std::time::SystemTime::UNIX_EPOCH)
-            // Unlikely err lol
-            .map_err(|e| Cow::Owned(e.to_string()))?;
+/// The rest of [`decode_qoi`], once there's a decoder to `decode` `len_bytes` of pixels in the
+/// header's channels, of a QOI image or one [`decode_png`] made out to be.
+fn decode_qoi_with(
+    header: qoi::Header,
+    (width, height): (std::num::NonZeroU32, std::num::NonZeroU32),
+    len_bytes: usize,
+    decode: impl FnOnce(&mut [u8]) -> Result<(), Error>,
+) -> Result<DecodedImage, Error> {
+    let mut blank = false;
+    let pixels = match header.channels {
+        qoi::Channels::Rgba => {
+            // Force align of buffer to 4, for SIMD resize later
+            // Round up length
+            let mut data = pixel_buf::<U8x4>(len_bytes.div_ceil(4));
+            // take exact number of bytes requested (decode fails otherwise)
+            // OK - we're casing to bytes, no align requirement
+            let data_slice = &mut bytemuck::cast_slice_mut(&mut data)[..len_bytes];
+            decode(data_slice)?;
+            if header.colorspace == qoi::ColorSpace::Linear {
+                transfer::to_srgb(data_slice, 4);
+            }
+            blank = U8x4::all_transparent(&data);
+            Pixels::Rgba(data)
+        }
+        qoi::Channels::Rgb => {
+            let mut data = pixel_buf::<U8x3>(len_bytes.div_ceil(3));
+            let data_slice = &mut bytemuck::cast_slice_mut(&mut data)[..len_bytes];
+            decode(data_slice)?;
+            if header.colorspace == qoi::ColorSpace::Linear {
+                transfer::to_srgb(data_slice, 3);
+            }
+            Pixels::Rgb(data)
+        }
+    };
 
-        // Fetch a reader of the raw image data.
-        let qoi_reader = read_fzp_thmb(BufReader::new(file))
-            .map_err(|io| Cow::Owned(format!("failed to parse input file: {io}").into()))?;
+    Ok(DecodedImage {
+        width,
+        height,
+        pixels,
+        prescaled: false,
+        mime: MIME_TYPE,
+        canvas: None,
+        info: DocumentInfo::default(),
+        blank,
+    })
+}
 
-        (unix_time, qoi_reader)
-    };
-    // ========== Read QOI ============
-    let (width, height, colorspace, rgba, rgba_len_bytes) = {
-        let mut image_decoder = qoi::Decoder::from_stream(qoi_reader)
-            .map_err(|img| Cow::Owned(format!("failed to parse thumbnail header: {img}")))?
-            // XDG thumbnailer requires RGBA8
-            .with_channels(qoi::Channels::Rgba);
+/// A buffer of `len` pixels to decode into, all zero.
+///
+/// Allocated zeroed rather than filled with zeros, so the allocator can hand over pages the OS has
+/// already zeroed instead of clearing megabytes the decoder is about to overwrite anyway.
+fn pixel_buf<P: bytemuck::Zeroable>(len: usize) -> Vec<P> {
+    bytemuck::allocation::zeroed_vec(len)
+}
 
-        let qoi::Header {
-            width,
-            height,
-            colorspace,
-            ..
-        } = *image_decoder.header();
-        if width > MAX_INPUT_IMAGE_DIMENSION || height > MAX_INPUT_IMAGE_DIMENSION {
-            return Err("thumbnail size exceeds limit".into());
-        }
-        let (width, height) = std::num::NonZeroU32::new(width)
-            .zip(std::num::NonZeroU32::new(height))
-            .ok_or_else(|| Cow::Borrowed("thumbnail has zero size"))?;
-
-        // Force align of buffer to 4, for SIMD resize later
-        let len_bytes = image_decoder.required_buf_len();
-        // Round up length
-        let mut data = vec![U8x4([0u8; 4]); len_bytes.div_ceil(4)];
-        // take exact number of bytes requested (decode fails otherwise)
-        // OK - we're casing to bytes, no align requirement
-        let data_slice = &mut bytemuck::cast_slice_mut(&mut data)[..len_bytes];
-        image_decoder
-            .decode_to_buf(data_slice)
-            .map_err(|img| Cow::Owned(format!("failed to parse thumbnail data: {img}")))?;
-
-        (width, height, colorspace, data, len_bytes)
+/// The rest of [`decode_qoi_planned`], decoding the `body` following `header` a strip at a
+/// time, scaled as `plan` has it.
+fn decode_strips(
+    header: qoi::Header,
+    size: (std::num::NonZeroU32, std::num::NonZeroU32),
+    plan: ScalePlan,
+    body: impl Read,
+) -> Result<DecodedImage, Error> {
+    let data_err = |img| qoi_error("failed to parse thumbnail data", img);
+    let decoder = strips::RowDecoder::new(body, &header);
+    let ScalePlan {
+        region,
+        scaled,
+        filter,
+        premultiply,
+        threads,
+    } = plan;
+    let rows = strips::STRIP_ROWS;
+    let (pixels, blank) = match header.channels {
+        qoi::Channels::Rgba => {
+            let (pixels, blank) = strips::scale(
+                decoder,
+                size,
+                region,
+                scaled,
+                filter,
+                premultiply,
+                rows,
+                threads,
+            )
+            .map_err(data_err)?;
+            (Pixels::Rgba(pixels), blank)
+        }
+        qoi::Channels::Rgb => {
+            let (pixels, blank) = strips::scale(
+                decoder,
+                size,
+                region,
+                scaled,
+                filter,
+                premultiply,
+                rows,
+                threads,
+            )
+            .map_err(data_err)?;
+            (Pixels::Rgb(pixels), blank)
+        }
     };
+    Ok(DecodedImage {
+        width: size.0,
+        height: size.1,
+        pixels,
+        prescaled: true,
+        mime: MIME_TYPE,
+        canvas: None,
+        info: DocumentInfo::default(),
+        blank,
+    })
+}
 
-    // ============= Scale ===============
-    let (scaled_width, scaled_height) = {
-        let max_dim = width.max(height);
-        let scale_factor = size as f32 / max_dim.get() as f32;
-        let scaled_width = (width.get() as f32 * scale_factor).ceil() as u32;
-        let scaled_height = (height.get() as f32 * scale_factor).ceil() as u32;
+/// Copy `region` out of an image `width` pixels wide.
+fn crop_pixels<P: Copy>(pixels: &[P], width: std::num::NonZeroU32, region: fit::Region) -> Vec<P> {
+    let left = region.left as usize;
+    let right = left + region.width.get() as usize;
+    pixels
+        .chunks_exact(width.get() as usize)
+        .skip(region.top as usize)
+        .take(region.height.get() as usize)
+        .flat_map(|row| &row[left..right])
+        .copied()
+        .collect()
+}
+
+/// A resizer using `filter`.
+fn resizer(filter: args::Filter) -> fast_image_resize::Resizer {
+    use fast_image_resize::{FilterType, ResizeAlg, Resizer};
+    Resizer::new(match filter {
+        args::Filter::Nearest => ResizeAlg::Nearest,
+        args::Filter::Bilinear => ResizeAlg::Convolution(FilterType::Bilinear),
+        args::Filter::CatmullRom => ResizeAlg::Convolution(FilterType::CatmullRom),
+        args::Filter::Lanczos3 => ResizeAlg::Convolution(FilterType::Lanczos3),
+        args::Filter::Box => ResizeAlg::Convolution(FilterType::Box),
+    })
+}
+
+/// Multiply the colour of every pixel of `image` by its alpha, or divide it back out if `divide`,
+/// leaving those with no alpha be. Colours fully transparent pixels are divided back to are black.
+fn mul_div_alpha(mut image: fast_image_resize::DynamicImageViewMut<'_>, divide: bool) {
+    use fast_image_resize as fr;
+    if matches!(
+        image,
+        fr::DynamicImageViewMut::U8x3(_) | fr::DynamicImageViewMut::U16x3(_)
+    ) {
+        return;
+    }
+    let mul_div = fr::MulDiv::default();
+    if divide {
+        mul_div.divide_alpha_inplace(&mut image)
+    } else {
+        mul_div.multiply_alpha_inplace(&mut image)
+    }
+    // OK - every other layout we scale has alpha.
+    .unwrap();
+}
 
-        std::num::NonZeroU32::new(scaled_width)
-            .zip(std::num::NonZeroU32::new(scaled_height))
-            .ok_or_else(|| Cow::Borrowed("scaled thumbnail has zero size"))?
+/// Resize `region` of an image, weighing colours by their alpha if `premultiply`, on up to
+/// `threads` threads.
+fn resize<P: Pixel>(
+    (width, height): (std::num::NonZeroU32, std::num::NonZeroU32),
+    pixels: &[P],
+    region: fit::Region,
+    (scaled_width, scaled_height): (std::num::NonZeroU32, std::num::NonZeroU32),
+    filter: args::Filter,
+    premultiply: bool,
+    threads: std::num::NonZeroUsize,
+) -> Vec<P> {
+    use fast_image_resize as fr;
+
+    // Premultiplied into a copy, as the image may be rendered again. All of it, as the filter
+    // reaches past the edges of the region. Opaque pixels have no alpha to weigh by.
+    let premultiply = premultiply && P::COLOR == png::ColorType::Rgba;
+    let mut premultiplied;
+    let pixels = if premultiply {
+        premultiplied = pixels.to_vec();
+        mul_div_alpha(
+            P::view_mut(
+                fr::ImageViewMut::from_buffer(
+                    width,
+                    height,
+                    bytemuck::cast_slice_mut(&mut premultiplied),
+                )
+                // OK - sized exactly, and U8x4 is aligned to 4.
+                .unwrap(),
+            ),
+            false,
+        );
+        &premultiplied[..]
+    } else {
+        pixels
     };
 
-    let scaled_rgba = {
-        use fast_image_resize as fr;
-        let mut resizer = fr::Resizer::new(fr::ResizeAlg::Convolution(fr::FilterType::Bilinear));
+    // Alloc destination buffer
+    let mut destination =
+        vec![P::zeroed(); scaled_width.get() as usize * scaled_height.get() as usize];
+    // Filters the sRGB-encoded values as they are, see `resize_linear` for otherwise. The region
+    // is read in place rather than copied out. U8x4 is manually aligned to 4, and U8x3 needs none.
+    bands::resize::<P::Resize>(
+        filter,
+        (width, height),
+        bytemuck::cast_slice(pixels),
+        region,
+        (scaled_width, scaled_height),
+        bytemuck::cast_slice_mut(&mut destination),
+        threads,
+    );
 
-        let source_view = fr::ImageView::<'_, fr::pixels::U8x4>::from_buffer(
-            width,
-            height,
-            // OK - we're casing to bytes, no align requirement
-            &bytemuck::cast_slice(&rgba)[..rgba_len_bytes],
-        )
-        // OK - we manually aligned rgba to 4.
-        .unwrap();
-        // Alloc destination buffer
-        let mut destination = fr::Image::new(scaled_width, scaled_height, fr::PixelType::U8x4);
-
-        // TODO: Wrong interp for sRGB
-        resizer
-            .resize(
-                &fr::DynamicImageView::U8x4(source_view),
-                &mut destination.view_mut(),
-            )
-            // Unwrap ok - we unconditionally use the same pixel type constant for both.
-            .unwrap();
+    if premultiply {
+        mul_div_alpha(
+            P::view_mut(
+                fr::ImageViewMut::from_buffer(
+                    scaled_width,
+                    scaled_height,
+                    bytemuck::cast_slice_mut(&mut destination),
+                )
+                // OK - as above.
+                .unwrap(),
+            ),
+            true,
+        );
+    }
+    destination
+}
+
+/// As [`resize`], but filtering 16-bit linear light rather than the sRGB-encoded bytes, and
+/// premultiplying it there too. Only the region is converted, which still takes twice the memory
+/// of the pixels it's scaled from.
+fn resize_linear<P: Pixel>(
+    width: std::num::NonZeroU32,
+    pixels: &[P],
+    region: fit::Region,
+    (scaled_width, scaled_height): (std::num::NonZeroU32, std::num::NonZeroU32),
+    filter: args::Filter,
+    premultiply: bool,
+    threads: std::num::NonZeroUsize,
+) -> Vec<P> {
+    use fast_image_resize as fr;
+    let channels = std::mem::size_of::<P>();
+    let cropped = crop_pixels(pixels, width, region);
+    let mut source = vec![0u16; cropped.len() * channels];
+    transfer::to_linear16(bytemuck::cast_slice(&cropped), channels, &mut source);
+    drop(cropped);
+    if premultiply {
+        mul_div_alpha(
+            P::linear_view_mut(
+                fr::ImageViewMut::from_buffer(
+                    region.width,
+                    region.height,
+                    bytemuck::cast_slice_mut(&mut source),
+                )
+                // OK - sized exactly, and a `Vec<u16>` is aligned for 16-bit channels.
+                .unwrap(),
+            ),
+            false,
+        );
+    }
+    let len = scaled_width.get() as usize * scaled_height.get() as usize;
+    let mut scaled = vec![0u16; len * channels];
+    // A `Vec<u16>` is aligned for 16-bit channels.
+    bands::resize::<P::Linear>(
+        filter,
+        (region.width, region.height),
+        bytemuck::cast_slice(&source),
+        fit::Region::whole(region.width, region.height),
+        (scaled_width, scaled_height),
+        bytemuck::cast_slice_mut(&mut scaled),
+        threads,
+    );
+    drop(source);
+    if premultiply {
+        mul_div_alpha(
+            P::linear_view_mut(
+                fr::ImageViewMut::from_buffer(
+                    scaled_width,
+                    scaled_height,
+                    bytemuck::cast_slice_mut(&mut scaled),
+                )
+                // OK - as above.
+                .unwrap(),
+            ),
+            true,
+        );
+    }
+
+    let mut destination = vec![P::zeroed(); len];
+    transfer::from_linear16(
+        &scaled,
+        channels,
+        bytemuck::cast_slice_mut(&mut destination),
+    );
+    destination
+}
 
-        destination.into_vec()
+/// `rgba` pixels as RGB, without their alpha.
+fn drop_alpha(rgba: &[u8]) -> Vec<u8> {
+    rgba.chunks_exact(4)
+        .flat_map(|px| &px[..3])
+        .copied()
+        .collect()
+}
+
+/// `rgb` pixels as RGBA, fully opaque.
+fn add_alpha(rgb: &[u8]) -> Vec<u8> {
+    rgb.chunks_exact(3)
+        .flat_map(|px| [px[0], px[1], px[2], u8::MAX])
+        .collect()
+}
+
+/// `pixels` of `color`, RGB or RGBA, packed into grayscale of the same alpha, unless any pixel
+/// isn't gray. Gives up at the first that isn't.
+fn to_gray(color: png::ColorType, pixels: &[u8]) -> Option<(png::ColorType, Vec<u8>)> {
+    let (channels, gray_color) = match color {
+        png::ColorType::Rgba => (4, png::ColorType::GrayscaleAlpha),
+        png::ColorType::Rgb => (3, png::ColorType::Grayscale),
+        _ => return None,
     };
-    // Dealloc unscaled image asap
-    drop(rgba);
+    let pixels = pixels.chunks_exact(channels);
+    if !pixels.clone().all(|px| px[0] == px[1] && px[1] == px[2]) {
+        return None;
+    }
+    // Blue, then any alpha, as blue is as good as red or green.
+    Some((
+        gray_color,
+        pixels.flat_map(|px| &px[2..]).copied().collect(),
+    ))
+}
 
-    // ============= Write PNG ===============
-    let file = std::fs::File::create(out_path)
-        .map_err(|io| Cow::Owned(format!("failed to open out_path for writing: {io}")))?;
-    let mut png = png::Encoder::new(file, scaled_width.get(), scaled_height.get());
-    png.set_color(png::ColorType::Rgba);
+/// An 8-bit image, ready for encoding.
+struct ScaledImage<'a> {
+    width: std::num::NonZeroU32,
+    height: std::num::NonZeroU32,
+    /// [`png::ColorType::Rgba`] or [`png::ColorType::Rgb`], or the grayscale of either.
+    color: png::ColorType,
+    pixels: &'a [u8],
+    mime: &'static str,
+    /// Width and height of the document's canvas, for `Thumb::Image`.
+    canvas: (u32, u32),
+    info: &'a DocumentInfo,
+}
+
+/// Encode the image as PNG along with the XDG metadata, followed by any `--png-text` chunks.
+///
+/// `Thumb::MTime` is omitted if `modified_unix_time` is `None`.
+fn write_png<W: std::io::Write>(
+    sink: W,
+    image: &ScaledImage,
+    in_uri: &str,
+    modified_unix_time: Option<std::time::Duration>,
+    options: &args::Options,
+) -> Result<(), Error> {
+    let mut png = png::Encoder::new(sink, image.width.get(), image.height.get());
+    png.set_color(image.color);
     png.set_depth(png::BitDepth::Eight);
-    if colorspace == qoi::ColorSpace::Srgb {
+    // A profile says more than sRGB does, and the two mustn't both be given.
+    let iccp = image
+        .info
+        .icc_profile()
+        .map(icc::iccp_chunk)
+        .transpose()
+        .map_err(|io| {
+            Error::new(
+                Class::OutputIo,
+                format!("failed to compress ICC profile: {io}"),
+            )
+        })?;
+    if iccp.is_none() {
         png.set_srgb(png::SrgbRenderingIntent::Perceptual);
     }
-    // Avoid expensive compression. The shell's thumbnailer consumes and re-encodes it anyway!
-    // This still pulls in flate2 and fdeflate libraries :V
-    png.set_compression(png::Compression::Fast);
+    // The canvas's, not scaled to the thumbnail, which `X-Fuzzpaint::DPI` makes clear.
+    if let Some((xppu, yppu)) = image.info.pixels_per_metre() {
+        png.set_pixel_dims(Some(png::PixelDimensions {
+            xppu,
+            yppu,
+            unit: png::Unit::Meter,
+        }));
+    }
+    // Avoid expensive compression unless asked. The shell's thumbnailer consumes and re-encodes
+    // it anyway! This still pulls in flate2 and fdeflate libraries :V
+    png.set_compression(match options.compression {
+        args::Compression::Fast => png::Compression::Fast,
+        args::Compression::Balanced => png::Compression::Default,
+        args::Compression::Best => png::Compression::Best,
+    });
     png.set_adaptive_filter(png::AdaptiveFilterType::NonAdaptive);
     png.set_filter(png::FilterType::NoFilter);
     // Write XDG Metas (https://specifications.freedesktop.org/thumbnail-spec/thumbnail-spec-latest.html#CREATION)
-    let try_metas = || -> Result<(), png::EncodingError> {
+    let mut try_metas = || -> Result<(), png::EncodingError> {
         // PNG
-        png.add_text_chunk("Software".into(), "Fuzzpaint".into())?;
+        png.add_text_chunk("Software".into(), image.info.software())?;
         // XDG required
-        png.add_text_chunk("Thumb::URI".into(), in_uri)?;
-        png.add_text_chunk(
-            "Thumb::MTime".into(),
-            modified_unix_time.as_secs().to_string(),
-        )?;
+        png.add_text_chunk("Thumb::URI".into(), in_uri.into())?;
+        if let Some(mtime) = modified_unix_time {
+            png.add_text_chunk("Thumb::MTime".into(), mtime.as_secs().to_string())?;
+        }
         // XDG Additional
-        png.add_text_chunk("Thumb::Mimetype".into(), MIME_TYPE.into())?;
+        png.add_text_chunk("Thumb::Mimetype".into(), image.mime.into())?;
         // XDG Filetype specific
-        png.add_text_chunk("Thumb::Image::Width".into(), "1080".into())?;
-        png.add_text_chunk("Thumb::Image::Height".into(), "1080".into())?;
+        png.add_text_chunk("Thumb::Image::Width".into(), image.canvas.0.to_string())?;
+        png.add_text_chunk("Thumb::Image::Height".into(), image.canvas.1.to_string())?;
         // XDG Fuzzpaint ext
-        png.add_text_chunk("X-Fuzzpaint::Soup".into(), "very good".into())?;
+        if let Some(dpi) = image.info.dpi_text() {
+            png.add_text_chunk("X-Fuzzpaint::DPI".into(), dpi)?;
+        }
+        for (keyword, count) in image.info.count_text() {
+            png.add_text_chunk(keyword.into(), count)?;
+        }
+        // The document's own, unless the user's say otherwise
+        for (keyword, text) in image.info.png_text() {
+            if !options.png_text.iter().any(|(own, _)| own == keyword) {
+                png.add_text_chunk(keyword.into(), text.into())?;
+            }
+        }
+        // User's own
+        for (keyword, text) in &options.png_text {
+            png.add_text_chunk(keyword.clone(), text.clone())?;
+        }
 
         Ok(())
     };
     // Write metas then write pixels
-    try_metas().map_err(|enc| Cow::Owned(format!("failed to write metadata: {enc}")))?;
+    try_metas()
+        .map_err(|enc| Error::new(Class::OutputIo, format!("failed to write metadata: {enc}")))?;
     png.write_header()
-        .and_then(|mut png| png.write_image_data(&scaled_rgba))
-        .map_err(|enc| Cow::Owned(format!("failed to write png: {enc}")))
+        .and_then(|mut png| {
+            if let Some(iccp) = &iccp {
+                png.write_chunk(png::chunk::iCCP, iccp)?;
+            }
+            // The document's background, for viewers to show behind the transparency. Without an
+            // alpha channel, there's none for it to show through, but it's harmless.
+            if let Some([r, g, b, _]) = image.info.background() {
+                let bkgd: Vec<u8> = [r, g, b]
+                    .iter()
+                    .flat_map(|&c| u16::from(c).to_be_bytes())
+                    .collect();
+                png.write_chunk(png::chunk::bKGD, &bkgd)?;
+            }
+            png.write_image_data(image.pixels)?;
+            png.finish()
+        })
+        .map_err(|enc| Error::new(Class::OutputIo, format!("failed to write png: {enc}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The default scan, as with no flags.
+    fn scan() -> Scan {
+        Scan {
+            strict: false,
+            lenient: false,
+            max_chunks: MAX_CHUNKS,
+            max_thumb_bytes: MAX_THUMB_BYTES,
+            max_encoded_ratio: MAX_ENCODED_RATIO,
+            size: None,
+            chunk: None,
+            recover: false,
+            flatten: false,
+            sidecar: false,
+            accept_form: None,
+            colorspace: None,
+        }
+    }
+
+    #[test]
+    fn chunk_walk() {
+        // Chunks are given with their declared size, and data that may disagree.
+        let document = |chunks: &[(&[u8; 4], u32, &[u8])]| {
+            let mut body = b"fzp ".to_vec();
+            for (fourcc, declared, data) in chunks {
+                body.extend_from_slice(*fourcc);
+                body.extend_from_slice(&declared.to_le_bytes());
+                body.extend_from_slice(data);
+            }
+            let mut document = b"RIFF".to_vec();
+            document.extend_from_slice(&(body.len() as u32).to_le_bytes());
+            document.extend_from_slice(&body);
+            std::io::Cursor::new(document)
+        };
+        let read = |document, max_chunks| {
+            let mut warnings = Vec::new();
+            let scan = Scan {
+                max_chunks,
+                ..scan()
+            };
+            let thmb = read_fzp_thmb(document, scan, &mut warnings)
+                .map(|found| found.thmb.remaining())
+                .map_err(|err| err.class);
+            (thmb, warnings)
+        };
+
+        // Exactly as declared, wherever it is. Odd sizes are padded.
+        let (len, warnings) = read(
+            document(&[
+                (b"LIST", 2, b"ab"),
+                (b"meta", 1, b"a\0"),
+                (b"thmb", 3, b"xyz"),
+            ]),
+            MAX_CHUNKS,
+        );
+        assert_eq!(len, Ok(3));
+        assert_eq!(warnings, []);
+        let (len, _) = read(document(&[(b"thmb", 3, b"xyz")]), 1);
+        assert_eq!(len, Ok(3));
+
+        // Only so far.
+        let chunks = [(b"LIST", 2, &b"ab"[..]); 4];
+        let (len, _) = read(document(&[&chunks[..], &[(b"thmb", 0, b"")]].concat()), 5);
+        assert_eq!(len, Ok(0));
+        let (len, _) = read(document(&[&chunks[..], &[(b"thmb", 0, b"")]].concat()), 4);
+        assert_eq!(len, Err(Class::NoThumbnail));
+        let (len, warnings) = read(document(&chunks), MAX_CHUNKS);
+        assert_eq!(len, Err(Class::NoThumbnail));
+        assert_eq!(warnings, []);
+        // Chunks within lists count as much as the lists themselves.
+        let nested = [&b"Layr"[..], b"meta", &[0; 4], b"hist", &[0; 4]].concat();
+        let list = (b"LIST", nested.len() as u32, &nested[..]);
+        let (len, _) = read(document(&[list, (b"thmb", 0, b"")]), 4);
+        assert_eq!(len, Ok(0));
+        let (len, _) = read(document(&[list, (b"thmb", 0, b"")]), 3);
+        assert_eq!(len, Err(Class::NoThumbnail));
+
+        // The document is as long as the file, whatever its header says.
+        for riff_len in [0u32, 13, 17, 100] {
+            let mut misstated = document(&[(b"LIST", 2, b"ab"), (b"thmb", 3, b"xyz")]);
+            misstated.get_mut()[4..8].copy_from_slice(&riff_len.to_le_bytes());
+            let (len, warnings) = read(misstated, MAX_CHUNKS);
+            assert_eq!(len, Ok(3), "{riff_len}");
+            assert_eq!(
+                warnings,
+                [Warning::SizeMismatch {
+                    magic: *b"RIFF",
+                    declared: riff_len.into(),
+                    actual: 25,
+                }]
+            );
+        }
+        assert_eq!(
+            Warning::SizeMismatch {
+                magic: *b"RIFF",
+                declared: 0,
+                actual: 25
+            }
+            .to_string(),
+            "RIFF header declares 0 bytes, but 25 follow it"
+        );
+
+        // Ending early, within a chunk or its header.
+        let mut overrun = document(&[(b"LIST", 2, b"ab"), (b"thmb", 30, b"xyz")]);
+        overrun.get_mut()[4..8].copy_from_slice(&52u32.to_le_bytes());
+        assert_eq!(read(overrun, MAX_CHUNKS).0, Err(Class::Corrupt));
+        let mut cut = document(&[(b"LIST", 2, b"ab"), (b"thmb", 3, b"xyz")]);
+        cut.get_mut().truncate(25);
+        assert_eq!(read(cut, MAX_CHUNKS).0, Err(Class::Corrupt));
+    }
+    #[test]
+    fn best_candidate() {
+        let candidate = |longest| Candidate {
+            offset: 0,
+            len: 0,
+            longest,
+        };
+        let better = |a, b, size| candidate(a).better_than(&candidate(b), size);
+        assert!(better(Some(16), Some(64), Some(8)));
+        assert!(better(Some(8), Some(4), Some(8)));
+        assert!(!better(Some(4), Some(8), Some(8)));
+        assert!(better(Some(4), Some(2), Some(8)));
+        assert!(better(Some(64), Some(16), None));
+        // The first of equals.
+        assert!(!better(Some(16), Some(16), Some(8)));
+        assert!(!better(Some(16), Some(16), None));
+        // Anything that might decode.
+        assert!(better(Some(1), None, Some(8)));
+        assert!(!better(None, Some(1), None));
+        assert!(!better(None, None, None));
+    }
+    #[test]
+    fn index_records() {
+        let read_nth = |index: &[u8], file_len, nth| {
+            let selection = Selection::new(Some(8), std::num::NonZeroU32::new(nth));
+            read_index(&mut &index[..], index.len() as u64, file_len, selection)
+                .map(|selection| selection.best.map(|best| (best.offset, best.len)))
+        };
+        let read = |index: &[u8], file_len| read_nth(index, file_len, 0);
+        let record = |width: u32, height: u32, offset: u64, len: u64| {
+            [
+                &width.to_le_bytes()[..],
+                &height.to_le_bytes(),
+                &offset.to_le_bytes(),
+                &len.to_le_bytes(),
+            ]
+            .concat()
+        };
+        let index = [
+            &2u32.to_le_bytes()[..],
+            &record(4, 4, 100, 10),
+            &record(8, 16, 200, 20),
+        ]
+        .concat();
+        assert_eq!(read(&index, 220), Ok(Some((200, 20))));
+        assert_eq!(read_nth(&index, 220, 1), Ok(Some((100, 10))));
+        assert_eq!(read_nth(&index, 220, 2), Ok(Some((200, 20))));
+        assert_eq!(read_nth(&index, 220, 3), Ok(None));
+        // Every record is checked, even past the one picked.
+        assert!(read_nth(&index, 219, 1).is_err());
+        assert!(read(&index, 219).is_err());
+        assert_eq!(read(&0u32.to_le_bytes(), 0), Ok(None));
+
+        assert_eq!(
+            read(&index[..index.len() - 1], 1000),
+            Err("declares 2 records, but has room for only 1".to_owned())
+        );
+        assert!(read(&index[..2], 1000).is_err());
+        let zero = [&1u32.to_le_bytes()[..], &record(0, 4, 100, 10)].concat();
+        assert_eq!(read(&zero, 1000), Err("lists a 0x4 thumbnail".to_owned()));
+        let overflow = [&1u32.to_le_bytes()[..], &record(4, 4, u64::MAX, 10)].concat();
+        assert!(read(&overflow, 1000).is_err());
+    }
+    #[test]
+    fn canvas_sizes() {
+        let read = |dims: &[u8]| read_canvas_size(&mut &dims[..], dims.len() as u64);
+        let dims = |width: u32, height: u32| [width.to_le_bytes(), height.to_le_bytes()].concat();
+        assert_eq!(read(&dims(1920, 1080)), Ok((1920, 1080)));
+        assert_eq!(read(&dims(1, u32::MAX)), Ok((1, u32::MAX)));
+        assert_eq!(
+            read(&dims(0, 1080)),
+            Err("gives a 0x1080 canvas".to_owned())
+        );
+        assert_eq!(
+            read(&dims(1920, 1080)[..6]),
+            Err("is 6 bytes, not 8".to_owned())
+        );
+        assert_eq!(read(b""), Err("is 0 bytes, not 8".to_owned()));
+    }
+    #[test]
+    fn odd_sized_chunks_are_padded() {
+        let document = |chunks: &[(&[u8; 4], &[u8])]| {
+            let mut body = b"fzp ".to_vec();
+            for (fourcc, data) in chunks {
+                body.extend_from_slice(*fourcc);
+                body.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                body.extend_from_slice(data);
+                if data.len() % 2 == 1 {
+                    body.push(0);
+                }
+            }
+            let mut document = b"RIFF".to_vec();
+            document.extend_from_slice(&(body.len() as u32).to_le_bytes());
+            document.extend_from_slice(&body);
+            std::io::Cursor::new(document)
+        };
+        let read = |chunks: &[(&[u8; 4], &[u8])]| {
+            let mut warnings = Vec::new();
+            let Found { mut thmb, .. } =
+                read_fzp_thmb(document(chunks), scan(), &mut warnings).unwrap();
+            assert_eq!(warnings, []);
+            let mut data = Vec::new();
+            thmb.read_to_end(&mut data).unwrap();
+            data
+        };
+        assert_eq!(read(&[(b"meta", b"a"), (b"thmb", b"qoif")]), b"qoif");
+        assert_eq!(
+            read(&[(b"meta", b"abc"), (b"hist", b"de"), (b"thmb", b"qoif")]),
+            b"qoif"
+        );
+        assert_eq!(
+            read(&[(b"meta", b"abc"), (b"hist", b"def"), (b"thmb", b"qoi")]),
+            b"qoi"
+        );
+    }
+    #[test]
+    fn limits_pixels_not_sides() {
+        let check = |width, height, max_dimension| {
+            // Built directly, as `try_new` refuses some of these itself.
+            let header = qoi::Header {
+                width,
+                height,
+                channels: qoi::Channels::Rgba,
+                colorspace: qoi::ColorSpace::Srgb,
+            };
+            check_size(&header, max_dimension).map(|(w, h)| (w.get(), h.get()))
+        };
+        // Long and thin, with fewer pixels than the square.
+        assert_eq!(check(2048, 64, 1024).unwrap(), (2048, 64));
+        assert_eq!(
+            check(1024 * 1024, 1, 1024).unwrap_err().class,
+            Class::Corrupt
+        );
+        assert_eq!(check(1024, 1024, 1024).unwrap(), (1024, 1024));
+        assert!(check(1024, 1025, 1024).is_err());
+        // Never too long on a side, and never so large as to overflow.
+        assert_eq!(check(16384, 1, 1024).unwrap(), (16384, 1));
+        assert!(check(16385, 1, 8192).is_err());
+        assert!(check(u32::MAX / 4, 2, 8192).is_err());
+    }
+    #[test]
+    fn refuses_far_too_many_bytes() {
+        let header = |width, height| qoi::Header {
+            width,
+            height,
+            channels: qoi::Channels::Rgba,
+            colorspace: qoi::ColorSpace::Srgb,
+        };
+        // At most 5 bytes a pixel, with the header and padding.
+        let most = 8 * 8 * 5 + 14 + 8;
+        assert!(check_len(&header(8, 8), most, 1).is_ok());
+        assert!(check_len(&header(8, 8), most + 1, 1).is_err());
+        assert!(check_len(&header(8, 8), most * 2, MAX_ENCODED_RATIO).is_ok());
+        let err = check_len(&header(8, 8), 10 << 20, MAX_ENCODED_RATIO).unwrap_err();
+        assert_eq!(err.class, Class::Corrupt);
+        assert!(check_len(&header(8, 8), 10 << 20, u64::MAX).is_ok());
+        // Flat color packs down to far fewer.
+        assert!(check_len(&header(1024, 1024), 200, MAX_ENCODED_RATIO).is_ok());
+    }
+    #[test]
+    fn scales_large_thumbnails_as_decoded() {
+        use std::num::NonZeroU32;
+        // Large enough to be decoded in strips.
+        let (width, height) = (700, 400);
+        let pixels: Vec<u8> = (0..width * height * 4)
+            .map(|i| (i * 37 % 251) as u8)
+            .collect();
+        let qoi = qoi::encode_to_vec(&pixels, width, height).unwrap();
+        let len = qoi.len() as u64;
+        let size = (
+            NonZeroU32::new(width).unwrap(),
+            NonZeroU32::new(height).unwrap(),
+        );
+        let plan = ScalePlan {
+            region: fit::Region {
+                left: 30,
+                top: 20,
+                width: NonZeroU32::new(500).unwrap(),
+                height: NonZeroU32::new(300).unwrap(),
+            },
+            scaled: (NonZeroU32::new(123).unwrap(), NonZeroU32::new(77).unwrap()),
+            filter: args::Filter::Lanczos3,
+            // Compared exactly enough below without alpha's magnified rounding, see `strips`.
+            premultiply: false,
+            threads: std::num::NonZeroUsize::new(4).unwrap(),
+        };
+        let whole = decode_qoi(MyTake::new(&qoi[..], len), 1024, MAX_ENCODED_RATIO, None).unwrap();
+        assert!(!whole.prescaled);
+        let Pixels::Rgba(whole) = whole.pixels else {
+            panic!("decoded without alpha");
+        };
+        let expected = resize(
+            size,
+            &whole,
+            plan.region,
+            plan.scaled,
+            plan.filter,
+            plan.premultiply,
+            std::num::NonZeroUsize::MIN,
+        );
+
+        // In memory, and streamed.
+        for capacity in [qoi.len(), 1] {
+            let reader = BufReader::with_capacity(capacity, &qoi[..]);
+            let decoded = decode_qoi_planned(
+                MyTake::new(reader, len),
+                1024,
+                MAX_ENCODED_RATIO,
+                None,
+                |decoded_size| {
+                    assert_eq!(decoded_size, size);
+                    Some(plan)
+                },
+            )
+            .unwrap();
+            assert!(decoded.prescaled);
+            assert_eq!((decoded.width, decoded.height), size);
+            let Pixels::Rgba(scaled) = decoded.pixels else {
+                panic!("decoded without alpha");
+            };
+            // Within rounding, as each strip is scaled down on its own.
+            let scaled = bytemuck::cast_slice::<_, u8>(&scaled);
+            let expected = bytemuck::cast_slice::<_, u8>(&expected);
+            assert_eq!(scaled.len(), expected.len());
+            assert!(scaled
+                .iter()
+                .zip(expected)
+                .all(|(a, b)| a.abs_diff(*b) <= 1));
+        }
+
+        // Small ones are decoded whole all the same.
+        let qoi = qoi::encode_to_vec(&pixels[..64 * 64 * 4], 64, 64).unwrap();
+        let decoded = decode_qoi_planned(
+            MyTake::new(&qoi[..], qoi.len() as u64),
+            1024,
+            MAX_ENCODED_RATIO,
+            None,
+            |_| Some(plan),
+        )
+        .unwrap();
+        assert!(!decoded.prescaled);
+    }
+    #[test]
+    fn decodes_in_memory_as_streamed() {
+        let decode = |qoi: &[u8], in_memory: bool| {
+            let len = qoi.len() as u64;
+            // A one-byte buffer never holds all of it.
+            let capacity = if in_memory { qoi.len().max(1) } else { 1 };
+            let reader = BufReader::with_capacity(capacity, qoi);
+            decode_qoi(MyTake::new(reader, len), 1024, MAX_ENCODED_RATIO, None)
+                .map(|image| match image.pixels {
+                    Pixels::Rgba(rgba) => (4, bytemuck::cast_slice::<_, u8>(&rgba).to_vec()),
+                    Pixels::Rgb(rgb) => (3, bytemuck::cast_slice::<_, u8>(&rgb).to_vec()),
+                })
+                .map_err(|err| (err.class, err.message))
+        };
+        // In whichever channels it was stored with.
+        for channels in [4, 3] {
+            let pixels: Vec<u8> = (0..6 * 5 * channels)
+                .map(|i| (i * 37 % 256) as u8)
+                .collect();
+            let qoi = qoi::encode_to_vec(&pixels, 6, 5).unwrap();
+            assert_eq!(decode(&qoi, true), Ok((channels, pixels.clone())));
+            assert_eq!(decode(&qoi, false), Ok((channels, pixels)));
+            for len in [0, 10, 14, 40, qoi.len() - 1] {
+                let err = decode(&qoi[..len], true);
+                assert!(err.is_err(), "{len}");
+                assert_eq!(err, decode(&qoi[..len], false), "{len}");
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_large_as_qoi_does() {
+        // Large enough for the allocator to map fresh pages, rather than reuse freed ones.
+        let (width, height) = (1024, 1024);
+        for channels in [4, 3] {
+            let pixels: Vec<u8> = (0..width * height * channels)
+                .map(|i| (i * 37 % 251) as u8)
+                .collect();
+            let qoi = qoi::encode_to_vec(&pixels, width as u32, height as u32).unwrap();
+            let (_, expected) = qoi::decode_to_vec(&qoi).unwrap();
+            let decoded = decode_qoi(
+                MyTake::new(&qoi[..], qoi.len() as u64),
+                1024,
+                MAX_ENCODED_RATIO,
+                None,
+            )
+            .unwrap();
+            let decoded = match &decoded.pixels {
+                Pixels::Rgba(rgba) => {
+                    // Still aligned for the resizer.
+                    assert_eq!(rgba.as_ptr() as usize % 4, 0);
+                    bytemuck::cast_slice::<_, u8>(rgba)
+                }
+                Pixels::Rgb(rgb) => bytemuck::cast_slice::<_, u8>(rgb),
+            };
+            assert!(decoded == expected, "{channels} channels");
+        }
+        assert!(pixel_buf::<U8x4>(1 << 20)
+            .iter()
+            .all(|px| bytemuck::bytes_of(px) == [0; 4]));
+    }
+
+    #[test]
+    fn all_transparent() {
+        let clear = vec![U8x4([0xff, 0x80, 0x00, 0]); 1000];
+        assert!(U8x4::all_transparent(&clear));
+        // However faint, and wherever it is.
+        for (at, alpha) in [(0, 1), (999, 1), (500, 0xff), (7, 0x80)] {
+            let mut visible = clear.clone();
+            visible[at].0[3] = alpha;
+            assert!(!U8x4::all_transparent(&visible), "{alpha} at {at}");
+        }
+        assert!(!U8x3::all_transparent(&[U8x3([0; 3]); 4]));
+    }
+
+    #[test]
+    fn all_opaque() {
+        let opaque = vec![U8x4([0, 0x80, 0xff, 0xff]); 1000];
+        assert!(U8x4::all_opaque(&opaque));
+        for (at, alpha) in [(0, 0xfe), (999, 0), (500, 0x7f)] {
+            let mut translucent = opaque.clone();
+            translucent[at].0[3] = alpha;
+            assert!(!U8x4::all_opaque(&translucent), "{alpha} at {at}");
+        }
+        assert!(U8x3::all_opaque(&[U8x3([0; 3]); 4]));
+    }
+
+    #[test]
+    fn repacks_alpha() {
+        let rgba = [1, 2, 3, 255, 4, 5, 6, 255];
+        assert_eq!(drop_alpha(&rgba), [1, 2, 3, 4, 5, 6]);
+        assert_eq!(add_alpha(&drop_alpha(&rgba)), rgba);
+        assert!(drop_alpha(&[]).is_empty());
+    }
+
+    #[test]
+    fn packs_gray() {
+        use png::ColorType::{Grayscale, GrayscaleAlpha, Rgb, Rgba};
+        let rgba = [9, 9, 9, 0, 200, 200, 200, 255];
+        assert_eq!(
+            to_gray(Rgba, &rgba),
+            Some((GrayscaleAlpha, vec![9, 0, 200, 255]))
+        );
+        assert_eq!(
+            to_gray(Rgb, &[9, 9, 9, 200, 200, 200]),
+            Some((Grayscale, vec![9, 200]))
+        );
+        // Any channel off by one, in the last pixel.
+        for channel in 0..3 {
+            let mut tinted = rgba;
+            tinted[4 + channel] ^= 1;
+            assert_eq!(to_gray(Rgba, &tinted), None, "{channel}");
+        }
+        assert_eq!(to_gray(Grayscale, &rgba), None);
+    }
+
+    #[test]
+    fn rereads_changed_files() {
+        let document = |width: u32| {
+            let qoi = qoi::encode_to_vec(vec![0x80; width as usize * 4], width, 1).unwrap();
+            let mut body = b"fzp thmb".to_vec();
+            body.extend_from_slice(&(qoi.len() as u32).to_le_bytes());
+            body.extend_from_slice(&qoi);
+            body.resize(body.len().next_multiple_of(2), 0);
+            let mut document = b"RIFF".to_vec();
+            document.extend_from_slice(&(body.len() as u32).to_le_bytes());
+            document.extend_from_slice(&body);
+            document
+        };
+        let path = std::env::temp_dir().join(format!(
+            "fuzzpaint-thumbnailer-rereads-{}.fzp",
+            std::process::id()
+        ));
+        let time = |secs| std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs);
+        let write = |width, secs| {
+            let file = std::fs::File::create(&path).unwrap();
+            std::io::Write::write_all(&mut &file, &document(width)).unwrap();
+            file.set_modified(time(secs)).unwrap();
+        };
+        let scan = scan();
+        let mut timings = timings::Timings::start(false);
+
+        write(2, 1000);
+        let opened = open_input(&path, None).unwrap();
+        let version = opened.version.as_ref().unwrap();
+        assert!(!version.changed());
+        // Saved over in place, larger, between opening and reading.
+        write(3, 2000);
+        assert!(version.changed());
+        let (image, mtime) = read_settled(opened, 1024, scan, None, &mut timings).unwrap();
+        assert_eq!(image.width.get(), 3);
+        assert_eq!(mtime, Some(std::time::Duration::from_secs(2000)));
+
+        // Unchanged, or only given a time.
+        let opened = open_input(&path, Some(std::time::Duration::from_secs(5))).unwrap();
+        let (image, mtime) = read_settled(opened, 1024, scan, None, &mut timings).unwrap();
+        assert_eq!(image.width.get(), 3);
+        assert_eq!(mtime, Some(std::time::Duration::from_secs(5)));
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn header_sniffing() {
+        let message = |start: &[u8]| {
+            read_fzp_header(&mut std::io::Cursor::new(start), None)
+                .err()
+                .unwrap()
+                .to_string()
+        };
+        assert_eq!(
+            message(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"),
+            "input looks like PNG, not a fuzzpaint document"
+        );
+        assert_eq!(
+            message(b"\xff\xd8\xff\xe0"),
+            "input looks like JPEG, not a fuzzpaint document"
+        );
+        assert_eq!(
+            message(b"PK\x03\x04\x14\0\0\0\x08\0\0\0"),
+            "input looks like ZIP, not a fuzzpaint document"
+        );
+        assert_eq!(message(b""), "input is empty, not a fuzzpaint document");
+        assert_eq!(
+            message(b"hi\n"),
+            "unrecognized file type, starting with \"hi\\x0a\""
+        );
+        assert_eq!(
+            message(b"RIFF\x04\0\0\0WAVE"),
+            "unrecognized file type: RIFF form \"WAVE\""
+        );
+        // Too short to tell.
+        assert_eq!(
+            read_fzp_header(&mut std::io::Cursor::new(b"RIFF\x04\0"), None)
+                .err()
+                .unwrap()
+                .kind(),
+            std::io::ErrorKind::UnexpectedEof
+        );
+    }
+    #[test]
+    fn filters_configure_resizer() {
+        // `ResizeAlg` isn't `PartialEq`, so compare descriptions.
+        let algorithms: Vec<String> = args::Filter::NAMED
+            .iter()
+            .map(|&(_, filter)| format!("{:?}", resizer(filter).algorithm))
+            .collect();
+        for (i, algorithm) in algorithms.iter().enumerate() {
+            assert!(
+                !algorithms[i + 1..].contains(algorithm),
+                "{algorithm} configured twice"
+            );
+        }
+    }
 }
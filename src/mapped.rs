@@ -0,0 +1,27 @@
+//! Documents mapped into memory rather than read through a buffer, so walking their chunks is
+//! only moving a cursor, and their thumbnail is decoded where it lies.
+//!
+//! Only with the `mmap` feature. Files that can't be mapped, as on some network and FUSE
+//! filesystems, are read through a buffer as usual.
+use crate::BufReadSeek;
+use std::fs::File;
+
+/// Map `file` into memory, or give it back if it can't be.
+#[cfg(feature = "mmap")]
+pub fn map(file: File) -> Result<Box<dyn BufReadSeek>, File> {
+    // SAFETY: Writes to the file while it's mapped show through, which parsing copes with as it
+    // would any other garbage. Truncating it faults, but editors save by replacing the file.
+    match unsafe { memmap2::Mmap::map(&file) } {
+        Ok(map) => Ok(Box::new(std::io::Cursor::new(map))),
+        Err(io) => {
+            crate::log::verbose(format_args!("failed to map input file, reading it: {io}"));
+            Err(file)
+        }
+    }
+}
+
+/// Without the `mmap` feature, nothing is mapped.
+#[cfg(not(feature = "mmap"))]
+pub fn map(file: File) -> Result<Box<dyn BufReadSeek>, File> {
+    Err(file)
+}
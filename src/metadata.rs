@@ -0,0 +1,455 @@
+//! Descriptive metadata from a document's `LIST INFO`, `vrsn`, `reso`, `icc `, `bgcl`, `ornt`,
+//! and `strc` chunks, copied into the thumbnail so file properties dialogs have something to show.
+use crate::args::{Flip, Rotation};
+use crate::chunks::{ChunkReader, Warning};
+use std::io::{BufRead, Read, Seek};
+
+/// Longest text kept from an INFO entry, in bytes. Anything more is cut off.
+const MAX_TEXT_LEN: u64 = 1024;
+/// Longest version string kept, in bytes. Anything more is cut off.
+const MAX_VERSION_LEN: u64 = 64;
+/// Highest resolution believed, in pixels per inch. Anything more is nonsense.
+const MAX_DPI: u32 = 100_000;
+/// Most layers shown. More are shown as this many, which is already absurd.
+const MAX_LAYERS: u32 = 100_000;
+/// Most objects shown. More are shown as this many, which is already absurd.
+const MAX_OBJECTS: u32 = 100_000_000;
+
+/// Standard RIFF INFO entries, and the PNG keyword each is written as.
+const ENTRIES: [([u8; 4], &str); 3] = [
+    (*b"INAM", "Title"),
+    (*b"IART", "Author"),
+    (*b"ICMT", "Description"),
+];
+
+/// What a document says about itself.
+#[derive(Debug, Default, PartialEq, Eq, Clone)]
+pub struct DocumentInfo {
+    /// Text of the entries of `LIST INFO` chunks, in the order of [`ENTRIES`].
+    entries: [Option<String>; 3],
+    /// Version of fuzzpaint that wrote the document, from its `vrsn` chunk.
+    version: Option<String>,
+    /// Horizontal and vertical pixels per inch of the canvas, from its `reso` chunk.
+    resolution: Option<(u32, u32)>,
+    /// ICC profile of the canvas, from its `icc ` chunk.
+    icc_profile: Option<Vec<u8>>,
+    /// Background color of the canvas as sRGB RGBA8, from its `bgcl` chunk.
+    background: Option<[u8; 4]>,
+    /// How the canvas is rotated and whether it's then mirrored horizontally in the editor's
+    /// view, from its `ornt` chunk.
+    orientation: Option<(Option<Rotation>, bool)>,
+    /// How many layers and objects the document holds, from its `strc` chunk.
+    counts: Option<(u32, u32)>,
+}
+impl DocumentInfo {
+    /// Read the entries of an INFO list, keeping the first of each. Anything malformed that
+    /// can be carried on past is pushed to `warnings`, and what's been read so far is kept if
+    /// the rest can't be.
+    pub fn read_list<R: BufRead + Seek>(
+        &mut self,
+        mut list: ChunkReader<R>,
+        warnings: &mut Vec<Warning>,
+    ) {
+        while let Ok(Some(chunk)) = list.next(warnings) {
+            let Some(index) = ENTRIES.iter().position(|(id, _)| *id == chunk.fourcc) else {
+                continue;
+            };
+            if self.entries[index].is_some() {
+                continue;
+            }
+            let mut text = Vec::new();
+            if (&mut *chunk.data)
+                .take(MAX_TEXT_LEN)
+                .read_to_end(&mut text)
+                .is_err()
+            {
+                break;
+            }
+            let cut_short = text.len() as u64 == MAX_TEXT_LEN;
+            self.entries[index] = sanitize(&text, cut_short);
+        }
+    }
+    /// Read the version string of a `vrsn` chunk, unless one already was. Only printable
+    /// characters are kept, it's no use otherwise.
+    pub fn read_version(&mut self, vrsn: &mut impl Read) {
+        if self.version.is_some() {
+            return;
+        }
+        let mut version = Vec::new();
+        if vrsn
+            .take(MAX_VERSION_LEN)
+            .read_to_end(&mut version)
+            .is_err()
+        {
+            return;
+        }
+        let cut_short = version.len() as u64 == MAX_VERSION_LEN;
+        self.version = sanitize(&version, cut_short).map(|version| version.replace('\n', " "));
+    }
+    /// Read the canvas resolution from a `len`-byte `reso` chunk, as horizontal then vertical
+    /// pixels per inch in little-endian `u32`s, unless one already was. Nonsense is ignored.
+    pub fn read_resolution(&mut self, reso: &mut impl Read, len: u64) {
+        let mut dpi = [0; 8];
+        if self.resolution.is_some() || len != 8 || reso.read_exact(&mut dpi).is_err() {
+            return;
+        }
+        let x = u32::from_le_bytes(dpi[0..4].try_into().unwrap());
+        let y = u32::from_le_bytes(dpi[4..8].try_into().unwrap());
+        if (1..=MAX_DPI).contains(&x) && (1..=MAX_DPI).contains(&y) {
+            self.resolution = Some((x, y));
+        }
+    }
+    /// Read the canvas's ICC profile from a `len`-byte `icc ` chunk, unless one already was.
+    pub fn read_icc_profile(&mut self, icc: &mut impl Read, len: u64) {
+        if self.icc_profile.is_none() {
+            self.icc_profile = crate::icc::read_profile(icc, len);
+        }
+    }
+    /// The canvas's ICC profile, if it has one.
+    pub fn icc_profile(&self) -> Option<&[u8]> {
+        self.icc_profile.as_deref()
+    }
+    /// Read the canvas's background color from a `len`-byte `bgcl` chunk, as linear RGBA in
+    /// little-endian `f32`s, unless one already was. Nonsense is ignored.
+    pub fn read_background(&mut self, bgcl: &mut impl Read, len: u64) {
+        let mut rgba = [0; 16];
+        if self.background.is_some() || len != 16 || bgcl.read_exact(&mut rgba).is_err() {
+            return;
+        }
+        let channels = rgba
+            .chunks_exact(4)
+            .map(|channel| f32::from_le_bytes(channel.try_into().unwrap()));
+        let mut color = [0; 4];
+        for (i, (channel, out)) in channels.zip(&mut color).enumerate() {
+            if !(0.0..=1.0).contains(&channel) {
+                return;
+            }
+            // Alpha is linear either way.
+            let encoded = if i == 3 {
+                channel
+            } else {
+                crate::transfer::linear_to_srgb(channel)
+            };
+            // In 0..=255, so can't truncate.
+            *out = (encoded * 255.0).round() as u8;
+        }
+        self.background = Some(color);
+    }
+    /// Read the canvas's orientation from a `len`-byte `ornt` chunk, as a clockwise rotation in
+    /// degrees then a mirror flag, both little-endian `u16`s, unless one already was. Nonsense
+    /// is ignored.
+    pub fn read_orientation(&mut self, ornt: &mut impl Read, len: u64) {
+        let mut orientation = [0; 4];
+        if self.orientation.is_some() || len != 4 || ornt.read_exact(&mut orientation).is_err() {
+            return;
+        }
+        let rotation = match u16::from_le_bytes([orientation[0], orientation[1]]) {
+            0 => None,
+            90 => Some(Rotation::Quarter),
+            180 => Some(Rotation::Half),
+            270 => Some(Rotation::ThreeQuarter),
+            _ => return,
+        };
+        let mirrored = match u16::from_le_bytes([orientation[2], orientation[3]]) {
+            0 => false,
+            1 => true,
+            _ => return,
+        };
+        self.orientation = Some((rotation, mirrored));
+    }
+    /// Read how many layers and objects the document holds from a `len`-byte `strc` chunk, as
+    /// little-endian `u32`s, unless they already were.
+    pub fn read_structure(&mut self, strc: &mut impl Read, len: u64) {
+        let mut counts = [0; 8];
+        if self.counts.is_some() || len != 8 || strc.read_exact(&mut counts).is_err() {
+            return;
+        }
+        let layers = u32::from_le_bytes(counts[0..4].try_into().unwrap());
+        let objects = u32::from_le_bytes(counts[4..8].try_into().unwrap());
+        self.counts = Some((layers.min(MAX_LAYERS), objects.min(MAX_OBJECTS)));
+    }
+    /// PNG keywords and text of the layer and object counts, if known.
+    pub fn count_text(&self) -> impl Iterator<Item = (&'static str, String)> {
+        self.counts.into_iter().flat_map(|(layers, objects)| {
+            [
+                ("X-Fuzzpaint::Layers", layers.to_string()),
+                ("X-Fuzzpaint::Objects", objects.to_string()),
+            ]
+        })
+    }
+    /// The rotation, then flip, that shows the canvas as it's seen in the editor.
+    pub fn orientation(&self) -> (Option<Rotation>, Option<Flip>) {
+        self.orientation
+            .map_or((None, None), |(rotation, mirrored)| {
+                (rotation, mirrored.then_some(Flip::Horizontal))
+            })
+    }
+    /// The canvas's background color as sRGB RGBA8, if it has one.
+    pub fn background(&self) -> Option<[u8; 4]> {
+        self.background
+    }
+    /// The canvas resolution in pixels per metre, for a `pHYs` chunk.
+    pub fn pixels_per_metre(&self) -> Option<(u32, u32)> {
+        // An inch is 0.0254 metres, rounded to the nearest.
+        let ppm = |dpi: u32| (u64::from(dpi) * 10_000 + 127) / 254;
+        // Can't overflow, with `MAX_DPI`.
+        self.resolution.map(|(x, y)| (ppm(x) as u32, ppm(y) as u32))
+    }
+    /// The canvas resolution in pixels per inch, as `300`, or `300x600` if not square.
+    pub fn dpi_text(&self) -> Option<String> {
+        self.resolution.map(|(x, y)| {
+            if x == y {
+                x.to_string()
+            } else {
+                format!("{x}x{y}")
+            }
+        })
+    }
+    /// Text for the `Software` tEXt chunk, naming the writer and its version if known, and this
+    /// thumbnailer's, see [`crate::xdg::SOFTWARE_SUFFIX`].
+    pub fn software(&self) -> String {
+        match &self.version {
+            Some(version) => format!("Fuzzpaint {version} {}", crate::xdg::SOFTWARE_SUFFIX),
+            None => format!("Fuzzpaint {}", crate::xdg::SOFTWARE_SUFFIX),
+        }
+    }
+    /// PNG keywords and text of each entry present.
+    pub fn png_text(&self) -> impl Iterator<Item = (&'static str, &str)> {
+        ENTRIES
+            .iter()
+            .zip(&self.entries)
+            .filter_map(|((_, keyword), text)| Some((*keyword, text.as_deref()?)))
+    }
+}
+
+/// Make INFO text fit for a tEXt chunk, or `None` if there's nothing left of it.
+///
+/// It ends at the first NUL. It's meant to be ASCII, but is often UTF-8, and anything else is
+/// taken to be Latin-1. Characters Latin-1 can't represent are replaced with `?`, and control
+/// characters besides newlines are dropped, as tEXt doesn't allow them. If the text was
+/// `cut_short`, it may end partway through a UTF-8 character, which is dropped.
+fn sanitize(text: &[u8], cut_short: bool) -> Option<String> {
+    let text = text.split(|&b| b == 0).next().unwrap_or_default();
+    let text: String = match std::str::from_utf8(text) {
+        Ok(utf8) => utf8.into(),
+        Err(err) if cut_short && err.error_len().is_none() => {
+            String::from_utf8_lossy(&text[..err.valid_up_to()]).into()
+        }
+        Err(_) => text.iter().map(|&b| char::from(b)).collect(),
+    };
+    let text: String = text
+        .chars()
+        .filter(|&c| c == '\n' || !c.is_control())
+        .map(|c| if u32::from(c) <= 0xff { c } else { '?' })
+        .collect();
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_owned())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sanitizes() {
+        assert_eq!(sanitize(b"Sunset\0", false), Some("Sunset".into()));
+        assert_eq!(
+            sanitize(b"Sunset\0junk after", false),
+            Some("Sunset".into())
+        );
+        assert_eq!(sanitize(b"caf\xc3\xa9", false), Some("caf\u{e9}".into()));
+        assert_eq!(sanitize(b"caf\xe9", false), Some("caf\u{e9}".into()));
+        assert_eq!(
+            sanitize("日本 art".as_bytes(), false),
+            Some("?? art".into())
+        );
+        assert_eq!(
+            sanitize(b"two\nlines\r\x07", false),
+            Some("two\nlines".into())
+        );
+        // Cut off mid-character.
+        assert_eq!(sanitize(b"caf\xc3", true), Some("caf".into()));
+        assert_eq!(sanitize(b"caf\xc3", false), Some("caf\u{c3}".into()));
+        assert_eq!(sanitize(b"  \0", false), None);
+        assert_eq!(sanitize(b"", false), None);
+    }
+
+    #[test]
+    fn reads_entries() {
+        let list = |entries: &[(&[u8; 4], &[u8])]| {
+            let mut data = Vec::new();
+            for (id, text) in entries {
+                data.extend_from_slice(*id);
+                data.extend_from_slice(&(text.len() as u32).to_le_bytes());
+                data.extend_from_slice(text);
+                if text.len() % 2 == 1 {
+                    data.push(0);
+                }
+            }
+            data
+        };
+        let read = |data: Vec<u8>| {
+            let mut warnings = Vec::new();
+            let len = data.len() as u64;
+            let mut reader = std::io::Cursor::new(data);
+            let mut info = DocumentInfo::default();
+            info.read_list(ChunkReader::new(&mut reader, len, 12), &mut warnings);
+            (
+                info.png_text()
+                    .map(|(k, v)| (k, v.to_owned()))
+                    .collect::<Vec<_>>(),
+                warnings,
+            )
+        };
+
+        let (text, warnings) = read(list(&[
+            (b"ICMT", b"A comment\0"),
+            (b"ISFT", b"fuzzpaint\0"),
+            (b"INAM", b"Title\0"),
+            (b"INAM", b"Second title\0"),
+        ]));
+        assert_eq!(
+            text,
+            [
+                ("Title", "Title".into()),
+                ("Description", "A comment".into())
+            ]
+        );
+        assert_eq!(warnings, []);
+
+        let long = vec![b'a'; 5000];
+        let (text, _) = read(list(&[(b"IART", &long), (b"INAM", b"After\0")]));
+        assert_eq!(
+            text,
+            [("Title", "After".into()), ("Author", "a".repeat(1024))]
+        );
+
+        // An entry overrunning the list keeps what's there.
+        let mut data = list(&[(b"INAM", b"Cut\0")]);
+        data.extend_from_slice(b"IART\xff\0\0\0Some");
+        let (text, warnings) = read(data);
+        assert_eq!(text, [("Title", "Cut".into()), ("Author", "Some".into())]);
+        assert_eq!(warnings.len(), 1);
+    }
+
+    #[test]
+    fn versions() {
+        let software = |vrsn: &[u8]| {
+            let mut info = DocumentInfo::default();
+            info.read_version(&mut &vrsn[..]);
+            info.software()
+        };
+        let ours = env!("CARGO_PKG_VERSION");
+        assert_eq!(
+            software(b"0.3.1\0"),
+            format!("Fuzzpaint 0.3.1 (fuzzpaint-thumbnailer {ours})")
+        );
+        assert_eq!(
+            software(b"0.3.1-dev\n+git \x07abc"),
+            format!("Fuzzpaint 0.3.1-dev +git abc (fuzzpaint-thumbnailer {ours})")
+        );
+        assert_eq!(
+            software(&[b'9'; 100]),
+            format!(
+                "Fuzzpaint {} (fuzzpaint-thumbnailer {ours})",
+                "9".repeat(64)
+            )
+        );
+        let unknown = format!("Fuzzpaint (fuzzpaint-thumbnailer {ours})");
+        assert_eq!(software(b"\0"), unknown);
+        assert_eq!(software(b""), unknown);
+        assert_eq!(DocumentInfo::default().software(), unknown);
+    }
+
+    #[test]
+    fn backgrounds() {
+        let read = |rgba: &[f32]| {
+            let bgcl: Vec<u8> = rgba.iter().flat_map(|c| c.to_le_bytes()).collect();
+            let mut info = DocumentInfo::default();
+            info.read_background(&mut &bgcl[..], bgcl.len() as u64);
+            info.background()
+        };
+        assert_eq!(read(&[1.0, 1.0, 1.0, 1.0]), Some([255; 4]));
+        assert_eq!(read(&[0.0, 0.0, 0.0, 0.0]), Some([0; 4]));
+        // Mid-grey in linear light is much lighter in sRGB, alpha isn't.
+        assert_eq!(read(&[0.5, 0.2159, 0.0, 0.5]), Some([188, 128, 0, 128]));
+        assert_eq!(read(&[1.5, 0.0, 0.0, 1.0]), None);
+        assert_eq!(read(&[-0.1, 0.0, 0.0, 1.0]), None);
+        assert_eq!(read(&[f32::NAN, 0.0, 0.0, 1.0]), None);
+        assert_eq!(read(&[1.0, 1.0, 1.0]), None);
+    }
+
+    #[test]
+    fn orientations() {
+        let read = |degrees: u16, mirror: u16| {
+            let ornt = [degrees.to_le_bytes(), mirror.to_le_bytes()].concat();
+            let mut info = DocumentInfo::default();
+            info.read_orientation(&mut &ornt[..], ornt.len() as u64);
+            info.orientation()
+        };
+        assert_eq!(read(0, 0), (None, None));
+        assert_eq!(read(90, 0), (Some(Rotation::Quarter), None));
+        assert_eq!(
+            read(270, 1),
+            (Some(Rotation::ThreeQuarter), Some(Flip::Horizontal))
+        );
+        assert_eq!(read(0, 1), (None, Some(Flip::Horizontal)));
+        assert_eq!(read(45, 0), (None, None));
+        assert_eq!(read(180, 2), (None, None));
+        let mut info = DocumentInfo::default();
+        info.read_orientation(&mut &[90, 0][..], 2);
+        assert_eq!(info.orientation(), (None, None));
+    }
+
+    #[test]
+    fn counts() {
+        let read = |strc: &[u8]| {
+            let mut info = DocumentInfo::default();
+            info.read_structure(&mut &strc[..], strc.len() as u64);
+            info.count_text().collect::<Vec<_>>()
+        };
+        let strc =
+            |layers: u32, objects: u32| [layers.to_le_bytes(), objects.to_le_bytes()].concat();
+        assert_eq!(
+            read(&strc(3, 1200)),
+            [
+                ("X-Fuzzpaint::Layers", "3".to_owned()),
+                ("X-Fuzzpaint::Objects", "1200".to_owned())
+            ]
+        );
+        assert_eq!(
+            read(&strc(u32::MAX, u32::MAX)),
+            [
+                ("X-Fuzzpaint::Layers", MAX_LAYERS.to_string()),
+                ("X-Fuzzpaint::Objects", MAX_OBJECTS.to_string())
+            ]
+        );
+        assert_eq!(read(&strc(3, 1200)[..6]), []);
+        assert_eq!(read(&[]), []);
+    }
+
+    #[test]
+    fn resolutions() {
+        let read = |reso: &[u8]| {
+            let mut info = DocumentInfo::default();
+            info.read_resolution(&mut &reso[..], reso.len() as u64);
+            (info.pixels_per_metre(), info.dpi_text())
+        };
+        let reso = |x: u32, y: u32| [x.to_le_bytes(), y.to_le_bytes()].concat();
+        assert_eq!(
+            read(&reso(300, 300)),
+            (Some((11811, 11811)), Some("300".into()))
+        );
+        assert_eq!(
+            read(&reso(72, 144)),
+            (Some((2835, 5669)), Some("72x144".into()))
+        );
+        assert_eq!(
+            read(&reso(100_000, 1)),
+            (Some((3_937_008, 39)), Some("100000x1".into()))
+        );
+        assert_eq!(read(&reso(0, 300)), (None, None));
+        assert_eq!(read(&reso(300, 100_001)), (None, None));
+        assert_eq!(read(&reso(300, 300)[..6]), (None, None));
+    }
+}
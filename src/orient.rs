@@ -0,0 +1,83 @@
+//! Reorienting decoded thumbnails, for `--rotate` and `--flip`, or as a document's `ornt` chunk
+//! says.
+//!
+//! Images are row-major slices of `width * height` pixels.
+use crate::args::{Flip, Rotation};
+
+/// Rotate clockwise. The result is `height` pixels wide for quarter turns.
+pub fn rotate<P: Copy>(pixels: &[P], width: usize, height: usize, rotation: Rotation) -> Vec<P> {
+    debug_assert_eq!(pixels.len(), width * height);
+    let at = |x: usize, y: usize| pixels[y * width + x];
+    match rotation {
+        // Each output row is a source column, read bottom to top.
+        Rotation::Quarter => (0..width)
+            .flat_map(|x| (0..height).rev().map(move |y| at(x, y)))
+            .collect(),
+        Rotation::Half => pixels.iter().rev().copied().collect(),
+        // Each output row is a source column, read top to bottom, starting from the right.
+        Rotation::ThreeQuarter => (0..width)
+            .rev()
+            .flat_map(|x| (0..height).map(move |y| at(x, y)))
+            .collect(),
+    }
+}
+
+/// Mirror in place.
+pub fn flip<P>(pixels: &mut [P], width: usize, flip: Flip) {
+    debug_assert_eq!(pixels.len() % width.max(1), 0);
+    match flip {
+        Flip::Horizontal => pixels.chunks_exact_mut(width).for_each(<[P]>::reverse),
+        Flip::Vertical => {
+            let height = pixels.len() / width;
+            for y in 0..height / 2 {
+                let (top, bottom) = pixels.split_at_mut((height - 1 - y) * width);
+                top[y * width..(y + 1) * width].swap_with_slice(&mut bottom[..width]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    /// 2 wide, 3 tall:
+    /// ```text
+    /// 1 2
+    /// 3 4
+    /// 5 6
+    /// ```
+    const IMAGE: [u8; 6] = [1, 2, 3, 4, 5, 6];
+
+    #[test]
+    fn rotations() {
+        // 3 wide, 2 tall.
+        assert_eq!(rotate(&IMAGE, 2, 3, Rotation::Quarter), [5, 3, 1, 6, 4, 2]);
+        assert_eq!(rotate(&IMAGE, 2, 3, Rotation::Half), [6, 5, 4, 3, 2, 1]);
+        assert_eq!(
+            rotate(&IMAGE, 2, 3, Rotation::ThreeQuarter),
+            [2, 4, 6, 1, 3, 5]
+        );
+        // Four quarter turns go all the way around.
+        let mut image = IMAGE.to_vec();
+        let (mut width, mut height) = (2, 3);
+        for _ in 0..4 {
+            image = rotate(&image, width, height, Rotation::Quarter);
+            (width, height) = (height, width);
+        }
+        assert_eq!(image, IMAGE);
+    }
+    #[test]
+    fn flips() {
+        let flipped = |f| {
+            let mut image = IMAGE;
+            flip(&mut image, 2, f);
+            image
+        };
+        assert_eq!(flipped(Flip::Horizontal), [2, 1, 4, 3, 6, 5]);
+        assert_eq!(flipped(Flip::Vertical), [5, 6, 3, 4, 1, 2]);
+        // Even number of rows.
+        let mut image = [1, 2, 3, 4, 5, 6];
+        flip(&mut image, 3, Flip::Vertical);
+        assert_eq!(image, [4, 5, 6, 1, 2, 3]);
+    }
+}
@@ -0,0 +1,183 @@
+//! Machine-readable results.
+use crate::args::Size;
+use crate::error::{Class, Error};
+use crate::timings::Timings;
+use std::fmt::Write;
+use std::path::Path;
+
+/// The outcome of thumbnailing one document.
+///
+/// Paths that aren't UTF-8 are reported lossily.
+pub struct ItemReport<'a> {
+    pub input: &'a Path,
+    pub output: &'a Path,
+    /// Output dimensions, or why there are none.
+    pub result: Result<(u32, u32), &'a Error>,
+    pub elapsed: std::time::Duration,
+    /// Per-stage durations, if requested.
+    pub timings: Option<&'a Timings>,
+}
+impl ItemReport<'_> {
+    /// Serialize as a single-line JSON object.
+    pub fn to_json(&self) -> String {
+        let mut json = format!(
+            "{{\"input\":{},\"output\":{},",
+            json_string(&self.input.to_string_lossy()),
+            json_string(&self.output.to_string_lossy())
+        );
+        // Writing to a string is infallible.
+        match self.result {
+            Ok((width, height)) => {
+                let _ = write!(
+                    json,
+                    "\"status\":\"ok\",\"width\":{width},\"height\":{height},"
+                );
+            }
+            Err(err) => {
+                let _ = write!(
+                    json,
+                    "\"status\":\"{}\",\"message\":{},",
+                    status(err.class),
+                    json_string(&err.message)
+                );
+            }
+        }
+        if let Some(timings) = self.timings {
+            json.push_str("\"timings_us\":{");
+            for (i, (name, duration)) in timings.stages().into_iter().enumerate() {
+                let comma = if i == 0 { "" } else { "," };
+                let _ = write!(json, "{comma}\"{name}\":{}", duration.as_micros());
+            }
+            json.push_str("},");
+        }
+        let _ = write!(json, "\"elapsed_ms\":{}}}", self.elapsed.as_millis());
+        json
+    }
+}
+
+/// Description of a document's thumbnail, for `--info`.
+pub struct ThumbnailInfo {
+    pub width: u32,
+    pub height: u32,
+    pub channels: u8,
+    pub colorspace: qoi::ColorSpace,
+    /// Length of the `thmb` chunk.
+    pub thumbnail_bytes: u64,
+    /// Modification time of the document, if known.
+    pub mtime: Option<std::time::Duration>,
+}
+impl ThumbnailInfo {
+    /// Serialize as a single-line JSON object.
+    pub fn to_json(&self) -> String {
+        let colorspace = match self.colorspace {
+            qoi::ColorSpace::Srgb => "srgb",
+            qoi::ColorSpace::Linear => "linear",
+        };
+        let mtime = self
+            .mtime
+            .map_or_else(|| "null".to_owned(), |mtime| mtime.as_secs().to_string());
+        format!(
+            "{{\"width\":{},\"height\":{},\"channels\":{},\"colorspace\":\"{colorspace}\",\"thumbnail_bytes\":{},\"mtime\":{mtime}}}",
+            self.width, self.height, self.channels, self.thumbnail_bytes
+        )
+    }
+}
+
+/// What thumbnailing would do, for `--dry-run`.
+///
+/// Paths that aren't UTF-8 are reported lossily.
+pub struct Plan {
+    pub input: String,
+    pub output: String,
+    pub uri: String,
+    /// Requested size, or `None` for native.
+    pub size: Option<Size>,
+    /// Dimensions of the thumbnail that would be written.
+    pub width: u32,
+    pub height: u32,
+    /// Written as `Thumb::MTime`, if known.
+    pub mtime: Option<std::time::Duration>,
+    /// The output is already up to date, so would be left alone.
+    pub fresh: bool,
+}
+impl Plan {
+    /// Serialize as a single-line JSON object.
+    pub fn to_json(&self) -> String {
+        let size = self
+            .size
+            .map_or_else(|| "native".to_owned(), |size| size.to_string());
+        let mtime = self
+            .mtime
+            .map_or_else(|| "null".to_owned(), |mtime| mtime.as_secs().to_string());
+        format!(
+            "{{\"input\":{},\"output\":{},\"uri\":{},\"size\":{},\"width\":{},\"height\":{},\"mtime\":{mtime},\"fresh\":{}}}",
+            json_string(&self.input),
+            json_string(&self.output),
+            json_string(&self.uri),
+            json_string(&size),
+            self.width,
+            self.height,
+            self.fresh
+        )
+    }
+}
+impl std::fmt::Display for Plan {
+    /// One `key=value` line for each field, with `none` for a missing mtime.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "input={}", self.input)?;
+        writeln!(f, "output={}", self.output)?;
+        writeln!(f, "uri={}", self.uri)?;
+        match self.size {
+            Some(size) => writeln!(f, "size={size}")?,
+            None => writeln!(f, "size=native")?,
+        }
+        writeln!(f, "width={}", self.width)?;
+        writeln!(f, "height={}", self.height)?;
+        match self.mtime {
+            Some(mtime) => writeln!(f, "mtime={}", mtime.as_secs())?,
+            None => writeln!(f, "mtime=none")?,
+        }
+        writeln!(f, "fresh={}", self.fresh)
+    }
+}
+
+/// Serialize an error as a single-line JSON object.
+pub fn error_json(err: &Error) -> String {
+    format!(
+        "{{\"error\":\"{}\",\"message\":{}}}",
+        status(err.class),
+        json_string(&err.message)
+    )
+}
+
+/// Short machine-readable name for a failure class.
+fn status(class: Class) -> &'static str {
+    match class {
+        Class::Usage => "usage",
+        Class::NoThumbnail => "no-thumbnail",
+        Class::Corrupt => "corrupt",
+        Class::InputIo | Class::OutputIo => "io-error",
+        Class::Transient => "transient",
+    }
+}
+
+/// Quote and escape a string for JSON.
+pub fn json_string(s: &str) -> String {
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if c.is_control() => {
+                let _ = write!(quoted, "\\u{:04x}", u32::from(c));
+            }
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
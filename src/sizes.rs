@@ -0,0 +1,340 @@
+//! Thumbnailing one document at several sizes, decoding it only once.
+use crate::args;
+use crate::error::{Class, Error};
+use crate::log;
+use crate::report::ItemReport;
+use crate::template;
+use crate::timings::Timings;
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::time::{Duration, Instant};
+
+/// Write every size, reporting failures as we go rather than bailing.
+///
+/// The thumbnail is found as `scan` says, so with [`crate::Scan::strict`] anything wrong with the
+/// document fails every size.
+pub fn run(sizes: &args::Sizes, scan: crate::Scan) -> Result<ExitCode, Error> {
+    let uri = crate::input_uri(&sizes.input, sizes.uri.clone())?;
+    let outputs = output_paths(sizes, &uri)?;
+
+    let start = Instant::now();
+    let mut timings = Timings::start(sizes.options.timings);
+    let written = thumbnail_sizes(
+        &sizes.input,
+        &outputs,
+        &uri,
+        sizes.mtime.map(Duration::from_secs),
+        &sizes.options,
+        scan,
+        &mut timings,
+    );
+    let written = match written {
+        Ok(written) => written,
+        // Nothing was written, so every size failed the same way.
+        Err(err) => match sizes.report {
+            Some(args::ReportFormat::Jsonl) => {
+                for (_, out_path) in &outputs {
+                    let report = ItemReport {
+                        input: &sizes.input,
+                        output: out_path,
+                        result: Err(&err),
+                        elapsed: start.elapsed(),
+                        timings: timings.enabled().then_some(&timings),
+                    };
+                    println!("{}", report.to_json());
+                }
+                return Ok(err.class.exit_code());
+            }
+            None => {
+                if timings.enabled() {
+                    // Asked for explicitly, so not subject to --quiet.
+                    eprintln!("{timings}");
+                }
+                return Err(err);
+            }
+        },
+    };
+
+    let mut failures = 0;
+    for (written, (_, out_path)) in written.iter().zip(&outputs) {
+        if written.result.is_err() {
+            failures += 1;
+        }
+        match sizes.report {
+            Some(args::ReportFormat::Jsonl) => {
+                let report = ItemReport {
+                    input: &sizes.input,
+                    output: out_path,
+                    result: written.result.as_ref().copied(),
+                    elapsed: written.elapsed,
+                    timings: written.timings.enabled().then_some(&written.timings),
+                };
+                println!("{}", report.to_json());
+            }
+            None => {
+                if written.timings.enabled() {
+                    // Asked for explicitly, so not subject to --quiet.
+                    eprintln!("{}: {}", out_path.display(), written.timings);
+                }
+                if let Err(err) = &written.result {
+                    log::warn(format_args!("{}: {err}", out_path.display()));
+                }
+            }
+        }
+    }
+
+    match failures {
+        0 => Ok(ExitCode::SUCCESS),
+        failures => {
+            if sizes.report.is_none() {
+                eprintln!("{failures} of {} sizes failed", outputs.len());
+            }
+            Ok(ExitCode::FAILURE)
+        }
+    }
+}
+
+/// Where to write each size, by expanding the `--out-template`.
+///
+/// Fails if the template can't be expanded, or names two sizes the same.
+fn output_paths(sizes: &args::Sizes, uri: &str) -> Result<Vec<(args::Size, PathBuf)>, Error> {
+    let input = (sizes.input.as_os_str() != "-").then_some(&sizes.input);
+    let md5uri = crate::xdg::md5_uri(uri);
+    let mut outputs: Vec<(args::Size, PathBuf)> = Vec::with_capacity(sizes.sizes.len());
+    for &size in &sizes.sizes {
+        let fields = template::Fields {
+            stem: input.and_then(|input| input.file_stem()),
+            ext: input.and_then(|input| input.extension()),
+            size: Some(size),
+            md5uri: &md5uri,
+        };
+        let name = sizes
+            .out_template
+            .expand(&fields)
+            .map_err(|message| Error::new(Class::Usage, message))?;
+        let path = sizes.out_dir.join(name);
+        if let Some((other, _)) = outputs.iter().find(|(_, other)| *other == path) {
+            return Err(Error::new(
+                Class::Usage,
+                format!(
+                    "--out-template names both sizes {other} and {size} `{}`",
+                    path.display()
+                ),
+            ));
+        }
+        outputs.push((size, path));
+    }
+    Ok(outputs)
+}
+
+/// The outcome of writing one size.
+struct Written {
+    /// Dimensions of the written thumbnail, or why there isn't one.
+    result: Result<(u32, u32), Error>,
+    timings: Timings,
+    elapsed: Duration,
+}
+
+/// Read `in_path` (or stdin, for `-`) and write each `(size, out_path)` of `outputs`, decoding
+/// the thumbnail only once.
+///
+/// Outputs that are already thumbnails of this version of the input are left alone unless
+/// forced. Fails outright only if the thumbnail couldn't be decoded, otherwise each output
+/// succeeds or fails alone. Like [`crate::thumbnail_one`], a fail marker is written if anything
+/// goes wrong after the input is opened.
+///
+/// Opening and decoding are timed into `timings`, and shared by every output, so they're only
+/// counted against the first.
+fn thumbnail_sizes(
+    in_path: &Path,
+    outputs: &[(args::Size, PathBuf)],
+    in_uri: &str,
+    mtime_override: Option<Duration>,
+    options: &args::Options,
+    scan: crate::Scan,
+    timings: &mut Timings,
+) -> Result<Vec<Written>, Error> {
+    let start = Instant::now();
+    let opened = crate::open_input(in_path, mtime_override);
+    timings.open = timings.lap();
+    let opened = opened?;
+    let mut modified_unix_time = opened.mtime;
+    let fail_marker = |modified_unix_time: Option<Duration>, err: &Error| {
+        // Only if it's the document that failed, see `thumbnail_one`.
+        if let Some(mtime) = modified_unix_time.filter(|_| err.class.marks_failed()) {
+            // Tell the shell not to bother retrying this version of the file.
+            // Best-effort - if the cache isn't writable, there's nothing more we can do.
+            let _ = crate::xdg::write_fail_marker(in_uri, mtime.as_secs(), &err.to_string());
+        }
+    };
+
+    let fresh: Vec<Option<(u32, u32)>> = outputs
+        .iter()
+        .map(|(_, out_path)| match (options.force, modified_unix_time) {
+            (false, Some(mtime)) if out_path.as_os_str() != "-" => {
+                crate::xdg::fresh_thumbnail(out_path, in_uri, mtime.as_secs())
+            }
+            _ => None,
+        })
+        .collect();
+    // Nothing to decode if every output is already up to date.
+    let image = if fresh.iter().any(Option::is_none) {
+        // Enough for the largest, which the rest are scaled down from.
+        let scan = crate::Scan {
+            size: outputs
+                .iter()
+                .map(|(size, _)| size.width.max(size.height))
+                .max(),
+            ..scan
+        };
+        // Rendered at several sizes, so decoded whole.
+        match crate::read_settled(opened, options.max_dimension, scan, None, timings) {
+            Ok((image, mtime)) => {
+                // Of the version read, if it had to be read again.
+                modified_unix_time = mtime;
+                if let Err(err) = crate::check_blank(&image, options) {
+                    fail_marker(modified_unix_time, &err);
+                    return Err(err);
+                }
+                Some(image)
+            }
+            // This isn't the version that failed.
+            Err(err) if err.class == crate::error::Class::Transient => return Err(err),
+            Err(err) => {
+                fail_marker(modified_unix_time, &err);
+                return Err(err);
+            }
+        }
+    } else {
+        None
+    };
+
+    let mut shared = Some((*timings, start));
+    let written: Vec<Written> = outputs
+        .iter()
+        .zip(fresh)
+        .map(|((size, out_path), fresh)| {
+            let (mut timings, start) = shared
+                .take()
+                .unwrap_or_else(|| (Timings::start(options.timings), Instant::now()));
+            let result = match fresh {
+                Some(dimensions) => {
+                    log::verbose(log::Stage::Fresh);
+                    Ok(dimensions)
+                }
+                None => {
+                    let image = image.as_ref().expect("decoded, as this output is stale");
+                    let options = args::Options {
+                        size: Some(*size),
+                        ..options.clone()
+                    };
+                    crate::render(
+                        image,
+                        &options,
+                        crate::Destination::Path(out_path),
+                        in_uri,
+                        modified_unix_time,
+                        &mut timings,
+                    )
+                }
+            };
+            Written {
+                result,
+                timings,
+                elapsed: start.elapsed(),
+            }
+        })
+        .collect();
+    // Why the first failed, as any one of them is reason enough.
+    if let Some(Err(err)) = written
+        .iter()
+        .map(|written| &written.result)
+        .find(|r| r.is_err())
+    {
+        fail_marker(modified_unix_time, err);
+    }
+    Ok(written)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        let square = args::Size::square(128);
+        let boxed = args::Size {
+            width: 320,
+            height: 180,
+        };
+        let paths = |input: &str, template: &str, sizes: &[args::Size]| {
+            let sizes = args::Sizes {
+                input: input.into(),
+                uri: None,
+                mtime: None,
+                sizes: sizes.to_vec(),
+                out_dir: "thumbs".into(),
+                out_template: template::Template::parse(template).unwrap(),
+                report: None,
+                options: args::Options {
+                    size: None,
+                    mode: args::ScaleMode::Fit,
+                    filter: None,
+                    no_upscale: true,
+                    compression: args::Compression::Fast,
+                    max_dimension: crate::MAX_INPUT_IMAGE_DIMENSION,
+                    square: false,
+                    background: None,
+                    timings: false,
+                    print_hash: false,
+                    force: false,
+                    data_uri: false,
+                    png_text: Vec::new(),
+                    crop: None,
+                    rotate: None,
+                    flip: None,
+                    allow_blank: false,
+                    no_optimize_color: false,
+                    force_rgba: false,
+                    gamma_correct: false,
+                    no_premultiply: false,
+                    threads: None,
+                },
+            };
+            output_paths(&sizes, "file:///doc.fzp").map(|outputs| {
+                outputs
+                    .into_iter()
+                    .map(|(_, path)| path)
+                    .collect::<Vec<_>>()
+            })
+        };
+        assert_eq!(
+            paths("doc.v2.fzp", args::DEFAULT_NAME_TEMPLATE, &[square, boxed]).unwrap(),
+            [
+                PathBuf::from("thumbs/doc.v2-128.png"),
+                PathBuf::from("thumbs/doc.v2-320x180.png")
+            ]
+        );
+        assert_eq!(
+            paths("-", "{md5uri}-{width}.png", &[square]).unwrap(),
+            [PathBuf::from(format!(
+                "thumbs/{}-128.png",
+                crate::xdg::md5_uri("file:///doc.fzp")
+            ))]
+        );
+        // Nothing to expand `{stem}` from.
+        let err = paths("-", "{stem}-{size}.png", &[square]).unwrap_err();
+        assert_eq!(err.class, Class::Usage);
+        // Same width, so the same name.
+        let tall = args::Size {
+            width: 128,
+            height: 256,
+        };
+        let err = paths("doc.fzp", "{stem}-{width}.png", &[square, tall]).unwrap_err();
+        assert_eq!(err.class, Class::Usage);
+        assert!(
+            err.message.contains("names both sizes 128 and 128x256"),
+            "{err}"
+        );
+    }
+}
@@ -0,0 +1,579 @@
+//! Decoding large thumbnails a strip of rows at a time, scaling each strip as it's decoded, so
+//! the whole image is never held at once.
+//!
+//! Each strip is scaled across by `fast_image_resize`, then down here, a row at a time, as soon
+//! as every row the filter reaches for it has been scaled across. Rows are weighed as
+//! `fast_image_resize` weighs them, so the output is that of scaling the whole image, give or
+//! take rounding. At most a strip, and the rows within reach of the filter, are held at once, so
+//! the memory needed is set by the image's width and not its height.
+use crate::{args, bands, fit, mul_div_alpha, Pixel};
+use std::io::Read;
+use std::num::NonZeroU32;
+
+/// Thumbnails decoding to fewer bytes than this are decoded whole, as there's little to save.
+pub const STRIP_MIN_BYTES: usize = 1024 * 1024;
+/// Rows decoded at a time.
+pub const STRIP_ROWS: NonZeroU32 = NonZeroU32::new(64).unwrap();
+
+/// The body of a QOI image, after its header, decoded a few rows at a time.
+///
+/// Decodes just as `qoi`'s own streaming decoder does, down to its handling of malformed data.
+pub struct RowDecoder<R> {
+    reader: R,
+    /// Whether the image has an alpha channel. If not, [`Self::px`]'s stays opaque.
+    rgba: bool,
+    /// Whether the image is stored in linear light, rather than sRGB.
+    linear: bool,
+    /// The previous pixel.
+    px: [u8; 4],
+    /// Previously seen pixels, by hash.
+    index: [[u8; 4]; 64],
+    /// How many more times the previous pixel repeats.
+    run: usize,
+}
+
+impl<R: Read> RowDecoder<R> {
+    /// Decode the body at `reader` of an image with `header`.
+    pub fn new(reader: R, header: &qoi::Header) -> Self {
+        let rgba = header.channels == qoi::Channels::Rgba;
+        Self {
+            reader,
+            rgba,
+            linear: header.colorspace == qoi::ColorSpace::Linear,
+            px: [0, 0, 0, 0xff],
+            index: [[0, 0, 0, if rgba { 0 } else { 0xff }]; 64],
+            run: 0,
+        }
+    }
+    /// Decode the next pixels into `out`, in the image's channels.
+    pub fn decode(&mut self, out: &mut [u8]) -> qoi::Result<()> {
+        let channels = if self.rgba { 4 } else { 3 };
+        for px_out in out.chunks_exact_mut(channels) {
+            if self.run > 0 {
+                self.run -= 1;
+            } else {
+                self.next_px()?;
+            }
+            px_out.copy_from_slice(&self.px[..channels]);
+        }
+        Ok(())
+    }
+    /// Read the op for the next pixel, and update [`Self::px`] by it.
+    fn next_px(&mut self) -> qoi::Result<()> {
+        let [b1] = self.read()?;
+        match b1 {
+            // QOI_OP_INDEX
+            0x00..=0x3f => {
+                self.px = self.index[usize::from(b1)];
+                return Ok(());
+            }
+            // QOI_OP_RGB
+            0xfe => {
+                let [r, g, b] = self.read()?;
+                self.px[..3].copy_from_slice(&[r, g, b]);
+            }
+            // QOI_OP_RGBA, only in images with alpha.
+            0xff if self.rgba => self.px = self.read()?,
+            // QOI_OP_RUN, of this pixel and some more.
+            0xc0..=0xfd => {
+                self.run = usize::from(b1 & 0x3f);
+                return Ok(());
+            }
+            // QOI_OP_DIFF
+            0x40..=0x7f => {
+                let diff = |shift: u8| ((b1 >> shift) & 0x03).wrapping_sub(2);
+                let px = &mut self.px;
+                px[0] = px[0].wrapping_add(diff(4));
+                px[1] = px[1].wrapping_add(diff(2));
+                px[2] = px[2].wrapping_add(diff(0));
+            }
+            // QOI_OP_LUMA
+            0x80..=0xbf => {
+                let [b2] = self.read()?;
+                let px = &mut self.px;
+                let vg = (b1 & 0x3f).wrapping_sub(32);
+                let vg_8 = vg.wrapping_sub(8);
+                px[0] = px[0].wrapping_add(vg_8.wrapping_add(b2 >> 4));
+                px[1] = px[1].wrapping_add(vg);
+                px[2] = px[2].wrapping_add(vg_8.wrapping_add(b2 & 0x0f));
+            }
+            // QOI_OP_RGBA in an image without alpha, taken to repeat the pixel.
+            0xff => (),
+        }
+        let [r, g, b, a] = self.px.map(u32::from);
+        self.index[((r * 3 + g * 5 + b * 7 + a * 11) % 64) as usize] = self.px;
+        Ok(())
+    }
+    fn read<const N: usize>(&mut self) -> qoi::Result<[u8; N]> {
+        let mut bytes = [0; N];
+        self.reader.read_exact(&mut bytes)?;
+        Ok(bytes)
+    }
+    /// Check for the padding that ends the image, once every pixel has been decoded.
+    pub fn finish(mut self) -> qoi::Result<()> {
+        if self.read()? != [0, 0, 0, 0, 0, 0, 0, 1] {
+            return Err(qoi::Error::InvalidPadding);
+        }
+        Ok(())
+    }
+}
+
+/// The rows of the input an output row is weighed from, and by how much.
+struct Taps {
+    /// The first of the rows.
+    top: u32,
+    /// Weights of each row from [`Self::top`] on, summing to one.
+    weights: Vec<f64>,
+}
+impl Taps {
+    /// One past the last of the rows.
+    fn end(&self) -> u32 {
+        self.top + self.weights.len() as u32
+    }
+}
+
+/// A filter's weight at a distance from its center.
+type Kernel = fn(f64) -> f64;
+
+/// The filter's function, and how far either side of its center it reaches, as
+/// `fast_image_resize` defines them. `None` for nearest-neighbour, which isn't a convolution.
+fn kernel(filter: args::Filter) -> Option<(Kernel, f64)> {
+    fn sinc(x: f64) -> f64 {
+        if x == 0.0 {
+            1.0
+        } else {
+            let x = x * std::f64::consts::PI;
+            x.sin() / x
+        }
+    }
+    Some(match filter {
+        args::Filter::Nearest => return None,
+        args::Filter::Box => (|x| if x > -0.5 && x <= 0.5 { 1.0 } else { 0.0 }, 0.5),
+        args::Filter::Bilinear => (|x: f64| (1.0 - x.abs()).max(0.0), 1.0),
+        args::Filter::CatmullRom => (
+            |x: f64| {
+                const A: f64 = -0.5;
+                let x = x.abs();
+                if x < 1.0 {
+                    ((A + 2.0) * x - (A + 3.0)) * x * x + 1.0
+                } else if x < 2.0 {
+                    (((x - 5.0) * x + 8.0) * x - 4.0) * A
+                } else {
+                    0.0
+                }
+            },
+            2.0,
+        ),
+        args::Filter::Lanczos3 => (
+            |x| {
+                if (-3.0..3.0).contains(&x) {
+                    sinc(x) * sinc(x / 3.0)
+                } else {
+                    0.0
+                }
+            },
+            3.0,
+        ),
+    })
+}
+
+/// Weights for scaling rows `top..top + height` of an image `in_height` tall to `out_height`
+/// rows, matching those `fast_image_resize` uses, up to its rounding.
+///
+/// Each output row reaches past the region for the filter's support, up to the image's edges.
+fn taps(
+    in_height: u32,
+    (top, height): (u32, NonZeroU32),
+    out_height: NonZeroU32,
+    filter: args::Filter,
+) -> Vec<Taps> {
+    let scale = f64::from(height.get()) / f64::from(out_height.get());
+    let single = |top| Taps {
+        top,
+        weights: vec![1.0],
+    };
+    match kernel(filter) {
+        // Not scaled at all, just cropped.
+        _ if height == out_height => (top..top + height.get()).map(single).collect(),
+        None => {
+            // Stepped rather than multiplied, as `fast_image_resize` does, to pick the same rows.
+            let mut y = f64::from(top) + scale * 0.5;
+            (0..out_height.get())
+                .map(|_| {
+                    let row = (y as u32).min(in_height - 1);
+                    y += scale;
+                    single(row)
+                })
+                .collect()
+        }
+        Some((kernel, support)) => {
+            let filter_scale = scale.max(1.0);
+            let radius = support * filter_scale;
+            (0..out_height.get())
+                .map(|y| {
+                    let center = f64::from(top) + (f64::from(y) + 0.5) * scale;
+                    let first = (center - radius).floor().max(0.0) as u32;
+                    let end = ((center + radius).ceil() as u32).min(in_height);
+                    let mut weights: Vec<f64> = (first..end)
+                        .map(|row| kernel((f64::from(row) - (center - 0.5)) / filter_scale))
+                        .collect();
+                    let sum: f64 = weights.iter().sum();
+                    if sum != 0.0 {
+                        weights.iter_mut().for_each(|weight| *weight /= sum);
+                    }
+                    Taps {
+                        top: first,
+                        weights,
+                    }
+                })
+                .collect()
+        }
+    }
+}
+
+/// Weigh together `rows`, each `row_bytes` long, into one more row of `out`.
+fn weigh(rows: &[u8], row_bytes: usize, weights: &[f64], out: &mut Vec<u8>) {
+    out.extend((0..row_bytes).map(|i| {
+        let sum: f64 = weights
+            .iter()
+            .zip(rows[i..].iter().step_by(row_bytes))
+            .map(|(weight, &component)| weight * f64::from(component))
+            .sum();
+        sum.round().clamp(0.0, 255.0) as u8
+    }));
+}
+
+/// Decode the rest of a `width`x`height` image `strip_rows` at a time, scaling `region` of it to
+/// `scaled_width`x`scaled_height`, weighing colours by their alpha if `premultiply`, scaling each
+/// strip across on up to `threads` threads. Also returns whether the whole image, not just
+/// `region`, is fully transparent, as [`Pixel::all_transparent`].
+#[allow(clippy::too_many_arguments)]
+pub fn scale<P: Pixel>(
+    mut decoder: RowDecoder<impl Read>,
+    (width, height): (NonZeroU32, NonZeroU32),
+    region: fit::Region,
+    (scaled_width, scaled_height): (NonZeroU32, NonZeroU32),
+    filter: args::Filter,
+    premultiply: bool,
+    strip_rows: NonZeroU32,
+    threads: std::num::NonZeroUsize,
+) -> qoi::Result<(Vec<P>, bool)> {
+    use fast_image_resize as fr;
+    let across = scaled_width.get() as usize;
+    let row_bytes = across * std::mem::size_of::<P>();
+    let taps = taps(
+        height.get(),
+        (region.top, region.height),
+        scaled_height,
+        filter,
+    );
+    // OK - there's at least one output row.
+    let needed_end = taps.last().unwrap().end();
+
+    // Rows scaled across but not yet down, from `window_top` on. Rows are dropped once no output
+    // row left to weigh needs them, and rows no output row needs are never scaled across.
+    let mut window = Vec::<P>::new();
+    let mut window_top = taps[0].top;
+    let mut scaled = Vec::with_capacity(row_bytes * scaled_height.get() as usize);
+    let mut strip = vec![P::zeroed(); width.get() as usize * strip_rows.get() as usize];
+    let mut blank = true;
+    for top in (0..height.get()).step_by(strip_rows.get() as usize) {
+        // OK - nonzero, as `top` is below the height.
+        let strip_height = NonZeroU32::new(strip_rows.get().min(height.get() - top)).unwrap();
+        let strip = &mut strip[..width.get() as usize * strip_height.get() as usize];
+        decoder.decode(bytemuck::cast_slice_mut(strip))?;
+        if decoder.linear {
+            crate::transfer::to_srgb(bytemuck::cast_slice_mut(strip), std::mem::size_of::<P>());
+        }
+        blank &= P::all_transparent(strip);
+        if premultiply {
+            mul_div_alpha(
+                P::view_mut(
+                    fr::ImageViewMut::from_buffer(
+                        width,
+                        strip_height,
+                        bytemuck::cast_slice_mut(strip),
+                    )
+                    // OK - sized exactly, and U8x4 is aligned to 4.
+                    .unwrap(),
+                ),
+                false,
+            );
+        }
+
+        // Only the rows some output row is weighed from need scaling across.
+        let rows = top.max(window_top)..(top + strip_height.get()).min(needed_end);
+        let Some(rows_height) = NonZeroU32::new(rows.end.saturating_sub(rows.start)) else {
+            continue;
+        };
+        let filled = window.len();
+        window.resize(filled + across * rows_height.get() as usize, P::zeroed());
+        // Rows clamped to the strip above, and the region to the image by `fit::clamp_crop`.
+        bands::resize::<P::Resize>(
+            filter,
+            (width, strip_height),
+            bytemuck::cast_slice(strip),
+            fit::Region {
+                left: region.left,
+                top: rows.start - top,
+                width: region.width,
+                height: rows_height,
+            },
+            (scaled_width, rows_height),
+            bytemuck::cast_slice_mut(&mut window[filled..]),
+            threads,
+        );
+
+        // Scale down every output row whose rows are all in, then forget the rows behind them.
+        let window_end = window_top + (window.len() / across) as u32;
+        while let Some(next) = taps
+            .get(scaled.len() / row_bytes)
+            .filter(|next| next.end() <= window_end)
+        {
+            let from = (next.top - window_top) as usize * across;
+            let rows = &window[from..from + next.weights.len() * across];
+            weigh(
+                bytemuck::cast_slice(rows),
+                row_bytes,
+                &next.weights,
+                &mut scaled,
+            );
+        }
+        if let Some(next) = taps.get(scaled.len() / row_bytes) {
+            let behind = (next.top - window_top) as usize * across;
+            window.drain(..behind.min(window.len()));
+            window_top = next.top;
+        }
+    }
+    decoder.finish()?;
+    let mut scaled: Vec<P> = scaled
+        .chunks_exact(std::mem::size_of::<P>())
+        .map(bytemuck::pod_read_unaligned)
+        .collect();
+    if premultiply {
+        mul_div_alpha(
+            P::view_mut(
+                fr::ImageViewMut::from_buffer(
+                    scaled_width,
+                    scaled_height,
+                    bytemuck::cast_slice_mut(&mut scaled),
+                )
+                // OK - as above.
+                .unwrap(),
+            ),
+            true,
+        );
+    }
+    Ok((scaled, blank))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{resize, U8x3, U8x4};
+
+    /// Pixels of a `width`x`height` image with some of everything QOI encodes: runs, small and
+    /// large changes, repeats of earlier pixels, and translucency.
+    fn pattern(width: u32, height: u32, channels: usize) -> Vec<u8> {
+        (0..height)
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .flat_map(|(x, y)| {
+                let px = match (x / 5 + y / 3) % 4 {
+                    0 => [200, 10, 10, 255],
+                    1 => [(x * 7) as u8, (y * 13) as u8, (x ^ y) as u8, (x * y) as u8],
+                    2 => [100 + (x % 3) as u8, 100, 99 + (y % 2) as u8, 128],
+                    _ => [(x * 31) as u8, 40, (y * 17) as u8, 255],
+                };
+                px.into_iter().take(channels)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decodes_rows_as_qoi_does() {
+        for channels in [3, 4] {
+            let (width, height) = (37, 23);
+            let pixels = pattern(width, height, channels);
+            let qoi = qoi::encode_to_vec(&pixels, width, height).unwrap();
+            let header = qoi::decode_header(&qoi).unwrap();
+            let row = width as usize * channels;
+            // A row at a time, and unevenly.
+            for rows in [1, 2, 5, 23] {
+                let mut decoder = RowDecoder::new(&qoi[14..], &header);
+                let mut decoded = vec![0; pixels.len()];
+                for strip in decoded.chunks_mut(row * rows) {
+                    decoder.decode(strip).unwrap();
+                }
+                decoder.finish().unwrap();
+                assert_eq!(decoded, pixels, "{channels} channels, {rows} rows");
+            }
+
+            // Cut short, or with the wrong padding.
+            let mut decoder = RowDecoder::new(&qoi[14..qoi.len() - 20], &header);
+            assert!(decoder.decode(&mut vec![0; pixels.len()]).is_err());
+            let mut bad_padding = qoi.clone();
+            *bad_padding.last_mut().unwrap() = 2;
+            let mut decoder = RowDecoder::new(&bad_padding[14..], &header);
+            decoder.decode(&mut vec![0; pixels.len()]).unwrap();
+            assert!(matches!(decoder.finish(), Err(qoi::Error::InvalidPadding)));
+        }
+    }
+
+    /// Assert `scaled` is within rounding of `expected`, naming the first row that isn't. If
+    /// `premultiplied`, colours are compared weighed by their alpha, as the rounding was, since
+    /// dividing it back out magnifies it for faint pixels.
+    fn assert_near<P: Pixel>(
+        scaled: &[P],
+        expected: &[P],
+        width: u32,
+        premultiplied: bool,
+        context: &str,
+    ) {
+        let channels = std::mem::size_of::<P>();
+        let scaled = bytemuck::cast_slice::<_, u8>(scaled);
+        let expected = bytemuck::cast_slice::<_, u8>(expected);
+        assert_eq!(scaled.len(), expected.len(), "{context}");
+        let weighed = |px: &[u8], channel: usize| match px.get(3) {
+            Some(&alpha) if premultiplied && channel < 3 => {
+                u32::from(px[channel]) * u32::from(alpha)
+            }
+            _ => u32::from(px[channel]) * 255,
+        };
+        let row = width as usize * channels;
+        for (y, (scaled, expected)) in scaled.chunks(row).zip(expected.chunks(row)).enumerate() {
+            let near =
+                scaled
+                    .chunks(channels)
+                    .zip(expected.chunks(channels))
+                    .all(|(scaled, expected)| {
+                        (0..channels).all(|channel| {
+                            let tolerance = if premultiplied { 2 } else { 1 };
+                            weighed(scaled, channel).abs_diff(weighed(expected, channel))
+                                <= tolerance * 255
+                        })
+                    });
+            assert!(near, "{context}, row {y}: {scaled:?} != {expected:?}");
+        }
+    }
+
+    /// Scale `region` of a pattern both ways, for every filter and a few strip heights.
+    fn assert_scales_alike<P: Pixel>(
+        (width, height): (u32, u32),
+        region: fit::Region,
+        scaled: (u32, u32),
+    ) {
+        let channels = std::mem::size_of::<P>();
+        let pixels = pattern(width, height, channels);
+        let qoi = qoi::encode_to_vec(&pixels, width, height).unwrap();
+        let header = qoi::decode_header(&qoi).unwrap();
+        let size = (
+            NonZeroU32::new(width).unwrap(),
+            NonZeroU32::new(height).unwrap(),
+        );
+        let scaled = (
+            NonZeroU32::new(scaled.0).unwrap(),
+            NonZeroU32::new(scaled.1).unwrap(),
+        );
+        let whole: Vec<P> = pixels
+            .chunks_exact(channels)
+            .map(bytemuck::pod_read_unaligned)
+            .collect();
+        for filter in [
+            args::Filter::Nearest,
+            args::Filter::Bilinear,
+            args::Filter::CatmullRom,
+            args::Filter::Lanczos3,
+            args::Filter::Box,
+        ] {
+            for premultiply in [false, true] {
+                let one = std::num::NonZeroUsize::MIN;
+                let expected = resize(size, &whole, region, scaled, filter, premultiply, one);
+                for strip_rows in [1, 3, 16, STRIP_ROWS.get(), height] {
+                    let decoder = RowDecoder::new(&qoi[14..], &header);
+                    let strip_rows = NonZeroU32::new(strip_rows).unwrap();
+                    let (streamed, blank) = scale::<P>(
+                        decoder,
+                        size,
+                        region,
+                        scaled,
+                        filter,
+                        premultiply,
+                        strip_rows,
+                        // Scaled across on more threads than the whole is, to the same bits.
+                        std::num::NonZeroUsize::new(3).unwrap(),
+                    )
+                    .unwrap();
+                    assert_eq!(blank, P::all_transparent(&whole));
+                    assert_near(
+                        &streamed,
+                        &expected,
+                        scaled.0.get(),
+                        premultiply,
+                        &format!(
+                            "{channels} channels, {filter:?}, premultiply {premultiply}, \
+                            {strip_rows} rows, {region:?} to {scaled:?}"
+                        ),
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn scales_as_whole_images_do() {
+        let region = |left, top, width, height| fit::Region {
+            left,
+            top,
+            width: NonZeroU32::new(width).unwrap(),
+            height: NonZeroU32::new(height).unwrap(),
+        };
+        let whole = fit::Region::whole(NonZeroU32::new(40).unwrap(), NonZeroU32::new(30).unwrap());
+        for (region, scaled) in [
+            (whole, (13, 10)),
+            (whole, (40, 7)),
+            (whole, (9, 30)),
+            (whole, (40, 30)),
+            // Upscaled.
+            (whole, (64, 51)),
+            // Cropped, reaching outside the crop for the filter's support.
+            (region(5, 7, 20, 15), (8, 6)),
+            (region(5, 7, 20, 15), (20, 4)),
+            (region(0, 29, 40, 1), (10, 1)),
+        ] {
+            assert_scales_alike::<U8x4>((40, 30), region, scaled);
+            assert_scales_alike::<U8x3>((40, 30), region, scaled);
+        }
+
+        // Tall enough for many strips, with output rows weighed from either side of their
+        // boundaries.
+        let whole = fit::Region::whole(NonZeroU32::new(24).unwrap(), NonZeroU32::new(300).unwrap());
+        for (region, scaled) in [
+            (whole, (24, 37)),
+            (whole, (6, 3)),
+            (whole, (24, 299)),
+            (whole, (24, 301)),
+            (region(3, 62, 18, 68), (9, 17)),
+            (region(3, 63, 18, 2), (9, 5)),
+        ] {
+            assert_scales_alike::<U8x4>((24, 300), region, scaled);
+            assert_scales_alike::<U8x3>((24, 300), region, scaled);
+        }
+    }
+
+    #[test]
+    fn holds_only_rows_within_reach() {
+        let taps = taps(
+            4096,
+            (0, NonZeroU32::new(4096).unwrap()),
+            NonZeroU32::new(256).unwrap(),
+            args::Filter::Lanczos3,
+        );
+        // Lanczos3 reaches 3 rows either side, 16 times over when scaling down 16 times.
+        assert!(taps.iter().all(|taps| taps.weights.len() <= 97));
+        assert!(taps.windows(2).all(|pair| pair[0].top <= pair[1].top));
+        for taps in &taps {
+            let sum: f64 = taps.weights.iter().sum();
+            assert!((sum - 1.0).abs() < 1e-9);
+        }
+    }
+}
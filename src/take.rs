@@ -0,0 +1,674 @@
+//! A window onto part of a reader, which can be seeked within unlike [`std::io::Take`].
+use az::{CheckedAs, SaturatingAs};
+use std::io::{BufRead, Error as IOError, IoSliceMut, Read, Result as IOResult, Seek};
+
+/// Most reserved up front by [`MyTake::read_to_end`], as a take's length is often only what a
+/// document claims.
+const MAX_RESERVE: u64 = 16 * 1024 * 1024;
+
+/// std::io::Take, except it's Seek. Not sure why std's isn't D:
+///
+/// If the base reader is Seek, it shifts the basis of it
+/// such that the position at the time of MyTake's construction is the start,
+/// and that position + len is the end. Seeks past-the-end are clamped to the end.
+pub(crate) struct MyTake<R> {
+    reader: R,
+    cursor: u64,
+    len: u64,
+}
+impl<R> MyTake<R> {
+    pub fn new(reader: R, len: u64) -> Self {
+        Self {
+            reader,
+            len,
+            cursor: 0,
+        }
+    }
+    pub fn remaining(&self) -> u64 {
+        self.len
+            .checked_sub(self.cursor)
+            .expect("cursor past the end")
+    }
+    /// The inner reader. Reading or seeking it directly desyncs the cursor.
+    pub fn get_mut(&mut self) -> &mut R {
+        &mut self.reader
+    }
+    pub fn into_inner(self) -> R {
+        self.reader
+    }
+    /// Start a new take of `new_len` bytes from wherever the inner reader is now, as after
+    /// [`Self::skip_remaining`] to walk on to the next chunk.
+    pub fn set_len(&mut self, new_len: u64) {
+        self.len = new_len;
+        self.cursor = 0;
+    }
+}
+impl<R: Seek> MyTake<R> {
+    /// Move the inner reader to the end of the take in one seek, returning how far it moved.
+    pub fn skip_remaining(&mut self) -> IOResult<u64> {
+        let remaining = self.remaining();
+        if remaining == 0 {
+            return Ok(0);
+        }
+        let delta: i64 = remaining
+            .checked_as()
+            .ok_or_else(|| IOError::other("delta seek overflows"))?;
+        self.reader.seek(std::io::SeekFrom::Current(delta))?;
+        self.cursor = self.len;
+        Ok(remaining)
+    }
+    /// The inner reader, moved to just past the take.
+    pub fn into_inner_at_end(mut self) -> IOResult<R> {
+        self.skip_remaining()?;
+        Ok(self.into_inner())
+    }
+}
+impl<R> MyTake<R> {
+    /// Move the cursor on past `num_read` bytes read from the inner reader.
+    fn advance(&mut self, num_read: usize) -> IOResult<()> {
+        // Defensive checks for bad inner reader impl
+        // (or my own bugs :P)
+        let new_cursor = self
+            .cursor
+            .checked_add(num_read as u64)
+            .ok_or_else(|| IOError::other("inner reader overflowed MyTake cursor"))?;
+        debug_assert!(new_cursor <= self.len);
+        self.cursor = new_cursor;
+        Ok(())
+    }
+}
+impl<R: Read> Read for MyTake<R> {
+    fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+        let trimmed_len: usize = (buf.len() as u64).min(self.remaining()).saturating_as();
+        let buf = &mut buf[..trimmed_len];
+        // Short circuit if we can't read any more data
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let num_read = self.reader.read(buf)?;
+        self.advance(num_read)?;
+
+        Ok(num_read)
+    }
+    fn read_vectored(&mut self, bufs: &mut [IoSliceMut<'_>]) -> IOResult<usize> {
+        // Trim the buffers to what remains, dropping those left empty.
+        let mut remaining = self.remaining();
+        let mut trimmed = Vec::with_capacity(bufs.len());
+        for buf in bufs.iter_mut() {
+            let trimmed_len: usize = (buf.len() as u64).min(remaining).saturating_as();
+            if trimmed_len == 0 {
+                continue;
+            }
+            remaining -= trimmed_len as u64;
+            trimmed.push(IoSliceMut::new(&mut buf[..trimmed_len]));
+        }
+        if trimmed.is_empty() {
+            return Ok(0);
+        }
+        let num_read = self.reader.read_vectored(&mut trimmed)?;
+        self.advance(num_read)?;
+
+        Ok(num_read)
+    }
+    fn read_to_end(&mut self, buf: &mut Vec<u8>) -> IOResult<usize> {
+        let remaining = self.remaining();
+        // Only a hint, so if it can't be had, grow as usual.
+        let _ = buf.try_reserve(remaining.min(MAX_RESERVE).saturating_as());
+        let mut inner = (&mut self.reader).take(remaining);
+        let read = inner.read_to_end(buf);
+        // Count what was read even if it then failed, so the cursor doesn't fall behind.
+        let num_read = remaining - inner.limit();
+        self.advance(num_read.saturating_as())?;
+
+        read
+    }
+}
+impl<R: BufRead> BufRead for MyTake<R> {
+    fn consume(&mut self, amt: usize) {
+        // Only allow consuming as much as we're allowed to view.
+        let trimmed_amt = (amt as u64).min(self.remaining());
+        self.cursor = self
+            .cursor
+            .checked_add(trimmed_amt)
+            .expect("consume overflowed cursor");
+        debug_assert!(self.cursor <= self.len);
+
+        let trimmed_amt: usize = trimmed_amt.saturating_as();
+        self.reader.consume(trimmed_amt)
+    }
+    fn fill_buf(&mut self) -> IOResult<&[u8]> {
+        // Early call. Borrow weirdness.
+        let remaining = self.remaining();
+
+        let buf = self.reader.fill_buf()?;
+
+        // Limit buffer's size, prevent user from seeing past-the-end
+        let trimmed_len: usize = (buf.len() as u64).min(remaining).saturating_as();
+        let buf = &buf[..trimmed_len];
+
+        Ok(buf)
+    }
+}
+impl<R: Seek> Seek for MyTake<R> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> IOResult<u64> {
+        use std::io::SeekFrom;
+        let err_past_the_start = "seek offset past-the-start";
+        let err_overflow_cursor = "seek offset overflows cursor";
+        let new_cursor: u64 = match pos {
+            SeekFrom::Current(delta) => {
+                // Clamp upper bound to self length
+                let delta = if delta > 0 {
+                    // Saturate OK - we're taking the min with a i64 anyway
+                    delta.min(self.remaining().saturating_as())
+                } else {
+                    delta
+                };
+                self.cursor
+                    .checked_add_signed(delta)
+                    // Also catches past-the-start
+                    .ok_or_else(|| IOError::other(err_overflow_cursor))?
+            }
+            // Relative to our start, as is the length.
+            SeekFrom::Start(pos) => pos.min(self.len),
+            // Past-the-end clamps to the end, before it counts back from there.
+            SeekFrom::End(pos) if pos >= 0 => self.len,
+            SeekFrom::End(pos) => self
+                .len
+                .checked_sub(pos.unsigned_abs())
+                .ok_or_else(|| IOError::other(err_past_the_start))?,
+        };
+
+        // Each branch checks this individually. Still, make very sure.
+        debug_assert!(new_cursor <= self.len);
+
+        // We must seek the underlying reader with a Relative seek, as we
+        // don't know what it's End and Start are relative to ours
+        let delta: i64 = new_cursor
+            .checked_as::<i64>()
+            .zip(self.cursor.checked_as::<i64>())
+            .and_then(|(new, old)| new.checked_sub(old))
+            .ok_or_else(|| IOError::other("delta seek overflows"))?;
+
+        self.reader.seek(SeekFrom::Current(delta))?;
+        self.cursor = new_cursor;
+
+        Ok(self.cursor)
+    }
+    fn stream_position(&mut self) -> IOResult<u64> {
+        Ok(self.cursor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use quickcheck::{quickcheck, Arbitrary, Gen};
+    use std::io::{Cursor, SeekFrom};
+
+    #[test]
+    fn take_seeks_like_cursor() {
+        use std::io::SeekFrom;
+        let data: Vec<u8> = (0..64).collect();
+        let (start, len) = (16, 32);
+        let mut inner = std::io::Cursor::new(&data[..]);
+        inner.set_position(start);
+        let mut take = MyTake::new(inner, len);
+        // Over the same bytes, clamped after each seek as the take is.
+        let mut cursor = std::io::Cursor::new(&data[start as usize..(start + len) as usize]);
+
+        let mut ops = vec![
+            SeekFrom::Start(30),
+            SeekFrom::Start(4),
+            SeekFrom::Current(10),
+            SeekFrom::Current(-3),
+            SeekFrom::End(-5),
+            SeekFrom::End(0),
+            SeekFrom::End(7),
+            SeekFrom::Current(-40),
+            SeekFrom::End(-33),
+            SeekFrom::Start(100),
+            SeekFrom::Current(-1),
+            SeekFrom::Start(0),
+        ];
+        // And a stream of arbitrary ones.
+        let mut state = 0x2545_f491_u64;
+        for _ in 0..500 {
+            state = state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1);
+            let offset = (state >> 33) as i64 % 48 - 8;
+            ops.push(match state % 3 {
+                0 => SeekFrom::Start(offset.unsigned_abs()),
+                1 => SeekFrom::Current(offset),
+                _ => SeekFrom::End(offset),
+            });
+        }
+        for (i, &op) in ops.iter().enumerate() {
+            let expected = cursor.seek(op).map(|pos| {
+                cursor.set_position(pos.min(len));
+                pos.min(len)
+            });
+            match (take.seek(op), expected) {
+                (Ok(pos), Ok(expected)) => assert_eq!(pos, expected, "{op:?}"),
+                (Err(_), Err(_)) => (),
+                (pos, expected) => panic!("{op:?}: {pos:?}, not {expected:?}"),
+            }
+            // Whatever's read is the same too, moving both on.
+            let mut read = vec![0; i % 7];
+            let mut expected = vec![0; i % 7];
+            let n = take.read(&mut read).unwrap();
+            assert_eq!(n, cursor.read(&mut expected).unwrap(), "{op:?}");
+            assert_eq!(read[..n], expected[..n], "{op:?}");
+            assert_eq!(take.stream_position().unwrap(), cursor.position());
+        }
+    }
+
+    /// One operation on a take, mirrored onto a cursor.
+    #[derive(Clone, Debug)]
+    enum Op {
+        Read(u8),
+        FillBuf,
+        Consume(u8),
+        Start(u8),
+        Current(i8),
+        End(i8),
+    }
+    impl Arbitrary for Op {
+        fn arbitrary(g: &mut Gen) -> Self {
+            match u8::arbitrary(g) % 6 {
+                0 => Op::Read(u8::arbitrary(g)),
+                1 => Op::FillBuf,
+                2 => Op::Consume(u8::arbitrary(g)),
+                3 => Op::Start(u8::arbitrary(g)),
+                4 => Op::Current(i8::arbitrary(g)),
+                _ => Op::End(i8::arbitrary(g)),
+            }
+        }
+    }
+
+    /// A take of `len` bytes from `start` in `data`, the inner reader buffered by `capacity` so
+    /// its `fill_buf` sees past the end of the take. Both are wrapped to fit within `data`, as a
+    /// take longer than its reader seeks differently to a cursor over what's there.
+    fn take_of(
+        data: &[u8],
+        start: u8,
+        len: u8,
+        capacity: u8,
+    ) -> (MyTake<impl BufRead + Seek + '_>, Cursor<Vec<u8>>) {
+        let start = usize::from(start) % (data.len() + 1);
+        let len = usize::from(len) % (data.len() - start + 1);
+        let mut inner = Cursor::new(data);
+        inner.set_position(start as u64);
+        let inner = std::io::BufReader::with_capacity(usize::from(capacity) + 1, inner);
+        let cursor = Cursor::new(data[start..start + len].to_vec());
+        (MyTake::new(inner, len as u64), cursor)
+    }
+
+    /// Apply `op` to both, failing on any difference.
+    fn apply(
+        take: &mut MyTake<impl BufRead + Seek>,
+        cursor: &mut Cursor<Vec<u8>>,
+        op: &Op,
+    ) -> Result<(), String> {
+        let len = take.len;
+        let pos = match *op {
+            Op::Read(n) => {
+                let (mut read, mut expected) = (vec![0; n.into()], vec![0; n.into()]);
+                let n = take.read(&mut read).map_err(|e| e.to_string())?;
+                // Reads may come up short, so the cursor reads as many.
+                let m = cursor.read(&mut expected[..n]).unwrap();
+                if read[..n] != expected[..m]
+                    || (n == 0 && !read.is_empty() && !cursor.fill_buf().unwrap().is_empty())
+                {
+                    return Err(format!("read {:?}, not {:?}", &read[..n], &expected[..m]));
+                }
+                None
+            }
+            Op::FillBuf => {
+                let buf = take.fill_buf().map_err(|e| e.to_string())?.to_vec();
+                let expected = cursor.fill_buf().unwrap();
+                // Either may give less, but never anything else.
+                if !expected.starts_with(&buf) || (buf.is_empty() && !expected.is_empty()) {
+                    return Err(format!("filled {buf:?}, not {expected:?}"));
+                }
+                if buf.len() as u64 > take.remaining() {
+                    return Err("filled past the end".to_owned());
+                }
+                None
+            }
+            Op::Consume(n) => {
+                // Consuming more than was filled is up to the inner reader, so fill first.
+                let filled = take.fill_buf().map_err(|e| e.to_string())?.len();
+                let n = usize::from(n).min(filled);
+                take.consume(n);
+                cursor.consume(n);
+                None
+            }
+            Op::Start(pos) => Some(SeekFrom::Start(pos.into())),
+            Op::Current(delta) => Some(SeekFrom::Current(delta.into())),
+            Op::End(delta) => Some(SeekFrom::End(delta.into())),
+        };
+        if let Some(pos) = pos {
+            // The cursor doesn't clamp, so is clamped after to match.
+            let expected = cursor.seek(pos).map(|pos| {
+                let clamped = pos.min(len);
+                cursor.set_position(clamped);
+                clamped
+            });
+            match (take.seek(pos), expected) {
+                (Ok(pos), Ok(expected)) if pos == expected => (),
+                (Err(_), Err(_)) => (),
+                (pos, expected) => return Err(format!("seeked to {pos:?}, not {expected:?}")),
+            }
+        }
+        let position = take.stream_position().unwrap();
+        if position > len {
+            return Err(format!("position {position} past the end, {len}"));
+        }
+        if position != cursor.position() {
+            return Err(format!("at {position}, not {}", cursor.position()));
+        }
+        Ok(())
+    }
+
+    quickcheck! {
+        fn matches_cursor(data: Vec<u8>, start: u8, len: u8, capacity: u8, ops: Vec<Op>) -> Result<(), String> {
+            let (mut take, mut cursor) = take_of(&data, start, len, capacity);
+            ops.iter().try_for_each(|op| apply(&mut take, &mut cursor, op).map_err(|e| format!("{op:?}: {e}")))
+        }
+
+        fn reads_at_most_len(data: Vec<u8>, start: u8, len: u8, capacity: u8) -> bool {
+            let (mut take, cursor) = take_of(&data, start, len, capacity);
+            let mut read = Vec::new();
+            take.read_to_end(&mut read).unwrap();
+            read.len() <= usize::from(len) && read == cursor.into_inner()
+        }
+
+        fn position_is_bytes_consumed(data: Vec<u8>, start: u8, len: u8, capacity: u8, reads: Vec<u8>) -> bool {
+            let (mut take, _) = take_of(&data, start, len, capacity);
+            let mut consumed = 0;
+            reads.iter().all(|&n| {
+                consumed += take.read(&mut vec![0; n.into()]).unwrap() as u64;
+                take.stream_position().unwrap() == consumed
+            })
+        }
+
+        fn vectored_reads_stay_within(data: Vec<u8>, start: u8, len: u8, capacity: u8, lens: Vec<u8>) -> bool {
+            let (mut take, cursor) = take_of(&data, start, len, capacity);
+            let expected = cursor.into_inner();
+            let mut bufs: Vec<Vec<u8>> = lens.iter().map(|&n| vec![0xAA; n.into()]).collect();
+            let mut slices: Vec<IoSliceMut> = bufs.iter_mut().map(|buf| IoSliceMut::new(buf)).collect();
+            let n = take.read_vectored(&mut slices).unwrap();
+            // Filled in order, up to what was read, and untouched after.
+            let read: Vec<u8> = bufs.concat();
+            n <= expected.len()
+                && read[..n] == expected[..n]
+                && read[n..].iter().all(|&b| b == 0xAA)
+                && take.stream_position().unwrap() == n as u64
+        }
+
+        fn read_to_end_stays_within(data: Vec<u8>, start: u8, len: u8, capacity: u8, skip: u8) -> bool {
+            let (mut take, cursor) = take_of(&data, start, len, capacity);
+            let expected = cursor.into_inner();
+            let skip = usize::from(skip).min(expected.len());
+            take.read_exact(&mut vec![0; skip]).unwrap();
+            let mut read = vec![1, 2];
+            let n = take.read_to_end(&mut read).unwrap();
+            n == expected.len() - skip
+                && read[2..] == expected[skip..]
+                && take.stream_position().unwrap() == expected.len() as u64
+        }
+
+        fn fill_buf_agrees_with_read(data: Vec<u8>, start: u8, len: u8, capacity: u8, steps: Vec<u8>) -> bool {
+            let (mut by_buf, _) = take_of(&data, start, len, capacity);
+            let (mut by_read, _) = take_of(&data, start, len, capacity);
+            steps.iter().all(|&n| {
+                let buf = by_buf.fill_buf().unwrap();
+                let n = usize::from(n).min(buf.len());
+                let expected = buf[..n].to_vec();
+                by_buf.consume(n);
+                let mut read = vec![0; n];
+                by_read.read_exact(&mut read).unwrap();
+                read == expected && by_buf.stream_position().unwrap() == by_read.stream_position().unwrap()
+            })
+        }
+    }
+
+    #[test]
+    fn read_to_end_reserves() {
+        let data: Vec<u8> = (0..64).collect();
+        let mut inner = Cursor::new(&data[..]);
+        inner.set_position(8);
+        let mut take = MyTake::new(inner, 40);
+        take.read_exact(&mut [0; 4]).unwrap();
+        let mut read = Vec::new();
+        assert_eq!(take.read_to_end(&mut read).unwrap(), 36);
+        assert_eq!(read, data[12..48]);
+        assert!(read.capacity() >= 36);
+        // Past the take, the inner reader is untouched.
+        assert_eq!(take.into_inner().position(), 48);
+
+        // Lengths as large as a document might claim are only a hint.
+        let mut take = MyTake::new(&data[..], u64::MAX);
+        let mut read = Vec::new();
+        assert_eq!(take.read_to_end(&mut read).unwrap(), 64);
+        assert!(read.capacity() <= MAX_RESERVE as usize);
+    }
+
+    /// Reads a byte at a time, failing after `fail_after` of them.
+    struct Flaky {
+        data: Vec<u8>,
+        fail_after: usize,
+        read: usize,
+    }
+    impl Read for Flaky {
+        fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+            if self.read == self.fail_after {
+                return Err(IOError::other("flaky"));
+            }
+            let Some((&byte, buf)) = self.data.get(self.read).zip(buf.first_mut()) else {
+                return Ok(0);
+            };
+            *buf = byte;
+            self.read += 1;
+            Ok(1)
+        }
+    }
+
+    #[test]
+    fn read_to_end_counts_before_failing() {
+        let flaky = Flaky {
+            data: (0..16).collect(),
+            fail_after: 5,
+            read: 0,
+        };
+        let mut take = MyTake::new(flaky, 10);
+        let mut read = Vec::new();
+        assert!(take.read_to_end(&mut read).is_err());
+        assert_eq!(read, [0, 1, 2, 3, 4]);
+        assert_eq!(take.remaining(), 5);
+    }
+
+    #[test]
+    fn read_vectored_trims() {
+        let data: Vec<u8> = (0..16).collect();
+        let mut take = MyTake::new(&data[..], 6);
+        let (mut a, mut b, mut c) = ([0; 4], [0; 0], [0; 4]);
+        let mut bufs = [
+            IoSliceMut::new(&mut a),
+            IoSliceMut::new(&mut b),
+            IoSliceMut::new(&mut c),
+        ];
+        assert_eq!(take.read_vectored(&mut bufs).unwrap(), 6);
+        assert_eq!((a, c), ([0, 1, 2, 3], [4, 5, 0, 0]));
+        let mut bufs = [IoSliceMut::new(&mut a)];
+        assert_eq!(take.read_vectored(&mut bufs).unwrap(), 0);
+    }
+
+    /// Counts seeks and reads of the inner reader, as seeking it may be slow.
+    struct Counted<R> {
+        inner: R,
+        seeks: usize,
+        reads: usize,
+    }
+    impl<R> Counted<R> {
+        fn new(inner: R) -> Self {
+            Self {
+                inner,
+                seeks: 0,
+                reads: 0,
+            }
+        }
+    }
+    impl<R: Read> Read for Counted<R> {
+        fn read(&mut self, buf: &mut [u8]) -> IOResult<usize> {
+            self.reads += 1;
+            self.inner.read(buf)
+        }
+    }
+    impl<R: Seek> Seek for Counted<R> {
+        fn seek(&mut self, pos: SeekFrom) -> IOResult<u64> {
+            self.seeks += 1;
+            self.inner.seek(pos)
+        }
+    }
+
+    #[test]
+    fn skips_remaining() {
+        let data: Vec<u8> = (0..32).collect();
+        let mut inner = Counted::new(Cursor::new(&data[..]));
+        inner.inner.set_position(4);
+        let mut take = MyTake::new(inner, 20);
+        // Part read.
+        take.read_exact(&mut [0; 5]).unwrap();
+        assert_eq!(take.skip_remaining().unwrap(), 15);
+        assert_eq!(take.stream_position().unwrap(), 20);
+        assert_eq!(take.read(&mut [0; 1]).unwrap(), 0);
+        // Nothing left, so nothing to seek.
+        assert_eq!(take.skip_remaining().unwrap(), 0);
+        let inner = take.into_inner();
+        assert_eq!((inner.seeks, inner.reads), (1, 1));
+        assert_eq!(inner.inner.position(), 24);
+
+        // Part consumed through the buffer.
+        let mut take = MyTake::new(std::io::BufReader::with_capacity(4, Cursor::new(&data)), 10);
+        take.fill_buf().unwrap();
+        take.consume(3);
+        assert_eq!(take.skip_remaining().unwrap(), 7);
+        let mut next = [0; 2];
+        take.get_mut().read_exact(&mut next).unwrap();
+        assert_eq!(next, [10, 11]);
+    }
+
+    #[test]
+    fn into_inner_at_end() {
+        let data: Vec<u8> = (0..32).collect();
+        let mut inner = Counted::new(Cursor::new(&data[..]));
+        inner.inner.set_position(2);
+        let mut take = MyTake::new(inner, 12);
+        take.seek(SeekFrom::Start(7)).unwrap();
+        let inner = take.into_inner_at_end().unwrap();
+        assert_eq!(inner.inner.position(), 14);
+        assert_eq!(inner.seeks, 2);
+
+        // Untouched, or already at the end, it's one seek or none.
+        let inner = MyTake::new(Counted::new(Cursor::new(&data[..])), 9)
+            .into_inner_at_end()
+            .unwrap();
+        assert_eq!((inner.inner.position(), inner.seeks), (9, 1));
+        let mut take = MyTake::new(Counted::new(Cursor::new(&data[..])), 9);
+        take.read_to_end(&mut Vec::new()).unwrap();
+        let inner = take.into_inner_at_end().unwrap();
+        assert_eq!((inner.inner.position(), inner.seeks), (9, 0));
+
+        // Lengths too large to seek past are an error, not a wrong position.
+        let take = MyTake::new(Cursor::new(&data[..]), u64::MAX);
+        assert!(take.into_inner_at_end().is_err());
+    }
+
+    #[test]
+    fn walks_chunks_with_set_len() {
+        // Length-prefixed records, each read in part.
+        let data = [3, b'a', b'b', b'c', 0, 2, b'd', b'e', 1, b'f'];
+        let mut take = MyTake::new(Counted::new(Cursor::new(&data[..])), 0);
+        let mut firsts = Vec::new();
+        loop {
+            take.set_len(1);
+            let mut len = [0];
+            if take.read(&mut len).unwrap() == 0 {
+                break;
+            }
+            take.set_len(len[0].into());
+            let mut first = [0];
+            let n = take.read(&mut first).unwrap();
+            firsts.push(first[..n].to_vec());
+            take.skip_remaining().unwrap();
+        }
+        assert_eq!(firsts, [&b"a"[..], b"", b"d", b"f"]);
+        // Only the records with something left after their first byte were seeked past.
+        assert_eq!(take.into_inner().seeks, 2);
+    }
+
+    #[test]
+    fn zero_length() {
+        let data = [1, 2, 3];
+        let mut inner = Cursor::new(&data[..]);
+        inner.set_position(1);
+        let mut take = MyTake::new(inner, 0);
+        assert_eq!(take.read(&mut [0; 4]).unwrap(), 0);
+        assert!(take.fill_buf().unwrap().is_empty());
+        take.consume(5);
+        assert_eq!(take.seek(SeekFrom::Start(3)).unwrap(), 0);
+        assert_eq!(take.seek(SeekFrom::End(2)).unwrap(), 0);
+        assert_eq!(take.seek(SeekFrom::Current(1)).unwrap(), 0);
+        assert!(take.seek(SeekFrom::End(-1)).is_err());
+        assert!(take.seek(SeekFrom::Current(-1)).is_err());
+        assert_eq!(take.stream_position().unwrap(), 0);
+        // Nothing moved the inner reader.
+        assert_eq!(take.into_inner().position(), 1);
+    }
+
+    #[test]
+    fn longer_than_data() {
+        let data = [1, 2, 3, 4];
+        let mut inner = Cursor::new(&data[..]);
+        inner.set_position(1);
+        let mut take = MyTake::new(inner, 10);
+        let mut read = Vec::new();
+        take.read_to_end(&mut read).unwrap();
+        assert_eq!(read, [2, 3, 4]);
+        assert_eq!(take.stream_position().unwrap(), 3);
+        // Seeks go by the take's length, not the data's.
+        assert_eq!(take.seek(SeekFrom::End(-2)).unwrap(), 8);
+        assert_eq!(take.read(&mut [0; 4]).unwrap(), 0);
+        assert_eq!(take.seek(SeekFrom::Start(1)).unwrap(), 1);
+        assert_eq!(take.read(&mut [0; 1]).unwrap(), 1);
+        assert_eq!(take.into_inner().position(), 3);
+    }
+
+    #[test]
+    fn seeks_to_boundary() {
+        let data: Vec<u8> = (0..16).collect();
+        let mut inner = Cursor::new(&data[..]);
+        inner.set_position(4);
+        let mut take = MyTake::new(inner, 8);
+
+        assert_eq!(take.seek(SeekFrom::Start(8)).unwrap(), 8);
+        assert_eq!(take.read(&mut [0; 1]).unwrap(), 0);
+        assert!(take.fill_buf().unwrap().is_empty());
+        assert_eq!(take.seek(SeekFrom::End(-8)).unwrap(), 0);
+        assert_eq!(take.get_mut().position(), 4);
+        assert!(take.seek(SeekFrom::End(-9)).is_err());
+        assert_eq!(take.seek(SeekFrom::End(0)).unwrap(), 8);
+        assert_eq!(take.get_mut().position(), 12);
+        assert_eq!(take.seek(SeekFrom::Current(-8)).unwrap(), 0);
+        assert!(take.seek(SeekFrom::Current(-1)).is_err());
+        assert_eq!(take.seek(SeekFrom::Current(8)).unwrap(), 8);
+        // The last byte, and no more.
+        assert_eq!(take.seek(SeekFrom::End(-1)).unwrap(), 7);
+        let mut buf = [0; 4];
+        assert_eq!(take.read(&mut buf).unwrap(), 1);
+        assert_eq!(buf[0], 11);
+        assert_eq!(take.get_mut().position(), 12);
+    }
+}
@@ -0,0 +1,256 @@
+//! Naming output files from an `--out-template`.
+//!
+//! Placeholders are written `{name}`, and literal braces doubled as `{{` and `}}`.
+use crate::args::Size;
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+
+/// Something a template can be expanded with.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Placeholder {
+    /// The input's file name, without its extension.
+    Stem,
+    /// The input's extension, without its dot.
+    Ext,
+    /// The requested size, as it would be given on the command line.
+    Size,
+    /// Width of the requested size.
+    Width,
+    /// Height of the requested size.
+    Height,
+    /// Lowercase hex MD5 of the input's URI, as the XDG cache names thumbnails.
+    Md5Uri,
+}
+impl Placeholder {
+    /// Every placeholder, with its name in a template.
+    pub const NAMED: [(&'static str, Self); 6] = [
+        ("stem", Self::Stem),
+        ("ext", Self::Ext),
+        ("size", Self::Size),
+        ("width", Self::Width),
+        ("height", Self::Height),
+        ("md5uri", Self::Md5Uri),
+    ];
+    /// Placeholders that differ between sizes.
+    pub const SIZED: [Self; 3] = [Self::Size, Self::Width, Self::Height];
+}
+
+#[derive(Debug, PartialEq, Eq, Clone)]
+enum Part {
+    Literal(String),
+    Placeholder(Placeholder),
+}
+
+/// A parsed template, known to contain only valid placeholders.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Template {
+    /// As given, for messages.
+    source: String,
+    parts: Vec<Part>,
+}
+
+/// What to expand a template with, for one output.
+pub struct Fields<'a> {
+    /// `None` if the input has no file name, as for stdin.
+    pub stem: Option<&'a OsStr>,
+    pub ext: Option<&'a OsStr>,
+    /// `None` for native size.
+    pub size: Option<Size>,
+    pub md5uri: &'a str,
+}
+
+impl Template {
+    /// Parse `template`, checking its braces and placeholders.
+    pub fn parse(template: &str) -> Result<Self, Cow<'static, str>> {
+        let mut parts = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.as_str().starts_with('{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.as_str().starts_with('}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let Some((name, rest)) = chars.as_str().split_once('}') else {
+                        return Err(format!(
+                            "unclosed `{{` in template `{template}`, write `{{{{` for a literal brace"
+                        )
+                        .into());
+                    };
+                    let placeholder =
+                        crate::args::parse_named("placeholder", &Placeholder::NAMED, name)?;
+                    if !literal.is_empty() {
+                        parts.push(Part::Literal(std::mem::take(&mut literal)));
+                    }
+                    parts.push(Part::Placeholder(placeholder));
+                    chars = rest.chars();
+                }
+                '}' => {
+                    return Err(format!(
+                        "unmatched `}}` in template `{template}`, write `}}}}` for a literal brace"
+                    )
+                    .into())
+                }
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            parts.push(Part::Literal(literal));
+        }
+        Ok(Self {
+            source: template.to_owned(),
+            parts,
+        })
+    }
+    /// Whether `placeholder` appears anywhere.
+    pub fn uses(&self, placeholder: Placeholder) -> bool {
+        self.parts.contains(&Part::Placeholder(placeholder))
+    }
+    /// Whether any placeholder that differs between sizes appears.
+    pub fn uses_size(&self) -> bool {
+        Placeholder::SIZED.iter().any(|&sized| self.uses(sized))
+    }
+    /// Whether anything at all is substituted, so different outputs may get different names.
+    pub fn has_placeholders(&self) -> bool {
+        self.parts
+            .iter()
+            .any(|part| matches!(part, Part::Placeholder(_)))
+    }
+    /// Expand the template for one output.
+    ///
+    /// Fails if a placeholder has nothing to expand to.
+    pub fn expand(&self, fields: &Fields) -> Result<OsString, Cow<'static, str>> {
+        let mut name = OsString::new();
+        for part in &self.parts {
+            let size = || {
+                fields.size.ok_or_else(|| {
+                    Cow::Owned(format!(
+                        "template `{}` needs a <size> other than `native`",
+                        self.source
+                    ))
+                })
+            };
+            match *part {
+                Part::Literal(ref literal) => name.push(literal),
+                Part::Placeholder(Placeholder::Stem) => match fields.stem {
+                    Some(stem) => name.push(stem),
+                    None => {
+                        return Err(format!(
+                            "template `{}` uses `{{stem}}`, so <in_path> must be a file",
+                            self.source
+                        )
+                        .into())
+                    }
+                },
+                // No extension is just an empty one.
+                Part::Placeholder(Placeholder::Ext) => name.push(fields.ext.unwrap_or_default()),
+                Part::Placeholder(Placeholder::Size) => name.push(size()?.to_string()),
+                Part::Placeholder(Placeholder::Width) => name.push(size()?.width.to_string()),
+                Part::Placeholder(Placeholder::Height) => name.push(size()?.height.to_string()),
+                Part::Placeholder(Placeholder::Md5Uri) => name.push(fields.md5uri),
+            }
+        }
+        Ok(name)
+    }
+}
+impl std::fmt::Display for Template {
+    /// As it was given.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(size: Option<Size>) -> Fields<'static> {
+        Fields {
+            stem: Some(OsStr::new("drawing")),
+            ext: Some(OsStr::new("fzp")),
+            size,
+            md5uri: "0123abcd",
+        }
+    }
+    fn expand(template: &str, size: Option<Size>) -> Result<OsString, Cow<'static, str>> {
+        Template::parse(template)?.expand(&fields(size))
+    }
+
+    #[test]
+    fn placeholders() {
+        let size = Some(Size {
+            width: 320,
+            height: 180,
+        });
+        assert_eq!(
+            expand("{stem}.{ext}-{size}-{width}_{height}-{md5uri}.png", size).unwrap(),
+            "drawing.fzp-320x180-320_180-0123abcd.png"
+        );
+        assert_eq!(expand("{stem}{stem}", None).unwrap(), "drawingdrawing");
+        assert_eq!(expand("plain.png", None).unwrap(), "plain.png");
+        // Placeholders aren't expanded from fields themselves.
+        let braced = Fields {
+            stem: Some(OsStr::new("{size}")),
+            ..fields(Some(Size::square(8)))
+        };
+        assert_eq!(
+            Template::parse("{stem}-{size}")
+                .unwrap()
+                .expand(&braced)
+                .unwrap(),
+            "{size}-8"
+        );
+        assert_eq!(
+            expand("{size}.png", Some(Size::square(64))).unwrap(),
+            "64.png"
+        );
+        // Nothing to expand to.
+        assert!(expand("{size}.png", None).is_err());
+        let stdin = Fields {
+            stem: None,
+            ext: None,
+            size: None,
+            md5uri: "0123abcd",
+        };
+        let template = Template::parse("{md5uri}{ext}.png").unwrap();
+        assert_eq!(template.expand(&stdin).unwrap(), "0123abcd.png");
+        assert!(Template::parse("{stem}.png")
+            .unwrap()
+            .expand(&stdin)
+            .is_err());
+    }
+    #[test]
+    fn escaping() {
+        assert_eq!(
+            expand("{{{stem}}}-}}{{.png", None).unwrap(),
+            "{drawing}-}{.png"
+        );
+        assert_eq!(expand("{{stem}}", None).unwrap(), "{stem}");
+        for bad in ["{stem", "stem}", "{stem}}", "{", "}"] {
+            assert!(Template::parse(bad).is_err(), "{bad:?}");
+        }
+    }
+    #[test]
+    fn unknown_placeholders() {
+        let err = Template::parse("{name}.png").unwrap_err();
+        assert!(err.contains("unknown placeholder `name`"), "{err}");
+        assert!(Template::parse("{}.png").is_err());
+        assert!(Template::parse("{STEM}.png").is_err());
+    }
+    #[test]
+    fn uses() {
+        let template = Template::parse("{stem}-{width}.png").unwrap();
+        assert!(template.uses(Placeholder::Stem));
+        assert!(!template.uses(Placeholder::Md5Uri));
+        assert!(template.uses_size());
+        assert!(template.has_placeholders());
+        let template = Template::parse("{{size}}.png").unwrap();
+        assert!(!template.uses_size());
+        assert!(!template.has_placeholders());
+    }
+}
@@ -0,0 +1,66 @@
+//! Per-stage durations, for `--timings`.
+use std::fmt::Display;
+use std::time::{Duration, Instant};
+
+/// How long each stage of thumbnailing a document took.
+///
+/// Created disabled, nothing is measured and every duration stays zero.
+#[derive(Default, Clone, Copy)]
+pub struct Timings {
+    /// End of the previous lap, or `None` if disabled.
+    last: Option<Instant>,
+    /// Opening and statting the input.
+    pub open: Duration,
+    /// Finding the thumbnail chunk.
+    pub scan: Duration,
+    pub decode: Duration,
+    pub resize: Duration,
+    /// Encoding and writing the PNG.
+    pub encode: Duration,
+}
+impl Timings {
+    /// Start timing from now, if `enabled`.
+    pub fn start(enabled: bool) -> Self {
+        Self {
+            last: enabled.then(Instant::now),
+            ..Self::default()
+        }
+    }
+    pub fn enabled(&self) -> bool {
+        self.last.is_some()
+    }
+    /// Time since the previous lap (or the start).
+    pub fn lap(&mut self) -> Duration {
+        let Some(last) = &mut self.last else {
+            return Duration::ZERO;
+        };
+        let now = Instant::now();
+        let elapsed = now - *last;
+        *last = now;
+        elapsed
+    }
+    pub fn total(&self) -> Duration {
+        self.open + self.scan + self.decode + self.resize + self.encode
+    }
+    /// Each stage, with its name.
+    pub fn stages(&self) -> [(&'static str, Duration); 6] {
+        [
+            ("open", self.open),
+            ("scan", self.scan),
+            ("decode", self.decode),
+            ("resize", self.resize),
+            ("encode", self.encode),
+            ("total", self.total()),
+        ]
+    }
+}
+impl Display for Timings {
+    /// `timings: open=0.123ms scan=...`
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "timings:")?;
+        for (name, duration) in self.stages() {
+            write!(f, " {name}={:.3}ms", duration.as_secs_f64() * 1000.0)?;
+        }
+        Ok(())
+    }
+}
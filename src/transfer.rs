@@ -0,0 +1,126 @@
+//! Converting thumbnails stored in linear light to sRGB, which is what every viewer of the PNG
+//! assumes of untagged pixels.
+//!
+//! And back again, for `--gamma-correct`: averaging sRGB-encoded values darkens fine detail, so
+//! the resizer can instead filter 16-bit linear light, wide enough to lose nothing on the way.
+
+/// The sRGB transfer function, from linear light in `0.0..=1.0`.
+pub fn linear_to_srgb(linear: f32) -> f32 {
+    if linear <= 0.003_130_8 {
+        linear * 12.92
+    } else {
+        1.055 * linear.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// The inverse of [`linear_to_srgb`], to linear light in `0.0..=1.0`.
+pub fn srgb_to_linear(srgb: f32) -> f32 {
+    if srgb <= 0.040_45 {
+        srgb / 12.92
+    } else {
+        ((srgb + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Each 8-bit linear value, encoded as 8-bit sRGB.
+fn table() -> [u8; 256] {
+    // In 0..=255, so can't truncate.
+    std::array::from_fn(|i| (linear_to_srgb(i as f32 / 255.0) * 255.0).round() as u8)
+}
+
+/// Encode 8-bit linear `pixels` as sRGB in place, each of `channels` bytes. The alpha of RGBA
+/// pixels, being linear either way, is left alone.
+pub fn to_srgb(pixels: &mut [u8], channels: usize) {
+    let table = table();
+    for px in pixels.chunks_exact_mut(channels) {
+        for channel in &mut px[..3] {
+            *channel = table[usize::from(*channel)];
+        }
+    }
+}
+
+/// Decode 8-bit sRGB `pixels`, each of `channels` bytes, into `linear` as 16-bit linear light.
+/// The alpha of RGBA pixels is only widened.
+pub fn to_linear16(pixels: &[u8], channels: usize, linear: &mut [u16]) {
+    // In 0..=65535, so can't truncate.
+    let table: [u16; 256] = std::array::from_fn(|i| {
+        (srgb_to_linear(i as f32 / 255.0) * f32::from(u16::MAX)).round() as u16
+    });
+    for (i, (&value, wide)) in pixels.iter().zip(linear).enumerate() {
+        *wide = if i % channels < 3 {
+            table[usize::from(value)]
+        } else {
+            u16::from(value) * 257
+        };
+    }
+}
+
+/// Encode 16-bit `linear` pixels back into 8-bit sRGB `pixels`, undoing [`to_linear16`], each
+/// value rounded to the nearest in sRGB.
+pub fn from_linear16(linear: &[u16], channels: usize, pixels: &mut [u8]) {
+    // Where each sRGB value gives way to the next, in linear light. Searched rather than tabled
+    // for all 65536 inputs, as that'd cost more to build than most thumbnails have pixels.
+    let bounds: [f32; 255] =
+        std::array::from_fn(|i| srgb_to_linear((i as f32 + 0.5) / 255.0) * f32::from(u16::MAX));
+    for (i, (&wide, value)) in linear.iter().zip(pixels).enumerate() {
+        // At most 255 either way, so can't truncate.
+        *value = if i % channels < 3 {
+            bounds.partition_point(|&bound| bound <= f32::from(wide)) as u8
+        } else {
+            ((u32::from(wide) + 128) / 257) as u8
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_values() {
+        for (linear, srgb) in [
+            (0, 0),
+            // Darks are stretched the most.
+            (1, 13),
+            (2, 22),
+            (10, 56),
+            (55, 128),
+            (128, 188),
+            (200, 229),
+            (255, 255),
+        ] {
+            let mut rgba = [linear, linear, linear, linear];
+            to_srgb(&mut rgba, 4);
+            assert_eq!(rgba, [srgb, srgb, srgb, linear], "{linear}");
+            let mut rgb = [linear; 3];
+            to_srgb(&mut rgb, 3);
+            assert_eq!(rgb, [srgb; 3], "{linear}");
+        }
+    }
+
+    #[test]
+    fn linear16_round_trip() {
+        let all: Vec<u8> = (0..=255).collect();
+        for channels in [3, 4] {
+            let pixels = &all[..all.len() / channels * channels];
+            let mut linear = vec![0; pixels.len()];
+            to_linear16(pixels, channels, &mut linear);
+            let mut back = vec![0; pixels.len()];
+            from_linear16(&linear, channels, &mut back);
+            assert_eq!(back, pixels, "{channels}");
+        }
+        // Halfway between black and white in light is far brighter than halfway in sRGB.
+        let mut srgb = [0; 4];
+        from_linear16(&[u16::MAX / 2, 0, u16::MAX, u16::MAX / 2], 4, &mut srgb);
+        assert_eq!(srgb, [188, 0, 255, 127]);
+        let mut linear = [0; 3];
+        to_linear16(&[128, 0, 255], 3, &mut linear);
+        assert_eq!(linear, [14_146, 0, u16::MAX]);
+    }
+
+    #[test]
+    fn monotonic() {
+        let table = table();
+        assert!(table.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+}
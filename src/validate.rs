@@ -0,0 +1,77 @@
+//! `--validate`: check a document's container structure and thumbnail, collecting every problem
+//! rather than stopping at the first.
+
+use crate::error::Error;
+use crate::{document_chunks, open_input, read_fzp_header, read_fzp_thmb, walk_chunks, Scan};
+use crate::{BufReadSeek, DocumentChunks};
+use std::io::Seek;
+use std::path::Path;
+
+/// Check the document at `in_path`, returning a description of each problem found.
+///
+/// Thumbnails larger than `max_dimension` in either axis, or not within the first
+/// [`Scan::max_chunks`] chunks, are a problem, as they would be when thumbnailing, as is anything
+/// thumbnailing would cope with. Only fails if the document couldn't be read at all. Never writes
+/// anything.
+pub fn validate(in_path: &Path, max_dimension: u32, scan: Scan) -> Result<Vec<String>, Error> {
+    let io_err = |io: std::io::Error| Error::from_input("failed to read input file", &io);
+    let mut fzp = open_input(in_path, None)?.fzp;
+
+    // ========== Container ============
+    let header = match read_fzp_header(&mut fzp, scan.accept_form) {
+        Ok(header) => header,
+        // Nothing else to check if this isn't even a document.
+        Err(err) => return Ok(vec![format!("bad RIFF header: {err}")]),
+    };
+    let mut issues = Vec::new();
+    // The same problem may well be found both walking the chunks and searching them.
+    let mut report = |issue: String| {
+        if !issues.contains(&issue) {
+            issues.push(issue);
+        }
+    };
+    // Every chunk, not just as far as the thumbnail.
+    let mut warnings = Vec::new();
+    let DocumentChunks { mut chunks, .. } = document_chunks(
+        &mut *fzp as &mut dyn BufReadSeek,
+        &header,
+        scan.lenient,
+        &mut warnings,
+    )?;
+    let walked = walk_chunks(&mut chunks, 0, &mut warnings, &mut |_, _| Ok(()));
+    for warning in warnings.drain(..) {
+        report(warning.to_string());
+    }
+    if let Err(err) = walked {
+        report(err.to_string());
+    }
+
+    // ========== Thumbnail ============
+    // Found exactly as the thumbnailer would find it.
+    fzp.rewind().map_err(io_err)?;
+    let scan = Scan {
+        size: None,
+        flatten: false,
+        ..scan
+    };
+    let found = read_fzp_thmb(fzp, scan, &mut warnings);
+    // Of what only the search reads, such as the canvas size.
+    for warning in warnings {
+        report(warning.to_string());
+    }
+    match found {
+        Ok(found) => {
+            if let Err(err) = crate::decode_qoi(
+                found.thmb,
+                max_dimension,
+                scan.max_encoded_ratio,
+                scan.colorspace,
+            ) {
+                report(err.to_string());
+            }
+        }
+        Err(err) => report(err.to_string()),
+    }
+
+    Ok(issues)
+}
@@ -0,0 +1,132 @@
+//! Helpers for the XDG base directories and thumbnail cache layout.
+//! (https://specifications.freedesktop.org/thumbnail-spec/thumbnail-spec-latest.html)
+use std::io::Result as IOResult;
+use std::path::{Path, PathBuf};
+
+/// Name of our subdirectory within `thumbnails/fail/`.
+const FAIL_APP_NAME: &str = "fuzzpaint-thumbnailer";
+
+/// Ends the `Software` text of every thumbnail we write, naming this version of the thumbnailer.
+///
+/// Thumbnails written by other versions are never [fresh](fresh_thumbnail), so they're rewritten
+/// whenever a new version would write them differently, as when filters are chosen anew.
+pub const SOFTWARE_SUFFIX: &str =
+    concat!("(fuzzpaint-thumbnailer ", env!("CARGO_PKG_VERSION"), ")");
+
+/// The base directory named by `var`, falling back on `$HOME/<home_relative>`.
+fn base_dir(var: &str, home_relative: &str) -> Option<PathBuf> {
+    // Spec says to ignore empty or relative values.
+    let from_env = |var: &str| {
+        std::env::var_os(var)
+            .map(PathBuf::from)
+            .filter(|path| path.is_absolute())
+    };
+    from_env(var).or_else(|| from_env("HOME").map(|h| h.join(home_relative)))
+}
+
+/// `$XDG_CACHE_HOME/thumbnails`, falling back on `$HOME/.cache/thumbnails`.
+pub fn thumbnail_cache_dir() -> Option<PathBuf> {
+    Some(base_dir("XDG_CACHE_HOME", ".cache")?.join("thumbnails"))
+}
+
+/// `$XDG_CONFIG_HOME`, falling back on `$HOME/.config`.
+pub fn config_dir() -> Option<PathBuf> {
+    base_dir("XDG_CONFIG_HOME", ".config")
+}
+
+/// `file://` URI of an absolute path, percent-encoded byte-for-byte as GLib's
+/// `g_filename_to_uri` does, so our `Thumb::URI` (and therefore cache names) match the shell's.
+pub fn path_uri(path: &Path) -> String {
+    // Left as-is by GLib, everything else is escaped.
+    const KEEP: &[u8] = b"!$&'()*+,-./:=@_~";
+    let mut uri = String::from("file://");
+    for &byte in path.as_os_str().as_encoded_bytes() {
+        if byte.is_ascii_alphanumeric() || KEEP.contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{byte:02X}"));
+        }
+    }
+    uri
+}
+
+/// Lowercase hex MD5 of the URI, as used for thumbnail file names.
+pub fn md5_uri(uri: &str) -> String {
+    format!("{:x}", md5::compute(uri.as_bytes()))
+}
+
+/// If the PNG at `path` is a thumbnail of `uri` as of `mtime_secs`, written by this version, its
+/// dimensions.
+///
+/// Only the header and the text chunks before the image data are read. Anything unreadable or
+/// unexpected is simply not fresh.
+pub fn fresh_thumbnail(path: &Path, uri: &str, mtime_secs: u64) -> Option<(u32, u32)> {
+    let file = std::fs::File::open(path).ok()?;
+    let reader = png::Decoder::new(std::io::BufReader::new(file))
+        .read_info()
+        .ok()?;
+    let info = reader.info();
+    let text = |key: &str| {
+        info.uncompressed_latin1_text
+            .iter()
+            .find(|chunk| chunk.keyword == key)
+            .map(|chunk| chunk.text.as_str())
+    };
+    let fresh = text("Thumb::URI") == Some(uri)
+        && text("Thumb::MTime").and_then(|mtime| mtime.parse().ok()) == Some(mtime_secs)
+        && text("Software").is_some_and(|software| software.ends_with(SOFTWARE_SUFFIX));
+    fresh.then_some((info.width, info.height))
+}
+
+/// Write a failure marker for `uri` so that shells stop retrying us for this version of the file.
+///
+/// This is a 1x1 transparent PNG carrying the required `Thumb::URI` and `Thumb::MTime`, and why it
+/// failed as `X-Fuzzpaint::Failure`, placed at
+/// `$XDG_CACHE_HOME/thumbnails/fail/fuzzpaint-thumbnailer/<md5 of uri>.png`.
+/// Errors are returned rather than panicking - a read-only cache is not our problem to solve.
+pub fn write_fail_marker(uri: &str, mtime_secs: u64, reason: &str) -> IOResult<()> {
+    let dir = thumbnail_cache_dir()
+        .ok_or_else(|| std::io::Error::other("no cache directory"))?
+        .join("fail")
+        .join(FAIL_APP_NAME);
+    std::fs::create_dir_all(&dir)?;
+
+    let file = std::fs::File::create(dir.join(md5_uri(uri) + ".png"))?;
+    let mut png = png::Encoder::new(file, 1, 1);
+    png.set_color(png::ColorType::Rgba);
+    png.set_depth(png::BitDepth::Eight);
+    let write = || -> Result<(), png::EncodingError> {
+        png.add_text_chunk("Thumb::URI".into(), uri.into())?;
+        png.add_text_chunk("Thumb::MTime".into(), mtime_secs.to_string())?;
+        // Not necessarily Latin-1, as it may quote the document.
+        png.add_itxt_chunk("X-Fuzzpaint::Failure".into(), reason.into())?;
+        png.write_header()?.write_image_data(&[0; 4])
+    };
+    write().map_err(std::io::Error::other)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn path_uri_matches_glib() {
+        // Expected values from `g_filename_to_uri`.
+        let cases = [
+            ("/home/me/art.fzp", "file:///home/me/art.fzp"),
+            (
+                "/tmp/a b#c?d%e;f[g]\u{fc}.fzp",
+                "file:///tmp/a%20b%23c%3Fd%25e%3Bf%5Bg%5D%C3%BC.fzp",
+            ),
+            ("/keep/!$&'()*+,-.:=@_~", "file:///keep/!$&'()*+,-.:=@_~"),
+            (
+                "/esc/\"<>\\^`{|}",
+                "file:///esc/%22%3C%3E%5C%5E%60%7B%7C%7D",
+            ),
+            ("/\u{732b}/\u{1f3a8}", "file:///%E7%8C%AB/%F0%9F%8E%A8"),
+        ];
+        for (path, uri) in cases {
+            assert_eq!(path_uri(Path::new(path)), uri);
+        }
+    }
+}
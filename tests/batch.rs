@@ -0,0 +1,320 @@
+mod common;
+use common::*;
+
+#[test]
+fn continues_past_failures() {
+    let dir = temp_dir("continues_past_failures");
+    let good = write_file(&dir, "good.fzp", &simple_fzp(16, 16));
+    let bad = write_file(&dir, "bad.fzp", b"not a document");
+    let also_good = write_file(&dir, "also_good.fzp", &simple_fzp(8, 16));
+
+    let pair = |input: &std::path::Path, out: &str| {
+        format!("{}:{}", input.display(), dir.join(out).display())
+    };
+    let output = thumbnailer(&dir)
+        .args(["--batch", "--size", "8"])
+        .arg(pair(&good, "good.png"))
+        .arg(pair(&bad, "bad.png"))
+        .arg(pair(&also_good, "also_good.png"))
+        .output()
+        .unwrap();
+
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains(&bad.display().to_string()), "{stderr}");
+    assert!(!stderr.contains("good.fzp"), "{stderr}");
+
+    let good = decode_png_file(&dir.join("good.png"));
+    assert_eq!((good.info.width, good.info.height), (8, 8));
+    assert!(good.text("Thumb::URI").unwrap().starts_with("file:///"));
+    let also_good = decode_png_file(&dir.join("also_good.png"));
+    assert_eq!((also_good.info.width, also_good.info.height), (4, 8));
+    assert!(!dir.join("bad.png").exists());
+}
+
+#[test]
+fn parallel_jobs() {
+    let dir = temp_dir("parallel_jobs");
+    let mut command = thumbnailer(&dir);
+    command.args(["--batch", "--size", "16", "--jobs", "4"]);
+    for i in 0..10 {
+        let input = write_file(&dir, &format!("{i}.fzp"), &simple_fzp(32 + i, 32));
+        command.arg(format!(
+            "{}:{}",
+            input.display(),
+            dir.join(format!("{i}.png")).display()
+        ));
+    }
+    let output = command.output().unwrap();
+    assert!(output.status.success(), "{output:?}");
+
+    for i in 0..10 {
+        let png = decode_png_file(&dir.join(format!("{i}.png")));
+        assert_eq!(png.info.width, 16);
+        assert_eq!(png.info.color_type, png::ColorType::Rgb);
+        assert!(png
+            .text("Thumb::URI")
+            .unwrap()
+            .ends_with(&format!("/{i}.fzp")));
+    }
+}
+
+#[test]
+fn shared_output_path() {
+    let dir = temp_dir("shared_output_path");
+    let out = dir.join("out.png");
+    let mut command = thumbnailer(&dir);
+    command.args(["--batch", "--size", "16", "--jobs", "4"]);
+    for i in 0..10 {
+        let input = write_file(&dir, &format!("{i}.fzp"), &simple_fzp(32 + i, 32));
+        command.arg(format!("{}:{}", input.display(), out.display()));
+    }
+    let output = command.output().unwrap();
+    assert!(output.status.success(), "{output:?}");
+
+    // One of them, whole, and none of the temporary files they were written to.
+    let png = decode_png_file(&out);
+    assert_eq!(png.info.width, 16);
+    let names: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .filter(|name| name.ends_with(".tmp"))
+        .collect();
+    assert!(names.is_empty(), "{names:?}");
+}
+
+#[test]
+fn files_from_nul_delimited_stdin() {
+    let dir = temp_dir("files_from_nul_delimited_stdin");
+    let out_dir = dir.join("thumbs");
+    std::fs::create_dir(&out_dir).unwrap();
+    let plain = write_file(&dir, "plain.fzp", &simple_fzp(16, 16));
+    let newline = write_file(&dir, "new\nline.fzp", &simple_fzp(16, 16));
+
+    let mut list = Vec::new();
+    for path in [&plain, &newline] {
+        list.extend_from_slice(path.to_str().unwrap().as_bytes());
+        // Doubled to make an empty entry, which is skipped.
+        list.extend_from_slice(b"\0\0");
+    }
+    let mut command = thumbnailer(&dir);
+    command
+        .args(["--files-from=-", "--size", "8", "--out-dir"])
+        .arg(&out_dir);
+    let output = run_with_stdin(command, &list);
+    assert!(output.status.success(), "{output:?}");
+
+    let mut uris: Vec<_> = std::fs::read_dir(&out_dir)
+        .unwrap()
+        .map(|entry| {
+            let png = decode_png_file(&entry.unwrap().path());
+            assert_eq!(png.info.width, 8);
+            png.text("Thumb::URI").unwrap().to_owned()
+        })
+        .collect();
+    uris.sort();
+    assert_eq!(uris.len(), 2);
+    assert!(uris[0].ends_with("new%0Aline.fzp"), "{uris:?}");
+    assert!(uris[1].ends_with("plain.fzp"), "{uris:?}");
+}
+
+#[test]
+fn files_from_newline_delimited_file() {
+    let dir = temp_dir("files_from_newline_delimited_file");
+    let out_dir = dir.join("thumbs");
+    std::fs::create_dir(&out_dir).unwrap();
+    let a = write_file(&dir, "a.fzp", &simple_fzp(16, 16));
+    let b = write_file(&dir, "b.fzp", &simple_fzp(16, 16));
+    let list = format!("{}\n\n{}\n", a.display(), b.display());
+    let list = write_file(&dir, "list.txt", list.as_bytes());
+
+    let output = thumbnailer(&dir)
+        .args(["--size", "8", "--files-from"])
+        .arg(&list)
+        .arg("--out-dir")
+        .arg(&out_dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(std::fs::read_dir(&out_dir).unwrap().count(), 2);
+}
+
+#[test]
+fn files_from_out_template() {
+    let dir = temp_dir("files_from_out_template");
+    let out_dir = dir.join("thumbs");
+    std::fs::create_dir(&out_dir).unwrap();
+    let sub = dir.join("sub");
+    std::fs::create_dir(&sub).unwrap();
+    let a = write_file(&dir, "a.fzp", &simple_fzp(16, 16));
+    let b = write_file(&dir, "b.fzp", &simple_fzp(16, 16));
+    // Same name as `a`, in another directory.
+    let clash = write_file(&sub, "a.fzp", &simple_fzp(16, 16));
+    let list = format!("{}\n{}\n{}\n", a.display(), b.display(), clash.display());
+    let list = write_file(&dir, "list.txt", list.as_bytes());
+
+    let output = thumbnailer(&dir)
+        .args(["--size", "8x4", "--files-from"])
+        .arg(&list)
+        .arg("--out-dir")
+        .arg(&out_dir)
+        .arg("--out-template={stem}.{ext}-{width}_{height}.png")
+        .output()
+        .unwrap();
+    // The clash fails alone.
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "{}: --out-template names this the same as {}",
+            clash.display(),
+            a.display()
+        )),
+        "{stderr}"
+    );
+    assert!(stderr.contains("1 of 3 thumbnails failed"), "{stderr}");
+    let mut names: Vec<_> = std::fs::read_dir(&out_dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["a.fzp-8_4.png", "b.fzp-8_4.png"]);
+    let png = decode_png_file(&out_dir.join("a.fzp-8_4.png"));
+    assert!(png.text("Thumb::URI").unwrap().ends_with("/a.fzp"));
+}
+
+#[test]
+fn jsonl_report() {
+    let dir = temp_dir("jsonl_report");
+    let good = write_file(&dir, "good.fzp", &simple_fzp(32, 16));
+    let no_thumb = write_file(
+        &dir,
+        "none.fzp",
+        &fzp(&[(b"meta", b"ab"), (b"data", b"cd")]),
+    );
+    let corrupt = write_file(&dir, "corrupt.fzp", b"RIFF\x0c\0\0\0fzp thmb");
+    let missing = dir.join("missing.fzp");
+
+    let mut command = thumbnailer(&dir);
+    command.args(["--batch", "--size", "8", "--jobs", "1", "--report", "jsonl"]);
+    for input in [&good, &no_thumb, &corrupt, &missing] {
+        command.arg(format!("{}:{}.png", input.display(), input.display()));
+    }
+    let output = command.output().unwrap();
+    assert!(!output.status.success());
+    // Human-readable messages are suppressed.
+    assert!(output.stderr.is_empty(), "{output:?}");
+
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<_> = stdout.lines().collect();
+    assert_eq!(lines.len(), 4, "{stdout}");
+    let line_for = |input: &std::path::Path| {
+        let needle = format!("\"input\":\"{}\"", input.display());
+        *lines.iter().find(|line| line.contains(&needle)).unwrap()
+    };
+    for line in &lines {
+        assert!(line.starts_with('{') && line.ends_with('}'), "{line}");
+        assert!(line.contains("\"elapsed_ms\":"), "{line}");
+    }
+    let good = line_for(&good);
+    assert!(good.contains("\"status\":\"ok\""), "{good}");
+    assert!(good.contains("\"width\":8,\"height\":4"), "{good}");
+    assert!(line_for(&no_thumb).contains("\"status\":\"no-thumbnail\""));
+    assert!(line_for(&corrupt).contains("\"status\":\"corrupt\""));
+    assert!(line_for(&missing).contains("\"status\":\"io-error\""));
+}
+
+#[test]
+fn quiet_keeps_only_final_error() {
+    let dir = temp_dir("quiet_keeps_only_final_error");
+    let good = write_file(&dir, "good.fzp", &simple_fzp(16, 16));
+    let bad = write_file(&dir, "bad.fzp", b"not a document");
+
+    let pair = |input: &std::path::Path, out: &str| {
+        format!("{}:{}", input.display(), dir.join(out).display())
+    };
+    let output = thumbnailer(&dir)
+        .args(["--quiet", "--batch", "--size", "8"])
+        .arg(pair(&good, "good.png"))
+        .arg(pair(&bad, "bad.png"))
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        "1 of 2 thumbnails failed\n"
+    );
+
+    // Verbose batch narration says which file it's about.
+    let output = thumbnailer(&dir)
+        .args(["-v", "--force", "--batch", "--size", "8", "--jobs", "1"])
+        .arg(pair(&good, "good.png"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let prefix = format!("{}: ", good.display());
+    assert!(
+        stderr.lines().all(|line| line.starts_with(&prefix)),
+        "{stderr}"
+    );
+    assert!(stderr.contains("resized to 8x8"), "{stderr}");
+}
+
+#[test]
+fn jsonl_timings() {
+    let dir = temp_dir("jsonl_timings");
+    let input = write_file(&dir, "in.fzp", &simple_fzp(16, 16));
+
+    let output = thumbnailer(&dir)
+        .args(["--batch", "--size", "8", "--report", "jsonl", "--timings"])
+        .arg(format!(
+            "{}:{}",
+            input.display(),
+            dir.join("out.png").display()
+        ))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert!(output.stderr.is_empty(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.contains("\"timings_us\":{\"open\":"), "{stdout}");
+    for stage in ["scan", "decode", "resize", "encode", "total"] {
+        assert!(stdout.contains(&format!(",\"{stage}\":")), "{stdout}");
+    }
+}
+
+#[test]
+fn continues_past_panics() {
+    let dir = temp_dir("continues_past_panics");
+    let names = ["a", "panic", "b", "c"];
+    for jobs in ["1", "2"] {
+        let mut command = thumbnailer(&dir);
+        // Only honoured by debug builds, as tests are.
+        command
+            .env("FUZZPAINT_THUMB_TEST_PANIC", "panic.fzp")
+            .args(["--batch", "--size", "8", "--jobs", jobs]);
+        for name in names {
+            let input = write_file(&dir, &format!("{name}.fzp"), &simple_fzp(16, 16));
+            command.arg(format!(
+                "{}:{}",
+                input.display(),
+                dir.join(format!("{name}.png")).display()
+            ));
+        }
+        let output = command.output().unwrap();
+        assert_eq!(output.status.code(), Some(1), "jobs {jobs}: {output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("panic.fzp: thumbnailer panicked"),
+            "{stderr}"
+        );
+        assert!(stderr.contains("1 of 4 thumbnails failed"), "{stderr}");
+        for name in ["a", "b", "c"] {
+            let png = dir.join(format!("{name}.png"));
+            assert_eq!(decode_png_file(&png).info.width, 8, "jobs {jobs}: {name}");
+            std::fs::remove_file(png).unwrap();
+        }
+        assert!(!dir.join("panic.png").exists());
+    }
+}
@@ -0,0 +1,240 @@
+//! Fixture builders shared between integration tests.
+#![allow(dead_code)]
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Build a RIFF container of the given form type, with the given chunks.
+pub fn riff(form: &[u8; 4], chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+    let body = list(form, chunks);
+    let mut file = b"RIFF".to_vec();
+    file.extend_from_slice(&u32::try_from(body.len()).unwrap().to_le_bytes());
+    file.extend_from_slice(&body);
+    file
+}
+
+/// Build the data of a `LIST` chunk of the given list type, with the given chunks.
+pub fn list(list_type: &[u8; 4], chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+    let mut body = list_type.to_vec();
+    for (fourcc, data) in chunks {
+        body.extend_from_slice(*fourcc);
+        body.extend_from_slice(&u32::try_from(data.len()).unwrap().to_le_bytes());
+        body.extend_from_slice(data);
+        // Word-align
+        if data.len() % 2 == 1 {
+            body.push(0);
+        }
+    }
+    body
+}
+
+/// Build a RIFX container, as [`riff`] would but with every size big-endian.
+pub fn rifx(form: &[u8; 4], chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+    let mut file = riff(form, chunks);
+    file[0..4].copy_from_slice(b"RIFX");
+    file[4..8].reverse();
+    swap_sizes(&mut file[12..]);
+    file
+}
+
+/// Reverse the size field of each chunk in `chunks`, and of those in its `LIST`s.
+fn swap_sizes(chunks: &mut [u8]) {
+    let mut at = 0;
+    while at + 8 <= chunks.len() {
+        let len = u32::from_le_bytes(chunks[at + 4..at + 8].try_into().unwrap()) as usize;
+        chunks[at + 4..at + 8].reverse();
+        if &chunks[at..at + 4] == b"LIST" {
+            swap_sizes(&mut chunks[at + 12..at + 8 + len]);
+        }
+        at += 8 + len + len % 2;
+    }
+}
+
+/// Build a fuzzpaint document with the given chunks.
+pub fn fzp(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+    riff(b"fzp ", chunks)
+}
+
+/// Encode an RGBA8 QOI image where each pixel is given by `pixel(x, y)`.
+pub fn qoi_with(width: u32, height: u32, pixel: impl Fn(u32, u32) -> [u8; 4]) -> Vec<u8> {
+    let rgba: Vec<u8> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .flat_map(|(x, y)| pixel(x, y))
+        .collect();
+    qoi::encode_to_vec(rgba, width, height).unwrap()
+}
+
+/// Encode an RGB8 QOI image, as of an opaque document, where each pixel is given by
+/// `pixel(x, y)`.
+pub fn qoi_rgb_with(width: u32, height: u32, pixel: impl Fn(u32, u32) -> [u8; 3]) -> Vec<u8> {
+    let rgb: Vec<u8> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| (x, y)))
+        .flat_map(|(x, y)| pixel(x, y))
+        .collect();
+    qoi::encode_to_vec(rgb, width, height).unwrap()
+}
+
+/// A QOI image with an arbitrary colorful pattern.
+pub fn qoi_pattern(width: u32, height: u32) -> Vec<u8> {
+    qoi_with(width, height, |x, y| {
+        [(x * 7) as u8, (y * 13) as u8, (x ^ y) as u8, 255]
+    })
+}
+
+/// Encode a `width`x`height` opaque black QOI image, a run at a time, without ever holding its
+/// pixels.
+pub fn qoi_black(width: u32, height: u32) -> Vec<u8> {
+    let mut qoi = b"qoif".to_vec();
+    qoi.extend(width.to_be_bytes());
+    qoi.extend(height.to_be_bytes());
+    qoi.extend([4, 0]);
+    // Opaque black is the pixel before the first, so every pixel repeats it.
+    let pixels = u64::from(width) * u64::from(height);
+    qoi.resize(qoi.len() + (pixels / 62) as usize, 0xfd);
+    if pixels % 62 > 0 {
+        qoi.push(0xc0 | (pixels % 62 - 1) as u8);
+    }
+    qoi.extend([0, 0, 0, 0, 0, 0, 0, 1]);
+    qoi
+}
+
+/// Encode 8-bit `pixels` of `color` as a PNG image.
+pub fn png_with(width: u32, height: u32, color: png::ColorType, pixels: &[u8]) -> Vec<u8> {
+    let mut data = Vec::new();
+    let mut encoder = png::Encoder::new(&mut data, width, height);
+    encoder.set_color(color);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header().unwrap();
+    writer.write_image_data(pixels).unwrap();
+    writer.finish().unwrap();
+    data
+}
+
+/// A document with a single `thmb` chunk holding a `width`x`height` pattern.
+pub fn simple_fzp(width: u32, height: u32) -> Vec<u8> {
+    fzp(&[(b"thmb", &qoi_pattern(width, height))])
+}
+
+/// A fresh, empty directory unique to this test.
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir()
+        .join("fuzzpaint-thumbnailer-tests")
+        .join(format!("{name}-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Write `data` to `dir/name`, returning the path.
+pub fn write_file(dir: &Path, name: &str, data: &[u8]) -> PathBuf {
+    let path = dir.join(name);
+    std::fs::write(&path, data).unwrap();
+    path
+}
+
+/// The thumbnailer binary, with a private cache dir so fail markers don't escape, and a private
+/// config dir and clean environment so the user's settings don't get in.
+pub fn thumbnailer(dir: &Path) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_fuzzpaint-thumbnailer"));
+    command.env("XDG_CACHE_HOME", dir.join("cache"));
+    command.env("XDG_CONFIG_HOME", dir.join("config"));
+    for (var, _) in std::env::vars_os() {
+        if var.to_string_lossy().starts_with("FUZZPAINT_THUMB_") {
+            command.env_remove(var);
+        }
+    }
+    command
+}
+
+/// Write `text` as the config file used by [`thumbnailer`] in `dir`.
+pub fn write_config(dir: &Path, text: &str) {
+    let config_dir = dir.join("config").join("fuzzpaint-thumbnailer");
+    std::fs::create_dir_all(&config_dir).unwrap();
+    std::fs::write(config_dir.join("config.toml"), text).unwrap();
+}
+
+/// Run to completion, with the given bytes on stdin.
+pub fn run_with_stdin(mut command: Command, stdin: &[u8]) -> Output {
+    use std::io::Write;
+    use std::process::Stdio;
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    // Ignore errors - the child may not read all of it.
+    let _ = child.stdin.take().unwrap().write_all(stdin);
+    child.wait_with_output().unwrap()
+}
+
+/// A decoded PNG along with its text metadata.
+pub struct Png {
+    pub info: png::OutputInfo,
+    pub pixels: Vec<u8>,
+    pub text: Vec<(String, String)>,
+    pub srgb: bool,
+    /// From the `pHYs` chunk.
+    pub pixel_dims: Option<png::PixelDimensions>,
+    /// Decompressed from the `iCCP` chunk.
+    pub icc_profile: Option<Vec<u8>>,
+}
+impl Png {
+    pub fn text(&self, key: &str) -> Option<&str> {
+        self.text
+            .iter()
+            .find(|(k, _)| k == key)
+            .map(|(_, v)| v.as_str())
+    }
+    /// The pixels as RGBA, whichever channels they were written in.
+    pub fn rgba(&self) -> Vec<u8> {
+        match self.info.color_type {
+            png::ColorType::Rgb => self
+                .pixels
+                .chunks_exact(3)
+                .flat_map(|px| [px[0], px[1], px[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => self
+                .pixels
+                .chunks_exact(2)
+                .flat_map(|px| [px[0], px[0], px[0], px[1]])
+                .collect(),
+            png::ColorType::Grayscale => self.pixels.iter().flat_map(|&v| [v, v, v, 255]).collect(),
+            _ => self.pixels.clone(),
+        }
+    }
+}
+
+pub fn decode_png(data: &[u8]) -> Png {
+    let mut reader = png::Decoder::new(data).read_info().unwrap();
+    let mut pixels = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut pixels).unwrap();
+    pixels.truncate(info.buffer_size());
+    let text = reader
+        .info()
+        .uncompressed_latin1_text
+        .iter()
+        .map(|chunk| (chunk.keyword.clone(), chunk.text.clone()))
+        .chain(
+            reader
+                .info()
+                .utf8_text
+                .iter()
+                .map(|chunk| (chunk.keyword.clone(), chunk.get_text().unwrap())),
+        )
+        .collect();
+    let srgb = reader.info().srgb.is_some();
+    let pixel_dims = reader.info().pixel_dims;
+    let icc_profile = reader.info().icc_profile.as_ref().map(|icc| icc.to_vec());
+    Png {
+        info,
+        pixels,
+        text,
+        srgb,
+        pixel_dims,
+        icc_profile,
+    }
+}
+
+pub fn decode_png_file(path: &Path) -> Png {
+    decode_png(&std::fs::read(path).unwrap())
+}
@@ -0,0 +1,129 @@
+mod common;
+use common::*;
+
+fn run(dir: &std::path::Path, flags: &[&str], size: &str) -> std::process::Output {
+    thumbnailer(dir)
+        .arg("--force")
+        .args(flags)
+        .arg(dir.join("in.fzp"))
+        .arg(size)
+        .arg(dir.join("out.png"))
+        .arg("file:///in.fzp")
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn seeds_defaults() {
+    let dir = temp_dir("config_seeds_defaults");
+    write_file(&dir, "in.fzp", &simple_fzp(16, 16));
+    let out = dir.join("out.png");
+
+    write_config(&dir, "no-upscale = false\nmax-dimension = 32\n");
+    let output = run(&dir, &[], "64");
+    assert!(output.status.success(), "{output:?}");
+    assert!(output.stderr.is_empty(), "{output:?}");
+    assert_eq!(decode_png_file(&out).info.width, 64);
+
+    // The command line wins.
+    write_config(&dir, "max-dimension = 8\n");
+    let output = run(&dir, &[], "64");
+    assert_eq!(output.status.code(), Some(4));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("exceeds limit of 8"), "{stderr}");
+    let output = run(&dir, &["--max-dimension", "16"], "64");
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(decode_png_file(&out).info.width, 16);
+}
+
+#[test]
+fn compression() {
+    let dir = temp_dir("config_compression");
+    write_file(&dir, "in.fzp", &simple_fzp(128, 128));
+    let out = dir.join("out.png");
+
+    let output = run(&dir, &[], "native");
+    assert!(output.status.success(), "{output:?}");
+    let fast = std::fs::read(&out).unwrap();
+
+    write_config(&dir, "compression = \"best\"\n");
+    let output = run(&dir, &[], "native");
+    assert!(output.status.success(), "{output:?}");
+    let best = std::fs::read(&out).unwrap();
+    assert!(best.len() < fast.len(), "{} >= {}", best.len(), fast.len());
+    assert_eq!(decode_png(&best).pixels, decode_png(&fast).pixels);
+
+    let output = run(&dir, &["--compression=fast"], "native");
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(std::fs::read(&out).unwrap(), fast);
+}
+
+#[test]
+fn invalid_config() {
+    let dir = temp_dir("config_invalid");
+    write_file(&dir, "in.fzp", &simple_fzp(16, 16));
+    let out = dir.join("out.png");
+    write_config(&dir, "filter = \"sharpest\"\nno-upscale = true\n");
+
+    // Warned about, and the rest still applies.
+    let output = run(&dir, &[], "64");
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("line 1: `filter`: unknown filter `sharpest`"),
+        "{stderr}"
+    );
+    assert_eq!(decode_png_file(&out).info.width, 16);
+
+    std::fs::remove_file(&out).unwrap();
+    let output = run(&dir, &["--strict"], "64");
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("`filter`"), "{stderr}");
+    assert!(!out.exists());
+}
+
+#[test]
+fn environment() {
+    let dir = temp_dir("config_environment");
+    write_file(&dir, "in.fzp", &simple_fzp(16, 16));
+    let out = dir.join("out.png");
+    write_config(&dir, "max-dimension = 8\nno-upscale = true\n");
+    let run_env = |vars: &[(&str, &str)], flags: &[&str]| {
+        thumbnailer(&dir)
+            .envs(vars.iter().copied())
+            .arg("--force")
+            .args(flags)
+            .arg(dir.join("in.fzp"))
+            .arg("64")
+            .arg(&out)
+            .arg("file:///in.fzp")
+            .output()
+            .unwrap()
+    };
+
+    // Over the config file.
+    let output = run_env(
+        &[
+            ("FUZZPAINT_THUMB_MAX_DIM", "16"),
+            ("FUZZPAINT_THUMB_NO_UPSCALE", "false"),
+        ],
+        &[],
+    );
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(decode_png_file(&out).info.width, 64);
+
+    // Under the command line.
+    let output = run_env(&[("FUZZPAINT_THUMB_MAX_DIM", "8")], &["--max-dimension=16"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(decode_png_file(&out).info.width, 16);
+
+    // Invalid values fall back on the config file.
+    let output = run_env(&[("FUZZPAINT_THUMB_MAX_DIM", "lots")], &[]);
+    assert_eq!(output.status.code(), Some(4));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("FUZZPAINT_THUMB_MAX_DIM"), "{stderr}");
+    assert!(stderr.contains("exceeds limit of 8"), "{stderr}");
+    let output = run_env(&[("FUZZPAINT_THUMB_MAX_DIM", "lots")], &["--strict"]);
+    assert_eq!(output.status.code(), Some(2));
+}
@@ -0,0 +1,44 @@
+mod common;
+use common::*;
+
+fn exit_code(dir: &std::path::Path, args: &[&std::ffi::OsStr]) -> Option<i32> {
+    let output = thumbnailer(dir).args(args).output().unwrap();
+    output.status.code()
+}
+
+#[test]
+fn each_failure_class() {
+    let dir = temp_dir("exit_code_classes");
+    let out = dir.join("out.png");
+    let run = |input: &std::path::Path| {
+        exit_code(&dir, &[input.as_os_str(), "16".as_ref(), out.as_os_str()])
+    };
+
+    let good = write_file(&dir, "good.fzp", &simple_fzp(16, 16));
+    assert_eq!(run(&good), Some(0));
+
+    assert_eq!(exit_code(&dir, &["--no-such-flag".as_ref()]), Some(2));
+
+    let none = write_file(
+        &dir,
+        "none.fzp",
+        &fzp(&[(b"LIST", b"INFO"), (b"docv", b"")]),
+    );
+    assert_eq!(run(&none), Some(3));
+
+    let not_riff = write_file(&dir, "not_riff.fzp", b"\x89PNG\r\n\x1a\n");
+    assert_eq!(run(&not_riff), Some(4));
+    let garbage = write_file(&dir, "garbage.fzp", &fzp(&[(b"thmb", b"qoif nonsense")]));
+    assert_eq!(run(&garbage), Some(4));
+
+    assert_eq!(run(&dir.join("missing.fzp")), Some(5));
+
+    let unwritable = dir.join("no").join("such").join("dir").join("out.png");
+    assert_eq!(
+        exit_code(
+            &dir,
+            &[good.as_os_str(), "16".as_ref(), unwritable.as_os_str()]
+        ),
+        Some(6)
+    );
+}
@@ -0,0 +1,89 @@
+mod common;
+use common::*;
+
+#[test]
+fn byte_identical() {
+    let dir = temp_dir("extract_identical");
+    let qoi = qoi_pattern(13, 7);
+    let input = write_file(&dir, "in.fzp", &fzp(&[(b"LIST", b"INFO"), (b"thmb", &qoi)]));
+    let out = dir.join("out.qoi");
+    let status = thumbnailer(&dir)
+        .arg("--extract")
+        .arg(&input)
+        .arg(&out)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(std::fs::read(&out).unwrap(), qoi);
+    // No temporary files left behind.
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+
+    let output = thumbnailer(&dir)
+        .args(["--extract", "-", "-"])
+        .stdin(std::fs::File::open(&input).unwrap())
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(output.stdout, qoi);
+}
+
+#[test]
+fn bad_chunks_write_nothing() {
+    let dir = temp_dir("extract_bad");
+    let empty = write_file(&dir, "empty.fzp", &fzp(&[(b"thmb", b"")]));
+    let mut truncated = fzp(&[(b"thmb", &qoi_pattern(4, 4))]);
+    truncated.truncate(truncated.len() - 4);
+    let truncated = write_file(&dir, "truncated.fzp", &truncated);
+
+    for input in [empty, truncated] {
+        let out = dir.join("out.qoi");
+        let output = thumbnailer(&dir)
+            .arg("--extract")
+            .arg(&input)
+            .arg(&out)
+            .output()
+            .unwrap();
+        assert!(!output.status.success());
+        assert!(!output.stderr.is_empty());
+        assert!(!out.exists());
+    }
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+}
+
+#[test]
+fn chosen_chunk() {
+    let dir = temp_dir("extract_chunk");
+    let thumbnails = [qoi_pattern(8, 8), qoi_pattern(4, 4), qoi_pattern(16, 2)];
+    let input = write_file(
+        &dir,
+        "in.fzp",
+        &fzp(&[
+            (b"thmb", &thumbnails[0]),
+            (b"meta", b"abcd"),
+            (b"thmb", &thumbnails[1]),
+            (b"thmb", &thumbnails[2]),
+        ]),
+    );
+    let extract = |flags: &[&str]| {
+        thumbnailer(&dir)
+            .arg("--extract")
+            .args(flags)
+            .arg(&input)
+            .arg("-")
+            .output()
+            .unwrap()
+    };
+    // By default, the largest.
+    assert_eq!(extract(&[]).stdout, thumbnails[2]);
+    for (n, thumbnail) in thumbnails.iter().enumerate() {
+        let output = extract(&["--chunk", &(n + 1).to_string()]);
+        assert!(output.status.success(), "{output:?}");
+        assert_eq!(&output.stdout, thumbnail);
+    }
+
+    let output = extract(&["--chunk", "4"]);
+    assert_eq!(output.status.code(), Some(3), "{output:?}");
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("only 3 thumbnails"), "{stderr}");
+}
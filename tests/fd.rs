@@ -0,0 +1,165 @@
+//! Inherited file descriptors, in place of paths.
+#![cfg(target_os = "linux")]
+mod common;
+use common::*;
+use std::io::{Read, Seek};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+
+/// An anonymous in-memory file, inheritable by children.
+fn memfd() -> std::fs::File {
+    // SAFETY: The name is NUL-terminated, and no flags means no CLOEXEC.
+    let fd = unsafe { libc::memfd_create(c"thumbnail".as_ptr(), 0) };
+    assert!(fd >= 0, "{}", std::io::Error::last_os_error());
+    // SAFETY: Freshly created, and owned by nobody else.
+    std::fs::File::from(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+/// Open `path` read-only, inheritable by children.
+fn open_inheritable(path: &std::path::Path) -> OwnedFd {
+    let path = std::ffi::CString::new(path.as_os_str().as_encoded_bytes()).unwrap();
+    // SAFETY: The path is NUL-terminated, and no flags means no CLOEXEC.
+    let fd = unsafe { libc::open(path.as_ptr(), libc::O_RDONLY) };
+    assert!(fd >= 0, "{}", std::io::Error::last_os_error());
+    // SAFETY: Freshly opened, and owned by nobody else.
+    unsafe { OwnedFd::from_raw_fd(fd) }
+}
+
+#[test]
+fn out_fd() {
+    let dir = temp_dir("out_fd");
+    let input = write_file(&dir, "in.fzp", &simple_fzp(16, 8));
+    let mut out = memfd();
+
+    let output = thumbnailer(&dir)
+        .arg("--out-fd")
+        .arg(out.as_raw_fd().to_string())
+        .arg(&input)
+        .arg("8")
+        .arg("file:///in.fzp")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert!(output.stdout.is_empty());
+
+    let mut png = Vec::new();
+    out.rewind().unwrap();
+    out.read_to_end(&mut png).unwrap();
+    let png = decode_png(&png);
+    assert_eq!((png.info.width, png.info.height), (8, 4));
+    assert_eq!(png.text("Thumb::URI"), Some("file:///in.fzp"));
+    // Written in place, nothing left beside the input.
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+    // Not open at all.
+    let output = thumbnailer(&dir)
+        .args(["--out-fd", "1000"])
+        .arg(&input)
+        .arg("8")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--out-fd 1000 is not open"), "{stderr}");
+
+    // Only open for reading.
+    let read_only = open_inheritable(&input);
+    let output = thumbnailer(&dir)
+        .arg("--out-fd")
+        .arg(read_only.as_raw_fd().to_string())
+        .arg(&input)
+        .arg("8")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("is not open for writing"), "{stderr}");
+}
+
+#[test]
+fn in_fd() {
+    let dir = temp_dir("in_fd");
+    let input = write_file(&dir, "in.fzp", &simple_fzp(16, 8));
+    let mtime = std::fs::metadata(&input)
+        .unwrap()
+        .modified()
+        .unwrap()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    let fd = open_inheritable(&input);
+    let out = dir.join("out.png");
+
+    let output = thumbnailer(&dir)
+        .arg("--in-fd")
+        .arg(fd.as_raw_fd().to_string())
+        .arg("8")
+        .arg(&out)
+        .arg("file:///in.fzp")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let png = decode_png_file(&out);
+    assert_eq!((png.info.width, png.info.height), (8, 4));
+    assert_eq!(png.text("Thumb::URI"), Some("file:///in.fzp"));
+    assert_eq!(png.text("Thumb::MTime"), Some(&*mtime.to_string()));
+
+    // No path to derive a URI from.
+    let output = thumbnailer(&dir)
+        .arg("--in-fd")
+        .arg(fd.as_raw_fd().to_string())
+        .arg("8")
+        .arg(&out)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+
+    // Pipes can't be seeked.
+    let mut pipe = [0; 2];
+    // SAFETY: Writes two descriptors into `pipe`. No flags means no CLOEXEC.
+    assert_eq!(unsafe { libc::pipe(pipe.as_mut_ptr()) }, 0);
+    // SAFETY: Freshly created, and owned by nobody else.
+    let (read, _write) = unsafe { (OwnedFd::from_raw_fd(pipe[0]), OwnedFd::from_raw_fd(pipe[1])) };
+    let output = thumbnailer(&dir)
+        .arg("--in-fd")
+        .arg(read.as_raw_fd().to_string())
+        .arg("8")
+        .arg(&out)
+        .arg("file:///in.fzp")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("is not a regular file"), "{stderr}");
+}
+
+#[test]
+fn shared_or_standard_fds() {
+    use std::io::Write;
+    let dir = temp_dir("shared_or_standard_fds");
+    let mut both = memfd();
+    both.write_all(&simple_fzp(16, 8)).unwrap();
+    let fd = both.as_raw_fd().to_string();
+
+    // Would be owned, and closed, twice over.
+    for size in [&["8"][..], &["-s", "8"]] {
+        let output = thumbnailer(&dir)
+            .args(["--in-fd", &fd, "--out-fd", &fd])
+            .args(size)
+            .arg("file:///in.fzp")
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(2), "{size:?}: {output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("cannot be the same descriptor"), "{stderr}");
+    }
+
+    for (flag, fd) in [("--in-fd", "0"), ("--out-fd", "1"), ("--out-fd", "2")] {
+        let output = thumbnailer(&dir)
+            .args([flag, fd, "8", "file:///in.fzp"])
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(2), "{flag} {fd}: {output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("must not be a standard stream"), "{stderr}");
+    }
+}
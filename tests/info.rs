@@ -0,0 +1,57 @@
+mod common;
+use common::*;
+
+#[test]
+fn describes_thumbnail() {
+    let dir = temp_dir("info_describes_thumbnail");
+    let qoi = qoi_pattern(40, 30);
+    let input = write_file(&dir, "in.fzp", &fzp(&[(b"thmb", &qoi)]));
+
+    let output = thumbnailer(&dir)
+        .arg("--info")
+        .arg(&input)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let mtime = std::fs::metadata(&input)
+        .unwrap()
+        .modified()
+        .unwrap()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap()
+        .as_secs();
+    assert_eq!(
+        stdout.trim_end(),
+        format!(
+            "{{\"width\":40,\"height\":30,\"channels\":4,\"colorspace\":\"srgb\",\"thumbnail_bytes\":{},\"mtime\":{mtime}}}",
+            qoi.len()
+        )
+    );
+}
+
+#[test]
+fn error_objects() {
+    let dir = temp_dir("info_error_objects");
+    let none = write_file(&dir, "none.fzp", &fzp(&[(b"meta", b""), (b"data", b"")]));
+    let output = thumbnailer(&dir).arg("--info").arg(&none).output().unwrap();
+    assert_eq!(output.status.code(), Some(3));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.starts_with("{\"error\":\"no-thumbnail\",\"message\":"),
+        "{stdout}"
+    );
+
+    let corrupt = write_file(&dir, "corrupt.fzp", &fzp(&[(b"thmb", b"qoif\0\0")]));
+    let output = thumbnailer(&dir)
+        .arg("--info")
+        .arg(&corrupt)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(4));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.starts_with("{\"error\":\"corrupt\",\"message\":"),
+        "{stdout}"
+    );
+}
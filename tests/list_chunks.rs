@@ -0,0 +1,146 @@
+mod common;
+use common::*;
+
+#[test]
+fn lists_top_level_chunks() {
+    let dir = temp_dir("list_chunks");
+    let mut file = fzp(&[
+        (b"LIST", b"INFO"),
+        // Padded to an even size.
+        (b"meta", b"abc"),
+        (b"thmb", b"abcd"),
+        (b"\0x\"y", b""),
+    ]);
+    // Claim a trailing chunk larger than what remains
+    file.extend_from_slice(b"docv\x40\0\0\0");
+    let input = write_file(&dir, "in.fzp", &file);
+    let output = thumbnailer(&dir)
+        .arg("--list-chunks")
+        .arg(&input)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!(
+            "offset=0 fourcc=\"RIFF\" size={}\n\
+             offset=12 fourcc=\"LIST\" size=4\n\
+             offset=24 fourcc=\"meta\" size=3\n\
+             offset=36 fourcc=\"thmb\" size=4\n\
+             offset=48 fourcc=\"\\x00x\\\"y\" size=0\n\
+             offset=56 fourcc=\"docv\" size=64 past-eof\n",
+            file.len() - 16,
+        )
+    );
+}
+
+#[test]
+fn rejects_non_riff() {
+    let dir = temp_dir("list_chunks_non_riff");
+    let input = write_file(&dir, "in.fzp", b"\x89PNG\r\n\x1a\n");
+    let output = thumbnailer(&dir)
+        .arg("--list-chunks")
+        .arg(&input)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn lists_nested_chunks() {
+    let dir = temp_dir("list_chunks_nested");
+    let sub = list(b"sub ", &[(b"thmb", b"x")]);
+    let layr = list(b"layr", &[(b"meta", b"ab"), (b"LIST", &sub)]);
+    let file = fzp(&[(b"LIST", &layr), (b"hist", b"")]);
+    let input = write_file(&dir, "in.fzp", &file);
+    let output = thumbnailer(&dir)
+        .arg("--list-chunks")
+        .arg(&input)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "offset=0 fourcc=\"RIFF\" size=56\n\
+         offset=12 fourcc=\"LIST\" size=36\n  \
+           offset=24 fourcc=\"meta\" size=2\n  \
+           offset=34 fourcc=\"LIST\" size=14\n    \
+             offset=46 fourcc=\"thmb\" size=1\n\
+         offset=56 fourcc=\"hist\" size=0\n"
+    );
+    assert!(output.stderr.is_empty(), "{:?}", output.stderr);
+
+    // A chunk claiming more than its list leaves is listed as it claims, and warned about.
+    let layr = [&b"layr"[..], b"meta", &500u32.to_le_bytes(), b"abcd"].concat();
+    let file = fzp(&[(b"LIST", &layr), (b"hist", b"")]);
+    let input = write_file(&dir, "in.fzp", &file);
+    let output = thumbnailer(&dir)
+        .arg("--list-chunks")
+        .arg(&input)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "offset=0 fourcc=\"RIFF\" size=36\n\
+         offset=12 fourcc=\"LIST\" size=16\n  \
+           offset=24 fourcc=\"meta\" size=500 past-eof\n\
+         offset=36 fourcc=\"hist\" size=0\n"
+    );
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "chunk \"meta\" at offset 24 declares 500 bytes, but its parent leaves only 4"
+        ),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn lists_leniently() {
+    let dir = temp_dir("list_chunks_lenient");
+    let mut file = fzp(&[
+        (b"meta", &[0x80; 40]),
+        (b"hist", &[0xfe; 11]),
+        (b"thmb", b"abcd"),
+    ]);
+    // Too small, so the next header is read from the middle of its data.
+    file[16..20].copy_from_slice(&20u32.to_le_bytes());
+    let input = write_file(&dir, "in.fzp", &file);
+    let list_chunks = |flags: &[&str]| {
+        let output = thumbnailer(&dir)
+            .args(flags)
+            .arg("--list-chunks")
+            .arg(&input)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr).into_owned();
+        (String::from_utf8(output.stdout).unwrap(), stderr)
+    };
+
+    // Taken at its word, as thumbnailing would.
+    let (stdout, _) = list_chunks(&[]);
+    assert_eq!(
+        stdout,
+        format!(
+            "offset=0 fourcc=\"RIFF\" size=84\n\
+             offset=12 fourcc=\"meta\" size=20\n\
+             offset=40 fourcc=\"\\x80\\x80\\x80\\x80\" size={} past-eof\n",
+            0x8080_8080u32
+        )
+    );
+    let (stdout, stderr) = list_chunks(&["--lenient"]);
+    assert_eq!(
+        stdout,
+        "offset=0 fourcc=\"RIFF\" size=84\n\
+         offset=12 fourcc=\"meta\" size=20\n\
+         offset=60 fourcc=\"hist\" size=11\n\
+         offset=80 fourcc=\"thmb\" size=4\n"
+    );
+    assert!(
+        stderr.contains("chunk header at offset 40 is malformed, so 20 bytes were skipped"),
+        "{stderr}"
+    );
+}
@@ -0,0 +1,89 @@
+mod common;
+use common::*;
+
+#[test]
+fn appends_narration() {
+    let dir = temp_dir("log_file_appends_narration");
+    let input = write_file(&dir, "in.fzp", &simple_fzp(16, 16));
+    let log = dir.join("thumbs.log");
+
+    let run = || {
+        thumbnailer(&dir)
+            .arg("--log-file")
+            .arg(&log)
+            .arg(&input)
+            .args(["8", "-"])
+            .output()
+            .unwrap()
+    };
+    let first = run();
+    assert!(first.status.success(), "{first:?}");
+    // Narration is only written to the log, not shown.
+    assert!(first.stderr.is_empty(), "{first:?}");
+    let second = run();
+    assert!(second.status.success(), "{second:?}");
+
+    let text = std::fs::read_to_string(&log).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    let prefix = format!("] {}: ", input.display());
+    for line in &lines {
+        // `2026-10-15T12:34:56.789Z [1234] in.fzp: message`
+        let (timestamp, rest) = line.split_once(" [").unwrap();
+        assert_eq!(timestamp.len(), 24, "{line}");
+        assert!(timestamp.ends_with('Z'), "{line}");
+        let (pid, _) = rest.split_once(']').unwrap();
+        assert!(pid.parse::<u32>().is_ok(), "{line}");
+        assert!(line.contains(&prefix), "{line}");
+    }
+    // Both runs are kept.
+    let decoded: Vec<&&str> = lines
+        .iter()
+        .filter(|line| line.ends_with("decoded 16x16"))
+        .collect();
+    assert_eq!(decoded.len(), 2, "{text}");
+    assert!(text.contains("resized to 8x8"), "{text}");
+}
+
+#[test]
+fn records_errors() {
+    let dir = temp_dir("log_file_records_errors");
+    let input = write_file(&dir, "in.fzp", &fzp(&[(b"LIST", b"info"), (b"data", b"")]));
+    let log = dir.join("thumbs.log");
+
+    let output = thumbnailer(&dir)
+        .env("FUZZPAINT_THUMB_LOG_FILE", &log)
+        .arg(&input)
+        .args(["8", "-"])
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3), "{output:?}");
+    let text = std::fs::read_to_string(&log).unwrap();
+    assert!(
+        text.contains(&format!(
+            "{}: error: failed to parse input file: document does not contain a thumbnail",
+            input.display()
+        )),
+        "{text}"
+    );
+}
+
+#[test]
+fn unopenable() {
+    let dir = temp_dir("log_file_unopenable");
+    let input = write_file(&dir, "in.fzp", &simple_fzp(16, 16));
+    let out = dir.join("out.png");
+
+    // A directory can't be appended to.
+    let output = thumbnailer(&dir)
+        .arg("--log-file")
+        .arg(&dir)
+        .arg(&input)
+        .arg("8")
+        .arg(&out)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("failed to open --log-file"), "{stderr}");
+    assert_eq!(decode_png_file(&out).info.width, 8);
+}
@@ -0,0 +1,42 @@
+mod common;
+use common::*;
+
+fn probe(dir: &std::path::Path, input: &std::path::Path) -> (Option<i32>, Vec<std::fs::DirEntry>) {
+    let output = thumbnailer(dir).arg("--probe").arg(input).output().unwrap();
+    assert!(output.stdout.is_empty());
+    let written = std::fs::read_dir(dir)
+        .unwrap()
+        .map(Result::unwrap)
+        .filter(|entry| entry.path() != input)
+        .collect();
+    (output.status.code(), written)
+}
+
+#[test]
+fn has_thumbnail() {
+    let dir = temp_dir("probe_has_thumbnail");
+    let input = write_file(&dir, "in.fzp", &simple_fzp(16, 16));
+    let (code, written) = probe(&dir, &input);
+    assert_eq!(code, Some(0));
+    assert!(written.is_empty());
+}
+
+#[test]
+fn no_thumbnail() {
+    let dir = temp_dir("probe_no_thumbnail");
+    let input = write_file(&dir, "in.fzp", &fzp(&[(b"LIST", b"INFO"), (b"docv", b"")]));
+    let (code, written) = probe(&dir, &input);
+    assert_eq!(code, Some(3));
+    // Not even a fail marker
+    assert!(written.is_empty());
+}
+
+#[test]
+fn corrupt() {
+    let dir = temp_dir("probe_corrupt");
+    let not_riff = write_file(&dir, "not_riff.fzp", b"\x89PNG\r\n\x1a\n");
+    assert_eq!(probe(&dir, &not_riff).0, Some(4));
+    let truncated = write_file(&dir, "truncated.fzp", b"RIFF\x10\0\0\0fzp LI");
+    assert_eq!(probe(&dir, &truncated).0, Some(4));
+    assert_eq!(probe(&dir, &dir.join("missing.fzp")).0, Some(5));
+}
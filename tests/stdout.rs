@@ -0,0 +1,103 @@
+mod common;
+use common::*;
+
+#[test]
+fn png_to_stdout() {
+    let dir = temp_dir("png_to_stdout");
+    let input = write_file(&dir, "in.fzp", &simple_fzp(64, 32));
+
+    let output = thumbnailer(&dir)
+        .arg(&input)
+        .args(["32", "-", "file:///in.fzp"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+
+    let png = decode_png(&output.stdout);
+    assert_eq!((png.info.width, png.info.height), (32, 16));
+    assert_eq!(png.info.color_type, png::ColorType::Rgb);
+    assert_eq!(png.info.bit_depth, png::BitDepth::Eight);
+    assert_eq!(png.text("Thumb::URI"), Some("file:///in.fzp"));
+    // Didn't litter a file named `-`
+    assert!(!std::path::Path::new("-").exists());
+}
+
+#[test]
+fn errors_stay_off_stdout() {
+    let dir = temp_dir("errors_stay_off_stdout");
+    let input = write_file(&dir, "in.fzp", &fzp(&[(b"junk", b"1234")]));
+
+    let output = thumbnailer(&dir)
+        .arg(&input)
+        .args(["32", "-", "file:///in.fzp"])
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(!output.stderr.is_empty());
+}
+
+#[test]
+fn verbose_narrates_on_stderr() {
+    let dir = temp_dir("verbose_narrates_on_stderr");
+    let input = write_file(&dir, "in.fzp", &simple_fzp(64, 32));
+
+    let output = thumbnailer(&dir)
+        .arg("--verbose")
+        .arg(&input)
+        .args(["32", "-", "file:///in.fzp"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    // Stdout is just the image.
+    decode_png(&output.stdout);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let lines: Vec<_> = stderr.lines().collect();
+    assert_eq!(lines.len(), 4, "{stderr}");
+    assert!(lines[0].starts_with("found thumbnail chunk at offset "));
+    assert_eq!(lines[1], "decoded 64x32");
+    assert_eq!(lines[2], "resized to 32x16");
+    assert_eq!(lines[3], format!("wrote {} bytes", output.stdout.len()));
+}
+
+#[test]
+fn data_uri() {
+    use base64::Engine;
+    let dir = temp_dir("data_uri");
+    let input = write_file(&dir, "in.fzp", &simple_fzp(64, 32));
+
+    let output = thumbnailer(&dir)
+        .arg("--data-uri")
+        .arg(&input)
+        .args(["32", "file:///in.fzp"])
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert!(output.stderr.is_empty());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let base64 = stdout
+        .strip_prefix("data:image/png;base64,")
+        .and_then(|uri| uri.strip_suffix('\n'))
+        .unwrap();
+    assert!(!base64.contains('\n'));
+    let png = base64::engine::general_purpose::STANDARD
+        .decode(base64)
+        .unwrap();
+    let png = decode_png(&png);
+    assert_eq!((png.info.width, png.info.height), (32, 16));
+    assert_eq!(png.text("Thumb::URI"), Some("file:///in.fzp"));
+    // Nothing written next to the input.
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+    // Errors stay on stderr.
+    let bad = write_file(&dir, "bad.fzp", b"not a document");
+    let output = thumbnailer(&dir)
+        .arg("--data-uri")
+        .arg(&bad)
+        .arg("32")
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    assert!(output.stdout.is_empty());
+    assert!(!output.stderr.is_empty());
+}
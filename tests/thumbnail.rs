@@ -0,0 +1,3411 @@
+mod common;
+use common::*;
+
+#[test]
+fn native_size_is_lossless() {
+    let dir = temp_dir("native_size_is_lossless");
+    let pixel = |x: u32, y: u32| [x as u8, y as u8, (x * y) as u8, 255 - x as u8];
+    let input = write_file(&dir, "in.fzp", &fzp(&[(b"thmb", &qoi_with(30, 20, pixel))]));
+    let out = dir.join("out.png");
+
+    let output = thumbnailer(&dir)
+        .arg(&input)
+        .arg("native")
+        .arg(&out)
+        .arg("file:///in.fzp")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+
+    let png = decode_png_file(&out);
+    assert_eq!((png.info.width, png.info.height), (30, 20));
+    let expected: Vec<u8> = (0..20)
+        .flat_map(|y| (0..30).flat_map(move |x| pixel(x, y)))
+        .collect();
+    assert_eq!(png.pixels, expected);
+    // Metadata is still written.
+    assert!(png.srgb);
+    assert_eq!(png.text("Thumb::URI"), Some("file:///in.fzp"));
+    assert!(png.text("Thumb::MTime").is_some());
+}
+
+#[test]
+fn uri_derived_from_path() {
+    let dir = temp_dir("uri_derived_from_path");
+    let input = write_file(&dir, "a b#c?d%ü.fzp", &simple_fzp(4, 4));
+    let out = dir.join("out.png");
+
+    let status = thumbnailer(&dir)
+        .arg(&input)
+        .arg("4")
+        .arg(&out)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let dir = std::fs::canonicalize(&dir).unwrap();
+    let expected = format!("file://{}/a%20b%23c%3Fd%25%C3%BC.fzp", dir.display());
+    assert_eq!(decode_png_file(&out).text("Thumb::URI"), Some(&*expected));
+
+    // Explicit URI wins.
+    let status = thumbnailer(&dir)
+        .arg(&input)
+        .arg("4")
+        .arg(&out)
+        .arg("file:///elsewhere.fzp")
+        .status()
+        .unwrap();
+    assert!(status.success());
+    assert_eq!(
+        decode_png_file(&out).text("Thumb::URI"),
+        Some("file:///elsewhere.fzp")
+    );
+
+    // Nothing to derive it from.
+    let output = thumbnailer(&dir).args(["-", "4", "-"]).output().unwrap();
+    assert!(!output.status.success());
+}
+
+#[test]
+fn mtime_override() {
+    let dir = temp_dir("mtime_override");
+    let input = write_file(&dir, "in.fzp", &simple_fzp(4, 4));
+
+    let run = |out: &str| {
+        let out = dir.join(out);
+        let status = thumbnailer(&dir)
+            .args(["--mtime", "1234567890"])
+            .arg(&input)
+            .arg("4")
+            .arg(&out)
+            .arg("file:///in.fzp")
+            .status()
+            .unwrap();
+        assert!(status.success());
+        std::fs::read(out).unwrap()
+    };
+    let first = run("a.png");
+    let png = decode_png(&first);
+    assert_eq!(png.text("Thumb::MTime"), Some("1234567890"));
+    // Deterministic, now that nothing depends on the filesystem.
+    assert_eq!(first, run("b.png"));
+
+    // Also lets stdin carry an mtime.
+    let mut command = thumbnailer(&dir);
+    command.args(["--mtime=42", "-", "4", "-", "file:///in.fzp"]);
+    let output = run_with_stdin(command, &simple_fzp(4, 4));
+    assert!(output.status.success());
+    assert_eq!(decode_png(&output.stdout).text("Thumb::MTime"), Some("42"));
+}
+
+#[test]
+fn stdin_too_large() {
+    let dir = temp_dir("stdin_too_large");
+    let run = |document: &[u8], flags: &[&str]| {
+        let mut command = thumbnailer(&dir);
+        command.args(flags).args(["-", "4", "-", "file:///in.fzp"]);
+        run_with_stdin(command, document)
+    };
+    let thmb = qoi_pattern(4, 4);
+    // Exactly as much as is read, and one byte more.
+    let fits = |len: usize| {
+        let junk = vec![0; len - fzp(&[(b"thmb", &thmb), (b"junk", b"")]).len()];
+        fzp(&[(b"thmb", &thmb), (b"junk", &junk)])
+    };
+    let output = run(&fits(4 << 20), &["--strict"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(decode_png(&output.stdout).info.width, 4);
+
+    // Refused as a whole, rather than as cut short.
+    for flags in [&[][..], &["--strict"]] {
+        let output = run(&fits((4 << 20) + 2), flags);
+        assert_eq!(output.status.code(), Some(4), "{output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("input too large"), "{stderr}");
+        assert!(output.stdout.is_empty());
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_paths() {
+    use std::ffi::OsStr;
+    use std::os::unix::ffi::OsStrExt;
+    let dir = temp_dir("non_utf8_paths");
+    let input = dir.join(OsStr::from_bytes(b"caf\xe9.fzp"));
+    std::fs::write(&input, simple_fzp(4, 4)).unwrap();
+    let out = dir.join(OsStr::from_bytes(b"caf\xe9.png"));
+
+    let status = thumbnailer(&dir)
+        .arg(&input)
+        .arg("4")
+        .arg(&out)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let dir = std::fs::canonicalize(&dir).unwrap();
+    let expected = format!("file://{}/caf%E9.fzp", dir.display());
+    assert_eq!(decode_png_file(&out).text("Thumb::URI"), Some(&*expected));
+}
+
+#[cfg(unix)]
+#[test]
+fn pre_epoch_mtime() {
+    let dir = temp_dir("pre_epoch_mtime");
+    let input = write_file(&dir, "in.fzp", &simple_fzp(4, 4));
+    let before_epoch = std::time::UNIX_EPOCH - std::time::Duration::from_secs(86_400);
+    std::fs::File::options()
+        .write(true)
+        .open(&input)
+        .unwrap()
+        .set_modified(before_epoch)
+        .unwrap();
+    let out = dir.join("out.png");
+
+    let output = thumbnailer(&dir)
+        .arg(&input)
+        .arg("4")
+        .arg(&out)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("modified before 1970"), "{stderr}");
+    assert_eq!(decode_png_file(&out).text("Thumb::MTime"), Some("0"));
+}
+
+#[test]
+fn timings() {
+    let dir = temp_dir("timings");
+    let input = write_file(&dir, "in.fzp", &simple_fzp(16, 16));
+
+    let output = thumbnailer(&dir)
+        .args(["--quiet", "--timings"])
+        .arg(&input)
+        .arg("8")
+        .arg(dir.join("out.png"))
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    let lines: Vec<_> = stderr.lines().collect();
+    assert_eq!(lines.len(), 1, "{stderr}");
+    for stage in ["open", "scan", "decode", "resize", "encode", "total"] {
+        assert!(lines[0].contains(&format!(" {stage}=")), "{stderr}");
+    }
+
+    // Otherwise silent.
+    let output = thumbnailer(&dir)
+        .arg(&input)
+        .arg("8")
+        .arg(dir.join("out.png"))
+        .output()
+        .unwrap();
+    assert!(output.stderr.is_empty());
+}
+
+#[test]
+fn print_hash() {
+    use sha2::Digest;
+    let hex = |data: &[u8]| -> String {
+        sha2::Sha256::digest(data)
+            .iter()
+            .map(|b| format!("{b:02x}"))
+            .collect()
+    };
+    let dir = temp_dir("print_hash");
+    let input = write_file(&dir, "in.fzp", &simple_fzp(16, 16));
+    let out = dir.join("out.png");
+
+    let output = thumbnailer(&dir)
+        .arg("--print-hash")
+        .arg(&input)
+        .arg("8")
+        .arg(&out)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    let on_disk = std::fs::read(&out).unwrap();
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!("sha256:{} {}\n", hex(&on_disk), out.display())
+    );
+
+    // With the image on stdout, the hash goes to stderr.
+    let output = thumbnailer(&dir)
+        .arg("--print-hash")
+        .arg(&input)
+        .args(["8", "-"])
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+    decode_png(&output.stdout);
+    assert_eq!(
+        String::from_utf8(output.stderr).unwrap(),
+        format!("sha256:{} -\n", hex(&output.stdout))
+    );
+}
+
+#[test]
+fn skips_fresh_output() {
+    let dir = temp_dir("skips_fresh_output");
+    let input = write_file(&dir, "in.fzp", &simple_fzp(16, 16));
+    let out = dir.join("out.png");
+    let run = |args: &[&str]| {
+        let output = thumbnailer(&dir)
+            .arg("-v")
+            .args(args)
+            .arg(&input)
+            .arg(&out)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        let png = decode_png_file(&out);
+        let skipped = String::from_utf8(output.stderr)
+            .unwrap()
+            .contains("skipped");
+        (png.info.width, skipped)
+    };
+    assert_eq!(run(&["--mtime", "100", "-s", "8"]), (8, false));
+    // Same URI and mtime, left alone even though the size differs.
+    assert_eq!(run(&["--mtime", "100", "-s", "4"]), (8, true));
+    // Document changed.
+    assert_eq!(run(&["--mtime", "101", "-s", "4"]), (4, false));
+    assert_eq!(run(&["--mtime", "101", "-s", "8", "--force"]), (8, false));
+    // Different document at the same path.
+    assert_eq!(
+        run(&["--mtime", "101", "-s", "4", "-u", "file:///other.fzp"]),
+        (4, false)
+    );
+
+    // Unreadable output is just regenerated.
+    std::fs::write(&out, b"\x89PNG\r\n\x1a\ngarbage").unwrap();
+    assert_eq!(run(&["--mtime", "101", "-s", "8"]), (8, false));
+
+    // As is output written by any other version, which may have scaled it differently.
+    let uri = decode_png_file(&out).text("Thumb::URI").unwrap().to_owned();
+    let mut older = Vec::new();
+    {
+        let mut png = png::Encoder::new(&mut older, 2, 2);
+        png.set_color(png::ColorType::Rgba);
+        png.add_text_chunk("Thumb::URI".into(), uri).unwrap();
+        png.add_text_chunk("Thumb::MTime".into(), "101".into())
+            .unwrap();
+        png.add_text_chunk(
+            "Software".into(),
+            "Fuzzpaint (fuzzpaint-thumbnailer 0.1.0)".into(),
+        )
+        .unwrap();
+        let mut png = png.write_header().unwrap();
+        png.write_image_data(&[0; 16]).unwrap();
+    }
+    std::fs::write(&out, older).unwrap();
+    assert_eq!(run(&["--mtime", "101", "-s", "8"]), (8, false));
+    assert_eq!(run(&["--mtime", "101", "-s", "4"]), (8, true));
+}
+
+#[test]
+fn dry_run() {
+    let dir = temp_dir("dry_run");
+    let input = write_file(&dir, "in.fzp", &simple_fzp(40, 20));
+    let out = dir.join("out.png");
+    let run = |args: &[&str]| {
+        thumbnailer(&dir)
+            .arg("--dry-run")
+            .args(args)
+            .arg(&input)
+            .arg("16")
+            .arg(&out)
+            .arg("file:///in.fzp")
+            .output()
+            .unwrap()
+    };
+
+    let output = run(&["--mtime=100"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!(
+            "input={}\noutput={}\nuri=file:///in.fzp\nsize=16\nwidth=16\nheight=8\nmtime=100\nfresh=false\n",
+            input.display(),
+            out.display()
+        )
+    );
+    assert!(!out.exists());
+
+    let output = run(&["--mtime=100", "--report=jsonl", "--mode=cover"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap().trim_end(),
+        format!(
+            "{{\"input\":\"{}\",\"output\":\"{}\",\"uri\":\"file:///in.fzp\",\"size\":\"16\",\"width\":16,\"height\":16,\"mtime\":100,\"fresh\":false}}",
+            input.display(),
+            out.display()
+        )
+    );
+    assert!(!out.exists());
+
+    // An existing output is neither replaced nor truncated.
+    std::fs::write(&out, b"old").unwrap();
+    assert!(run(&[]).status.success());
+    assert_eq!(std::fs::read(&out).unwrap(), b"old");
+
+    // The document is still read, and fails as it would for real, without a fail marker.
+    std::fs::remove_file(&out).unwrap();
+    write_file(&dir, "in.fzp", &fzp(&[(b"LIST", b"INFO"), (b"docv", b"")]));
+    let output = run(&[]);
+    assert_eq!(output.status.code(), Some(3));
+    assert!(output.stdout.is_empty());
+    assert!(!out.exists());
+    assert!(!dir.join("cache").exists());
+}
+
+#[test]
+fn png_text() {
+    let dir = temp_dir("png_text");
+    let input = write_file(&dir, "in.fzp", &simple_fzp(4, 4));
+    let out = dir.join("out.png");
+
+    let output = thumbnailer(&dir)
+        .args(["--png-text", "X-Pipeline::JobId=1234"])
+        .args(["--png-text=Comment=caf\u{e9} = ok", "--png-text", "Empty="])
+        .arg(&input)
+        .arg("4")
+        .arg(&out)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let png = decode_png_file(&out);
+    assert_eq!(png.text("X-Pipeline::JobId"), Some("1234"));
+    assert_eq!(png.text("Comment"), Some("caf\u{e9} = ok"));
+    assert_eq!(png.text("Empty"), Some(""));
+    // Still have our own.
+    assert!(png.text("Thumb::URI").is_some());
+    // Latin-1 on disk, not UTF-8.
+    let raw = std::fs::read(&out).unwrap();
+    assert!(raw.windows(17).any(|w| w == b"Comment\0caf\xe9 = ok"));
+
+    let output = thumbnailer(&dir)
+        .args(["--png-text", "Thumb::MTime=0"])
+        .arg(&input)
+        .arg("4")
+        .arg(&out)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("Thumb::MTime"), "{stderr}");
+}
+
+#[test]
+fn crop() {
+    let dir = temp_dir("crop");
+    let pixel = |x: u32, y: u32| [x as u8, y as u8, (x * y) as u8, 255];
+    let input = write_file(&dir, "in.fzp", &fzp(&[(b"thmb", &qoi_with(64, 32, pixel))]));
+    let out = dir.join("out.png");
+    // Forced, the same output is rewritten with each crop.
+    let run = |crop: &str, size: &str| {
+        thumbnailer(&dir)
+            .args(["--force", "--crop", crop])
+            .arg(&input)
+            .arg(size)
+            .arg(&out)
+            .arg("file:///in.fzp")
+            .output()
+            .unwrap()
+    };
+
+    // Native size is exactly the cropped pixels.
+    let output = run("10,5,7x3", "native");
+    assert!(output.status.success(), "{output:?}");
+    assert!(output.stderr.is_empty(), "{output:?}");
+    let png = decode_png_file(&out);
+    assert_eq!((png.info.width, png.info.height), (7, 3));
+    let expected: Vec<u8> = (5..8)
+        .flat_map(|y| (10..17).flat_map(move |x| pixel(x, y)))
+        .collect();
+    assert_eq!(png.rgba(), expected);
+
+    // The crop's aspect decides the fit.
+    for (crop, size, expected) in [
+        ("0,0,32x32", "16", (16, 16)),
+        ("0,0,64x16", "16", (16, 4)),
+        ("8,0,16x32", "16", (8, 16)),
+        ("0,0,16x8", "64x64", (16, 8)),
+        ("0,0,40x10", "20x20", (20, 5)),
+    ] {
+        let output = run(crop, size);
+        assert!(output.status.success(), "{crop} {size}: {output:?}");
+        let png = decode_png_file(&out);
+        assert_eq!((png.info.width, png.info.height), expected, "{crop} {size}");
+    }
+
+    // Overhanging crops are clamped, with a warning.
+    let output = run("48,16,100x100", "native");
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("clamped to 48,16,16x16"), "{stderr}");
+    let png = decode_png_file(&out);
+    assert_eq!((png.info.width, png.info.height), (16, 16));
+    assert_eq!(&png.rgba()[..4], &pixel(48, 16));
+
+    // Nothing left.
+    for crop in ["64,0,8x8", "0,0,0x8"] {
+        let output = run(crop, "16");
+        assert!(!output.status.success(), "{crop}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("zero size"), "{crop}: {stderr}");
+    }
+}
+
+#[test]
+fn orientation() {
+    let dir = temp_dir("orientation");
+    let pixel = |x: u32, y: u32| [x as u8, y as u8, 0, 255];
+    let input = write_file(&dir, "in.fzp", &fzp(&[(b"thmb", &qoi_with(3, 2, pixel))]));
+    let out = dir.join("out.png");
+    let run = |flags: &[&str], size: &str| {
+        let output = thumbnailer(&dir)
+            .arg("--force")
+            .args(flags)
+            .arg(&input)
+            .arg(size)
+            .arg(&out)
+            .arg("file:///in.fzp")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{flags:?}: {output:?}");
+        decode_png_file(&out)
+    };
+    let pixels = |coords: &[(u32, u32)]| -> Vec<u8> {
+        coords.iter().flat_map(|&(x, y)| pixel(x, y)).collect()
+    };
+
+    let png = run(&["--rotate", "90"], "native");
+    assert_eq!((png.info.width, png.info.height), (2, 3));
+    assert_eq!(
+        png.rgba(),
+        pixels(&[(0, 1), (0, 0), (1, 1), (1, 0), (2, 1), (2, 0)])
+    );
+    // Metadata is unaffected.
+    assert!(png.srgb);
+    assert_eq!(png.text("Thumb::URI"), Some("file:///in.fzp"));
+
+    let png = run(&["--flip", "h"], "native");
+    assert_eq!((png.info.width, png.info.height), (3, 2));
+    assert_eq!(
+        png.rgba(),
+        pixels(&[(2, 0), (1, 0), (0, 0), (2, 1), (1, 1), (0, 1)])
+    );
+
+    // Applied after cropping, then rotated, then flipped.
+    let png = run(
+        &["--crop", "1,0,2x2", "--flip", "v", "--rotate", "270"],
+        "native",
+    );
+    assert_eq!((png.info.width, png.info.height), (2, 2));
+    assert_eq!(png.rgba(), pixels(&[(1, 0), (1, 1), (2, 0), (2, 1)]));
+
+    // Quarter turns feed the fit with swapped dimensions.
+    let png = run(&["--rotate", "270", "--allow-upscale"], "30x60");
+    assert_eq!((png.info.width, png.info.height), (30, 45));
+    let png = run(&["--rotate", "180", "--allow-upscale"], "30x60");
+    assert_eq!((png.info.width, png.info.height), (30, 20));
+}
+
+#[test]
+fn document_orientation() {
+    let dir = temp_dir("document_orientation");
+    let thmb = qoi_with(3, 2, |x, y| [x as u8, y as u8, 0, 255]);
+    let plain = write_file(&dir, "plain.fzp", &fzp(&[(b"thmb", &thmb)]));
+    let out = dir.join("out.png");
+    let run = |flags: &[&str], input: &std::path::Path| {
+        let output = thumbnailer(&dir)
+            .arg("--force")
+            .args(flags)
+            .arg(input)
+            .arg("native")
+            .arg(&out)
+            .arg("file:///in.fzp")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{flags:?}: {output:?}");
+        decode_png_file(&out)
+    };
+    let with_ornt = |degrees: u16, mirror: u16| {
+        let ornt = [degrees.to_le_bytes(), mirror.to_le_bytes()].concat();
+        write_file(&dir, "in.fzp", &fzp(&[(b"ornt", &ornt), (b"thmb", &thmb)]))
+    };
+
+    // All eight agree with the same rotation, then a horizontal flip, asked for by flag.
+    for degrees in [0u16, 90, 180, 270] {
+        for mirror in [0, 1] {
+            let png = run(&[], &with_ornt(degrees, mirror));
+            let degrees = degrees.to_string();
+            let mut flags = vec!["--rotate", &degrees];
+            if mirror == 1 {
+                flags.extend(["--flip", "h"]);
+            }
+            if degrees == "0" {
+                flags.drain(..2);
+            }
+            let expected = run(&flags, &plain);
+            assert_eq!(
+                (png.info.width, png.info.height),
+                (expected.info.width, expected.info.height),
+                "{flags:?}"
+            );
+            assert_eq!(png.pixels, expected.pixels, "{flags:?}");
+        }
+    }
+
+    // Flags replace it entirely.
+    let input = with_ornt(90, 1);
+    let png = run(&["--flip", "v"], &input);
+    assert_eq!(png.pixels, run(&["--flip", "v"], &plain).pixels);
+    let png = run(&["--rotate", "180"], &input);
+    assert_eq!(png.pixels, run(&["--rotate", "180"], &plain).pixels);
+
+    // Nonsense is ignored.
+    let png = run(&[], &with_ornt(45, 0));
+    assert_eq!(png.pixels, run(&[], &plain).pixels);
+}
+
+#[test]
+fn cover_mode() {
+    let dir = temp_dir("cover_mode");
+    // Left and right thirds red, middle third blue.
+    let pixel = |x: u32, _| match x {
+        20..=39 => [0, 0, 255, 255],
+        _ => [255, 0, 0, 255],
+    };
+    let input = write_file(&dir, "in.fzp", &fzp(&[(b"thmb", &qoi_with(60, 20, pixel))]));
+    let out = dir.join("out.png");
+    let run = |flags: &[&str], size: &str| {
+        thumbnailer(&dir)
+            .arg("--force")
+            .args(flags)
+            .arg(&input)
+            .arg(size)
+            .arg(&out)
+            .arg("file:///in.fzp")
+            .output()
+            .unwrap()
+    };
+
+    // Fit is the default, letterboxed.
+    for flags in [&[][..], &["--mode", "fit"]] {
+        let output = run(flags, "16");
+        assert!(output.status.success(), "{output:?}");
+        let png = decode_png_file(&out);
+        assert_eq!((png.info.width, png.info.height), (16, 5));
+    }
+
+    // Covering a square keeps just the middle. The filter may blend in a little of the trimmed
+    // edges, as with any scaled region.
+    let output = run(&["--mode", "cover"], "16");
+    assert!(output.status.success(), "{output:?}");
+    let png = decode_png_file(&out);
+    assert_eq!((png.info.width, png.info.height), (16, 16));
+    assert!(png.rgba().chunks_exact(4).all(|pixel| pixel[2] > pixel[0]));
+
+    // Exactly the box, whatever the aspect ratio.
+    for size in ["64x8", "8x64", "1x1024", "1024x1"] {
+        let output = run(&["--mode=cover", "--allow-upscale"], size);
+        assert!(output.status.success(), "{size}: {output:?}");
+        let png = decode_png_file(&out);
+        let (width, height) = size.split_once('x').unwrap();
+        assert_eq!(
+            (png.info.width, png.info.height),
+            (width.parse().unwrap(), height.parse().unwrap())
+        );
+    }
+
+    // Without upscaling, the box is only trimmed to the image.
+    let output = run(&["--mode=cover"], "64x8");
+    assert!(output.status.success(), "{output:?}");
+    let png = decode_png_file(&out);
+    assert_eq!((png.info.width, png.info.height), (60, 8));
+
+    // Applies to the cropped region.
+    let output = run(&["--mode", "cover", "--crop", "0,0,20x20"], "4");
+    assert!(output.status.success(), "{output:?}");
+    let png = decode_png_file(&out);
+    assert!(png.rgba().chunks_exact(4).all(|pixel| pixel[0] > pixel[2]));
+
+    // Nothing to cover.
+    let output = run(&["--mode", "cover"], "native");
+    assert!(!output.status.success());
+}
+
+#[test]
+fn upscale() {
+    let dir = temp_dir("upscale");
+    let thmb = qoi_pattern(64, 64);
+    let input = write_file(&dir, "in.fzp", &fzp(&[(b"thmb", &thmb)]));
+    let out = dir.join("out.png");
+    let run = |flags: &[&str], size: &str| {
+        let output = thumbnailer(&dir)
+            .arg("--force")
+            .args(flags)
+            .arg(&input)
+            .arg(size)
+            .arg(&out)
+            .arg("file:///in.fzp")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{flags:?} {size}: {output:?}");
+        decode_png_file(&out)
+    };
+
+    // Smaller than asked for stays as stored, pixel for pixel.
+    let (_, rgba) = qoi::decode_to_vec(&thmb).unwrap();
+    for flags in [&[][..], &["--no-upscale"]] {
+        let png = run(flags, "512");
+        assert_eq!((png.info.width, png.info.height), (64, 64), "{flags:?}");
+        assert_eq!(png.rgba(), rgba, "{flags:?}");
+        assert_eq!(png.text("Thumb::URI"), Some("file:///in.fzp"));
+    }
+    // Capped on both axes alike, keeping the aspect.
+    let png = run(&[], "512x32");
+    assert_eq!((png.info.width, png.info.height), (32, 32));
+
+    let png = run(&["--allow-upscale"], "512");
+    assert_eq!((png.info.width, png.info.height), (512, 512));
+    assert_eq!(png.text("Thumb::URI"), Some("file:///in.fzp"));
+    // The last given wins.
+    let png = run(&["--allow-upscale", "--no-upscale"], "512");
+    assert_eq!((png.info.width, png.info.height), (64, 64));
+}
+
+#[test]
+fn thin_sources() {
+    let dir = temp_dir("thin_sources");
+    let out = dir.join("out.png");
+    let run = |thmb: &[u8], flags: &[&str], size: &str| {
+        let input = write_file(&dir, "in.fzp", &fzp(&[(b"thmb", thmb)]));
+        let output = thumbnailer(&dir)
+            .arg("--force")
+            .args(flags)
+            .arg(&input)
+            .arg(size)
+            .arg(&out)
+            .arg("file:///in.fzp")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{flags:?} {size}: {output:?}");
+        let png = decode_png_file(&out);
+        (png.info.width, png.info.height)
+    };
+
+    // Shrunk along, and kept at a pixel across rather than failing.
+    for len in [2, 100, 1024, 4000] {
+        let tall = qoi_pattern(1, len);
+        let wide = qoi_pattern(len, 1);
+        for size in [1, 16, 128, 512] {
+            let along = size.min(len);
+            assert_eq!(run(&tall, &[], &size.to_string()), (1, along), "1x{len}");
+            assert_eq!(run(&wide, &[], &size.to_string()), (along, 1), "{len}x1");
+            // Covering fills the box from a single pixel.
+            let flags = ["--mode", "cover", "--allow-upscale"];
+            assert_eq!(run(&tall, &flags, &size.to_string()), (size, size));
+            assert_eq!(run(&wide, &flags, &size.to_string()), (size, size));
+        }
+        // Into a box of the other shape.
+        assert_eq!(run(&tall, &[], "64x1"), (1, 1), "1x{len}");
+        assert_eq!(run(&wide, &[], "1x64"), (1, 1), "{len}x1");
+    }
+    // In proportion when enlarged, as any other shape.
+    assert_eq!(
+        run(&qoi_pattern(1, 4), &["--allow-upscale"], "64"),
+        (16, 64)
+    );
+}
+
+#[test]
+fn square() {
+    let dir = temp_dir("square");
+    let input = write_file(
+        &dir,
+        "in.fzp",
+        &fzp(&[(b"thmb", &qoi_with(30, 20, |_, _| [10, 20, 30, 255]))]),
+    );
+    let out = dir.join("out.png");
+    let run = |flags: &[&str], size: &str| {
+        let output = thumbnailer(&dir)
+            .arg("--force")
+            .args(flags)
+            .arg(&input)
+            .arg(size)
+            .arg(&out)
+            .arg("file:///in.fzp")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{flags:?} {size}: {output:?}");
+        decode_png_file(&out)
+    };
+    let opaque_rows = |png: &Png| -> Vec<bool> {
+        png.pixels
+            .chunks_exact(png.info.width as usize * 4)
+            .map(|row| row.chunks_exact(4).any(|pixel| pixel[3] != 0))
+            .collect()
+    };
+
+    // 30x20 fits 9x6, centered on 9x9 with the odd row at the bottom.
+    let png = run(&["--square"], "9");
+    assert_eq!((png.info.width, png.info.height), (9, 9));
+    assert_eq!(
+        opaque_rows(&png),
+        [false, true, true, true, true, true, true, false, false]
+    );
+    // Padding is fully transparent, the image untouched.
+    for (i, pixel) in png.pixels.chunks_exact(4).enumerate() {
+        let row = i / 9;
+        if (1..7).contains(&row) {
+            assert_eq!(pixel, [10, 20, 30, 255]);
+        } else {
+            assert_eq!(pixel, [0, 0, 0, 0]);
+        }
+    }
+
+    // Boxes too.
+    let png = run(&["--square"], "12x40");
+    assert_eq!((png.info.width, png.info.height), (12, 40));
+    assert_eq!(opaque_rows(&png).iter().filter(|&&row| row).count(), 8);
+
+    // Already exact, nothing to pad, so no transparency to keep.
+    let png = run(&["--square", "--mode", "cover"], "16");
+    assert_eq!((png.info.width, png.info.height), (16, 16));
+    assert_eq!(png.info.color_type, png::ColorType::Rgb);
+}
+
+#[test]
+fn background() {
+    let dir = temp_dir("background");
+    // Left half opaque red, right half transparent.
+    let input = write_file(
+        &dir,
+        "in.fzp",
+        &fzp(&[(
+            b"thmb",
+            &qoi_with(4, 2, |x, _| if x < 2 { [255, 0, 0, 255] } else { [0; 4] }),
+        )]),
+    );
+    let out = dir.join("out.png");
+    let run = |flags: &[&str], size: &str| {
+        let output = thumbnailer(&dir)
+            .arg("--force")
+            .args(flags)
+            .arg(&input)
+            .arg(size)
+            .arg(&out)
+            .arg("file:///in.fzp")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{flags:?} {size}: {output:?}");
+        decode_png_file(&out)
+    };
+
+    // Opaque backgrounds drop the alpha channel.
+    let png = run(&["--background", "#0000ff"], "native");
+    assert_eq!(png.info.color_type, png::ColorType::Rgb);
+    assert_eq!(
+        png.pixels,
+        [[255, 0, 0], [255, 0, 0], [0, 0, 255], [0, 0, 255]]
+            .repeat(2)
+            .concat()
+    );
+    assert_eq!(png.text("Thumb::URI"), Some("file:///in.fzp"));
+
+    // Translucent ones keep it.
+    let png = run(&["--background", "0000ff80"], "native");
+    assert_eq!(png.info.color_type, png::ColorType::Rgba);
+    assert_eq!(
+        png.pixels,
+        [
+            [255, 0, 0, 255],
+            [255, 0, 0, 255],
+            [0, 0, 255, 128],
+            [0, 0, 255, 128]
+        ]
+        .repeat(2)
+        .concat()
+    );
+
+    // Padding is flattened too.
+    let png = run(&["--background", "00ff00", "--square"], "8");
+    assert_eq!((png.info.width, png.info.height), (8, 8));
+    assert_eq!(png.info.color_type, png::ColorType::Rgb);
+    assert_eq!(&png.pixels[..3], [0, 255, 0]);
+
+    // Without it, nothing changes.
+    let png = run(&[], "native");
+    assert_eq!(png.info.color_type, png::ColorType::Rgba);
+    assert_eq!(&png.pixels[8..12], [0; 4]);
+}
+
+#[test]
+fn rgb_and_rgba_thumbnails() {
+    let dir = temp_dir("rgb_and_rgba_thumbnails");
+    // 2x2 blocks, left red, right blue.
+    let color = |x: u32| if x < 2 { [255, 0, 0] } else { [0, 0, 255] };
+    let rgb = write_file(
+        &dir,
+        "rgb.fzp",
+        &fzp(&[(b"thmb", &qoi_rgb_with(4, 2, |x, _| color(x)))]),
+    );
+    let rgba = write_file(
+        &dir,
+        "rgba.fzp",
+        &fzp(&[(
+            b"thmb",
+            &qoi_with(4, 2, |x, _| {
+                let [r, g, b] = color(x);
+                [r, g, b, 255]
+            }),
+        )]),
+    );
+    let out = dir.join("out.png");
+    let run = |input: &std::path::Path, flags: &[&str], size: &str| {
+        let output = thumbnailer(&dir)
+            .arg("--force")
+            .args(flags)
+            .arg(input)
+            .arg(size)
+            .arg(&out)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{flags:?} {size}: {output:?}");
+        decode_png_file(&out)
+    };
+    let (red, blue) = ([255, 0, 0], [0, 0, 255]);
+
+    // Opaque either way, so written without alpha.
+    for input in [&rgb, &rgba] {
+        let png = run(input, &[], "native");
+        assert_eq!(png.info.color_type, png::ColorType::Rgb);
+        assert_eq!(png.pixels, [red, red, blue, blue].repeat(2).concat());
+    }
+    // Unless RGBA is asked for, as strictly it's all the spec allows.
+    for input in [&rgb, &rgba] {
+        let png = run(input, &["--force-rgba"], "native");
+        assert_eq!(png.info.color_type, png::ColorType::Rgba);
+        assert_eq!(
+            png.pixels,
+            [
+                [255, 0, 0, 255],
+                [255, 0, 0, 255],
+                [0, 0, 255, 255],
+                [0, 0, 255, 255]
+            ]
+            .repeat(2)
+            .concat()
+        );
+    }
+
+    // Scaled, each block to a pixel.
+    let png = run(&rgb, &["--filter", "box"], "2");
+    assert_eq!((png.info.width, png.info.height), (2, 1));
+    assert_eq!(png.info.color_type, png::ColorType::Rgb);
+    assert_eq!(png.pixels, [red, blue].concat());
+    let png = run(&rgba, &["--filter", "box"], "2");
+    assert_eq!(png.info.color_type, png::ColorType::Rgb);
+    assert_eq!(png.pixels, [red, blue].concat());
+
+    // Cropped and rotated.
+    let png = run(&rgb, &["--crop", "1,0,2x2", "--rotate", "90"], "native");
+    assert_eq!((png.info.width, png.info.height), (2, 2));
+    assert_eq!(png.pixels, [red, red, blue, blue].concat());
+
+    // Padding with transparency needs an alpha channel.
+    let png = run(&rgb, &["--square", "--filter", "box"], "2");
+    assert_eq!((png.info.width, png.info.height), (2, 2));
+    assert_eq!(png.info.color_type, png::ColorType::Rgba);
+    assert_eq!(
+        png.pixels,
+        [[255, 0, 0, 255], [0, 0, 255, 255], [0; 4], [0; 4]].concat()
+    );
+    // Unless it's filled with an opaque background instead.
+    let png = run(
+        &rgb,
+        &["--square", "--filter", "box", "--background", "#00ff00"],
+        "2",
+    );
+    assert_eq!(png.info.color_type, png::ColorType::Rgb);
+    assert_eq!(png.pixels, [red, blue, [0, 255, 0], [0, 255, 0]].concat());
+    // A translucent background is drawn under nothing but the padding.
+    let png = run(&rgb, &["--background", "0000ff80"], "native");
+    assert_eq!(png.info.color_type, png::ColorType::Rgb);
+    assert_eq!(png.pixels, [red, red, blue, blue].repeat(2).concat());
+}
+
+#[test]
+fn max_dimension() {
+    let dir = temp_dir("max_dimension");
+    let long = write_file(&dir, "long.fzp", &simple_fzp(2048, 64));
+    let square = write_file(&dir, "square.fzp", &simple_fzp(1100, 1100));
+    let out = dir.join("out.png");
+    let run = |input: &std::path::Path, flags: &[&str]| {
+        thumbnailer(&dir)
+            .arg("--force")
+            .args(flags)
+            .arg(input)
+            .arg("64")
+            .arg(&out)
+            .arg("file:///in.fzp")
+            .output()
+            .unwrap()
+    };
+
+    // Long and thin, but fewer pixels than the default allows.
+    let output = run(&long, &[]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(decode_png_file(&out).info.width, 64);
+
+    // Square, and over the default.
+    let output = run(&square, &[]);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("1100x1100 exceeds limit of 1024x1024 px in area"),
+        "{stderr}"
+    );
+
+    let output = run(&square, &["--max-dimension", "2048"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(decode_png_file(&out).info.width, 64);
+
+    // Lowered, too.
+    let output = run(&long, &["--max-dimension=300"]);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("exceeds limit of 300x300"), "{stderr}");
+
+    // Never too long on a side, however few pixels.
+    let too_long = write_file(&dir, "too_long.fzp", &simple_fzp(16385, 1));
+    let output = run(&too_long, &["--max-dimension", "8192"]);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("16385x1 exceeds limit of 16384 px on a side"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn max_thumb_bytes() {
+    let dir = temp_dir("max_thumb_bytes");
+    let out = dir.join("out.png");
+    let run = |input: &std::path::Path, flags: &[&str]| {
+        thumbnailer(&dir)
+            .args(flags)
+            .arg(input)
+            .arg("64")
+            .arg(&out)
+            .output()
+            .unwrap()
+    };
+
+    // Every byte of it there, if only as a sparse file.
+    let mut huge = fzp(&[(b"thmb", &qoi_pattern(4, 4))]);
+    huge[16..20].copy_from_slice(&0xffff_fff0u32.to_le_bytes());
+    let riff_len = 4 + 8 + 0xffff_fff0u64;
+    huge[4..8].copy_from_slice(&u32::try_from(riff_len).unwrap().to_le_bytes());
+    let input = write_file(&dir, "huge.fzp", &huge);
+    std::fs::OpenOptions::new()
+        .write(true)
+        .open(&input)
+        .unwrap()
+        .set_len(8 + riff_len)
+        .unwrap();
+    for flags in [&[][..], &["--allow-flatten"]] {
+        let output = run(&input, flags);
+        assert_eq!(output.status.code(), Some(4), "{flags:?}: {output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("thumbnail chunk of 4294967280 bytes exceeds limit of 16777216"),
+            "{stderr}"
+        );
+    }
+    assert!(!out.exists());
+
+    // Claiming far more than there is.
+    let mut absurd = fzp(&[(b"thmb", &qoi_pattern(4, 4))]);
+    absurd[16..20].copy_from_slice(&0xffff_fff0u32.to_le_bytes());
+    absurd[4..8].copy_from_slice(&0xffff_fff8u32.to_le_bytes());
+    let input = write_file(&dir, "absurd.fzp", &absurd);
+    let output = run(&input, &[]);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+
+    // Overridable either way.
+    let input = write_file(&dir, "in.fzp", &simple_fzp(4, 4));
+    let len = qoi_pattern(4, 4).len();
+    let output = run(&input, &["--max-thumb-bytes", &(len - 1).to_string()]);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let output = run(&input, &["--max-thumb-bytes", &len.to_string()]);
+    assert!(output.status.success(), "{output:?}");
+}
+
+#[test]
+fn sizes() {
+    let dir = temp_dir("sizes");
+    let input = write_file(&dir, "doc.fzp", &simple_fzp(64, 32));
+    let out_dir = dir.join("thumbs");
+    std::fs::create_dir(&out_dir).unwrap();
+
+    let output = thumbnailer(&dir)
+        .args(["--sizes", "8,16,10x40", "--out-dir"])
+        .arg(&out_dir)
+        .arg(&input)
+        .arg("file:///doc.fzp")
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    for (name, dimensions) in [
+        ("doc-8.png", (8, 4)),
+        ("doc-16.png", (16, 8)),
+        ("doc-10x40.png", (10, 5)),
+    ] {
+        let png = decode_png_file(&out_dir.join(name));
+        assert_eq!((png.info.width, png.info.height), dimensions, "{name}");
+        assert_eq!(png.text("Thumb::URI"), Some("file:///doc.fzp"));
+        assert!(png.text("Thumb::MTime").is_some());
+    }
+
+    // One size failing doesn't stop the others, and each is reported.
+    std::fs::create_dir(out_dir.join("blocked-16.png")).unwrap();
+    let output = thumbnailer(&dir)
+        .args(["--force", "--report", "jsonl", "--sizes", "8,16,32"])
+        .args(["--name-template", "blocked-{size}.png", "--out-dir"])
+        .arg(&out_dir)
+        .arg(&input)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 3, "{stdout}");
+    assert!(lines[0].contains("\"status\":\"ok\",\"width\":8,\"height\":4"));
+    assert!(lines[1].contains("\"status\":\"io-error\""));
+    assert!(lines[2].contains("\"status\":\"ok\",\"width\":32,\"height\":16"));
+    assert!(out_dir.join("blocked-8.png").is_file());
+    assert!(out_dir.join("blocked-32.png").is_file());
+
+    // Nothing to decode, so every size fails alike.
+    let bad = write_file(&dir, "bad.fzp", b"not a document");
+    let output = thumbnailer(&dir)
+        .args(["--report=jsonl", "--sizes=8,16", "--out-dir"])
+        .arg(&out_dir)
+        .arg(&bad)
+        .output()
+        .unwrap();
+    assert!(!output.status.success());
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert_eq!(
+        stdout.matches("\"status\":\"corrupt\"").count(),
+        2,
+        "{stdout}"
+    );
+    assert!(!out_dir.join("bad-8.png").exists());
+}
+
+#[test]
+fn strict() {
+    let dir = temp_dir("strict");
+    let qoi = qoi_pattern(6, 4);
+    // The thumbnail chunk claims more bytes than there are, which is coped with by trimming it.
+    let mut data = fzp(&[(b"thmb", &qoi)]);
+    let declared = u32::try_from(qoi.len() + 100).unwrap();
+    data[16..20].copy_from_slice(&declared.to_le_bytes());
+    let input = write_file(&dir, "in.fzp", &data);
+    let out = dir.join("out.png");
+
+    let output = thumbnailer(&dir)
+        .arg(&input)
+        .args(["native"])
+        .arg(&out)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(decode_png_file(&out).info.width, 6);
+    std::fs::remove_file(&out).unwrap();
+
+    let output = thumbnailer(&dir)
+        .arg("--strict")
+        .arg(&input)
+        .args(["native"])
+        .arg(&out)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "chunk \"thmb\" at offset 12 declares {declared} bytes, but its parent leaves only"
+        )),
+        "{stderr}"
+    );
+    assert!(!out.exists());
+
+    // Other ways of finding the thumbnail are just as strict.
+    let status = thumbnailer(&dir)
+        .arg("--probe")
+        .arg(&input)
+        .status()
+        .unwrap();
+    assert!(status.success());
+    let status = thumbnailer(&dir)
+        .args(["--strict", "--probe"])
+        .arg(&input)
+        .status()
+        .unwrap();
+    assert_eq!(status.code(), Some(4));
+}
+
+#[test]
+fn thumbnail_chunk_anywhere() {
+    let dir = temp_dir("thumbnail_chunk_anywhere");
+    let qoi = qoi_pattern(6, 4);
+    let out = dir.join("out.png");
+    // Odd sizes, so padding must be skipped too.
+    let meta: (&[u8; 4], &[u8]) = (b"meta", b"abc");
+    let hist: (&[u8; 4], &[u8]) = (b"hist", b"defgh");
+    let layr: (&[u8; 4], &[u8]) = (b"layr", b"");
+    let thmb: (&[u8; 4], &[u8]) = (b"thmb", &qoi);
+    for (name, chunks) in [
+        ("first", [thmb, meta, hist, layr]),
+        ("after_odd", [meta, thmb, hist, layr]),
+        ("third", [meta, hist, thmb, layr]),
+        ("last", [meta, hist, layr, thmb]),
+    ] {
+        let input = write_file(&dir, &format!("{name}.fzp"), &fzp(&chunks));
+        let output = thumbnailer(&dir)
+            .arg(&input)
+            .args(["native"])
+            .arg(&out)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{name}: {output:?}");
+        assert_eq!(decode_png_file(&out).info.width, 6, "{name}");
+        std::fs::remove_file(&out).unwrap();
+    }
+
+    // Only so far.
+    let input = write_file(&dir, "in.fzp", &fzp(&[meta, hist, layr, thmb]));
+    let output = thumbnailer(&dir)
+        .arg("--max-chunks=3")
+        .arg(&input)
+        .args(["native"])
+        .arg(&out)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("in its first 3 chunks"), "{stderr}");
+    let status = thumbnailer(&dir)
+        .args(["--max-chunks=4", "--probe"])
+        .arg(&input)
+        .status()
+        .unwrap();
+    assert!(status.success());
+}
+
+#[test]
+fn exact_riff_sizes() {
+    let dir = temp_dir("exact_riff_sizes");
+    let pixel = |x: u32, y: u32| [x as u8, 3 * y as u8, 7, 255];
+    let qoi = qoi_with(5, 3, pixel);
+    let expected: Vec<u8> = (0..3)
+        .flat_map(|y| (0..5).flat_map(move |x| pixel(x, y)))
+        .collect();
+    let out = dir.join("out.png");
+    // Written out by hand rather than with `fzp`, so the sizes are exactly as declared: the form
+    // type, then each chunk's header and data, with nothing to spare after the thumbnail.
+    let chunk = |fourcc: &[u8; 4], data: &[u8]| {
+        [fourcc, &(data.len() as u32).to_le_bytes()[..], data].concat()
+    };
+    let thmb = chunk(b"thmb", &qoi);
+    for (name, body) in [
+        ("first", thmb.clone()),
+        ("second", [chunk(b"meta", b"abcd"), thmb.clone()].concat()),
+    ] {
+        let riff_len = 4 + body.len() as u32;
+        let document = [b"RIFF", &riff_len.to_le_bytes()[..], b"fzp ", &body].concat();
+        assert_eq!(document.len(), 8 + riff_len as usize);
+        let input = write_file(&dir, &format!("{name}.fzp"), &document);
+        // Trimming the thumbnail to the RIFF header would be a warning, which fails this.
+        let output = thumbnailer(&dir)
+            .arg("--strict")
+            .arg(&input)
+            .arg("native")
+            .arg(&out)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{name}: {output:?}");
+        assert_eq!(decode_png_file(&out).rgba(), expected, "{name}");
+        std::fs::remove_file(&out).unwrap();
+    }
+}
+
+#[test]
+fn thumbnail_in_list() {
+    let dir = temp_dir("thumbnail_in_list");
+    let qoi = qoi_pattern(6, 4);
+    let out = dir.join("out.png");
+    let run = |input: &std::path::Path, flags: &[&str]| {
+        thumbnailer(&dir)
+            .args(flags)
+            .arg(input)
+            .args(["native"])
+            .arg(&out)
+            .output()
+            .unwrap()
+    };
+
+    let meta = list(b"meta", &[(b"name", b"abc"), (b"thmb", &qoi)]);
+    let nested = list(b"outr", &[(b"junk", b"x"), (b"LIST", &meta)]);
+    let too_deep = list(b"deep", &[(b"LIST", &nested)]);
+    for (name, data) in [("meta", &meta), ("nested", &nested)] {
+        let input = write_file(
+            &dir,
+            &format!("{name}.fzp"),
+            &fzp(&[(b"hist", b"abc"), (b"LIST", data), (b"layr", b"")]),
+        );
+        let output = run(&input, &[]);
+        assert!(output.status.success(), "{name}: {output:?}");
+        assert_eq!(decode_png_file(&out).info.width, 6, "{name}");
+        std::fs::remove_file(&out).unwrap();
+    }
+    let input = write_file(&dir, "too_deep.fzp", &fzp(&[(b"LIST", &too_deep)]));
+    assert_eq!(run(&input, &[]).status.code(), Some(3));
+
+    // A list claiming more than the document has is trimmed to it.
+    let mut overrun = fzp(&[(b"hist", b"abc"), (b"LIST", &meta)]);
+    overrun[28..32].copy_from_slice(&1000u32.to_le_bytes());
+    let input = write_file(&dir, "overrun.fzp", &overrun);
+    let output = run(&input, &[]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(decode_png_file(&out).info.width, 6);
+    std::fs::remove_file(&out).unwrap();
+    let output = run(&input, &["--strict"]);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(
+            "chunk \"LIST\" at offset 24 declares 1000 bytes, but its parent leaves only"
+        ),
+        "{stderr}"
+    );
+
+    // A chunk claiming more than its list has can't hide what follows the list.
+    let mut list_overrun = list(b"meta", &[(b"name", b"abcd")]);
+    list_overrun[8..12].copy_from_slice(&1000u32.to_le_bytes());
+    let input = write_file(
+        &dir,
+        "list_overrun.fzp",
+        &fzp(&[(b"LIST", &list_overrun), (b"thmb", &qoi)]),
+    );
+    let output = run(&input, &[]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(decode_png_file(&out).info.width, 6);
+}
+
+#[test]
+fn truncated() {
+    let dir = temp_dir("truncated");
+    let qoi = qoi_pattern(6, 4);
+    // The RIFF header still claims all of it, as from a copy that didn't finish.
+    let whole = fzp(&[(b"hist", b"abc"), (b"thmb", &qoi)]);
+    for (len, message) in [
+        (
+            42,
+            "document is truncated (chunk \"thmb\" at offset 24 claims {qoi} bytes but only 10 remain)",
+        ),
+        (
+            22,
+            "document is truncated (chunk \"hist\" at offset 12 claims 3 bytes but only 2 remain)",
+        ),
+        (
+            28,
+            "document is truncated (chunk header at offset 24 needs 8 bytes but only 4 remain)",
+        ),
+    ] {
+        let input = write_file(&dir, "in.fzp", &whole[..len]);
+        let output = thumbnailer(&dir)
+            .arg(&input)
+            .args(["native", "-"])
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(4), "{len}: {output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let message = message.replace("{qoi}", &qoi.len().to_string());
+        assert!(stderr.contains(&message), "{len}: {stderr}");
+    }
+}
+
+#[test]
+fn rf64() {
+    use std::io::{Seek, SeekFrom, Write};
+    let dir = temp_dir("rf64");
+    let qoi = qoi_pattern(6, 4);
+    let data_len: u64 = 5 << 30;
+    let thmb = [b"thmb", &(qoi.len() as u32).to_le_bytes()[..], &qoi].concat();
+    let riff_len = 4 + (8 + 28) + (8 + data_len) + thmb.len() as u64;
+    let ds64 = [
+        &riff_len.to_le_bytes()[..],
+        &data_len.to_le_bytes(),
+        &0u64.to_le_bytes(),
+        &0u32.to_le_bytes(),
+    ]
+    .concat();
+
+    // Sparse, so the huge `data` chunk takes no space.
+    let input = dir.join("in.fzp");
+    let mut file = std::fs::File::create(&input).unwrap();
+    file.write_all(b"RF64\xff\xff\xff\xfffzp ds64\x1c\0\0\0")
+        .unwrap();
+    file.write_all(&ds64).unwrap();
+    file.write_all(b"data\xff\xff\xff\xff").unwrap();
+    file.seek(SeekFrom::Current(data_len as i64)).unwrap();
+    file.write_all(&thmb).unwrap();
+    drop(file);
+
+    let out = dir.join("out.png");
+    let output = thumbnailer(&dir)
+        .arg("--strict")
+        .arg(&input)
+        .arg("native")
+        .arg(&out)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(decode_png_file(&out).info.width, 6);
+
+    let output = thumbnailer(&dir)
+        .arg("--list-chunks")
+        .arg(&input)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        format!(
+            "offset=0 fourcc=\"RF64\" size={riff_len}\n\
+             offset=12 fourcc=\"ds64\" size=28\n\
+             offset=48 fourcc=\"data\" size={data_len}\n\
+             offset={} fourcc=\"thmb\" size={}\n",
+            56 + data_len,
+            qoi.len()
+        )
+    );
+}
+
+/// An RF64 document declaring `riff_len`, with `large` in its ds64 size table, then `chunks`.
+fn rf64_with(riff_len: Option<u64>, large: &[(&[u8; 4], u64)], chunks: &[u8]) -> Vec<u8> {
+    let mut ds64 = [0u64.to_le_bytes(), 0u64.to_le_bytes()].concat();
+    ds64.extend_from_slice(&(large.len() as u32).to_le_bytes());
+    for (fourcc, size) in large {
+        ds64.extend_from_slice(&fourcc[..]);
+        ds64.extend_from_slice(&size.to_le_bytes());
+    }
+    let len = 4 + 8 + 8 + ds64.len() + chunks.len();
+    let riff_len = riff_len.unwrap_or(len as u64);
+    [
+        &b"RF64\xff\xff\xff\xfffzp ds64"[..],
+        &(8 + ds64.len() as u32).to_le_bytes(),
+        &riff_len.to_le_bytes(),
+        &ds64,
+        chunks,
+    ]
+    .concat()
+}
+
+#[test]
+fn rf64_huge_sizes() {
+    let dir = temp_dir("rf64_huge_sizes");
+    let qoi = qoi_pattern(6, 4);
+    let mut thmb = [b"thmb", &(qoi.len() as u32).to_le_bytes()[..], &qoi].concat();
+    if thmb.len() % 2 == 1 {
+        thmb.push(0);
+    }
+    let out = dir.join("out.png");
+
+    // A container size no file could have is only wrong, not a crash.
+    let huge_riff = rf64_with(Some(u64::MAX), &[], &thmb);
+    let input = write_file(&dir, "in.fzp", &huge_riff);
+    let output = thumbnailer(&dir)
+        .arg(&input)
+        .arg("native")
+        .arg(&out)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(decode_png_file(&out).info.width, 6);
+    std::fs::remove_file(&out).unwrap();
+    let output = thumbnailer(&dir)
+        .arg("--strict")
+        .arg(&input)
+        .arg("native")
+        .arg(&out)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!("RF64 header declares {} bytes", u64::MAX)),
+        "{stderr}"
+    );
+    let run = |flag: &str, document: &[u8]| {
+        let input = write_file(&dir, "in.fzp", document);
+        thumbnailer(&dir).arg(flag).arg(&input).output().unwrap()
+    };
+    let output = run("--validate", &huge_riff);
+    assert_eq!(output.status.code(), Some(1), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.contains(&format!("RF64 header declares {}", u64::MAX)),
+        "{stdout}"
+    );
+    let output = run("--list-chunks", &huge_riff);
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(
+        stdout.starts_with(&format!(
+            "offset=0 fourcc=\"RF64\" size={} past-eof\n",
+            u64::MAX
+        )),
+        "{stdout}"
+    );
+    assert!(stdout.contains("fourcc=\"thmb\""), "{stdout}");
+
+    // Nor is a chunk size from the table, odd or even, after the thumbnail.
+    // After the header, and a ds64 chunk with a table of one.
+    let junk_offset = 12 + 8 + 40 + thmb.len();
+    for size in [u64::MAX, u64::MAX - 1] {
+        let junk = [&b"junk\xff\xff\xff\xff"[..], &[0; 16]].concat();
+        let document = rf64_with(None, &[(b"junk", size)], &[&thmb[..], &junk].concat());
+        let input = write_file(&dir, "in.fzp", &document);
+        std::fs::remove_file(&out).ok();
+        let output = thumbnailer(&dir)
+            .arg(&input)
+            .arg("native")
+            .arg(&out)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{size}: {output:?}");
+        assert_eq!(decode_png_file(&out).info.width, 6);
+
+        let output = run("--validate", &document);
+        assert_eq!(output.status.code(), Some(1), "{size}: {output:?}");
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(
+            stdout.contains(&format!(
+                "chunk \"junk\" at offset {junk_offset} declares {size} bytes, but its parent leaves only 16"
+            )),
+            "{size}: {stdout}"
+        );
+        let output = run("--list-chunks", &document);
+        assert!(output.status.success(), "{size}: {output:?}");
+        let stdout = String::from_utf8(output.stdout).unwrap();
+        assert!(
+            stdout.ends_with(&format!(
+                "offset={junk_offset} fourcc=\"junk\" size={size} past-eof\n"
+            )),
+            "{size}: {stdout}"
+        );
+    }
+}
+
+#[test]
+fn multiple_thumbnails() {
+    let dir = temp_dir("multiple_thumbnails");
+    let out = dir.join("out.png");
+    // Aspect ratios differ, to tell which was used after scaling.
+    let small = qoi_pattern(4, 4);
+    let wide = qoi_pattern(16, 8);
+    let tall = qoi_pattern(24, 32);
+    let meta = list(b"meta", &[(b"thmb", &tall)]);
+    let run = |input: &std::path::Path, size: &str| {
+        let output = thumbnailer(&dir)
+            .arg("--strict")
+            .arg(input)
+            .arg(size)
+            .arg(&out)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{size}: {output:?}");
+        let png = decode_png_file(&out);
+        std::fs::remove_file(&out).unwrap();
+        (png.info.width, png.info.height)
+    };
+
+    let two = write_file(
+        &dir,
+        "two.fzp",
+        &fzp(&[(b"thmb", &small), (b"hist", b"abc"), (b"thmb", &wide)]),
+    );
+    assert_eq!(run(&two, "4"), (4, 4));
+    assert_eq!(run(&two, "5"), (5, 3));
+    assert_eq!(run(&two, "native"), (16, 8));
+
+    let three = write_file(
+        &dir,
+        "three.fzp",
+        &fzp(&[(b"thmb", &wide), (b"LIST", &meta), (b"thmb", &small)]),
+    );
+    // The smallest at least as large as asked for.
+    assert_eq!(run(&three, "2"), (2, 2));
+    assert_eq!(run(&three, "8"), (8, 4));
+    assert_eq!(run(&three, "16"), (16, 8));
+    assert_eq!(run(&three, "20"), (15, 20));
+    // Otherwise the largest, at its own size.
+    assert_eq!(run(&three, "64"), (24, 32));
+    assert_eq!(run(&three, "native"), (24, 32));
+}
+
+#[test]
+fn thumbnail_index() {
+    let dir = temp_dir("thumbnail_index");
+    let out = dir.join("out.png");
+    let small = qoi_pattern(4, 4);
+    let wide = qoi_pattern(16, 8);
+    // Only found by searching.
+    let tall = qoi_pattern(24, 32);
+    let index = |records: &[(u32, u32, u64, u64)]| {
+        let mut index = (records.len() as u32).to_le_bytes().to_vec();
+        for &(width, height, offset, len) in records {
+            index.extend_from_slice(&width.to_le_bytes());
+            index.extend_from_slice(&height.to_le_bytes());
+            index.extend_from_slice(&offset.to_le_bytes());
+            index.extend_from_slice(&len.to_le_bytes());
+        }
+        index
+    };
+    // The index lists thumbnails in chunks that aren't otherwise searched for, after it and its
+    // two records.
+    let document = |records: &[(u32, u32, u64, u64)]| {
+        let index = index(records);
+        assert_eq!(index.len(), 4 + 2 * 24);
+        fzp(&[
+            (b"thix", &index),
+            (b"tdat", &small),
+            (b"tdat", &wide),
+            (b"thmb", &tall),
+        ])
+    };
+    let small_at = 12 + 8 + 52 + 8;
+    let wide_at = small_at + (small.len() as u64).next_multiple_of(2) + 8;
+    let (small_len, wide_len) = (small.len() as u64, wide.len() as u64);
+    let run = |input: &std::path::Path, flags: &[&str], size: &str| {
+        let output = thumbnailer(&dir)
+            .args(flags)
+            .arg(input)
+            .arg(size)
+            .arg(&out)
+            .output()
+            .unwrap();
+        let dimensions = output.status.success().then(|| {
+            let png = decode_png_file(&out);
+            std::fs::remove_file(&out).unwrap();
+            (png.info.width, png.info.height)
+        });
+        (dimensions, output)
+    };
+
+    let valid = write_file(
+        &dir,
+        "valid.fzp",
+        &document(&[(4, 4, small_at, small_len), (16, 8, wide_at, wide_len)]),
+    );
+    assert_eq!(run(&valid, &["--strict"], "4").0, Some((4, 4)));
+    assert_eq!(run(&valid, &["--strict"], "native").0, Some((16, 8)));
+    // Or any other, counted in the order listed.
+    assert_eq!(run(&valid, &["--chunk", "1"], "native").0, Some((4, 4)));
+    assert_eq!(run(&valid, &["--chunk", "2"], "4").0, Some((4, 2)));
+    let (dimensions, output) = run(&valid, &["--chunk", "3"], "native");
+    assert_eq!(dimensions, None);
+    assert_eq!(output.status.code(), Some(3), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("only 2 thumbnails"), "{stderr}");
+
+    // Anything wrong with the index, and it's searched for instead.
+    let out_of_range = write_file(
+        &dir,
+        "out_of_range.fzp",
+        &document(&[(4, 4, small_at, small_len), (16, 8, wide_at, 100_000)]),
+    );
+    assert_eq!(run(&out_of_range, &[], "native").0, Some((24, 32)));
+    let (dimensions, output) = run(&out_of_range, &["--strict"], "native");
+    assert_eq!(dimensions, None);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "thumbnail index at offset 12 lists a thumbnail of 100000 bytes at offset {wide_at}, \
+             past the end of the document"
+        )),
+        "{stderr}"
+    );
+
+    // Nothing listed is nothing wrong, but there's still a thumbnail to search for.
+    let empty = write_file(
+        &dir,
+        "empty.fzp",
+        &fzp(&[(b"thix", &index(&[])), (b"thmb", &tall)]),
+    );
+    assert_eq!(run(&empty, &["--strict"], "native").0, Some((24, 32)));
+}
+
+#[test]
+fn recover() {
+    let dir = temp_dir("recover");
+    let out = dir.join("out.png");
+    let qoi = qoi_pattern(6, 4);
+    let run = |input: &std::path::Path, flags: &[&str]| {
+        thumbnailer(&dir)
+            .args(flags)
+            .arg(input)
+            .arg("native")
+            .arg(&out)
+            .output()
+            .unwrap()
+    };
+
+    // A mangled header, and a false start.
+    let mut damaged = simple_fzp(6, 4);
+    damaged[..4].copy_from_slice(b"R\0FF");
+    damaged.splice(12..12, *b"qoif\0\0\0\0\0\0\0\0\x04\0");
+    // After the false start and the chunk header.
+    let offset = 12 + 14 + 8;
+    let input = write_file(&dir, "damaged.fzp", &damaged);
+    let output = run(&input, &[]);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    assert!(!out.exists());
+
+    let output = run(&input, &["--recover"]);
+    assert!(output.status.success(), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!(
+            "recovered a thumbnail from offset {offset}, after: "
+        )),
+        "{stderr}"
+    );
+    let png = decode_png_file(&out);
+    assert_eq!((png.info.width, png.info.height), (6, 4));
+    std::fs::remove_file(&out).unwrap();
+
+    // Only so far in.
+    let mut far = vec![0; 8 * 1024 * 1024];
+    far.extend_from_slice(&qoi);
+    let input = write_file(&dir, "far.fzp", &far);
+    let output = run(&input, &["--recover"]);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("unrecognized file type"), "{stderr}");
+}
+
+#[test]
+fn allow_flatten() {
+    let dir = temp_dir("allow_flatten");
+    let out = dir.join("out.png");
+    let run = |document: &[u8], flags: &[&str]| {
+        let input = write_file(&dir, "in.fzp", document);
+        let output = thumbnailer(&dir)
+            .args(flags)
+            .arg(&input)
+            .arg("64")
+            .arg(&out)
+            .output()
+            .unwrap();
+        let png = output.status.success().then(|| decode_png_file(&out));
+        let _ = std::fs::remove_file(&out);
+        (output, png)
+    };
+    // Larger than any thumbnail is allowed to be.
+    let flat = qoi_with(2000, 1000, |x, _| [(x / 8) as u8, 0, 0, 255]);
+    let document = fzp(&[(b"meta", b"abcd"), (b"flat", &flat)]);
+
+    let (output, _) = run(&document, &[]);
+    assert_eq!(output.status.code(), Some(3), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("--allow-flatten"), "{stderr}");
+
+    let (output, png) = run(&document, &["--allow-flatten"]);
+    assert!(output.status.success(), "{output:?}");
+    let png = png.unwrap();
+    assert_eq!((png.info.width, png.info.height), (64, 32));
+
+    // Only ever a fallback.
+    let document = fzp(&[(b"flat", &flat), (b"thmb", &qoi_pattern(6, 4))]);
+    let (output, png) = run(&document, &["--allow-flatten"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(png.unwrap().rgba().len(), 6 * 4 * 4);
+
+    // Within its own limit, which `--max-dimension` can only raise.
+    let huge = fzp(&[(b"flat", &qoi_black(4200, 4000))]);
+    let (output, _) = run(&huge, &["--allow-flatten"]);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let (output, _) = run(&huge, &["--allow-flatten", "--max-dimension", "8192"]);
+    assert!(output.status.success(), "{output:?}");
+
+    // Neither is no thumbnail, flag or not.
+    let (output, _) = run(&fzp(&[(b"meta", b"abcd")]), &["--allow-flatten"]);
+    assert_eq!(output.status.code(), Some(3), "{output:?}");
+}
+
+#[test]
+fn placeholder_size() {
+    let dir = temp_dir("placeholder_size");
+    let out = dir.join("out.png");
+    // As left by a crash mid-save, with the last chunk's size never patched.
+    let mut document = fzp(&[(b"meta", b"abcd"), (b"thmb", &qoi_pattern(6, 4))]);
+    document.truncate(12 + 12 + 8 + qoi_pattern(6, 4).len());
+    let riff_len = document.len() as u32 - 8;
+    document[4..8].copy_from_slice(&riff_len.to_le_bytes());
+    document[28..32].copy_from_slice(&u32::MAX.to_le_bytes());
+    let input = write_file(&dir, "in.fzp", &document);
+
+    let output = thumbnailer(&dir)
+        .args(["-v"])
+        .arg(&input)
+        .arg("native")
+        .arg(&out)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(decode_png_file(&out).info.width, 6);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("chunk \"thmb\" at offset 24 has a placeholder size"),
+        "{stderr}"
+    );
+
+    let output = thumbnailer(&dir)
+        .arg("--strict")
+        .arg(&input)
+        .arg("native")
+        .arg(dir.join("strict.png"))
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("placeholder size"), "{stderr}");
+}
+
+#[test]
+fn misstated_riff_size() {
+    let dir = temp_dir("misstated_riff_size");
+    let out = dir.join("out.png");
+    let document = fzp(&[(b"meta", b"abcd"), (b"thmb", &qoi_pattern(6, 4))]);
+    let actual = document.len() - 8;
+    for (name, riff_len) in [
+        ("zero", 0),
+        ("too_small", 16),
+        ("too_large", actual as u32 + 100),
+    ] {
+        let mut document = document.clone();
+        document[4..8].copy_from_slice(&riff_len.to_le_bytes());
+        let input = write_file(&dir, &format!("{name}.fzp"), &document);
+        let output = thumbnailer(&dir)
+            .arg(&input)
+            .arg("native")
+            .arg(&out)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{name}: {output:?}");
+        assert_eq!(decode_png_file(&out).info.width, 6, "{name}");
+        std::fs::remove_file(&out).unwrap();
+
+        let output = thumbnailer(&dir)
+            .arg("--strict")
+            .arg(&input)
+            .arg("native")
+            .arg(&out)
+            .output()
+            .unwrap();
+        assert_eq!(output.status.code(), Some(4), "{name}: {output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains(&format!(
+                "RIFF header declares {riff_len} bytes, but {actual} follow it"
+            )),
+            "{name}: {stderr}"
+        );
+    }
+}
+
+#[test]
+fn form_types() {
+    let dir = temp_dir("form_types");
+    let out = dir.join("out.png");
+    let thumbnail = |input: &std::path::Path, extra: &[&str]| {
+        thumbnailer(&dir)
+            .args(extra)
+            .arg(input)
+            .arg("native")
+            .arg(&out)
+            .output()
+            .unwrap()
+    };
+    for (form, mime) in [
+        (b"fzp ", "application/x.fuzzpaint-doc"),
+        (b"fzp2", "application/x.fuzzpaint-doc"),
+        (b"fzpB", "application/x.fuzzpaint-brush"),
+    ] {
+        let name = String::from_utf8_lossy(form).trim().to_owned();
+        let document = riff(form, &[(b"thmb", &qoi_pattern(4, 4))]);
+        let input = write_file(&dir, &format!("{name}.fzp"), &document);
+        let output = thumbnail(&input, &[]);
+        assert!(output.status.success(), "{name}: {output:?}");
+        let png = decode_png_file(&out);
+        assert_eq!(png.text("Thumb::Mimetype"), Some(mime), "{name}");
+        std::fs::remove_file(&out).unwrap();
+    }
+
+    let unknown = write_file(
+        &dir,
+        "unknown.fzp",
+        &riff(b"fzp3", &[(b"thmb", &qoi_pattern(4, 4))]),
+    );
+    let output = thumbnail(&unknown, &[]);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("unrecognized file type"), "{stderr}");
+    assert!(!out.exists());
+
+    // Unless it's asked for.
+    let output = thumbnail(&unknown, &["--accept-form", "fzp3"]);
+    assert!(output.status.success(), "{output:?}");
+    let png = decode_png_file(&out);
+    assert_eq!(
+        png.text("Thumb::Mimetype"),
+        Some("application/x.fuzzpaint-doc")
+    );
+}
+
+#[test]
+fn directory_bundle() {
+    let dir = temp_dir("directory_bundle");
+    let out = dir.join("out.png");
+    let bundle = dir.join("autosave.fzp");
+    std::fs::create_dir(&bundle).unwrap();
+    let member = write_file(&bundle, "document.riff", &simple_fzp(6, 4));
+    write_file(&bundle, "brush.qoi", &qoi_pattern(2, 2));
+    std::fs::File::options()
+        .write(true)
+        .open(&member)
+        .unwrap()
+        .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(1_000_000))
+        .unwrap();
+
+    let output = thumbnailer(&dir)
+        .arg(&bundle)
+        .arg("native")
+        .arg(&out)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let png = decode_png_file(&out);
+    assert_eq!((png.info.width, png.info.height), (6, 4));
+    assert_eq!(png.text("Thumb::MTime"), Some("1000000"));
+    assert!(
+        png.text("Thumb::URI").unwrap().ends_with("/autosave.fzp"),
+        "{:?}",
+        png.text("Thumb::URI")
+    );
+    std::fs::remove_file(&out).unwrap();
+
+    // Any other directory just has no thumbnail.
+    let output = thumbnailer(&dir)
+        .arg(&dir)
+        .arg("native")
+        .arg(&out)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("not a document bundle"), "{stderr}");
+    assert!(!out.exists());
+}
+
+#[test]
+fn sniffed_not_named() {
+    let dir = temp_dir("sniffed_not_named");
+    let out = dir.join("out.png");
+    let run = |input: &std::path::Path| {
+        thumbnailer(&dir)
+            .arg(input)
+            .arg("native")
+            .arg(&out)
+            .output()
+            .unwrap()
+    };
+    // Named like anything but a document.
+    for name in [
+        "painting.fzp~",
+        "painting.fzp.bak",
+        ".autosave-x1Yz9q",
+        "noext",
+    ] {
+        let input = write_file(&dir, name, &simple_fzp(6, 4));
+        let output = run(&input);
+        assert!(output.status.success(), "{name}: {output:?}");
+        assert_eq!(decode_png_file(&out).info.width, 6, "{name}");
+        std::fs::remove_file(&out).unwrap();
+    }
+
+    // Named like a document, but isn't one.
+    let mut png = Vec::new();
+    {
+        let mut encoder = png::Encoder::new(&mut png, 1, 1);
+        encoder.set_color(png::ColorType::Rgba);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(&[0, 0, 0, 255]).unwrap();
+    }
+    let input = write_file(&dir, "painting.fzp", &png);
+    let output = run(&input);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("input looks like PNG, not a fuzzpaint document"),
+        "{stderr}"
+    );
+    assert!(!out.exists());
+}
+
+#[test]
+#[cfg(feature = "zstd")]
+fn zstd_compressed() {
+    use ruzstd::encoding::{compress_to_vec, CompressionLevel};
+    let dir = temp_dir("zstd_compressed");
+    let out = dir.join("out.png");
+    let run = |input: &std::path::Path| {
+        thumbnailer(&dir)
+            .arg(input)
+            .arg("native")
+            .arg(&out)
+            .output()
+            .unwrap()
+    };
+    let document = fzp(&[(b"meta", b"abcd"), (b"thmb", &qoi_pattern(6, 4))]);
+    let input = write_file(
+        &dir,
+        "doc.fzp.zst",
+        &compress_to_vec(&document[..], CompressionLevel::Fastest),
+    );
+    let output = run(&input);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(decode_png_file(&out).info.width, 6);
+    std::fs::remove_file(&out).unwrap();
+
+    // Far more than is worth decompressing.
+    let huge = fzp(&[
+        (b"thmb", &qoi_pattern(6, 4)),
+        (b"junk", &vec![0; 33 * 1024 * 1024]),
+    ]);
+    let input = write_file(
+        &dir,
+        "huge.fzp.zst",
+        &compress_to_vec(&huge[..], CompressionLevel::Fastest),
+    );
+    let output = run(&input);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("compressed document too large to thumbnail"),
+        "{stderr}"
+    );
+    assert!(!out.exists());
+}
+
+#[test]
+#[cfg(not(feature = "zstd"))]
+fn zstd_unsupported() {
+    let dir = temp_dir("zstd_unsupported");
+    let out = dir.join("out.png");
+    let input = write_file(&dir, "doc.fzp.zst", b"\x28\xb5\x2f\xfd\x20\x04abcd");
+    let output = thumbnailer(&dir)
+        .arg(&input)
+        .arg("native")
+        .arg(&out)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("input looks like zstd-compressed data"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn canvas_size() {
+    let dir = temp_dir("canvas_size");
+    let out = dir.join("out.png");
+    let run = |input: &std::path::Path, extra: &[&str]| {
+        thumbnailer(&dir)
+            .args(extra)
+            .arg(input)
+            .arg("8")
+            .arg(&out)
+            .output()
+            .unwrap()
+    };
+    let dims = |width: u32, height: u32| [width.to_le_bytes(), height.to_le_bytes()].concat();
+    let thmb = qoi_pattern(24, 16);
+    for (name, document, expected) in [
+        (
+            "landscape",
+            fzp(&[(b"dims", &dims(1920, 1080)), (b"thmb", &thmb)]),
+            ("1920", "1080"),
+        ),
+        (
+            "after_thumbnail",
+            fzp(&[(b"thmb", &thmb), (b"dims", &dims(300, 4000))]),
+            ("300", "4000"),
+        ),
+        // The thumbnail's own size, not what it was resized to.
+        ("absent", fzp(&[(b"thmb", &thmb)]), ("24", "16")),
+        (
+            "malformed",
+            fzp(&[(b"dims", &dims(0, 1080)), (b"thmb", &thmb)]),
+            ("24", "16"),
+        ),
+    ] {
+        let input = write_file(&dir, &format!("{name}.fzp"), &document);
+        let output = run(&input, &[]);
+        assert!(output.status.success(), "{name}: {output:?}");
+        let png = decode_png_file(&out);
+        assert_eq!(png.info.width, 8, "{name}");
+        assert_eq!(
+            (
+                png.text("Thumb::Image::Width").unwrap(),
+                png.text("Thumb::Image::Height").unwrap()
+            ),
+            expected,
+            "{name}"
+        );
+        std::fs::remove_file(&out).unwrap();
+    }
+
+    let input = dir.join("malformed.fzp");
+    let output = run(&input, &["--strict"]);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("canvas size at offset 12 gives a 0x1080 canvas"),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn info_metadata() {
+    let dir = temp_dir("info_metadata");
+    let out = dir.join("out.png");
+    let run = |input: &std::path::Path, extra: &[&str]| {
+        let output = thumbnailer(&dir)
+            .args(extra)
+            .arg(input)
+            .arg("native")
+            .arg(&out)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        let png = decode_png_file(&out);
+        std::fs::remove_file(&out).unwrap();
+        png
+    };
+    let info = |entries: &[(&[u8; 4], &[u8])]| list(b"INFO", entries);
+    let thmb = qoi_pattern(4, 4);
+
+    let entries = info(&[
+        (b"INAM", b"Sunset over the bay\0"),
+        (b"IART", b"Zilla\0"),
+        (b"ICMT", b"Painted on location.\nTwo sessions.\0"),
+        (b"ISFT", b"fuzzpaint\0"),
+    ]);
+    let input = write_file(
+        &dir,
+        "all.fzp",
+        &fzp(&[(b"LIST", &entries), (b"thmb", &thmb)]),
+    );
+    let png = run(&input, &[]);
+    assert_eq!(png.text("Title"), Some("Sunset over the bay"));
+    assert_eq!(png.text("Author"), Some("Zilla"));
+    assert_eq!(
+        png.text("Description"),
+        Some("Painted on location.\nTwo sessions.")
+    );
+    // The user's own take precedence.
+    let png = run(&input, &["--png-text", "Title=Renamed"]);
+    assert_eq!(png.text("Title"), Some("Renamed"));
+    assert_eq!(png.text("Author"), Some("Zilla"));
+    assert_eq!(png.text.iter().filter(|(k, _)| k == "Title").count(), 1);
+
+    // Each alone, in other encodings.
+    for (id, text, keyword, expected) in [
+        (b"INAM", &b"caf\xe9\0"[..], "Title", "caf\u{e9}"),
+        (b"IART", "Zoë".as_bytes(), "Author", "Zo\u{eb}"),
+        (b"ICMT", "猫 cat".as_bytes(), "Description", "? cat"),
+    ] {
+        let entries = info(&[(id, text)]);
+        let input = write_file(
+            &dir,
+            "one.fzp",
+            &fzp(&[(b"LIST", &entries), (b"thmb", &thmb)]),
+        );
+        let png = run(&input, &[]);
+        assert_eq!(png.text(keyword), Some(expected), "{keyword}");
+        let others = ["Title", "Author", "Description"];
+        for other in others.iter().filter(|&&other| other != keyword) {
+            assert_eq!(png.text(other), None, "{keyword}");
+        }
+    }
+
+    // Malformed: an entry overrunning the list, followed by the thumbnail.
+    let mut entries = info(&[(b"INAM", b"Kept\0")]);
+    entries.extend_from_slice(b"IART\x00\x01\0\0Part");
+    let input = write_file(
+        &dir,
+        "malformed.fzp",
+        &fzp(&[(b"LIST", &entries), (b"thmb", &thmb)]),
+    );
+    let png = run(&input, &[]);
+    assert_eq!(png.text("Title"), Some("Kept"));
+    assert_eq!(png.text("Author"), Some("Part"));
+    assert_eq!(png.info.width, 4);
+
+    // Nothing to copy without one.
+    let input = write_file(&dir, "none.fzp", &simple_fzp(4, 4));
+    let png = run(&input, &[]);
+    for keyword in ["Title", "Author", "Description"] {
+        assert_eq!(png.text(keyword), None);
+    }
+}
+
+#[test]
+fn writer_version() {
+    let dir = temp_dir("writer_version");
+    let out = dir.join("out.png");
+    let software = |document: &[u8]| {
+        let input = write_file(&dir, "in.fzp", document);
+        let output = thumbnailer(&dir)
+            .arg(&input)
+            .arg("native")
+            .arg(&out)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        let png = decode_png_file(&out);
+        std::fs::remove_file(&out).unwrap();
+        png.text("Software").unwrap().to_owned()
+    };
+    let thmb = qoi_pattern(4, 4);
+    assert_eq!(
+        software(&fzp(&[(b"vrsn", b"0.2.0\0"), (b"thmb", &thmb)])),
+        format!(
+            "Fuzzpaint 0.2.0 (fuzzpaint-thumbnailer {})",
+            env!("CARGO_PKG_VERSION")
+        )
+    );
+    let unknown = format!(
+        "Fuzzpaint (fuzzpaint-thumbnailer {})",
+        env!("CARGO_PKG_VERSION")
+    );
+    assert_eq!(software(&fzp(&[(b"thmb", &thmb)])), unknown);
+    assert_eq!(
+        software(&fzp(&[(b"vrsn", b"\x01\x02"), (b"thmb", &thmb)])),
+        unknown
+    );
+}
+
+#[test]
+fn resolution() {
+    let dir = temp_dir("resolution");
+    let out = dir.join("out.png");
+    let run = |document: &[u8]| {
+        let input = write_file(&dir, "in.fzp", document);
+        let output = thumbnailer(&dir)
+            .arg(&input)
+            .arg("8")
+            .arg(&out)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        let png = decode_png_file(&out);
+        std::fs::remove_file(&out).unwrap();
+        png
+    };
+    let reso = |x: u32, y: u32| [x.to_le_bytes(), y.to_le_bytes()].concat();
+    let thmb = qoi_pattern(32, 32);
+
+    let png = run(&fzp(&[(b"reso", &reso(300, 300)), (b"thmb", &thmb)]));
+    let dims = png.pixel_dims.unwrap();
+    assert_eq!((dims.xppu, dims.yppu), (11811, 11811));
+    assert_eq!(dims.unit, png::Unit::Meter);
+    assert_eq!(png.text("X-Fuzzpaint::DPI"), Some("300"));
+
+    let png = run(&fzp(&[(b"thmb", &thmb), (b"reso", &reso(72, 144))]));
+    let dims = png.pixel_dims.unwrap();
+    assert_eq!((dims.xppu, dims.yppu), (2835, 5669));
+    assert_eq!(png.text("X-Fuzzpaint::DPI"), Some("72x144"));
+
+    for nonsense in [reso(0, 300), reso(300, 200_000), b"300".to_vec()] {
+        let png = run(&fzp(&[(b"reso", &nonsense), (b"thmb", &thmb)]));
+        assert!(png.pixel_dims.is_none(), "{nonsense:?}");
+        assert_eq!(png.text("X-Fuzzpaint::DPI"), None, "{nonsense:?}");
+    }
+    let png = run(&simple_fzp(4, 4));
+    assert!(png.pixel_dims.is_none());
+}
+
+#[test]
+fn structure_counts() {
+    let dir = temp_dir("structure_counts");
+    let out = dir.join("out.png");
+    let run = |document: &[u8]| {
+        let input = write_file(&dir, "in.fzp", document);
+        let output = thumbnailer(&dir)
+            .arg(&input)
+            .arg("8")
+            .arg(&out)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        let png = decode_png_file(&out);
+        std::fs::remove_file(&out).unwrap();
+        png
+    };
+    let strc = |layers: u32, objects: u32| [layers.to_le_bytes(), objects.to_le_bytes()].concat();
+    let thmb = qoi_pattern(32, 32);
+
+    let png = run(&fzp(&[(b"strc", &strc(4, 5210)), (b"thmb", &thmb)]));
+    assert_eq!(png.text("X-Fuzzpaint::Layers"), Some("4"));
+    assert_eq!(png.text("X-Fuzzpaint::Objects"), Some("5210"));
+    assert_eq!(png.text("X-Fuzzpaint::Soup"), None);
+
+    let png = run(&fzp(&[(b"thmb", &thmb), (b"strc", &strc(u32::MAX, 0))]));
+    assert_eq!(png.text("X-Fuzzpaint::Layers"), Some("100000"));
+    assert_eq!(png.text("X-Fuzzpaint::Objects"), Some("0"));
+
+    for png in [
+        run(&simple_fzp(4, 4)),
+        run(&fzp(&[(b"strc", b"4"), (b"thmb", &thmb)])),
+    ] {
+        assert_eq!(png.text("X-Fuzzpaint::Layers"), None);
+        assert_eq!(png.text("X-Fuzzpaint::Objects"), None);
+    }
+}
+
+#[test]
+fn icc_profile() {
+    let dir = temp_dir("icc_profile");
+    let out = dir.join("out.png");
+    let run = |document: &[u8]| {
+        let input = write_file(&dir, "in.fzp", document);
+        let output = thumbnailer(&dir)
+            .arg(&input)
+            .arg("8")
+            .arg(&out)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        let raw = std::fs::read(&out).unwrap();
+        std::fs::remove_file(&out).unwrap();
+        (decode_png(&raw), raw)
+    };
+    // A header, and a v2 `desc` tag naming it.
+    let mut profile = vec![0; 128];
+    profile[36..40].copy_from_slice(b"acsp");
+    profile.extend_from_slice(&1u32.to_be_bytes());
+    profile.extend_from_slice(b"desc");
+    profile.extend_from_slice(&144u32.to_be_bytes());
+    profile.extend_from_slice(&27u32.to_be_bytes());
+    profile.extend_from_slice(b"desc\0\0\0\0\0\0\0\x0fWide  gamut\tP3\0");
+    let len = profile.len() as u32;
+    profile[0..4].copy_from_slice(&len.to_be_bytes());
+    let thmb = qoi_pattern(16, 16);
+
+    let (png, raw) = run(&fzp(&[(b"icc ", &profile), (b"thmb", &thmb)]));
+    assert_eq!(png.icc_profile.as_deref(), Some(&profile[..]));
+    assert!(!png.srgb);
+    let name_at = raw.windows(4).position(|w| w == b"iCCP").unwrap() + 4;
+    assert!(raw[name_at..].starts_with(b"Wide gamut P3\0\0"));
+
+    // Too large to be worth carrying, so sRGB as ever.
+    let mut huge = profile.clone();
+    huge.resize(1024 * 1024 + 2, 0);
+    let (png, _) = run(&fzp(&[(b"icc ", &huge), (b"thmb", &thmb)]));
+    assert_eq!(png.icc_profile, None);
+    assert!(png.srgb);
+
+    let (png, _) = run(&simple_fzp(16, 16));
+    assert_eq!(png.icc_profile, None);
+    assert!(png.srgb);
+}
+
+#[test]
+fn png_thumbnails() {
+    let dir = temp_dir("png_thumbnails");
+    let out = dir.join("out.png");
+    let run = |thmb: &[u8], size: &str| {
+        let input = write_file(&dir, "in.fzp", &fzp(&[(b"thmb", thmb)]));
+        let output = thumbnailer(&dir)
+            .arg("--force")
+            .arg(&input)
+            .arg(size)
+            .arg(&out)
+            .output()
+            .unwrap();
+        let png = output.status.success().then(|| decode_png_file(&out));
+        (output, png)
+    };
+    let (width, height) = (40, 30);
+    let pixel = |x: u32, y: u32| [(x * 7) as u8, (y * 13) as u8, (x ^ y) as u8, (x * y) as u8];
+    let rgba: Vec<u8> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| pixel(x, y)))
+        .flatten()
+        .collect();
+    let channels = |keep: &[usize]| -> Vec<u8> {
+        rgba.chunks(4)
+            .flat_map(|px| keep.iter().map(|&i| px[i]))
+            .collect()
+    };
+
+    // The same image either way, in whichever channels.
+    let gray = channels(&[1]);
+    let gray_alpha = channels(&[1, 3]);
+    let expand = |gray: &[u8], alpha| -> Vec<u8> {
+        gray.chunks(if alpha { 2 } else { 1 })
+            .flat_map(|px| {
+                let rgb = [px[0]; 3];
+                rgb.into_iter().chain(px.get(1).copied())
+            })
+            .collect()
+    };
+    for (qoi, png) in [
+        (
+            qoi_with(width, height, pixel),
+            png_with(width, height, png::ColorType::Rgba, &rgba),
+        ),
+        (
+            qoi::encode_to_vec(channels(&[0, 1, 2]), width, height).unwrap(),
+            png_with(width, height, png::ColorType::Rgb, &channels(&[0, 1, 2])),
+        ),
+        (
+            qoi::encode_to_vec(expand(&gray, false), width, height).unwrap(),
+            png_with(width, height, png::ColorType::Grayscale, &gray),
+        ),
+        (
+            qoi::encode_to_vec(expand(&gray_alpha, true), width, height).unwrap(),
+            png_with(width, height, png::ColorType::GrayscaleAlpha, &gray_alpha),
+        ),
+    ] {
+        for size in ["native", "16", "64"] {
+            let (output, from_qoi) = run(&qoi, size);
+            assert!(output.status.success(), "{output:?}");
+            let (output, from_png) = run(&png, size);
+            assert!(output.status.success(), "{output:?}");
+            let (from_qoi, from_png) = (from_qoi.unwrap(), from_png.unwrap());
+            assert_eq!(from_png.info.color_type, from_qoi.info.color_type);
+            assert_eq!(from_png.pixels, from_qoi.pixels, "{size}");
+        }
+    }
+
+    // Held to the same limits.
+    let (output, _) = run(
+        &png_with(2000, 1000, png::ColorType::Rgb, &vec![0; 6_000_000]),
+        "64",
+    );
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("2000x1000 exceeds limit"), "{stderr}");
+
+    // With an IHDR claiming more than IDAT holds, or less.
+    let png = png_with(width, height, png::ColorType::Rgba, &rgba);
+    for claimed in [(width, height * 2), (width / 2, height)] {
+        let mut lying = png.clone();
+        lying[16..20].copy_from_slice(&claimed.0.to_be_bytes());
+        lying[20..24].copy_from_slice(&claimed.1.to_be_bytes());
+        let mut crc = flate2::Crc::new();
+        crc.update(&lying[12..29]);
+        lying[29..33].copy_from_slice(&crc.sum().to_be_bytes());
+        let (output, _) = run(&lying, "64");
+        assert_eq!(output.status.code(), Some(4), "{claimed:?} {output:?}");
+    }
+
+    // Described from its header alone.
+    let input = write_file(&dir, "in.fzp", &fzp(&[(b"thmb", &png)]));
+    let output = thumbnailer(&dir)
+        .arg("--info")
+        .arg(&input)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(
+        stdout.contains("\"width\":40,\"height\":30,\"channels\":4"),
+        "{stdout}"
+    );
+}
+
+#[test]
+fn linear_thumbnails() {
+    let dir = temp_dir("linear_thumbnails");
+    let out = dir.join("out.png");
+    let run = |thmb: &[u8], size: &str| {
+        let input = write_file(&dir, "in.fzp", &fzp(&[(b"thmb", thmb)]));
+        let output = thumbnailer(&dir)
+            .arg("--force")
+            .arg(&input)
+            .arg(size)
+            .arg(&out)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        decode_png_file(&out)
+    };
+    let linear = |width, height, channels: usize, px: &[u8]| {
+        let pixels = px.repeat(width as usize * height as usize);
+        assert_eq!(px.len(), channels);
+        qoi::Encoder::new(&pixels, width, height)
+            .unwrap()
+            .with_colorspace(qoi::ColorSpace::Linear)
+            .encode_to_vec()
+            .unwrap()
+    };
+
+    // Linear 55 is sRGB 128, and alpha is linear either way.
+    let png = run(&linear(4, 3, 4, &[55, 0, 255, 55]), "native");
+    assert!(png.srgb);
+    assert_eq!(&png.pixels[..4], [128, 0, 255, 55]);
+    let png = run(&linear(4, 3, 3, &[55, 200, 10]), "native");
+    assert!(png.srgb);
+    assert_eq!(&png.pixels[..3], [128, 229, 56]);
+
+    // Converted before scaling, even when scaled as it's decoded.
+    let png = run(&linear(800, 400, 4, &[55, 200, 10, 255]), "64");
+    assert!(png.srgb);
+    assert!(png.rgba().chunks(4).all(|px| px == [128, 229, 56, 255]));
+}
+
+#[test]
+fn colorspace_override() {
+    let dir = temp_dir("colorspace_override");
+    let out = dir.join("out.png");
+    let run = |thmb: &[u8], size: &str, extra: &[&str]| {
+        let input = write_file(&dir, "in.fzp", &fzp(&[(b"thmb", thmb)]));
+        let output = thumbnailer(&dir)
+            .args(extra)
+            .arg("--force")
+            .args(["--mtime", "1700000000"])
+            .arg(&input)
+            .arg(size)
+            .arg(&out)
+            .arg("file:///in.fzp")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        std::fs::read(&out).unwrap()
+    };
+    let tagged = |width, height, colorspace| {
+        let pixels: Vec<u8> = (0..width * height * 4)
+            .map(|i| (i * 37 % 251) as u8)
+            .collect();
+        qoi::Encoder::new(&pixels, width, height)
+            .unwrap()
+            .with_colorspace(colorspace)
+            .encode_to_vec()
+            .unwrap()
+    };
+
+    // Small enough to decode whole, and large enough to be scaled as it's decoded.
+    for (width, height, size) in [(6, 4, "native"), (800, 400, "64")] {
+        let srgb = tagged(width, height, qoi::ColorSpace::Srgb);
+        let linear = tagged(width, height, qoi::ColorSpace::Linear);
+        let as_srgb = run(&srgb, size, &[]);
+        let as_linear = run(&linear, size, &[]);
+        assert_ne!(as_srgb, as_linear, "{size}");
+
+        // Byte for byte as if it had been tagged the other way.
+        assert_eq!(run(&linear, size, &["--colorspace", "srgb"]), as_srgb);
+        assert_eq!(run(&srgb, size, &["--colorspace=linear"]), as_linear);
+        // Or as it is.
+        assert_eq!(run(&linear, size, &["--colorspace", "auto"]), as_linear);
+        assert_eq!(run(&srgb, size, &["--colorspace", "srgb"]), as_srgb);
+    }
+}
+
+#[test]
+fn document_background() {
+    let dir = temp_dir("document_background");
+    let out = dir.join("out.png");
+    let run = |document: &[u8], extra: &[&str]| {
+        let input = write_file(&dir, "in.fzp", document);
+        let output = thumbnailer(&dir)
+            .args(extra)
+            .arg(&input)
+            .arg("native")
+            .arg(&out)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        let raw = std::fs::read(&out).unwrap();
+        std::fs::remove_file(&out).unwrap();
+        (decode_png(&raw), raw)
+    };
+    let bkgd = |raw: &[u8]| {
+        let at = raw.windows(4).position(|w| w == b"bKGD")?;
+        Some(raw[at + 4..at + 10].to_vec())
+    };
+    // Left half transparent, right half opaque red.
+    let thmb = qoi_with(4, 2, |x, _| if x < 2 { [0; 4] } else { [255, 0, 0, 255] });
+    let bgcl = |rgba: [f32; 4]| -> Vec<u8> { rgba.iter().flat_map(|c| c.to_le_bytes()).collect() };
+    // Linear 0.2159 is sRGB 128.
+    let document = fzp(&[(b"bgcl", &bgcl([0.0, 0.2159, 1.0, 1.0])), (b"thmb", &thmb)]);
+
+    // Only a hint, without flattening.
+    let (png, raw) = run(&document, &[]);
+    assert_eq!(bkgd(&raw), Some(vec![0, 0, 0, 128, 0, 255]));
+    assert_eq!(png.info.color_type, png::ColorType::Rgba);
+    assert_eq!(&png.pixels[..4], [0; 4]);
+
+    let (png, _) = run(&document, &["--background", "doc"]);
+    assert_eq!(png.info.color_type, png::ColorType::Rgb);
+    assert_eq!(
+        png.pixels,
+        [[0, 128, 255], [0, 128, 255], [255, 0, 0], [255, 0, 0]]
+            .concat()
+            .repeat(2)
+    );
+
+    // The user's own color still wins over the document's.
+    let (png, raw) = run(&document, &["--background", "ffffff"]);
+    assert_eq!(&png.pixels[..3], [255, 255, 255]);
+    assert_eq!(bkgd(&raw), Some(vec![0, 0, 0, 128, 0, 255]));
+
+    // Missing or malformed: no hint, nothing to flatten onto.
+    for document in [
+        fzp(&[(b"thmb", &thmb)]),
+        fzp(&[(b"bgcl", &bgcl([2.0, 0.0, 0.0, 1.0])), (b"thmb", &thmb)]),
+        fzp(&[(b"bgcl", b"abc"), (b"thmb", &thmb)]),
+    ] {
+        let (png, raw) = run(&document, &["--background", "doc"]);
+        assert_eq!(bkgd(&raw), None);
+        assert_eq!(png.info.color_type, png::ColorType::Rgba);
+        assert_eq!(&png.pixels[..4], [0; 4]);
+    }
+}
+
+#[test]
+fn lenient() {
+    let dir = temp_dir("lenient");
+    let out = dir.join("out.png");
+    let qoi = qoi_pattern(6, 4);
+    let intact = fzp(&[
+        (b"meta", &[0x80; 40]),
+        (b"hist", &[0xfe; 11]),
+        (b"thmb", &qoi),
+    ]);
+    let run = |document: &[u8], flags: &[&str]| {
+        let input = write_file(&dir, "in.fzp", document);
+        let output = thumbnailer(&dir)
+            .args(flags)
+            .arg(&input)
+            .arg("native")
+            .arg(&out)
+            .output()
+            .unwrap();
+        let png = output.status.success().then(|| decode_png_file(&out));
+        let _ = std::fs::remove_file(&out);
+        (output, png)
+    };
+    let (_, expected) = run(&intact, &[]);
+    let expected = expected.unwrap();
+
+    // The size of the chunk in the middle too large to fit, or too small, so the next header is
+    // read from the middle of its data.
+    let mut too_large = intact.clone();
+    too_large[64..68].copy_from_slice(&0x7fff_0000u32.to_le_bytes());
+    let mut too_small = intact.clone();
+    too_small[16..20].copy_from_slice(&20u32.to_le_bytes());
+    for document in [too_large, too_small] {
+        let (output, _) = run(&document, &[]);
+        assert!(!output.status.success(), "{output:?}");
+
+        let (output, png) = run(&document, &["--lenient"]);
+        assert!(output.status.success(), "{output:?}");
+        assert_eq!(png.unwrap().pixels, expected.pixels);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(
+            stderr.contains("skipped 1 malformed chunk header"),
+            "{stderr}"
+        );
+
+        let (output, _) = run(&document, &["--lenient", "--strict"]);
+        assert_eq!(output.status.code(), Some(2), "{output:?}");
+    }
+}
+
+#[test]
+fn far_too_many_bytes() {
+    let dir = temp_dir("far_too_many_bytes");
+    let out = dir.join("out.png");
+    let run = |thmb: &[u8], flags: &[&str]| {
+        let input = write_file(&dir, "in.fzp", &fzp(&[(b"thmb", thmb)]));
+        thumbnailer(&dir)
+            .arg("--force")
+            .args(flags)
+            .arg(&input)
+            .arg("64")
+            .arg(&out)
+            .output()
+            .unwrap()
+    };
+
+    // A megapixel of flat color in a few KiB is nothing unusual.
+    let output = run(&qoi_black(1024, 1024), &[]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(decode_png_file(&out).info.width, 64);
+
+    // But an 8x8 image has no need of 10 MiB.
+    let mut bogus = qoi_pattern(8, 8);
+    bogus.resize(10 << 20, 0);
+    let output = run(&bogus, &[]);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("thumbnail of 10485760 bytes is far more than a 8x8 image needs"),
+        "{stderr}"
+    );
+    let output = run(&bogus, &["--lenient"]);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+
+    // Somewhat more is only allowed leniently.
+    let mut padded = qoi_pattern(8, 8);
+    padded.resize(2000, 0);
+    let output = run(&padded, &[]);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let output = run(&padded, &["--lenient"]);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(decode_png_file(&out).info.width, 8);
+}
+
+#[test]
+fn big_endian() {
+    let dir = temp_dir("big_endian");
+    let out = dir.join("out.png");
+    let small = qoi_pattern(4, 3);
+    let listed = list(
+        b"meta",
+        &[(b"name", b"odd"), (b"thmb", &qoi_pattern(12, 9))],
+    );
+    let chunks: [(&[u8; 4], &[u8]); 4] = [
+        (b"dims", &[0, 1, 0, 0, 0, 1, 0, 0]),
+        (b"thmb", &small),
+        (b"LIST", &listed),
+        (b"hist", b"abc"),
+    ];
+    let (little, big) = (fzp(&chunks), rifx(b"fzp ", &chunks));
+    // Only the magic and sizes differ.
+    assert_eq!(little.len(), big.len());
+    assert_eq!(&big[..4], b"RIFX");
+    assert_eq!(
+        big[4..8],
+        little[4..8].iter().rev().copied().collect::<Vec<_>>()
+    );
+    assert_eq!(big[8..20], [b"fzp dims".as_slice(), &[0, 0, 0, 8]].concat());
+
+    let run = |document: &[u8], size: &str| {
+        let input = write_file(&dir, "in.fzp", document);
+        let output = thumbnailer(&dir)
+            .args([
+                "--strict",
+                "--mtime",
+                "1700000000",
+                "--uri",
+                "file:///doc.fzp",
+            ])
+            .arg(&input)
+            .arg(size)
+            .arg(&out)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        let png = std::fs::read(&out).unwrap();
+        std::fs::remove_file(&out).unwrap();
+        png
+    };
+    for size in ["4", "native"] {
+        assert_eq!(run(&big, size), run(&little, size), "{size}");
+    }
+    assert_eq!(decode_png(&run(&big, "native")).info.width, 12);
+}
+
+#[test]
+fn sidecar() {
+    let dir = temp_dir("sidecar");
+    let out = dir.join("out.png");
+    let stripped = write_file(&dir, "art.fzp", &fzp(&[(b"meta", b"abcd")]));
+    let sidecar = write_file(&dir, "art.fzp.thumb", &qoi_pattern(6, 4));
+    let set_modified = |path: &std::path::Path, secs| {
+        std::fs::File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs))
+            .unwrap();
+    };
+    set_modified(&stripped, 1_000_000);
+    set_modified(&sidecar, 2_000_000);
+    let run = |flags: &[&str]| {
+        let output = thumbnailer(&dir)
+            .args(flags)
+            .arg(&stripped)
+            .arg("native")
+            .arg(&out)
+            .output()
+            .unwrap();
+        let png = output.status.success().then(|| decode_png_file(&out));
+        let _ = std::fs::remove_file(&out);
+        (output, png)
+    };
+
+    // Only if asked.
+    let (output, _) = run(&[]);
+    assert_eq!(output.status.code(), Some(3), "{output:?}");
+    let (output, png) = run(&["--sidecar"]);
+    assert!(output.status.success(), "{output:?}");
+    let png = png.unwrap();
+    assert_eq!((png.info.width, png.info.height), (6, 4));
+    // Of the document, not the sidecar.
+    assert_eq!(png.text("Thumb::MTime"), Some("1000000"));
+
+    // Never for stdin.
+    let output = thumbnailer(&dir)
+        .args(["--sidecar", "-", "native", "-", "file:///art.fzp"])
+        .stdin(std::fs::File::open(&stripped).unwrap())
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(3), "{output:?}");
+
+    // A thumbnail of its own comes first.
+    let whole = write_file(&dir, "whole.fzp", &simple_fzp(3, 3));
+    write_file(&dir, "whole.fzp.thumb", &qoi_pattern(6, 4));
+    let output = thumbnailer(&dir)
+        .args(["--sidecar"])
+        .arg(&whole)
+        .arg("native")
+        .arg(&out)
+        .output()
+        .unwrap();
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(decode_png_file(&out).info.width, 3);
+    std::fs::remove_file(&out).unwrap();
+
+    // A corrupt sidecar is as a corrupt thumbnail.
+    let mut truncated = qoi_pattern(6, 4);
+    truncated.truncate(truncated.len() - 12);
+    write_file(&dir, "art.fzp.thumb", &truncated);
+    let (output, _) = run(&["--sidecar"]);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("failed to read sidecar"), "{stderr}");
+    assert!(stderr.contains("art.fzp.thumb"), "{stderr}");
+    write_file(&dir, "art.fzp.thumb", b"not a qoi image");
+    let (output, _) = run(&["--sidecar"]);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+}
+
+#[test]
+#[cfg(feature = "extra-formats")]
+fn extra_formats() {
+    use image::ImageEncoder;
+    let dir = temp_dir("extra_formats");
+    let out = dir.join("out.png");
+    let run = |thmb: &[u8]| {
+        let input = write_file(&dir, "in.fzp", &fzp(&[(b"thmb", thmb)]));
+        let output = thumbnailer(&dir)
+            .arg("--force")
+            .arg(&input)
+            .arg("native")
+            .arg(&out)
+            .output()
+            .unwrap();
+        let png = output.status.success().then(|| decode_png_file(&out));
+        (output, png)
+    };
+    let (width, height) = (40, 30);
+    let pixel = |x: u32, y: u32| [(x * 6) as u8, (y * 8) as u8, 128, 255];
+    let rgba: Vec<u8> = (0..height)
+        .flat_map(|y| (0..width).map(move |x| pixel(x, y)))
+        .flatten()
+        .collect();
+    let (_, expected) = run(&qoi_with(width, height, pixel));
+    let expected = expected.unwrap();
+
+    // Lossless, so just as from QOI.
+    let mut webp = Vec::new();
+    image::codecs::webp::WebPEncoder::new_lossless(&mut webp)
+        .write_image(&rgba, width, height, image::ExtendedColorType::Rgba8)
+        .unwrap();
+    let (output, png) = run(&webp);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(png.unwrap().pixels, expected.pixels);
+
+    // Lossy, so only roughly.
+    let rgb: Vec<u8> = rgba.chunks(4).flat_map(|px| &px[..3]).copied().collect();
+    let mut jpeg = Vec::new();
+    image::codecs::jpeg::JpegEncoder::new_with_quality(&mut jpeg, 95)
+        .write_image(&rgb, width, height, image::ExtendedColorType::Rgb8)
+        .unwrap();
+    let (output, png) = run(&jpeg);
+    assert!(output.status.success(), "{output:?}");
+    let png = png.unwrap();
+    assert_eq!((png.info.width, png.info.height), (width, height));
+    let near = png
+        .pixels
+        .iter()
+        .zip(&expected.pixels)
+        .all(|(a, b)| a.abs_diff(*b) <= 16);
+    assert!(near);
+
+    // Held to the same limits.
+    let mut huge = Vec::new();
+    image::codecs::webp::WebPEncoder::new_lossless(&mut huge)
+        .write_image(
+            &vec![0; 2000 * 1000 * 3],
+            2000,
+            1000,
+            image::ExtendedColorType::Rgb8,
+        )
+        .unwrap();
+    let (output, _) = run(&huge);
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("2000x1000 exceeds limit"), "{stderr}");
+
+    // Recognized but not decodable, or not recognized at all, or cut short.
+    for thmb in [
+        &b"GIF89a\x01\0\x01\0\0\0\0;"[..],
+        b"not an image",
+        &jpeg[..100],
+    ] {
+        let (output, _) = run(thmb);
+        assert_eq!(output.status.code(), Some(4), "{output:?}");
+    }
+}
+
+#[test]
+#[cfg(feature = "zip")]
+fn zip_member() {
+    use std::io::Write;
+    let dir = temp_dir("zip_member");
+    let out = dir.join("out.png");
+    let archive = dir.join("art.zip");
+    let mut zip = zip::ZipWriter::new(std::fs::File::create(&archive).unwrap());
+    let options = zip::write::SimpleFileOptions::default()
+        .compression_method(zip::CompressionMethod::Deflated)
+        .last_modified_time(zip::DateTime::from_date_and_time(2023, 11, 14, 22, 13, 20).unwrap());
+    zip.start_file("sketches/doc.fzp", options).unwrap();
+    zip.write_all(&fzp(&[(b"meta", b"abcd"), (b"thmb", &qoi_pattern(6, 4))]))
+        .unwrap();
+    zip.start_file("huge.fzp", options).unwrap();
+    zip.write_all(&fzp(&[(b"junk", &vec![0; 33 * 1024 * 1024])]))
+        .unwrap();
+    zip.finish().unwrap();
+    let run = |input: &str| {
+        let output = thumbnailer(&dir)
+            .arg(input)
+            .arg("native")
+            .arg(&out)
+            .output()
+            .unwrap();
+        let png = output.status.success().then(|| decode_png_file(&out));
+        let _ = std::fs::remove_file(&out);
+        (output, png)
+    };
+    let archive_uri = format!(
+        "file://{}",
+        std::fs::canonicalize(&archive).unwrap().display()
+    );
+
+    let (output, png) = run(&format!("{}#sketches/doc.fzp", archive.display()));
+    assert!(output.status.success(), "{output:?}");
+    let png = png.unwrap();
+    assert_eq!((png.info.width, png.info.height), (6, 4));
+    // The entry's time, not the archive's.
+    assert_eq!(png.text("Thumb::MTime"), Some("1700000000"));
+    assert_eq!(
+        png.text("Thumb::URI"),
+        Some(format!("{archive_uri}#sketches/doc.fzp").as_str())
+    );
+
+    // As GVfs names it, with the archive's URI escaped twice over.
+    let escaped = archive_uri.replace(':', "%253A").replace('/', "%252F");
+    let uri = format!("archive://{escaped}/sketches/doc.fzp");
+    let (output, png) = run(&uri);
+    assert!(output.status.success(), "{output:?}");
+    assert_eq!(png.unwrap().text("Thumb::URI"), Some(uri.as_str()));
+
+    let (output, _) = run(&format!("{}#sketches/none.fzp", archive.display()));
+    assert_eq!(output.status.code(), Some(5), "{output:?}");
+    let (output, _) = run(&format!("{}#huge.fzp", archive.display()));
+    assert_eq!(output.status.code(), Some(4), "{output:?}");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("archive member too large"), "{stderr}");
+}
+
+#[test]
+fn fail_markers_only_for_documents() {
+    let dir = temp_dir("fail_markers_only_for_documents");
+    let markers = dir
+        .join("cache")
+        .join("thumbnails")
+        .join("fail")
+        .join("fuzzpaint-thumbnailer");
+    let input = dir.join("in.fzp");
+    let run = |document: &[u8], args: &[&std::ffi::OsStr]| {
+        let _ = std::fs::remove_dir_all(&markers);
+        write_file(&dir, "in.fzp", document);
+        let output = thumbnailer(&dir).args(args).output().unwrap();
+        (output.status.code(), markers.exists())
+    };
+    let missing = dir.join("missing");
+    let out = dir.join("out.png");
+
+    // The document would be fine next time, somewhere it can be written.
+    let good = simple_fzp(16, 16);
+    let nowhere = missing.join("out.png");
+    assert_eq!(
+        run(&good, &[input.as_ref(), "16".as_ref(), nowhere.as_ref()]),
+        (Some(6), false)
+    );
+    assert_eq!(
+        run(
+            &good,
+            &[
+                "--sizes=16,32".as_ref(),
+                "--out-dir".as_ref(),
+                missing.as_ref(),
+                input.as_ref()
+            ]
+        ),
+        // As every size failed to be written.
+        (Some(1), false)
+    );
+    // Nor is the command line the document's fault.
+    assert_eq!(
+        run(
+            &good,
+            &[
+                "--sizes=16,32".as_ref(),
+                "--out-template=same.png".as_ref(),
+                "--out-dir".as_ref(),
+                dir.as_ref(),
+                input.as_ref()
+            ]
+        ),
+        (Some(2), false)
+    );
+
+    // Whereas this version of the document will never have a thumbnail.
+    let none = fzp(&[(b"LIST", b"INFO")]);
+    let args = [input.as_ref(), "16".as_ref(), out.as_ref()];
+    assert_eq!(run(&none, &args), (Some(3), true));
+    let corrupt = fzp(&[(b"thmb", b"qoif nonsense")]);
+    assert_eq!(run(&corrupt, &args), (Some(4), true));
+}
+
+#[test]
+fn blank_thumbnails() {
+    let dir = temp_dir("blank_thumbnails");
+    let out = dir.join("out.png");
+    let markers = dir
+        .join("cache")
+        .join("thumbnails")
+        .join("fail")
+        .join("fuzzpaint-thumbnailer");
+    let run = |thmb: &[u8], args: &[&str]| {
+        let _ = std::fs::remove_dir_all(&markers);
+        let input = write_file(&dir, "in.fzp", &fzp(&[(b"thmb", thmb)]));
+        thumbnailer(&dir)
+            .args(args)
+            .arg("--force")
+            .arg(&input)
+            .arg("16")
+            .arg(&out)
+            .output()
+            .unwrap()
+    };
+
+    // Small enough to decode whole, and large enough to be scaled as it's decoded.
+    for side in [8, 1024] {
+        let clear = qoi_with(side, side, |_, _| [0xff, 0xff, 0xff, 0]);
+        let output = run(&clear, &[]);
+        assert_eq!(output.status.code(), Some(3), "{output:?}");
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("thumbnail is blank"), "{stderr}");
+        assert!(!out.exists());
+        let marker = std::fs::read_dir(&markers)
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            decode_png_file(&marker.path()).text("X-Fuzzpaint::Failure"),
+            Some("thumbnail is blank, every pixel fully transparent")
+        );
+
+        let output = run(&clear, &["--allow-blank"]);
+        assert!(output.status.success(), "{output:?}");
+        let png = decode_png_file(&out);
+        assert!(png.pixels.chunks_exact(4).all(|px| px[3] == 0));
+        assert!(!markers.exists());
+        std::fs::remove_file(&out).unwrap();
+
+        // A single pixel, however faint, is something to see, even if it scales away.
+        let faint = qoi_with(side, side, |x, y| {
+            [0xff, 0xff, 0xff, u8::from((x, y) == (side - 1, side - 1))]
+        });
+        let output = run(&faint, &[]);
+        assert!(output.status.success(), "{side}: {output:?}");
+        assert!(out.exists());
+        assert!(!markers.exists());
+        std::fs::remove_file(&out).unwrap();
+    }
+
+    // Nor are opaque thumbnails ever blank, black as they may be.
+    let output = run(&qoi_black(8, 8), &[]);
+    assert!(output.status.success(), "{output:?}");
+}
+
+#[test]
+fn grayscale_output() {
+    let dir = temp_dir("grayscale_output");
+    let out = dir.join("out.png");
+    let run = |thmb: &[u8], size: &str, extra: &[&str]| {
+        let input = write_file(&dir, "in.fzp", &fzp(&[(b"thmb", thmb)]));
+        let output = thumbnailer(&dir)
+            .args(extra)
+            .arg("--force")
+            .arg(&input)
+            .arg(size)
+            .arg(&out)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        decode_png_file(&out)
+    };
+    let gray = |x: u32, y: u32| (x * 40 + y * 7) as u8;
+    let gray_rgba = qoi_with(6, 4, |x, y| {
+        let v = gray(x, y);
+        [v, v, v, y as u8 * 80]
+    });
+    let gray_rgb = qoi_rgb_with(6, 4, |x, y| [gray(x, y); 3]);
+    let (_, rgba) = qoi::decode_to_vec(&gray_rgba).unwrap();
+
+    // Decoding back to exactly what was stored.
+    let png = run(&gray_rgba, "native", &[]);
+    assert_eq!(png.info.color_type, png::ColorType::GrayscaleAlpha);
+    assert_eq!(png.rgba(), rgba);
+    let png = run(&gray_rgb, "native", &[]);
+    assert_eq!(png.info.color_type, png::ColorType::Grayscale);
+    let (_, rgb) = qoi::decode_to_vec(&gray_rgb).unwrap();
+    assert_eq!(
+        png.pixels,
+        rgb.chunks_exact(3).map(|px| px[0]).collect::<Vec<_>>()
+    );
+
+    // Scaled just as it would be in color.
+    let png = run(&gray_rgba, "16", &[]);
+    assert_eq!(png.info.color_type, png::ColorType::GrayscaleAlpha);
+    let color = run(&gray_rgba, "16", &["--no-optimize-color"]);
+    assert_eq!(color.info.color_type, png::ColorType::Rgba);
+    assert_eq!(png.rgba(), color.pixels);
+
+    // Not even one pixel of color.
+    let tinted = qoi_with(6, 4, |x, y| {
+        let v = gray(x, y);
+        [v, v, if (x, y) == (5, 3) { v ^ 1 } else { v }, 254]
+    });
+    let png = run(&tinted, "native", &[]);
+    assert_eq!(png.info.color_type, png::ColorType::Rgba);
+    let (_, tinted) = qoi::decode_to_vec(&tinted).unwrap();
+    assert_eq!(png.pixels, tinted);
+
+    // Nor a colored background for it to show through onto.
+    let png = run(&gray_rgba, "native", &["--background", "ff000080"]);
+    assert_eq!(png.info.color_type, png::ColorType::Rgba);
+    let png = run(&gray_rgba, "native", &["--background", "808080"]);
+    assert_eq!(png.info.color_type, png::ColorType::Grayscale);
+}
+
+#[test]
+fn opaque_output() {
+    let dir = temp_dir("opaque_output");
+    let out = dir.join("out.png");
+    let run = |thmb: &[u8], extra: &[&str]| {
+        let input = write_file(&dir, "in.fzp", &fzp(&[(b"thmb", thmb)]));
+        let output = thumbnailer(&dir)
+            .args(extra)
+            .arg("--force")
+            .args(["--mtime", "1700000000"])
+            .arg(&input)
+            .arg("native")
+            .arg(&out)
+            .arg("file:///in.fzp")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        decode_png_file(&out)
+    };
+    let opaque = qoi_with(6, 4, |x, y| [x as u8 * 40, y as u8 * 60, 7, 255]);
+    let (_, rgba) = qoi::decode_to_vec(&opaque).unwrap();
+
+    let png = run(&opaque, &[]);
+    assert_eq!(png.info.color_type, png::ColorType::Rgb);
+    assert_eq!(png.rgba(), rgba);
+    // Everything but the alpha channel is as it would have been.
+    let forced = run(&opaque, &["--force-rgba"]);
+    assert_eq!(forced.info.color_type, png::ColorType::Rgba);
+    assert_eq!(forced.pixels, rgba);
+    assert!(png.srgb && forced.srgb);
+    assert_eq!(png.text, forced.text);
+    assert_eq!(png.text("Thumb::MTime"), Some("1700000000"));
+
+    // Only if every last pixel is fully opaque.
+    let almost = qoi_with(6, 4, |x, y| {
+        [
+            x as u8 * 40,
+            y as u8 * 60,
+            7,
+            if (x, y) == (3, 2) { 254 } else { 255 },
+        ]
+    });
+    let png = run(&almost, &[]);
+    assert_eq!(png.info.color_type, png::ColorType::Rgba);
+    let (_, rgba) = qoi::decode_to_vec(&almost).unwrap();
+    assert_eq!(png.pixels, rgba);
+}
+
+#[test]
+fn gamma_correct() {
+    let dir = temp_dir("gamma_correct");
+    let out = dir.join("out.png");
+    let run = |document: &[u8], extra: &[&str]| {
+        let input = write_file(&dir, "in.fzp", document);
+        let output = thumbnailer(&dir)
+            .args(extra)
+            .args(["--force", "--filter", "box"])
+            .arg(&input)
+            .arg("8")
+            .arg(&out)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        decode_png_file(&out)
+    };
+    let mean = |png: &Png| {
+        let rgba = png.rgba();
+        let sum: u64 = rgba.chunks_exact(4).map(|px| u64::from(px[0])).sum();
+        sum as f64 / (rgba.len() / 4) as f64
+    };
+    let checkerboard = |width, height| {
+        let pixel = |x: u32, y: u32| match (x + y) % 2 {
+            0 => [0, 0, 0, 255],
+            _ => [255, 255, 255, 255],
+        };
+        fzp(&[(b"thmb", &qoi_with(width, height, pixel))])
+    };
+
+    // Half the light is far brighter than half the sRGB value, at any size, strips or not.
+    for (width, height) in [(64, 64), (1024, 1024)] {
+        let document = checkerboard(width, height);
+        let gamma = mean(&run(&document, &[]));
+        assert!((gamma - 127.5).abs() < 1.0, "{width}x{height}: {gamma}");
+        let linear = mean(&run(&document, &["--gamma-correct"]));
+        assert!((linear - 187.5).abs() < 1.0, "{width}x{height}: {linear}");
+    }
+
+    // Unchanged where there's nothing to blend, or the pixels aren't known to be sRGB.
+    let flat = fzp(&[(b"thmb", &qoi_with(64, 64, |_, _| [200, 100, 50, 255]))]);
+    assert_eq!(
+        run(&flat, &["--gamma-correct"]).pixels,
+        run(&flat, &[]).pixels
+    );
+    let profiled = fzp(&[(b"icc ", b"some profile"), (b"thmb", &qoi_pattern(64, 64))]);
+    assert_eq!(
+        run(&profiled, &["--gamma-correct"]).pixels,
+        run(&profiled, &[]).pixels
+    );
+    let pattern = fzp(&[(b"thmb", &qoi_pattern(64, 64))]);
+    assert_ne!(
+        run(&pattern, &["--gamma-correct"]).pixels,
+        run(&pattern, &[]).pixels
+    );
+}
+
+#[test]
+fn premultiply() {
+    let dir = temp_dir("premultiply");
+    let out = dir.join("out.png");
+    let run = |document: &[u8], extra: &[&str]| {
+        let input = write_file(&dir, "in.fzp", document);
+        let output = thumbnailer(&dir)
+            .args(extra)
+            .args(["--force", "--filter", "lanczos3"])
+            .arg(&input)
+            .arg("24")
+            .arg(&out)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        decode_png_file(&out)
+    };
+    // An opaque red circle, on transparent black.
+    let circle = |side: u32| {
+        let pixel = move |x: u32, y: u32| {
+            let (dx, dy) = (x as f32 - side as f32 / 2.0, y as f32 - side as f32 / 2.0);
+            if dx.hypot(dy) < side as f32 / 3.0 {
+                [255, 0, 0, 255]
+            } else {
+                [0, 0, 0, 0]
+            }
+        };
+        fzp(&[(b"thmb", &qoi_with(side, side, pixel))])
+    };
+    // The darkest red of any pixel that shows at all.
+    let darkest = |png: &Png| {
+        let rgba = png.rgba();
+        let visible = rgba.chunks_exact(4).filter(|px| px[3] > 0);
+        assert!(visible.clone().all(|px| px[1] == 0 && px[2] == 0));
+        visible.map(|px| px[0]).min().unwrap()
+    };
+
+    // No dark ring around the edge, whether scaled whole or in strips, beyond the rounding of
+    // the faintest pixels.
+    for side in [96, 1024] {
+        let document = circle(side);
+        let png = run(&document, &[]);
+        assert!(darkest(&png) >= 250, "{side}");
+        assert!(png
+            .rgba()
+            .chunks_exact(4)
+            .any(|px| px[3] < 255 && px[3] > 0));
+        assert!(
+            darkest(&run(&document, &["--gamma-correct"])) >= 254,
+            "{side}"
+        );
+        // As there used to be.
+        assert!(
+            darkest(&run(&document, &["--no-premultiply"])) < 200,
+            "{side}"
+        );
+    }
+}
+
+#[test]
+fn default_filter() {
+    let dir = temp_dir("default_filter");
+    let out = dir.join("out.png");
+    let run = |thmb: &[u8], size: &str, extra: &[&str]| {
+        let input = write_file(&dir, "in.fzp", &fzp(&[(b"thmb", thmb)]));
+        let output = thumbnailer(&dir)
+            .args(extra)
+            .args(["--force", "--allow-upscale"])
+            .args(["--mtime", "1700000000"])
+            .arg(&input)
+            .arg(size)
+            .arg(&out)
+            .arg("file:///in.fzp")
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        std::fs::read(&out).unwrap()
+    };
+
+    // Chosen by how far it's scaled, unless one is asked for.
+    for (side, size, expected) in [
+        (1024, "128", "lanczos3"),
+        (300, "256", "catmullrom"),
+        (64, "256", "catmullrom"),
+        (256, "255", "nearest"),
+    ] {
+        let thmb = qoi_pattern(side, side);
+        let chosen = run(&thmb, size, &[]);
+        assert_eq!(
+            chosen,
+            run(&thmb, size, &["--filter", expected]),
+            "{side} to {size}"
+        );
+        assert_ne!(
+            chosen,
+            run(&thmb, size, &["--filter", "box"]),
+            "{side} to {size}"
+        );
+    }
+}
+
+#[test]
+fn threads() {
+    let dir = temp_dir("threads");
+    let out = dir.join("out.png");
+    // Noisy, and partly transparent, so any pixel weighed differently shows.
+    let thmb = qoi_with(1100, 900, |x, y| {
+        let v = (x * 7919 + y * 104_729) ^ (x * y);
+        [
+            v as u8,
+            (v >> 3) as u8,
+            (v >> 7) as u8,
+            (v >> 11) as u8 | 0x0f,
+        ]
+    });
+    let input = write_file(&dir, "in.fzp", &fzp(&[(b"thmb", &thmb)]));
+    let run = |flags: &[&str], env: Option<&str>| {
+        let mut command = thumbnailer(&dir);
+        if let Some(threads) = env {
+            command.env("FUZZPAINT_THUMB_THREADS", threads);
+        }
+        let output = command
+            .arg("--force")
+            .args(flags)
+            .arg(&input)
+            .arg("800")
+            .arg(&out)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{flags:?}: {output:?}");
+        decode_png_file(&out).rgba()
+    };
+
+    // The same to the bit however many threads, scaled in strips as it's decoded, whole, or in
+    // linear light.
+    for flags in [
+        &[][..],
+        &["--flip", "h"],
+        &["--gamma-correct"],
+        &["--filter", "lanczos3", "--crop", "100,50,900x700"],
+    ] {
+        let one = run(&[&["--threads", "1"], flags].concat(), None);
+        for threads in ["2", "16"] {
+            let many = run(&[&["--threads", threads], flags].concat(), None);
+            assert!(one == many, "{flags:?} on {threads} threads");
+        }
+        assert!(one == run(flags, None), "{flags:?} on every core");
+        assert!(
+            one == run(flags, Some("4")),
+            "{flags:?} from the environment"
+        );
+    }
+
+    let output = thumbnailer(&dir)
+        .args(["--threads", "0"])
+        .arg(&input)
+        .arg("800")
+        .arg(&out)
+        .output()
+        .unwrap();
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("positive integer"));
+}
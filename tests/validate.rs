@@ -0,0 +1,127 @@
+mod common;
+use common::*;
+
+fn validate(dir: &std::path::Path, input: &std::path::Path) -> (bool, Vec<String>) {
+    let output = thumbnailer(dir)
+        .arg("--validate")
+        .arg(input)
+        .output()
+        .unwrap();
+    let lines = String::from_utf8(output.stdout)
+        .unwrap()
+        .lines()
+        .map(|line| {
+            line.strip_prefix(&format!("{}: ", input.display()))
+                .expect("issue not prefixed with input path")
+                .to_owned()
+        })
+        .collect();
+    (output.status.success(), lines)
+}
+
+#[test]
+fn clean_file_is_silent() {
+    let dir = temp_dir("validate_clean");
+    let input = write_file(&dir, "in.fzp", &simple_fzp(16, 16));
+    assert_eq!(validate(&dir, &input), (true, vec![]));
+    // Nothing but the input was written.
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+}
+
+#[test]
+fn reports_each_issue() {
+    let dir = temp_dir("validate_issues");
+
+    let not_riff = write_file(&dir, "not_riff.fzp", b"\x89PNG\r\n\x1a\n");
+    let (ok, issues) = validate(&dir, &not_riff);
+    assert!(!ok);
+    assert_eq!(issues.len(), 1);
+
+    // Chunk claims more than the file holds, which is also why there's no thumbnail.
+    let mut truncated = fzp(&[(b"LIST", b"INFO"), (b"thmb", &qoi_pattern(4, 4))]);
+    truncated.truncate(truncated.len() - 4);
+    let truncated = write_file(&dir, "truncated.fzp", &truncated);
+    let (ok, issues) = validate(&dir, &truncated);
+    assert!(!ok);
+    assert_eq!(issues.len(), 2, "{issues:?}");
+    assert!(issues[0].contains("RIFF header declares"));
+    assert!(issues[1].contains("document is truncated (chunk \"thmb\""));
+
+    let garbage = write_file(&dir, "garbage.fzp", &fzp(&[(b"thmb", b"qoif nonsense")]));
+    let (ok, issues) = validate(&dir, &garbage);
+    assert!(!ok);
+    assert_eq!(issues.len(), 1, "{issues:?}");
+
+    // The encoder refuses to make this one.
+    let mut zero_qoi = b"qoif\0\0\0\0\0\0\0\x04\x04\0".to_vec();
+    zero_qoi.extend_from_slice(&[0, 0, 0, 0, 0, 0, 0, 1]);
+    let zero = write_file(&dir, "zero.fzp", &fzp(&[(b"thmb", &zero_qoi)]));
+    let (ok, issues) = validate(&dir, &zero);
+    assert!(!ok);
+    assert_eq!(issues.len(), 1, "{issues:?}");
+
+    let huge = write_file(
+        &dir,
+        "huge.fzp",
+        &fzp(&[(b"thmb", &qoi_pattern(1025, 1025))]),
+    );
+    let (ok, issues) = validate(&dir, &huge);
+    assert!(!ok);
+    assert_eq!(
+        issues,
+        ["thumbnail size 1025x1025 exceeds limit of 1024x1024 px in area"]
+    );
+
+    let missing = write_file(
+        &dir,
+        "missing.fzp",
+        &fzp(&[(b"LIST", b"INFO"), (b"docv", b"")]),
+    );
+    let (ok, issues) = validate(&dir, &missing);
+    assert!(!ok);
+    assert_eq!(issues, ["document does not contain a thumbnail"]);
+}
+
+#[test]
+fn reports_what_thumbnailing_copes_with() {
+    let dir = temp_dir("validate_coped");
+    let strict = |input: &std::path::Path| {
+        thumbnailer(&dir)
+            .arg("--strict")
+            .arg(input)
+            .arg("native")
+            .arg(dir.join("out.png"))
+            .status()
+            .unwrap()
+            .code()
+    };
+    let qoi = qoi_pattern(4, 4);
+
+    // A chunk within a list claiming more than the list leaves it.
+    let layr = [&b"layr"[..], b"meta", &500u32.to_le_bytes(), b"abcd"].concat();
+    let overrun = fzp(&[(b"LIST", &layr), (b"thmb", &qoi)]);
+    let overrun = write_file(&dir, "overrun.fzp", &overrun);
+    assert_eq!(
+        validate(&dir, &overrun),
+        (
+            false,
+            vec![
+                "chunk \"meta\" at offset 24 declares 500 bytes, but its parent leaves only 4"
+                    .to_owned()
+            ]
+        )
+    );
+    assert_eq!(strict(&overrun), Some(4));
+
+    // A canvas size too short to hold one.
+    let bad_dims = fzp(&[(b"dims", &[16, 0, 0, 0, 16, 0]), (b"thmb", &qoi)]);
+    let bad_dims = write_file(&dir, "bad_dims.fzp", &bad_dims);
+    assert_eq!(
+        validate(&dir, &bad_dims),
+        (
+            false,
+            vec!["canvas size at offset 12 is 6 bytes, not 8".to_owned()]
+        )
+    );
+    assert_eq!(strict(&bad_dims), Some(4));
+}